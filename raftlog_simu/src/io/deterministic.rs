@@ -1,5 +1,5 @@
 use raftlog::election::{Ballot, Role};
-use raftlog::log::{LogIndex, LogPrefix, LogSuffix};
+use raftlog::log::{LogIndex, LogPrefix, LogSuffix, SnapshotChunk};
 use raftlog::message::Message;
 use raftlog::node::NodeId;
 use raftlog::Io;
@@ -103,6 +103,12 @@ impl Io for DeterministicIo {
     fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog {
         self.storage.save_log_prefix(prefix)
     }
+    fn save_snapshot_chunk(&mut self, chunk: SnapshotChunk) -> Self::SaveLog {
+        self.storage.save_snapshot_chunk(chunk)
+    }
+    fn supports_snapshot_chunks(&self) -> bool {
+        true
+    }
     fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
         self.storage.save_log_suffix(suffix)
     }
@@ -2,7 +2,7 @@
 ///
 /// 全てのデータはメモリ上で保持する.
 use raftlog::election::Ballot;
-use raftlog::log::{Log, LogIndex, LogPosition, LogPrefix, LogSuffix};
+use raftlog::log::{Log, LogIndex, LogPosition, LogPrefix, LogSuffix, SnapshotChunk};
 use raftlog::node::NodeId;
 use trackable::error::ErrorKindExt;
 
@@ -21,6 +21,7 @@ pub struct Storage {
     ballot: Ballot,
     log_prefix: Option<LogPrefix>,
     log_suffix: LogSuffix,
+    partial_snapshot: Option<LogPrefix>,
 }
 impl Storage {
     /// 新しい`StorageConfig`インスタンスを生成する.
@@ -34,6 +35,7 @@ impl Storage {
             },
             log_prefix: None,
             log_suffix: LogSuffix::default(),
+            partial_snapshot: None,
         }
     }
 
@@ -70,6 +72,36 @@ impl Storage {
         DelayedResult::ok((), time)
     }
 
+    /// 分割転送されたスナップショットの一部を保存する.
+    ///
+    /// 最後のチャンクが渡された時点で、`save_log_prefix`と同様にスナップショットが保存される.
+    pub fn save_snapshot_chunk(&mut self, chunk: SnapshotChunk) -> SaveLog {
+        if chunk.offset == 0 {
+            self.partial_snapshot = Some(LogPrefix {
+                tail: chunk.tail,
+                config: chunk.config,
+                snapshot: Vec::new(),
+            });
+        }
+        let mut prefix = match self.partial_snapshot.take() {
+            Some(p) if p.tail == chunk.tail && p.snapshot.len() as u64 == chunk.offset => p,
+            _ => {
+                let e = ErrorKind::InconsistentState
+                    .cause(format!("Unexpected snapshot chunk: offset={}", chunk.offset));
+                let time = self.config.save_log_snapshot_time.choose(&mut self.rng);
+                return DelayedResult::err(e.into(), time);
+            }
+        };
+        prefix.snapshot.extend_from_slice(&chunk.data);
+        if chunk.done {
+            self.save_log_prefix(prefix)
+        } else {
+            self.partial_snapshot = Some(prefix);
+            let time = self.config.save_log_snapshot_time.choose(&mut self.rng);
+            DelayedResult::ok((), time)
+        }
+    }
+
     /// ログの末尾部分を保存(追記)する.
     pub fn save_log_suffix(&mut self, suffix: &LogSuffix) -> SaveLog {
         let result = self.log_append(suffix);
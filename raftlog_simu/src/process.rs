@@ -382,7 +382,9 @@ impl Stream for Alive {
         if let Async::Ready(Some(ref event)) = result {
             log!(self.logger, "Event: {:?}", event);
             match *event {
-                Event::Committed { ref entry, index } => {
+                Event::Committed {
+                    ref entry, index, ..
+                } => {
                     track!(self.handle_committed(index, entry))?;
                 }
                 Event::SnapshotLoaded {
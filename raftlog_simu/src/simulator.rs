@@ -126,7 +126,7 @@ impl Simulator {
         Ok(())
     }
     fn handle_event(&mut self, node_id: &NodeId, event: Event) -> Result<()> {
-        if let Event::Committed { index, entry } = event {
+        if let Event::Committed { index, entry, .. } = event {
             let index = index.as_u64() as usize;
             track_assert!(index <= self.commit_history.len(), ErrorKind::Other);
             let state = self
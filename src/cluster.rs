@@ -6,6 +6,7 @@ use std::cmp;
 use std::collections::BTreeSet;
 
 use crate::node::NodeId;
+use crate::{ErrorKind, Result};
 
 /// クラスタに属するメンバ群.
 pub type ClusterMembers = BTreeSet<NodeId>;
@@ -36,15 +37,121 @@ impl ClusterState {
     }
 }
 
+/// 合意(ログのコミットおよびリーダ選出)に必要な、承認数の決め方.
+///
+/// 新旧のメンバ群が存在する構成変更中には、それぞれのメンバ群に対して個別に適用される.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quorum {
+    /// ログのコミットとリーダ選出の両方で、投票権を有するメンバの過半数の承認を要求する.
+    #[default]
+    Majority,
+
+    /// ログのコミット(書き込み)とリーダ選出(読み込み)とで、異なる承認数を要求する.
+    ///
+    /// 安全性のためには、以下の両方が満たされる必要がある:
+    ///
+    /// - 任意の書き込み用の集合と読み込み用の集合が交わる (i.e., `write + read`がメンバ数よりも大きい)
+    /// - 任意の二つの読み込み用の集合が交わる (i.e., `read`がメンバ数の過半数以上)
+    ///
+    /// 後者が満たされない場合には、同じ`term`で二人のリーダが選出され得る.
+    /// そのため、承認数を減らせるのは書き込み側のみとなる.
+    /// 例えば五台構成の場合、`write: 2, read: 4`は有効だが、
+    /// `write: 4, read: 2`はリーダ選出用の集合同士が交わらないため`Quorum::validate`で拒否される.
+    ///
+    /// 構成変更によってメンバ数が増えて、これらの条件が満たされなくなった場合には、
+    /// 交わりが保証されるように、読み込みおよび書き込みに必要な承認数が引き上げられる.
+    /// また、メンバ数を超える承認数は、メンバ数として扱われる.
+    Flexible {
+        /// ログのコミットに必要な承認数.
+        write: usize,
+
+        /// リーダ選出に必要な承認数.
+        read: usize,
+    },
+}
+impl Quorum {
+    /// メンバ数が`members`の場合の、ログのコミットに必要な承認数を返す.
+    pub fn write_size(self, members: usize) -> usize {
+        match self {
+            Quorum::Majority => members / 2 + 1,
+            Quorum::Flexible { write, .. } => {
+                let read = self.read_size(members);
+                cmp::max(cmp::min(write, members), members + 1 - read)
+            }
+        }
+    }
+
+    /// メンバ数が`members`の場合の、リーダ選出に必要な承認数を返す.
+    pub fn read_size(self, members: usize) -> usize {
+        match self {
+            Quorum::Majority => members / 2 + 1,
+            Quorum::Flexible { read, .. } => cmp::max(cmp::min(read, members), members / 2 + 1),
+        }
+    }
+
+    /// メンバ数が`members`の場合に、書き込み用の集合と読み込み用の集合、
+    /// および二つの読み込み用の集合が、必ず交わるかどうかを検証する.
+    ///
+    /// 交わらない場合や、承認数に`0`ないしメンバ数を超える値が指定されている場合には、
+    /// `ErrorKind::InvalidInput`が返される.
+    pub fn validate(self, members: usize) -> Result<()> {
+        if let Quorum::Flexible { write, read } = self {
+            track_assert_ne!(write, 0, ErrorKind::InvalidInput);
+            track_assert_ne!(read, 0, ErrorKind::InvalidInput);
+            track_assert!(write <= members, ErrorKind::InvalidInput; write, members);
+            track_assert!(read <= members, ErrorKind::InvalidInput; read, members);
+            track_assert!(
+                write + read > members,
+                ErrorKind::InvalidInput,
+                "Write and read quorums never intersect: write={}, read={}, members={}",
+                write,
+                read,
+                members
+            );
+            track_assert!(
+                read * 2 > members,
+                ErrorKind::InvalidInput,
+                "Read quorums never intersect: read={}, members={}",
+                read,
+                members
+            );
+        }
+        Ok(())
+    }
+}
 /// クラスタ構成.
 ///
 /// クラスタに属するメンバの集合に加えて、
 /// 動的構成変更用の状態を管理する.
+///
+/// また、投票権を持たないメンバ(ラーナー)の集合も管理する.
+/// ラーナーにはログが複製されるが、ログのコミットやリーダ選出のための過半数の計算には含まれない.
+///
+/// 合意に必要な承認数は`Quorum`で指定する(デフォルトは過半数).
+///
+/// 投票権を有するメンバの一部は、ウィットネスとして指定することができる.
+/// ウィットネスは選挙やコミットの合意には参加するが、リーダにはならず、
+/// リーダからはコマンドの内容を取り除いたエントリ群(i.e., `LogEntry::Stripped`)が複製される.
+///
+/// ウィットネスの承認のみで過半数に達したエントリは、コマンドの内容を保持する投票メンバがリーダ以外に存在しない.
+/// そのため、その後にリーダが停止すると、他の投票メンバはウィットネスよりもログが古いので票を得られず、
+/// ウィットネス自身はリーダになれないので、リーダが復帰するまでクラスタは利用不能となる.
+/// (e.g., 二つの投票メンバと一つのウィットネスからなる構成で、一方の投票メンバが遅れている間にリーダが停止した場合)
+///
+/// ラーナー・承認数の決め方・ウィットネスは、いずれもメンバ群と同様にクラスタ構成の一部であり、
+/// `ReplicatedLog::add_learner`・`propose_quorum`・`propose_witnesses`を通して構成変更エントリとしてログに記録され、
+/// スナップショットにも含まれる.
+/// そのため`Io`の実装は、`LogEntry::Config`および`LogPrefix::config`の永続化の際に、
+/// メンバ群だけではなく、これらの値(`learners`・`quorum`・`witnesses`メソッドが返す値)も保存する必要がある.
+/// 復元時には`with_state`で生成したインスタンスに対して、`with_learners`・`with_witnesses`・`with_quorum`を適用すること.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClusterConfig {
     new: ClusterMembers,
     old: ClusterMembers,
     state: ClusterState,
+    learners: ClusterMembers,
+    witnesses: ClusterMembers,
+    quorum: Quorum,
 }
 impl ClusterConfig {
     /// 現在のクラスタ状態を返す.
@@ -83,16 +190,44 @@ impl ClusterConfig {
         }
     }
 
+    /// 投票権を持たないメンバ(ラーナー)の集合が返される.
+    pub fn learners(&self) -> &ClusterMembers {
+        &self.learners
+    }
+
+    /// ウィットネスの集合が返される.
+    pub fn witnesses(&self) -> &ClusterMembers {
+        &self.witnesses
+    }
+
+    /// 合意に必要な承認数の決め方を返す.
+    pub fn quorum(&self) -> Quorum {
+        self.quorum
+    }
+
     /// クラスタに属するメンバ群を返す.
     ///
     /// 構成変更中の場合には、新旧両方のメンバの和集合が返される.
+    /// ラーナーも含まれる.
     pub fn members(&self) -> impl Iterator<Item = &NodeId> {
-        self.new.union(&self.old)
+        self.new.union(&self.old).chain(self.learners.iter())
     }
 
     /// このクラスタ構成に含まれるノードかどうかを判定する.
+    ///
+    /// ラーナーに対しても`true`が返される.
     pub fn is_known_node(&self, node: &NodeId) -> bool {
-        self.new.contains(node) || self.old.contains(node)
+        self.new.contains(node) || self.old.contains(node) || self.learners.contains(node)
+    }
+
+    /// 投票権を持たないメンバ(ラーナー)かどうかを判定する.
+    pub fn is_learner(&self, node: &NodeId) -> bool {
+        self.learners.contains(node)
+    }
+
+    /// ウィットネスかどうかを判定する.
+    pub fn is_witness(&self, node: &NodeId) -> bool {
+        self.witnesses.contains(node)
     }
 
     /// 新しい安定状態の`ClusterConfig`インスタンスを生成する.
@@ -101,6 +236,9 @@ impl ClusterConfig {
             new: members,
             old: ClusterMembers::default(),
             state: ClusterState::Stable,
+            learners: ClusterMembers::default(),
+            witnesses: ClusterMembers::default(),
+            quorum: Quorum::Majority,
         }
     }
 
@@ -114,17 +252,133 @@ impl ClusterConfig {
             new: new_members,
             old: old_members,
             state,
+            learners: ClusterMembers::default(),
+            witnesses: ClusterMembers::default(),
+            quorum: Quorum::Majority,
         }
     }
 
+    /// ラーナーの集合を`learners`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// 新旧のメンバ群に含まれるノードは、ラーナーとしては扱われない.
+    pub fn with_learners(mut self, learners: ClusterMembers) -> Self {
+        self.learners = learners
+            .into_iter()
+            .filter(|n| !self.new.contains(n) && !self.old.contains(n))
+            .collect();
+        self
+    }
+
+    /// ウィットネスの集合を`witnesses`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// 新旧のメンバ群のいずれにも含まれないノードは、ウィットネスとしては扱われない.
+    pub fn with_witnesses(mut self, witnesses: ClusterMembers) -> Self {
+        self.witnesses = witnesses
+            .into_iter()
+            .filter(|n| self.new.contains(n) || self.old.contains(n))
+            .collect();
+        self
+    }
+
+    /// 合意に必要な承認数の決め方を`quorum`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// 投票権を有する各メンバ群(構成変更中の場合には新旧の両方)に対して`Quorum::validate`を行い、
+    /// 書き込み用の集合と読み込み用の集合が交わらない場合には`ErrorKind::InvalidInput`を返す.
+    pub fn with_quorum(mut self, quorum: Quorum) -> Result<Self> {
+        track!(quorum.validate(self.new.len()))?;
+        if !self.state.is_stable() {
+            track!(quorum.validate(self.old.len()))?;
+        }
+        self.quorum = quorum;
+        Ok(self)
+    }
+
+    /// このクラスタ構成から`other`への、メンバの変更内容を返す.
+    ///
+    /// 投票権を有するメンバとしては、構成変更後のメンバ群(`new_members`)が比較される.
+    /// 構成変更の計画時に、変更内容を確認したり、一度に変更される投票権の数を検査するために使用される.
+    pub fn diff(&self, other: &ClusterConfig) -> ConfigDiff {
+        let (old_voters, old_learners) = (&self.new, &self.learners);
+        let (new_voters, new_learners) = (&other.new, &other.learners);
+        let select = |xs: &ClusterMembers, pred: &dyn Fn(&NodeId) -> bool| {
+            xs.iter()
+                .filter(|n| pred(n))
+                .cloned()
+                .collect::<ClusterMembers>()
+        };
+        ConfigDiff {
+            old_voters: old_voters.clone(),
+            new_voters: new_voters.clone(),
+            old_quorum: self.quorum,
+            new_quorum: other.quorum,
+            added_voters: select(new_voters, &|n| {
+                !old_voters.contains(n) && !old_learners.contains(n)
+            }),
+            removed_voters: select(old_voters, &|n| {
+                !new_voters.contains(n) && !new_learners.contains(n)
+            }),
+            promoted_learners: select(new_voters, &|n| old_learners.contains(n)),
+            demoted_voters: select(old_voters, &|n| new_learners.contains(n)),
+            added_learners: select(new_learners, &|n| {
+                !old_learners.contains(n) && !old_voters.contains(n)
+            }),
+            removed_learners: select(old_learners, &|n| {
+                !new_learners.contains(n) && !new_voters.contains(n)
+            }),
+        }
+    }
+
+    /// `node`をラーナーとして追加した`ClusterConfig`インスタンスを返す.
+    ///
+    /// `node`が既に投票権を有するメンバの場合には、何も変更されない.
+    pub fn add_learner(&self, node: NodeId) -> Self {
+        let mut learners = self.learners.clone();
+        learners.insert(node);
+        self.clone().with_learners(learners)
+    }
+
+    /// ラーナーの`node`を、投票権を有するメンバに昇格させるための`ClusterConfig`インスタンスを返す.
+    ///
+    /// 通常の構成変更と同様に、返り値は`CatchUp`状態となり、`Joint`状態を経て新構成に移行する.
+    /// `node`がラーナーではない場合には、何も変更されない.
+    pub fn promote_learner(&self, node: &NodeId) -> Self {
+        if !self.is_learner(node) {
+            return self.clone();
+        }
+        let mut new = self.new.clone();
+        new.insert(node.clone());
+        let mut next = self.start_config_change(new);
+        next.learners.remove(node);
+        next
+    }
+
+    /// `new`を構成変更後のメンバ群として、`Joint`状態の`ClusterConfig`インスタンスを返す.
+    ///
+    /// `Joint`状態では、合意(コミットおよび選挙)には新旧両方のメンバ群の過半数の承認が必要となる.
+    ///
+    /// `start_config_change`とは異なり`CatchUp`状態を経由しないので、
+    /// 新規メンバのログの追いつきを待つ必要がない場合にのみ使用すること.
+    pub fn start_joint(&self, new: ClusterMembers) -> Self {
+        self.start_config_change(new).to_next_state()
+    }
+
     /// 構成変更を開始するために、`new`を構成変更後のメンバ群として設定し、
     /// `CatchUp`状態に遷移した`ClusterConfig`インスタンスを返す.
+    ///
+    /// `new`に含まれるラーナーは、ラーナーではなくなる.
+    /// ウィットネスは、新旧のいずれかのメンバ群に含まれている間は、ウィットネスのままとなる.
     pub(crate) fn start_config_change(&self, new: ClusterMembers) -> Self {
+        let learners = self.learners.difference(&new).cloned().collect();
+        let witnesses = self.witnesses.clone();
         ClusterConfig {
             new,
             old: self.primary_members().clone(),
             state: ClusterState::CatchUp,
+            learners,
+            witnesses: ClusterMembers::default(),
+            quorum: self.quorum,
         }
+        .with_witnesses(witnesses)
     }
 
     /// 次の状態に遷移する.
@@ -146,7 +400,8 @@ impl ClusterConfig {
                 let mut next = self.clone();
                 next.old = ClusterMembers::new();
                 next.state = ClusterState::Stable;
-                next
+                let witnesses = next.witnesses.clone();
+                next.with_witnesses(witnesses)
             }
         }
     }
@@ -160,19 +415,34 @@ impl ClusterConfig {
     ///
     /// 最終的な合意値は「メンバの過半数が承認した値集合の中で
     /// 最も大きな値」となる.
+    /// (`Quorum::Flexible`が指定されている場合には「過半数」ではなく、書き込み用の承認数となる)
+    ///
+    /// そのため、応答の遅いメンバが(書き込み用の承認数に対する)余剰分に留まる限りは、合意値の更新が遅れることはない.
+    ///
+    /// `Quorum::Flexible`では、過半数未満の承認でも合意できるが、
+    /// これはリーダ選出用の承認数が引き上げられて、任意の書き込み用の集合と交わることが保証されているためである.
+    /// 逆に、そのような保証が無いまま承認数を減らす(e.g., 遅いメンバを合意の対象から除外する)ことは、
+    /// 互いに交わらない二つの集合が別々に合意できてしまうため、安全ではない.
     pub(crate) fn consensus_value<F, T>(&self, f: F) -> T
     where
         F: Fn(&NodeId) -> T,
         T: Ord + Copy + Default,
     {
-        match self.state {
-            ClusterState::Stable => median(&self.new, &f),
-            ClusterState::CatchUp => median(&self.old, &f),
-            ClusterState::Joint => {
-                // joint consensus
-                cmp::min(median(&self.new, &f), median(&self.old, &f))
-            }
-        }
+        let size = |members: &ClusterMembers| self.quorum.write_size(members.len());
+        self.quorum_value(size, f)
+    }
+
+    /// リーダ選出用の合意値を返す.
+    ///
+    /// 基本的には`consensus_value`メソッドと同様だが、
+    /// `Quorum::Flexible`が指定されている場合には、読み込み用の承認数が使用される.
+    pub(crate) fn election_consensus_value<F, T>(&self, f: F) -> T
+    where
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy + Default,
+    {
+        let size = |members: &ClusterMembers| self.quorum.read_size(members.len());
+        self.quorum_value(size, f)
     }
 
     /// 基本的には`consensus_value`メソッドと同様.
@@ -184,16 +454,40 @@ impl ClusterConfig {
         F: Fn(&NodeId) -> T,
         T: Ord + Copy + Default,
     {
+        let quorum = |members: &ClusterMembers| {
+            let size = self.quorum.write_size(members.len());
+            nth_largest(members, size, &f)
+        };
         if self.state.is_stable() {
-            median(&self.new, &f)
+            quorum(&self.new)
         } else {
             // joint consensus
-            cmp::min(median(&self.new, &f), median(&self.old, &f))
+            cmp::min(quorum(&self.new), quorum(&self.old))
+        }
+    }
+
+    fn quorum_value<S, F, T>(&self, size: S, f: F) -> T
+    where
+        S: Fn(&ClusterMembers) -> usize,
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy + Default,
+    {
+        let quorum = |members: &ClusterMembers| nth_largest(members, size(members), &f);
+        match self.state {
+            ClusterState::Stable => quorum(&self.new),
+            ClusterState::CatchUp => quorum(&self.old),
+            ClusterState::Joint => {
+                // joint consensus
+                cmp::min(quorum(&self.new), quorum(&self.old))
+            }
         }
     }
 }
 
-fn median<F, T>(members: &ClusterMembers, f: F) -> T
+// `members`の承認値のうち、`n`番目に大きな値(i.e., `n`台のメンバが承認済みの値)を返す.
+//
+// 過半数の場合には、中央値となる.
+fn nth_largest<F, T>(members: &ClusterMembers, n: usize, f: F) -> T
 where
     F: Fn(&NodeId) -> T,
     T: Ord + Copy + Default,
@@ -201,9 +495,305 @@ where
     let mut values = members.iter().map(|n| f(n)).collect::<Vec<_>>();
     values.sort();
     values.reverse();
-    if values.is_empty() {
+    if values.is_empty() || n == 0 {
         T::default()
     } else {
-        values[members.len() / 2]
+        values[n - 1]
+    }
+}
+
+/// 二つのクラスタ構成間の、メンバの変更内容.
+///
+/// `ClusterConfig::diff`によって生成される.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// 新たに投票権を有するメンバとして追加されたノード群.
+    pub added_voters: ClusterMembers,
+
+    /// 取り除かれた、投票権を有するメンバ群.
+    pub removed_voters: ClusterMembers,
+
+    /// ラーナーから、投票権を有するメンバに昇格したノード群.
+    pub promoted_learners: ClusterMembers,
+
+    /// 投票権を有するメンバから、ラーナーに降格したノード群.
+    pub demoted_voters: ClusterMembers,
+
+    /// 新たにラーナーとして追加されたノード群.
+    pub added_learners: ClusterMembers,
+
+    /// 取り除かれたラーナー群.
+    pub removed_learners: ClusterMembers,
+
+    // 変更前後の、投票権を有するメンバ群と承認数の決め方 (`is_single_step`の判定に使用される).
+    old_voters: ClusterMembers,
+    new_voters: ClusterMembers,
+    old_quorum: Quorum,
+    new_quorum: Quorum,
+}
+impl ConfigDiff {
+    /// 変更が一切無い場合には`true`を返す.
+    pub fn is_empty(&self) -> bool {
+        self.added_voters.is_empty()
+            && self.removed_voters.is_empty()
+            && self.promoted_learners.is_empty()
+            && self.demoted_voters.is_empty()
+            && self.added_learners.is_empty()
+            && self.removed_learners.is_empty()
+    }
+
+    /// 投票権が変化するノードの数を返す.
+    ///
+    /// ラーナーの追加・削除は、合意に影響しないので数えない.
+    pub fn voting_changes(&self) -> usize {
+        self.added_voters.len()
+            + self.removed_voters.len()
+            + self.promoted_learners.len()
+            + self.demoted_voters.len()
+    }
+
+    /// 新旧混合状態(joint consensus)を経由せずに、一度に適用しても安全な変更の場合には`true`を返す.
+    ///
+    /// 変更前後の投票権を有するメンバ群と承認数から、新旧の構成の間で、
+    /// リーダ選出用の集合同士、および、書き込み用の集合とリーダ選出用の集合が、必ず交わるかどうかを判定する.
+    ///
+    /// `Quorum::Majority`同士であれば、投票権の変化が一ノード以下の場合に`true`となる.
+    /// 一方で`Quorum::Flexible`の場合には、承認数次第では、一ノードの追加や承認数の決め方の変更のみでも`false`となる.
+    /// `false`の場合には、`ClusterConfig::start_joint`等を用いて、新旧混合状態を経由する必要がある.
+    pub fn is_single_step(&self) -> bool {
+        let (old, new) = (self.old_voters.len(), self.new_voters.len());
+        let shared = self.old_voters.intersection(&self.new_voters).count();
+        let intersects = |old_size: usize, new_size: usize| {
+            // 新旧それぞれの集合が、共有されていないメンバだけでは賄えずに、共有メンバを使う必要がある数
+            let old_shared = old_size.saturating_sub(old - shared);
+            let new_shared = new_size.saturating_sub(new - shared);
+            shared < old_shared + new_shared
+        };
+        let (old_write, old_read) = (
+            self.old_quorum.write_size(old),
+            self.old_quorum.read_size(old),
+        );
+        let (new_write, new_read) = (
+            self.new_quorum.write_size(new),
+            self.new_quorum.read_size(new),
+        );
+        intersects(old_read, new_read)
+            && intersects(old_write, new_read)
+            && intersects(old_read, new_write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trackable::result::TestResult;
+
+    fn members(names: &[&str]) -> ClusterMembers {
+        names.iter().map(|&n| n.into()).collect()
+    }
+
+    #[test]
+    fn learners_are_excluded_from_consensus() {
+        let config = ClusterConfig::new(members(&["node1", "node2", "node3"]))
+            .add_learner("node4".into())
+            .add_learner("node5".into());
+        assert!(config.is_known_node(&"node4".into()));
+        assert!(config.is_learner(&"node4".into()));
+        assert_eq!(config.members().count(), 5);
+
+        // 投票権を有するメンバの過半数(二台)の承認で合意に至る
+        let acked = members(&["node1", "node2"]);
+        assert!(config.consensus_value(|n| acked.contains(n)));
+
+        // ラーナーの承認は数えない
+        let acked = members(&["node1", "node4", "node5"]);
+        assert!(!config.consensus_value(|n| acked.contains(n)));
+
+        // 既存のメンバをラーナーにすることはできない
+        assert_eq!(config.add_learner("node1".into()), config);
+    }
+
+    #[test]
+    fn promote_learner_works() {
+        let config =
+            ClusterConfig::new(members(&["node1", "node2", "node3"])).add_learner("node4".into());
+        let next = config.promote_learner(&"node4".into());
+        assert_eq!(next.state(), ClusterState::CatchUp);
+        assert!(!next.is_learner(&"node4".into()));
+        assert_eq!(
+            next.new_members(),
+            &members(&["node1", "node2", "node3", "node4"])
+        );
+        assert_eq!(next.old_members(), &members(&["node1", "node2", "node3"]));
+
+        let next = next.to_next_state().to_next_state();
+        assert_eq!(next.state(), ClusterState::Stable);
+        assert!(next.learners().is_empty());
+
+        // ラーナー以外は昇格できない
+        assert_eq!(config.promote_learner(&"node5".into()), config);
+    }
+
+    #[test]
+    fn flexible_quorum_works() -> TestResult {
+        let nodes = members(&["node1", "node2", "node3", "node4", "node5"]);
+        let quorum = Quorum::Flexible { write: 2, read: 4 };
+        let config = track!(ClusterConfig::new(nodes.clone()).with_quorum(quorum))?;
+        assert_eq!(config.quorum(), quorum);
+
+        // コミットは二台の承認で足りる
+        let acked = members(&["node1"]);
+        assert!(!config.consensus_value(|n| acked.contains(n)));
+        assert!(!config.full_consensus_value(|n| acked.contains(n)));
+        let acked = members(&["node1", "node2"]);
+        assert!(config.consensus_value(|n| acked.contains(n)));
+        assert!(config.full_consensus_value(|n| acked.contains(n)));
+
+        // リーダ選出には四台の承認が必要
+        let acked = members(&["node1", "node2", "node3", "node4"]);
+        assert!(config.election_consensus_value(|n| acked.contains(n)));
+        let acked = members(&["node1", "node2", "node3"]);
+        assert!(!config.election_consensus_value(|n| acked.contains(n)));
+
+        // 任意の書き込み用の集合と読み込み用の集合、および二つの読み込み用の集合は交わる
+        let nodes = nodes.into_iter().collect::<Vec<_>>();
+        for mask in 0..(1 << nodes.len()) {
+            let group = nodes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, n)| n.clone())
+                .collect::<ClusterMembers>();
+            let is_write = config.consensus_value(|n| group.contains(n));
+            let is_read = config.election_consensus_value(|n| group.contains(n));
+            let is_rest_read = config.election_consensus_value(|n| !group.contains(n));
+            assert!(!(is_write && is_rest_read), "group={:?}", group);
+            assert!(!(is_read && is_rest_read), "group={:?}", group);
+        }
+
+        // 交わらない組み合わせは拒否される
+        let invalid = Quorum::Flexible { write: 1, read: 4 };
+        let result = config.clone().with_quorum(invalid);
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        let invalid = Quorum::Flexible { write: 4, read: 2 };
+        assert!(config.clone().with_quorum(invalid).is_err());
+        let invalid = Quorum::Flexible { write: 6, read: 1 };
+        assert!(config.clone().with_quorum(invalid).is_err());
+
+        // 構成変更でメンバが増えた場合には、交わりを保つように承認数が引き上げられる
+        assert_eq!(quorum.write_size(9), 5);
+        assert_eq!(quorum.read_size(9), 5);
+        assert_eq!(quorum.write_size(3), 2);
+        assert_eq!(quorum.read_size(3), 3);
+        assert_eq!(Quorum::Majority.write_size(5), 3);
+        assert_eq!(Quorum::Majority.read_size(5), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_works() {
+        let current = ClusterConfig::new(members(&["node1", "node2", "node3"]))
+            .with_learners(members(&["node4", "node5"]));
+        let proposed = ClusterConfig::new(members(&["node1", "node2", "node4", "node6"]))
+            .with_learners(members(&["node3", "node7"]));
+        let diff = current.diff(&proposed);
+        assert_eq!(diff.added_voters, members(&["node6"]));
+        assert!(diff.removed_voters.is_empty());
+        assert_eq!(diff.promoted_learners, members(&["node4"]));
+        assert_eq!(diff.demoted_voters, members(&["node3"]));
+        assert_eq!(diff.added_learners, members(&["node7"]));
+        assert_eq!(diff.removed_learners, members(&["node5"]));
+        assert_eq!(diff.voting_changes(), 3);
+        assert!(!diff.is_single_step());
+
+        // 逆向きの差分では、追加と削除(昇格と降格)が入れ替わる
+        let reverse = proposed.diff(&current);
+        assert_eq!(reverse.removed_voters, diff.added_voters);
+        assert_eq!(reverse.demoted_voters, diff.promoted_learners);
+        assert_eq!(reverse.promoted_learners, diff.demoted_voters);
+        assert_eq!(reverse.added_learners, diff.removed_learners);
+        assert_eq!(reverse.removed_learners, diff.added_learners);
+
+        // 一ノードの削除だけなら、一度に適用できる
+        let single = current.diff(
+            &ClusterConfig::new(members(&["node1", "node2"]))
+                .with_learners(members(&["node4", "node5"])),
+        );
+        assert_eq!(single.removed_voters, members(&["node3"]));
+        assert!(single.is_single_step());
+        assert!(current.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn single_step_depends_on_quorum_sizes() -> TestResult {
+        let five = members(&["node1", "node2", "node3", "node4", "node5"]);
+        let flexible = Quorum::Flexible { write: 2, read: 4 };
+        let current = track!(ClusterConfig::new(five.clone()).with_quorum(flexible))?;
+
+        // 一ノードの削除は、書き込み用(二台)とリーダ選出用(四台)の集合が新旧間で交わるので、一度に適用できる
+        let mut four = five.clone();
+        four.remove(&"node5".into());
+        let removed = track!(ClusterConfig::new(four).with_quorum(flexible))?;
+        assert!(current.diff(&removed).is_single_step());
+
+        // 一ノードの追加でも、旧構成の書き込み用の集合(e.g., node1とnode2)と、
+        // 新構成のリーダ選出用の集合(e.g., node3からnode6)が交わらないので、一度には適用できない
+        let mut six = five.clone();
+        six.insert("node6".into());
+        let added =
+            track!(ClusterConfig::new(six).with_quorum(Quorum::Flexible { write: 3, read: 4 }))?;
+        let diff = current.diff(&added);
+        assert_eq!(diff.voting_changes(), 1);
+        assert!(!diff.is_single_step());
+
+        // 過半数の場合には、一ノードの追加は一度に適用できる
+        let majority = ClusterConfig::new(five.clone());
+        let mut six = five;
+        six.insert("node6".into());
+        assert!(majority.diff(&ClusterConfig::new(six)).is_single_step());
+
+        // メンバが同じでも、承認数の決め方の変更によって交わりが失われる場合には、一度には適用できない
+        let diff = majority.diff(&current);
+        assert_eq!(diff.voting_changes(), 0);
+        assert!(!diff.is_single_step());
+        Ok(())
+    }
+
+    #[test]
+    fn joint_config_has_no_disjoint_majorities() {
+        let config = ClusterConfig::new(members(&["node1", "node2", "node3"]));
+        let joint = config.start_joint(members(&["node3", "node4", "node5", "node6"]));
+        assert_eq!(joint.state(), ClusterState::Joint);
+        assert_eq!(joint.old_members(), &members(&["node1", "node2", "node3"]));
+
+        // 新旧いずれか一方の過半数だけでは合意に至らない
+        let acked = members(&["node1", "node2"]);
+        assert!(!joint.consensus_value(|n| acked.contains(n)));
+        let acked = members(&["node4", "node5", "node6"]);
+        assert!(!joint.consensus_value(|n| acked.contains(n)));
+        let acked = members(&["node2", "node3", "node4", "node5"]);
+        assert!(joint.consensus_value(|n| acked.contains(n)));
+
+        // 互いに素な二つのノード集合が、同時に合意に至ることはない
+        let nodes = joint.members().cloned().collect::<Vec<_>>();
+        for mask in 0..(1 << nodes.len()) {
+            let group = nodes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, n)| n.clone())
+                .collect::<ClusterMembers>();
+            let is_majority = joint.consensus_value(|n| group.contains(n));
+            let is_rest_majority = joint.consensus_value(|n| !group.contains(n));
+            assert!(!(is_majority && is_rest_majority), "group={:?}", group);
+        }
+
+        // 構成変更の完了後は、新メンバ群のみで合意が判定される
+        let stable = joint.to_next_state();
+        let acked = members(&["node4", "node5", "node6"]);
+        assert!(stable.consensus_value(|n| acked.contains(n)));
     }
 }
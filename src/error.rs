@@ -24,6 +24,16 @@ pub enum ErrorKind {
     /// 同じ要求をリトライすべきである.
     NotLeader,
 
+    /// リーダが、投票権を有するメンバの過半数と通信できていない.
+    ///
+    /// `ReplicatedLogOptions::quorum_loss_ticks`が有効な場合に、
+    /// 過半数からの応答が得られないまま、その期間を超えたリーダに対して提案が行われると、このエラーが返される.
+    /// そのまま提案を受け付けても、コミットされる見込みがないため.
+    ///
+    /// このエラーを受け取った場合、利用者はしばらく時間を空けてから、
+    /// (新しいリーダが選出されている可能性もあるので)改めてリーダを探して、同じ要求をリトライすべきである.
+    NoQuorum,
+
     /// リソースに空きが無くて、要求を受け付けることができない.
     ///
     /// このエラーを受け取った場合、利用者はある程度時間を空ける、ないし、
@@ -52,6 +62,15 @@ pub enum ErrorKind {
     /// 別のエントリによって上書きされてしまうこともあり得る.
     InconsistentState,
 
+    /// 処理が制限時間内に完了しなかった.
+    ///
+    /// 典型的には、単一ノード構成のクラスタの起動時に、
+    /// `ReplicatedLogOptions::bootstrap_deadline_ticks`で指定された期間内に
+    /// 自分自身をリーダとする選挙が完了しなかった場合に、このエラーが返される.
+    ///
+    /// このエラーを受け取った場合、利用者は`Io`の実装(e.g., ストレージ)に問題がないかを確認すべきである.
+    Timeout,
+
     /// その他エラー.
     ///
     /// 主に`Io`トレイトの実装のために設けられたエラー区分.
@@ -1,8 +1,10 @@
 use futures::Future;
+use std::time::{Duration, Instant};
 
 use crate::election::{Ballot, Role};
-use crate::log::{Log, LogIndex, LogPrefix, LogSuffix};
+use crate::log::{Log, LogIndex, LogPrefix, LogSuffix, SnapshotChunk, SnapshotTransferProgress};
 use crate::message::Message;
+use crate::node::NodeId;
 use crate::{Error, Result};
 
 /// Raftの実行に必要なI/O機能を提供するためのトレイト.
@@ -21,6 +23,26 @@ use crate::{Error, Result};
 ///      - ただし、メッセージの改竄や捏造、はNG
 /// - **タイマー**
 ///   - タイムアウト管理用のタイマー
+///
+/// なお、raftlog自体はログエントリやメッセージの直列化形式を規定しない.
+/// `save_log_suffix`等には型付きの値(e.g., `LogSuffix`)がそのまま渡されるので、
+/// ストレージやチャンネル上での表現形式(エンコーディング)は、実装者が自由に選択して構わない.
+///
+/// # 永続化の順序
+///
+/// Raftの正しさのためには、投票状況とローカルログの書き込みの間で、以下の順序が守られる必要がある:
+///
+/// - 投票状況の保存が完了するまでは、その投票に基づく`RequestVoteReply`を送信しない
+/// - 新しい`term`を含む投票状況の保存が完了するまでは、その`term`のエントリをローカルログに追記しない
+///   - そうでない場合、クラッシュ後に古い`term`で再起動したノードが、同じ`term`で再度投票してしまう可能性がある
+/// - ローカルログへの追記が完了するまでは、その追記を承認する`AppendEntriesReply`を送信しない
+///
+/// これらは、raftlog側で書き込みの完了を待ってから次の処理に進むことで保証されている.
+/// ただし、役割の遷移時には、完了を待たずに破棄された書き込みと、
+/// 新たに発行された書き込みが`Io`実装内で並行して実行される可能性がある.
+/// 書き込みの実行順序を保証しないストレージを用いる場合には、
+/// `ReplicatedLogOptions::ordered_persistence`を有効にすることで、
+/// 全ての書き込みを発行順に一つずつ実行させることができる.
 pub trait Io {
     /// ローカルノードの投票状況を保存するための`Future`.
     type SaveBallot: Future<Item = (), Error = Error>;
@@ -62,8 +84,46 @@ pub trait Io {
     /// ローカルログの前半部分(i.e., スナップショット)を保存する.
     ///
     /// 保存に成功した場合は、それ以前のログ領域は破棄してしまって構わない.
+    ///
+    /// 保存中に、より新しい地点のスナップショットのインストールが開始された場合には、
+    /// 返された`Future`は完了前に破棄され、続けて新しいスナップショットに対してこのメソッドが呼び出される.
+    /// そのため実装は、破棄された保存処理が(途中まで書き込まれた状態を含めて)、
+    /// 後続の保存結果を上書きしないことを保証する必要がある.
     fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog;
 
+    /// 分割して転送されたスナップショットの一部(チャンク)を保存する.
+    ///
+    /// 同じスナップショットのチャンク群は、`offset`の昇順に、
+    /// 一つ前のチャンクの保存が完了してから渡される.
+    /// `offset`が`0`のチャンクが渡された場合には、それまでに保存途中だったチャンク群は破棄して構わない.
+    ///
+    /// `done`が`true`のチャンクの保存が完了した時点で、
+    /// 全チャンクを連結したスナップショットに対する`save_log_prefix`が完了した場合と、同じ状態になっている必要がある.
+    ///
+    /// `load_snapshot_transfer`が返した進捗から保存が再開される場合には、
+    /// 最初に渡されるチャンクの`offset`は`SnapshotTransferProgress::saved_bytes`となる.
+    ///
+    /// このメソッドは`supports_snapshot_chunks`が`true`を返す場合にのみ呼び出される.
+    /// デフォルト実装は、`chunk`をスナップショット全体とみなして`save_log_prefix`を呼び出す.
+    fn save_snapshot_chunk(&mut self, chunk: SnapshotChunk) -> Self::SaveLog {
+        let prefix = LogPrefix {
+            tail: chunk.tail,
+            config: chunk.config,
+            snapshot: chunk.data,
+        };
+        self.save_log_prefix(prefix)
+    }
+
+    /// `save_snapshot_chunk`によるチャンク単位での保存に対応しているかどうかを返す.
+    ///
+    /// `false`の場合には、分割して転送されたスナップショットはメモリ上で連結され、
+    /// 最後のチャンクの受信後に、まとめて`save_log_prefix`で保存される.
+    ///
+    /// デフォルト実装は常に`false`を返す.
+    fn supports_snapshot_chunks(&self) -> bool {
+        false
+    }
+
     /// ローカルログの末尾部分を保存(追記)する.
     ///
     /// `suffix`の開始位置が、現在のログの末尾よりも前方の場合は、
@@ -86,10 +146,76 @@ pub trait Io {
     /// 選挙における役割に応じた時間のタイムアウトオブジェクトを生成する.
     fn create_timeout(&mut self, role: Role) -> Self::Timeout;
 
+    /// 選挙における役割に応じた時間に`jitter`を加えた、タイムアウトオブジェクトを生成する.
+    ///
+    /// `ReplicatedLogOptions::election_timeout_jitter`が指定されている場合に、
+    /// `create_timeout`の代わりに、フォロワーおよび候補者用のタイムアウトの生成に使用される.
+    ///
+    /// デフォルト実装は`jitter`を無視して`create_timeout`を呼び出す.
+    /// `jitter`を反映する実装は、これを上書きした上で、`supports_jittered_timeout`が`true`を返すようにすること.
+    fn create_jittered_timeout(&mut self, role: Role, _jitter: Duration) -> Self::Timeout {
+        self.create_timeout(role)
+    }
+
+    /// `create_jittered_timeout`が`jitter`を反映したタイムアウトを生成するかどうかを返す.
+    ///
+    /// `false`を返す実装に対して`ReplicatedLogOptions::election_timeout_jitter`を指定した場合には、
+    /// 揺らぎが黙って無視されることが無いように、`ReplicatedLog`の生成がエラーとなる.
+    ///
+    /// デフォルト実装は常に`false`を返す.
+    fn supports_jittered_timeout(&self) -> bool {
+        false
+    }
+
+    /// 最後に`create_timeout`ないし`create_jittered_timeout`で生成したタイムアウトの時間を返す.
+    ///
+    /// タイムアウト時間を乱数で決定している場合に、実際に選ばれた値を確認するために使われる.
+    /// 診断用途のためのメソッドであり、デフォルト実装は常に`None`を返す.
+    fn current_timeout_duration(&self) -> Option<Duration> {
+        None
+    }
+
     /// I/O処理を行う余裕があるかどうかを返す.
     ///
     /// これが`true`を返している間は、フォロワーの同期処理は実施されない.
     fn is_busy(&mut self) -> bool {
         false
     }
+
+    /// 受信済みで、まだ`try_recv_message`によって取り出されていないメッセージの数を返す.
+    ///
+    /// 診断用途のためのメソッドであり、値が正確である必要はない.
+    /// デフォルト実装は常に`0`を返す.
+    fn inbound_depth(&self) -> usize {
+        0
+    }
+
+    /// `peer`宛てに`send_message`で渡されたが、まだ送信が完了していないメッセージの数を返す.
+    ///
+    /// 診断用途のためのメソッドであり、値が正確である必要はない.
+    /// デフォルト実装は常に`0`を返す.
+    fn outbound_depth(&self, _peer: &NodeId) -> usize {
+        0
+    }
+
+    /// 保存途中の(i.e., `done`が`true`のチャンクがまだ保存されていない)分割スナップショットの進捗を返す.
+    ///
+    /// ノードの起動時に、ローカルログの読み込み後に一度だけ呼び出される.
+    /// `Some`が返された場合には、同じスナップショットのチャンク群が再び届いた際に、
+    /// 保存済みの範囲は読み飛ばされて、その続きから`save_snapshot_chunk`による保存が再開される.
+    /// (リーダは先頭からチャンクを送り直すので、ネットワーク上の転送量は変わらない)
+    ///
+    /// デフォルト実装は常に`None`を返す (i.e., 中断された転送は、常に先頭からやり直される).
+    fn load_snapshot_transfer(&mut self) -> Result<Option<SnapshotTransferProgress>> {
+        Ok(None)
+    }
+
+    /// 現在時刻を返す.
+    ///
+    /// raftlog自体は時刻源を持たないので、計時(e.g., `Event::Committed`の`append_to_commit`)には、
+    /// このメソッドが返した値が使用される.
+    /// デフォルト実装は常に`None`を返す (i.e., 計時は行われない).
+    fn now(&self) -> Option<Instant> {
+        None
+    }
 }
@@ -15,7 +15,12 @@ extern crate trackable;
 
 pub use crate::error::{Error, ErrorKind};
 pub use crate::io::Io;
-pub use crate::replicated_log::{Event, ReplicatedLog};
+pub use crate::replicated_log::{
+    AdminView, CommandTrace, CommonDebugState, Event, EventQueueOverflowPolicy, FollowerProgress,
+    IoStatus, LeadershipStatus, NodeCounters, PeerLag, ReadIndex, RemovedNodePolicy,
+    ReplaceIoError, ReplicatedLog, ReplicatedLogOptions, SizeHistogram, SnapshotInstallCallback,
+    TransportStats,
+};
 
 pub mod cluster;
 pub mod election;
@@ -0,0 +1,152 @@
+use futures::{Async, Future, Poll};
+use std::fmt;
+
+use super::{Log, LogHistory, LogIndex, LogSuffix};
+use crate::election::Term;
+use crate::{Error, Io};
+
+/// コミット済みログの監査結果.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    /// 検査したエントリの数.
+    pub entries_checked: u64,
+
+    /// 検出された問題と、その位置.
+    ///
+    /// 問題が無かった場合には空となる.
+    pub errors: Vec<(LogIndex, AuditError)>,
+}
+
+/// コミット済みログの監査で検出された問題.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditError {
+    /// エントリの`Term`が、ローカルログの歴史(`LogHistory`)に記録されているものと一致しない.
+    TermMismatch {
+        /// 歴史に記録されている`Term`.
+        expected: Term,
+
+        /// エントリの`Term`.
+        actual: Term,
+    },
+
+    /// エントリの`Term`が、直前のエントリのものよりも小さい.
+    TermRegression {
+        /// 直前のエントリの`Term`.
+        prev: Term,
+
+        /// エントリの`Term`.
+        actual: Term,
+    },
+
+    /// ストレージから読み込まれたログに、この位置以降のエントリが含まれていなかった.
+    Missing,
+
+    /// エントリ群の代わりに、スナップショットが読み込まれた.
+    UnexpectedSnapshot,
+}
+
+/// コミット済みログの監査を行うための`Future`.
+///
+/// 詳細は`ReplicatedLog::audit_committed_log`を参照のこと.
+pub struct AuditFuture<IO: Io> {
+    load: Option<IO::LoadLog>,
+    start: LogIndex,
+    end: LogIndex,
+    history: LogHistory,
+}
+impl<IO: Io> AuditFuture<IO> {
+    pub(crate) fn new(
+        load: Option<IO::LoadLog>,
+        start: LogIndex,
+        end: LogIndex,
+        history: LogHistory,
+    ) -> Self {
+        AuditFuture {
+            load,
+            start,
+            end,
+            history,
+        }
+    }
+
+    fn audit(&self, suffix: &LogSuffix) -> AuditReport {
+        let mut report = AuditReport {
+            entries_checked: 0,
+            errors: Vec::new(),
+        };
+        let mut prev_term = None;
+        if suffix.head.index <= self.start {
+            prev_term = Some(suffix.head.prev_term);
+        }
+        let mut next = self.start;
+        for (i, e) in suffix.entries.iter().enumerate() {
+            let index = suffix.head.index + i;
+            if index < self.start {
+                prev_term = Some(e.term());
+                continue;
+            }
+            if index != next || self.end <= index {
+                break;
+            }
+            let term = e.term();
+            if let Some(r) = self.history.get_record(index + 1) {
+                if r.head.prev_term != term {
+                    let error = AuditError::TermMismatch {
+                        expected: r.head.prev_term,
+                        actual: term,
+                    };
+                    report.errors.push((index, error));
+                }
+            }
+            if let Some(prev) = prev_term {
+                if term < prev {
+                    let error = AuditError::TermRegression { prev, actual: term };
+                    report.errors.push((index, error));
+                }
+            }
+            prev_term = Some(term);
+            report.entries_checked += 1;
+            next = index + 1;
+        }
+        if next < self.end {
+            report.errors.push((next, AuditError::Missing));
+        }
+        report
+    }
+}
+impl<IO: Io> Future for AuditFuture<IO> {
+    type Item = AuditReport;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let log = match self.load {
+            None => {
+                let report = AuditReport {
+                    entries_checked: 0,
+                    errors: Vec::new(),
+                };
+                return Ok(Async::Ready(report));
+            }
+            Some(ref mut f) => match track!(f.poll())? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(log) => log,
+            },
+        };
+        let report = match log {
+            Log::Prefix(_) => AuditReport {
+                entries_checked: 0,
+                errors: vec![(self.start, AuditError::UnexpectedSnapshot)],
+            },
+            Log::Suffix(ref suffix) => self.audit(suffix),
+        };
+        Ok(Async::Ready(report))
+    }
+}
+impl<IO: Io> fmt::Debug for AuditFuture<IO> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AuditFuture {{ start: {:?}, end: {:?}, .. }}",
+            self.start, self.end
+        )
+    }
+}
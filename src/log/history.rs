@@ -2,7 +2,9 @@ use std::collections::VecDeque;
 use trackable::error::ErrorKindExt;
 
 use crate::cluster::ClusterConfig;
-use crate::log::{LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix};
+use crate::election::Term;
+use crate::log::{LogEntry, LogIndex, LogPosition, LogPrefix, LogPrefixMeta, LogSuffix};
+use crate::node::NodeId;
 use crate::{ErrorKind, Result};
 
 /// ローカルログの歴史(要約)を保持するためのデータ構造.
@@ -20,6 +22,7 @@ pub struct LogHistory {
     committed_tail: LogPosition,
     consumed_tail: LogPosition,
     records: VecDeque<HistoryRecord>,
+    loaded_snapshot: Option<LogPrefixMeta>,
 }
 impl LogHistory {
     /// 初期クラスタ構成を与えて、新しい`LogHistory`インスタンスを生成する.
@@ -30,6 +33,7 @@ impl LogHistory {
             committed_tail: LogPosition::default(),
             consumed_tail: LogPosition::default(),
             records: vec![initial].into(),
+            loaded_snapshot: None,
         }
     }
 
@@ -55,16 +59,86 @@ impl LogHistory {
         self.consumed_tail
     }
 
+    /// 最後に読み込まれたスナップショットのメタデータを返す.
+    ///
+    /// インストールされただけで、まだ読み込まれていないスナップショットは対象外となる.
+    /// 一度もスナップショットが読み込まれていない場合には`None`が返される.
+    pub fn loaded_snapshot(&self) -> Option<&LogPrefixMeta> {
+        self.loaded_snapshot.as_ref()
+    }
+
     /// ローカルログに記録された最新のクラスタ構成を返す.
     pub fn config(&self) -> &ClusterConfig {
         &self.last_record().config
     }
 
+    /// コミット済みの最新のクラスタ構成と、それが有効となった位置を返す.
+    ///
+    /// 位置は、その構成を含む`LogEntry::Config`エントリのインデックスとなる.
+    /// 初期構成の場合には、ログの先頭(i.e., `0`)が返される.
+    ///
+    /// 構成変更エントリが既にスナップショットによって削除されている場合には、
+    /// 正確な位置は分からないので、代わりにログの先頭位置が返される.
+    pub fn config_version(&self) -> (LogIndex, ClusterConfig) {
+        let committed = self.committed_tail.index;
+        let mut records = self
+            .records
+            .iter()
+            .enumerate()
+            .rev()
+            .skip_while(|(_, r)| committed < r.head.index);
+        let (mut i, latest) = records.next().unwrap_or((0, &self.records[0]));
+        for (j, r) in records {
+            if r.config != latest.config {
+                break;
+            }
+            i = j;
+        }
+        let index = if i == 0 {
+            self.records[0].head.index
+        } else {
+            // 構成変更のレコードは、構成変更エントリの次の位置に記録されている
+            self.records[i].head.index - 1
+        };
+        (index, latest.config.clone())
+    }
+
+    /// 歴史に記録されているいずれかのクラスタ構成に、`node`が含まれている場合には`true`を返す.
+    ///
+    /// 最新の構成からは既に取り除かれたノードであっても、ログ内の以前の構成に含まれていれば`true`となる.
+    pub fn is_known_node(&self, node: &NodeId) -> bool {
+        self.records.iter().any(|r| r.config.is_known_node(node))
+    }
+
     /// 最後に追加された`HistoryRecord`を返す.
     pub fn last_record(&self) -> &HistoryRecord {
         self.records.back().expect("Never fails")
     }
 
+    /// ローカルログ内で、`term`のエントリ群が占める範囲を返す.
+    ///
+    /// 返り値は`(最初のエントリの位置, 最後のエントリの次の位置)`となる.
+    /// 範囲がログの先頭(スナップショット地点)よりも前から始まる場合には、最初の位置は先頭位置となる.
+    ///
+    /// `term`のエントリが存在しない場合には`None`が返される.
+    pub fn term_range(&self, term: Term) -> Option<(LogIndex, LogIndex)> {
+        let first = self.records.iter().position(|r| r.head.prev_term == term)?;
+        let last = self
+            .records
+            .iter()
+            .rposition(|r| r.head.prev_term == term)?;
+        let start = if first == 0 {
+            self.records[0].head.index
+        } else {
+            self.records[first].head.index - 1
+        };
+        let end = self
+            .records
+            .get(last + 1)
+            .map_or(self.appended_tail.index, |r| r.head.index - 1);
+        Some((start, end))
+    }
+
     /// 指定されたインデックスが属するレコードを返す.
     ///
     /// 既に削除された領域が指定された場合には`None`が返される.
@@ -77,6 +151,29 @@ impl LogHistory {
         None
     }
 
+    /// `index`の位置にあるエントリの`Term`を返す.
+    ///
+    /// `index`がローカルログの範囲外の場合(i.e., スナップショットによって削除済みの領域や、終端以降の場合)には
+    /// `None`が返される.
+    pub fn entry_term(&self, index: LogIndex) -> Option<Term> {
+        if index < self.head().index || self.appended_tail.index <= index {
+            return None;
+        }
+        self.get_record(index + 1).map(|r| r.head.prev_term)
+    }
+
+    /// ローカルログが`pos`の位置を含んでいる場合には`true`を返す.
+    ///
+    /// "含んでいる"とは「`pos.index`がローカルログの先頭から終端までの範囲内にあり、
+    /// かつ、その直前のエントリの`Term`が`pos.prev_term`と一致する」ということを意味する.
+    /// スナップショットによって削除済みの領域の位置に対しては、常に`false`が返される.
+    pub fn contains(&self, pos: LogPosition) -> bool {
+        if pos.index < self.head().index || self.appended_tail.index < pos.index {
+            return false;
+        }
+        matches!(self.get_record(pos.index), Some(r) if r.head.prev_term == pos.prev_term)
+    }
+
     /// `suffix`がローカルログに追記されたことを記録する.
     pub fn record_appended(&mut self, suffix: &LogSuffix) -> Result<()> {
         let entries_offset = if self.appended_tail.index <= suffix.head.index {
@@ -252,6 +349,9 @@ impl LogHistory {
             );
             self.consumed_tail = snapshot.tail;
         }
+        if !matches!(self.loaded_snapshot, Some(ref s) if snapshot.tail.index < s.tail.index) {
+            self.loaded_snapshot = Some(snapshot.meta());
+        }
         Ok(())
     }
 }
@@ -270,3 +370,58 @@ impl HistoryRecord {
         HistoryRecord { head, config }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    fn suffix(head: LogPosition, terms: &[u64]) -> LogSuffix {
+        let entries = terms
+            .iter()
+            .map(|&t| LogEntry::Noop { term: Term::new(t) })
+            .collect();
+        LogSuffix { head, entries }
+    }
+
+    fn position(prev_term: u64, index: u64) -> LogPosition {
+        LogPosition {
+            prev_term: Term::new(prev_term),
+            index: LogIndex::new(index),
+        }
+    }
+
+    #[test]
+    fn entry_term_and_contains_work() -> TestResult {
+        let config = ClusterConfig::new(vec!["node1".into()].into_iter().collect());
+        let mut history = LogHistory::new(config.clone());
+        track!(history.record_appended(&suffix(position(0, 0), &[0, 1, 1, 2, 2])))?;
+
+        assert_eq!(history.entry_term(LogIndex::new(0)), Some(Term::new(0)));
+        assert_eq!(history.entry_term(LogIndex::new(2)), Some(Term::new(1)));
+        assert_eq!(history.entry_term(LogIndex::new(4)), Some(Term::new(2)));
+        assert_eq!(history.entry_term(LogIndex::new(5)), None);
+
+        assert!(history.contains(position(0, 0)));
+        assert!(history.contains(position(1, 3)));
+        assert!(history.contains(position(2, 5)));
+        assert!(!history.contains(position(2, 3)));
+        assert!(!history.contains(position(2, 6)));
+
+        // スナップショット地点をまたぐ問い合わせ
+        track!(history.record_snapshot_installed(position(1, 3), config))?;
+        assert_eq!(history.head(), position(1, 3));
+        assert_eq!(history.entry_term(LogIndex::new(0)), None);
+        assert_eq!(history.entry_term(LogIndex::new(2)), None);
+        assert_eq!(history.entry_term(LogIndex::new(3)), Some(Term::new(2)));
+        assert_eq!(history.entry_term(LogIndex::new(4)), Some(Term::new(2)));
+
+        assert!(!history.contains(position(0, 0)));
+        assert!(!history.contains(position(1, 2)));
+        assert!(history.contains(position(1, 3)));
+        assert!(history.contains(position(2, 4)));
+        assert!(!history.contains(position(1, 4)));
+        Ok(())
+    }
+}
@@ -1,12 +1,15 @@
 //! ノードローカルなログ関連の構成要素群.
+use std::cmp;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
 
+pub use self::audit::{AuditError, AuditFuture, AuditReport};
 pub use self::history::{HistoryRecord, LogHistory};
 
 use crate::cluster::ClusterConfig;
 use crate::election::Term;
 use crate::{ErrorKind, Result};
 
+mod audit;
 mod history;
 
 /// ローカルログ.
@@ -29,6 +32,18 @@ impl From<LogSuffix> for Log {
     }
 }
 
+/// ログの前半部分(スナップショット)のメタデータ.
+///
+/// `LogPrefix`から、状態機械のスナップショット(バイト列)を除いたもの.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogPrefixMeta {
+    /// 前半部分の終端位置.
+    pub tail: LogPosition,
+
+    /// 前半部分に含まれる中で、最新の構成情報.
+    pub config: ClusterConfig,
+}
+
 /// ログの前半部分 (i.e., スナップショット).
 #[derive(Debug, Clone)]
 pub struct LogPrefix {
@@ -43,6 +58,83 @@ pub struct LogPrefix {
     /// 前半部分に含まれるコマンド群の適用後の状態機械のスナップショット.
     pub snapshot: Vec<u8>,
 }
+impl LogPrefix {
+    /// スナップショットのバイト列を除いた、メタデータ部分を返す.
+    pub fn meta(&self) -> LogPrefixMeta {
+        LogPrefixMeta {
+            tail: self.tail,
+            config: self.config.clone(),
+        }
+    }
+
+    /// スナップショットを、最大`chunk_size`バイトずつのチャンク群に分割する.
+    ///
+    /// 最後のチャンク以外の大きさは、全て`chunk_size`となる.
+    /// スナップショットが空の場合でも、最低一つのチャンクが生成される.
+    ///
+    /// # Panics
+    ///
+    /// `chunk_size`が`0`の場合には、現在のスレッドがパニックする.
+    pub fn into_chunks(self, chunk_size: usize) -> Vec<SnapshotChunk> {
+        assert_ne!(chunk_size, 0);
+        let LogPrefix {
+            tail,
+            config,
+            snapshot,
+        } = self;
+        let count = cmp::max(1, snapshot.len().div_ceil(chunk_size));
+        (0..count)
+            .map(|i| {
+                let start = i * chunk_size;
+                let end = cmp::min(start + chunk_size, snapshot.len());
+                SnapshotChunk {
+                    tail,
+                    config: config.clone(),
+                    offset: start as u64,
+                    data: snapshot[start..end].to_vec(),
+                    done: i + 1 == count,
+                }
+            })
+            .collect()
+    }
+}
+
+/// 分割して転送されるスナップショット(`LogPrefix`)の一部.
+#[derive(Debug, Clone)]
+pub struct SnapshotChunk {
+    /// スナップショットの終端位置 (`LogPrefix::tail`に相当).
+    pub tail: LogPosition,
+
+    /// スナップショットに含まれる中で、最新の構成情報 (`LogPrefix::config`に相当).
+    pub config: ClusterConfig,
+
+    /// `data`の、スナップショット全体の中での開始位置(バイト単位).
+    pub offset: u64,
+
+    /// スナップショットのバイト列の一部.
+    pub data: Vec<u8>,
+
+    /// スナップショットの最後のチャンクかどうか.
+    pub done: bool,
+}
+
+/// 保存途中の分割スナップショットの進捗.
+///
+/// ノードの再起動時に`Io::load_snapshot_transfer`によって読み込まれ、
+/// 中断された転送の保存を途中から再開するために使用される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTransferProgress {
+    /// スナップショットの終端位置.
+    pub tail: LogPosition,
+
+    /// スナップショットに含まれる中で、最新の構成情報.
+    pub config: ClusterConfig,
+
+    /// `Io::save_snapshot_chunk`による保存が完了しているバイト数.
+    ///
+    /// 次に保存されるチャンクの`offset`に相当する.
+    pub saved_bytes: u64,
+}
 
 /// ログの後半部分.
 ///
@@ -79,6 +171,24 @@ impl LogSuffix {
         LogPosition { prev_term, index }
     }
 
+    /// 各コマンドエントリを、同じ`Term`の`LogEntry::Stripped`に置き換えた後半部分を返す.
+    ///
+    /// ログの位置と`Term`は変化しないので、ウィットネスへの複製に使用される.
+    pub fn without_commands(&self) -> LogSuffix {
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| match *e {
+                LogEntry::Command { term, .. } => LogEntry::Stripped { term },
+                ref e => e.clone(),
+            })
+            .collect();
+        LogSuffix {
+            head: self.head,
+            entries,
+        }
+    }
+
     /// 後半部分に含まれるエントリの位置を走査するためのイテレータを返す.
     pub fn positions(&self) -> LogPositions {
         LogPositions {
@@ -208,6 +318,12 @@ pub enum LogEntry {
 
     /// 状態機械の入力となるコマンドを格納したエントリ.
     Command { term: Term, command: Vec<u8> },
+
+    /// ウィットネスへの複製のために、コマンドの内容が取り除かれたエントリ.
+    ///
+    /// 元のエントリと同じ位置と`Term`を持つが、状態機械に適用することはできない.
+    /// このエントリを保持するノードは、立候補を行わない (i.e., 他のノードにこのエントリを複製することはない).
+    Stripped { term: Term },
 }
 impl LogEntry {
     /// このエントリが発行された`Term`を返す.
@@ -216,6 +332,7 @@ impl LogEntry {
             LogEntry::Noop { term } => term,
             LogEntry::Config { term, .. } => term,
             LogEntry::Command { term, .. } => term,
+            LogEntry::Stripped { term } => term,
         }
     }
 }
@@ -230,6 +347,51 @@ pub struct ProposalId {
     pub index: LogIndex,
 }
 
+/// 読み込みが行われた時点の状態を表すトークン.
+///
+/// クライアントは、前回の読み込み時に得たトークンを次回の読み込み時に検証することで、
+/// リーダの交代等を跨いだ際に、古い状態を読み込んでしまっていないかを確認することができる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReadToken {
+    /// 読み込みが行われた時の`Term`.
+    pub term: Term,
+
+    /// 読み込みが行われた時点でのコミット済みログ領域の終端.
+    pub read_index: LogIndex,
+}
+
+/// クライアントが自身の書き込みを読み込むためのセッショントークン.
+///
+/// クライアントは、書き込み(提案)のコミット後に、そのエントリの位置を保持するトークンを受け取っておき、
+/// 以降の読み込み時に提示することで、読み込み先がフォロワーであっても、
+/// 自身の書き込みが反映された状態を読み込むことができる (read-your-writes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken {
+    /// クライアントが最後に書き込んだエントリの終端位置.
+    ///
+    /// 状態機械への適用済み位置がこの値以上であれば、読み込みに応じることができる.
+    pub index: LogIndex,
+}
+
+/// ローカルログと他ノードのログの整合性の判定結果.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConsistencyResult {
+    /// 両者のログは一致している.
+    Consistent,
+
+    /// ローカルログは、相手のログの接頭辞となっている (i.e., 相手の方が進んでいる).
+    Behind,
+
+    /// 相手のログは、ローカルログの接頭辞となっている (i.e., ローカルの方が進んでいる).
+    Ahead,
+
+    /// 両者のログは分岐している.
+    Diverged {
+        /// 両者の`Term`が異なる最初のエントリのインデックス.
+        at: LogIndex,
+    },
+}
+
 /// ログの特定位置を識別するためのデータ構造.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LogPosition {
@@ -438,4 +600,30 @@ mod test {
         );
         assert_eq!(slice.entries.len(), 2);
     }
+    #[test]
+    fn log_prefix_into_chunks() {
+        let prefix = |snapshot: Vec<u8>| LogPrefix {
+            tail: id(1, 10),
+            config: ClusterConfig::new(Default::default()),
+            snapshot,
+        };
+
+        let chunks = prefix((0..10).collect()).into_chunks(4);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.offset, c.data.clone(), c.done))
+                .collect::<Vec<_>>(),
+            [
+                (0, vec![0, 1, 2, 3], false),
+                (4, vec![4, 5, 6, 7], false),
+                (8, vec![8, 9], true)
+            ]
+        );
+        assert!(chunks.iter().all(|c| c.tail == id(1, 10)));
+
+        let chunks = prefix(Vec::new()).into_chunks(4);
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].done);
+    }
 }
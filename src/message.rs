@@ -4,7 +4,7 @@
 //! 実際にここで想定されている通信モデルは、RPCではなく
 //! 非同期のメッセージ送受信モデル、となっている.
 use crate::election::Term;
-use crate::log::{LogIndex, LogPosition, LogPrefix, LogSuffix};
+use crate::log::{LogIndex, LogPosition, LogPrefix, LogSuffix, SnapshotChunk};
 use crate::node::NodeId;
 
 /// RPC用のメッセージ全般.
@@ -16,6 +16,8 @@ pub enum Message {
     AppendEntriesCall(AppendEntriesCall),
     AppendEntriesReply(AppendEntriesReply),
     InstallSnapshotCast(InstallSnapshotCast),
+    InstallSnapshotCall(InstallSnapshotCall),
+    TimeoutNowCast(TimeoutNowCast),
 }
 impl Message {
     /// メッセージのヘッダを返す.
@@ -26,6 +28,8 @@ impl Message {
             Message::AppendEntriesCall(m) => &m.header,
             Message::AppendEntriesReply(m) => &m.header,
             Message::InstallSnapshotCast(m) => &m.header,
+            Message::InstallSnapshotCall(m) => &m.header,
+            Message::TimeoutNowCast(m) => &m.header,
         }
     }
 
@@ -46,6 +50,12 @@ impl Message {
             Message::InstallSnapshotCast(m) => {
                 m.header.destination = dst.clone();
             }
+            Message::InstallSnapshotCall(m) => {
+                m.header.destination = dst.clone();
+            }
+            Message::TimeoutNowCast(m) => {
+                m.header.destination = dst.clone();
+            }
         }
     }
 }
@@ -74,6 +84,16 @@ impl From<InstallSnapshotCast> for Message {
         Message::InstallSnapshotCast(f)
     }
 }
+impl From<InstallSnapshotCall> for Message {
+    fn from(f: InstallSnapshotCall) -> Self {
+        Message::InstallSnapshotCall(f)
+    }
+}
+impl From<TimeoutNowCast> for Message {
+    fn from(f: TimeoutNowCast) -> Self {
+        Message::TimeoutNowCast(f)
+    }
+}
 
 /// メッセージのヘッダ.
 #[derive(Debug, Clone)]
@@ -100,6 +120,17 @@ pub struct RequestVoteCall {
 
     /// 送信者のログの終端位置.
     pub log_tail: LogPosition,
+
+    /// 事前投票(pre-vote)の依頼かどうか.
+    ///
+    /// `true`の場合、ヘッダの`term`は送信者が次に立候補する際の`term`であり、
+    /// 送信者・受信者ともに、このメッセージによって`term`が更新されることはない.
+    pub pre_vote: bool,
+
+    /// リーダシップの移譲に伴う立候補かどうか.
+    ///
+    /// `true`の場合、受信者は現在のリーダをフォロー中であっても、この依頼を処理する.
+    pub transfer: bool,
 }
 
 /// `RequestVoteRPC`の応答メッセージ.
@@ -110,6 +141,14 @@ pub struct RequestVoteReply {
 
     /// 投票を行ったかどうか.
     pub voted: bool,
+
+    /// 同じ選挙期間内で既に投票済みの相手に対して、再送された投票依頼への応答かどうか.
+    ///
+    /// 元の応答が失われている可能性もあるため、候補者は送信者単位で重複を除いた上で、これも票として数える.
+    pub repeated: bool,
+
+    /// 事前投票(pre-vote)の依頼に対する応答かどうか.
+    pub pre_vote: bool,
 }
 
 /// `AppendEntriesRPC`の要求メッセージ.
@@ -123,6 +162,12 @@ pub struct AppendEntriesCall {
 
     /// 追記対象となるログの末尾部分.
     pub suffix: LogSuffix,
+
+    /// 送信者(リーダ)が休止状態かどうか.
+    ///
+    /// `true`の場合、リーダはハートビートの送信間隔を延ばしているので、
+    /// フォロワーは`ReplicatedLogOptions::quiescent_heartbeat_interval`回分まで選挙のタイムアウトを延長する.
+    pub quiescent: bool,
 }
 
 /// `AppendEntriesRPC`の応答メッセージ.
@@ -145,6 +190,23 @@ pub struct AppendEntriesReply {
     /// followerの`log_tail`が遅れていたとしても、
     /// リーダはログの同期のための追加のメッセージ送信を行わない.
     pub busy: bool,
+
+    /// 応答者が、自身を含まないクラスタ構成のコミットを把握済みで、受動モードにあるかどうか.
+    ///
+    /// この値が`true`の場合には、応答者はログの追記を行っておらず、
+    /// リーダは以後、そのフォロワーに対するログの同期を行わない.
+    /// 応答者の`NodeId`は再利用できない(再びクラスタ構成に追加されても追従しない)ものとして扱われる.
+    /// 詳細は`RemovedNodePolicy::Passive`を参照のこと.
+    pub removed: bool,
+
+    /// リーダのログとの分岐を検知した場合の、応答者のログの`log_tail`地点の`Term`.
+    ///
+    /// `conflict_index`と共に、リーダが同期点の探索を(一エントリずつではなく)`Term`単位で進めるために使用される.
+    /// 分岐を検知していない場合や、この情報を送信しない実装の場合には`None`となる.
+    pub conflict_term: Option<Term>,
+
+    /// 応答者のログ内での、`conflict_term`のエントリ群の開始位置.
+    pub conflict_index: Option<LogIndex>,
 }
 
 /// `InstallSnapshotRPC`用のメッセージ.
@@ -161,6 +223,33 @@ pub struct InstallSnapshotCast {
     pub prefix: LogPrefix,
 }
 
+/// スナップショットを分割して送信するためのメッセージ.
+///
+/// `ReplicatedLogOptions::snapshot_chunk_size`を超えるスナップショットは、
+/// `InstallSnapshotCast`の代わりに、このメッセージ群を用いて複数のチャンクに分けて送信される.
+///
+/// 論文中の`InstallSnapshot` RPCの`offset`・`data`・`done`引数に対応するものだが、
+/// `InstallSnapshotCast`と同様に、応答は返されない.
+/// 途中のチャンクが欠損した場合には、受信側で転送が破棄され、
+/// フォロワーのログが遅れたままであることを検知したリーダによって、後で再送される.
+#[derive(Debug, Clone)]
+pub struct InstallSnapshotCall {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+
+    /// スナップショットの一部.
+    pub chunk: SnapshotChunk,
+}
+
+/// リーダシップの移譲先に、即座に選挙を開始させるためのメッセージ.
+///
+/// リーダから、ログが十分に追い付いているフォロワーに対して送信される.
+#[derive(Debug, Clone)]
+pub struct TimeoutNowCast {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+}
+
 /// メッセージのシーケンス番号.
 ///
 /// この番号はノード毎に管理され、要求系のメッセージ送信の度にインクリメントされる.
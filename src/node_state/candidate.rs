@@ -1,9 +1,9 @@
-use futures::{Async, Future};
 use std::collections::HashSet;
 
+use super::common::PersistBallot;
 use super::{Common, NextState};
 use crate::election::Role;
-use crate::message::{Message, RequestVoteReply};
+use crate::message::{Message, RequestVoteReply, SequenceNumber};
 use crate::node::NodeId;
 use crate::{Io, Result};
 
@@ -15,9 +15,14 @@ use crate::{Io, Result};
 /// - 2. 投票依頼をブロードキャスト
 /// - 3-a. 過半数から投票を得られたら、リーダに遷移
 /// - 3-b. タイムアウトに達したら、次の選挙を開始して再び立候補
+///
+/// 事前投票(pre-vote)が有効な場合には、上記の前に`term`を増やさずに事前投票を依頼し、
+/// 過半数から承認を得られた時点で、改めて立候補を行う.
 pub struct Candidate<IO: Io> {
     followers: HashSet<NodeId>,
-    init: Option<IO::SaveBallot>,
+    init: Option<PersistBallot<IO>>,
+    pre_vote: Option<PreVote>,
+    transfer: bool,
 }
 impl<IO: Io> Candidate<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -26,21 +31,85 @@ impl<IO: Io> Candidate<IO> {
         Candidate {
             init: Some(future),
             followers: HashSet::new(),
+            pre_vote: None,
+            transfer: false,
+        }
+    }
+
+    /// リーダシップの移譲先となる候補者を生成する.
+    pub fn new_for_transfer(common: &mut Common<IO>) -> Self {
+        let mut this = Self::new(common);
+        this.transfer = true;
+        this
+    }
+
+    /// 事前投票を行う候補者を生成する.
+    ///
+    /// 投票状況は変更されていないので、その保存は行わない.
+    pub fn new_pre_vote(common: &mut Common<IO>) -> Self {
+        common.set_timeout(Role::Candidate);
+        Candidate {
+            init: None,
+            followers: HashSet::new(),
+            pre_vote: Some(PreVote {
+                seq_no: None,
+                granted: HashSet::new(),
+            }),
+            transfer: false,
         }
     }
+
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        Ok(Some(common.transit_to_candidate()))
+        track!(common.check_bootstrap_deadline())?;
+        if common.is_role_flapping_backoff() || common.is_shutting_down() {
+            // 役割の振動が収まるまで(ないし、停止処理中は)、フォロワーとして待機する
+            let local = common.local_node().id.clone();
+            return Ok(Some(common.transit_to_follower(local, None)));
+        }
+        Ok(Some(common.start_election()))
     }
     pub fn handle_message(
         &mut self,
         common: &mut Common<IO>,
         message: &Message,
     ) -> Result<NextState<IO>> {
+        if let Some(ref mut pre_vote) = self.pre_vote {
+            return match message {
+                Message::RequestVoteReply(RequestVoteReply {
+                    header,
+                    voted: true,
+                    pre_vote: true,
+                    ..
+                }) if pre_vote.seq_no == Some(header.seq_no) => {
+                    pre_vote.granted.insert(header.sender.clone());
+                    let is_granted = common
+                        .config()
+                        .election_consensus_value(|n| pre_vote.granted.contains(n));
+                    if is_granted {
+                        // 過半数の承認が得られたので、実際に立候補する
+                        Ok(Some(common.transit_to_candidate()))
+                    } else {
+                        Ok(None)
+                    }
+                }
+                Message::AppendEntriesCall(m) => {
+                    // 現在のリーダは健在だった
+                    // (このメッセージは破棄されるが、リーダによって再送される)
+                    let leader = m.header.sender.clone();
+                    Ok(Some(common.transit_to_follower(leader, None)))
+                }
+                _ => Ok(None),
+            };
+        }
+
         if let Message::RequestVoteReply(RequestVoteReply { voted: true, .. }) = message {
-            self.followers.insert(message.header().sender.clone());
+            // 再送された依頼への応答(`repeated`)を含め、票は送信者単位で数える
+            if !self.followers.insert(message.header().sender.clone()) {
+                return Ok(None);
+            }
             let is_elected = common
                 .config()
-                .consensus_value(|n| self.followers.contains(n));
+                .election_consensus_value(|n| self.followers.contains(n));
             if is_elected {
                 return Ok(Some(common.transit_to_leader()));
             }
@@ -48,10 +117,350 @@ impl<IO: Io> Candidate<IO> {
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        if let Async::Ready(Some(())) = track!(self.init.poll())? {
+        if let Some(ref mut pre_vote) = self.pre_vote {
+            if pre_vote.seq_no.is_none() {
+                pre_vote.seq_no = Some(common.rpc_caller().broadcast_pre_vote());
+            }
+            return Ok(None);
+        }
+        let is_saved = match self.init {
+            Some(ref mut f) => track!(f.poll_with(common))?.is_ready(),
+            None => false,
+        };
+        if is_saved {
             self.init = None;
-            common.rpc_caller().broadcast_request_vote();
+            if self.transfer {
+                common.rpc_caller().broadcast_transfer_request_vote();
+            } else {
+                common.rpc_caller().broadcast_request_vote();
+            }
         }
         Ok(None)
     }
 }
+
+/// 事前投票の状況.
+struct PreVote {
+    /// 依頼に使用したシーケンス番号 (未送信の場合は`None`).
+    seq_no: Option<SequenceNumber>,
+
+    /// 承認したノード群.
+    granted: HashSet<NodeId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometrics::metrics::MetricBuilder;
+    use trackable::result::TestResult;
+
+    use crate::cluster::Quorum;
+    use crate::election::Term;
+    use crate::log::{LogIndex, LogSuffix};
+    use crate::message::{AppendEntriesCall, MessageHeader};
+    use crate::metrics::NodeStateMetrics;
+    use crate::node_state::common::HandleMessageResult;
+    use crate::node_state::RoleState;
+    use crate::test_util::tests::{TestIo, TestIoBuilder};
+    use crate::ReplicatedLogOptions;
+
+    fn common(node_id: &str, pre_vote: bool) -> Common<TestIo> {
+        let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            pre_vote,
+            ..Default::default()
+        };
+        Common::new(node_id.into(), io, cluster, options, metrics)
+    }
+
+    fn heartbeat(common: &Common<TestIo>, leader: &str) -> Message {
+        AppendEntriesCall {
+            header: MessageHeader {
+                sender: leader.into(),
+                destination: common.local_node().id.clone(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix::default(),
+            quiescent: false,
+        }
+        .into()
+    }
+
+    fn pre_vote_reply(common: &Common<TestIo>, sender: &str, seq_no: SequenceNumber) -> Message {
+        RequestVoteReply {
+            header: MessageHeader {
+                sender: sender.into(),
+                destination: common.local_node().id.clone(),
+                seq_no,
+                term: common.term(),
+            },
+            voted: true,
+            repeated: false,
+            pre_vote: true,
+        }
+        .into()
+    }
+
+    fn vote(common: &Common<TestIo>, sender: &str, repeated: bool) -> Message {
+        RequestVoteReply {
+            header: MessageHeader {
+                sender: sender.into(),
+                destination: common.local_node().id.clone(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            voted: true,
+            repeated,
+            pre_vote: false,
+        }
+        .into()
+    }
+
+    fn last_sent(common: &Common<TestIo>) -> Option<Message> {
+        common
+            .io()
+            .sent_messages
+            .lock()
+            .expect("Never fails")
+            .last()
+            .cloned()
+    }
+
+    #[test]
+    fn repeated_votes_are_counted_once() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .add_member("node4".into())
+            .add_member("node5".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+        let mut candidate = Candidate::new(&mut common);
+
+        // 自分自身とnode2の票
+        let self_vote = vote(&common, "node1", false);
+        assert!(track!(candidate.handle_message(&mut common, &self_vote))?.is_none());
+        let fresh = vote(&common, "node2", false);
+        assert!(track!(candidate.handle_message(&mut common, &fresh))?.is_none());
+
+        // 再送された依頼への応答は、過半数の判定に影響しない
+        for _ in 0..3 {
+            let repeated = vote(&common, "node2", true);
+            assert!(track!(candidate.handle_message(&mut common, &repeated))?.is_none());
+        }
+
+        // 三人目の票で過半数に達する
+        let third = vote(&common, "node3", false);
+        let next = track!(candidate.handle_message(&mut common, &third))?;
+        assert!(matches!(next, Some(RoleState::Leader(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn flexible_read_quorum_is_used_for_election() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .add_member("node4".into())
+            .add_member("node5".into())
+            .finish();
+        let quorum = Quorum::Flexible { write: 2, read: 4 };
+        let cluster = track!(io.cluster.clone().with_quorum(quorum))?;
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+        let mut candidate = Candidate::new(&mut common);
+
+        // 過半数(三台)の票だけでは足りない
+        for sender in &["node1", "node2", "node3"] {
+            let vote = vote(&common, sender, false);
+            assert!(track!(candidate.handle_message(&mut common, &vote))?.is_none());
+        }
+
+        // 読み込み用の承認数(四台)に達した時点で、リーダとなる
+        let fourth = vote(&common, "node4", false);
+        let next = track!(candidate.handle_message(&mut common, &fourth))?;
+        assert!(matches!(next, Some(RoleState::Leader(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn split_vote_does_not_elect_two_leaders() -> TestResult {
+        let quorum = Quorum::Flexible { write: 2, read: 3 };
+        let candidate = |node_id: &str| -> Result<(Common<TestIo>, Candidate<TestIo>)> {
+            let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+            let io = TestIoBuilder::new()
+                .add_member("node1".into())
+                .add_member("node2".into())
+                .add_member("node3".into())
+                .add_member("node4".into())
+                .finish();
+            let cluster = track!(io.cluster.clone().with_quorum(quorum))?;
+            let mut common = Common::new(node_id.into(), io, cluster, Default::default(), metrics);
+            common.transit_to_candidate();
+            let candidate = Candidate::new(&mut common);
+            Ok((common, candidate))
+        };
+
+        // 同じ`term`で、"node1"と"node2"がそれぞれ半数ずつの票を得る
+        let (mut common1, mut candidate1) = track!(candidate("node1"))?;
+        let (mut common2, mut candidate2) = track!(candidate("node2"))?;
+        assert_eq!(common1.term(), common2.term());
+        let mut elected = 0;
+        for sender in &["node1", "node3"] {
+            let vote = vote(&common1, sender, false);
+            if let Some(RoleState::Leader(_)) =
+                track!(candidate1.handle_message(&mut common1, &vote))?
+            {
+                elected += 1;
+            }
+        }
+        for sender in &["node2", "node4"] {
+            let vote = vote(&common2, sender, false);
+            if let Some(RoleState::Leader(_)) =
+                track!(candidate2.handle_message(&mut common2, &vote))?
+            {
+                elected += 1;
+            }
+        }
+        assert_eq!(elected, 0);
+
+        // 読み込み用の承認数が半数以下となる指定は拒否される
+        let cluster = common1.config().clone();
+        let invalid = Quorum::Flexible { write: 3, read: 2 };
+        assert!(cluster.with_quorum(invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partitioned_pre_candidate_does_not_disrupt_cluster() -> TestResult {
+        // "node3"は分断されており、選挙のタイムアウトを繰り返す
+        let mut isolated = common("node3", true);
+        isolated.transit_to_follower("node2".into(), None);
+        let mut candidate = match isolated.start_election() {
+            RoleState::Candidate(c) => c,
+            _ => panic!(),
+        };
+        for _ in 0..3 {
+            track!(candidate.run_once(&mut isolated))?;
+            candidate = match track!(candidate.handle_timeout(&mut isolated))? {
+                Some(RoleState::Candidate(c)) => c,
+                _ => panic!(),
+            };
+        }
+        assert_eq!(isolated.term(), Term::new(0));
+
+        track!(candidate.run_once(&mut isolated))?;
+        let request = match last_sent(&isolated) {
+            Some(Message::RequestVoteCall(m)) => m,
+            m => panic!("{:?}", m),
+        };
+        assert!(request.pre_vote);
+        assert_eq!(request.header.term, Term::new(1));
+
+        // 分断の解消後: リーダ("node2")と通信中の"node1"は、事前投票を承認しない
+        let mut healthy = common("node1", true);
+        healthy.transit_to_follower("node2".into(), None);
+        let message = heartbeat(&healthy, "node2");
+        track!(healthy.handle_message(message))?;
+        let result = track!(healthy.handle_message(request.clone().into()))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+        assert!(matches!(
+            last_sent(&healthy),
+            Some(Message::RequestVoteReply(RequestVoteReply {
+                voted: false,
+                pre_vote: true,
+                ..
+            }))
+        ));
+        assert_eq!(healthy.term(), Term::new(0));
+        assert_eq!(healthy.local_node().ballot.voted_for, "node2".into());
+
+        // リーダからのメッセージを受け取った"node3"は、フォロワーに戻る
+        let message = heartbeat(&isolated, "node2");
+        let next = track!(candidate.handle_message(&mut isolated, &message))?;
+        assert!(matches!(next, Some(RoleState::Follower(_))));
+        assert_eq!(isolated.term(), Term::new(0));
+        Ok(())
+    }
+
+    #[test]
+    fn pre_vote_majority_starts_election() -> TestResult {
+        let mut common = common("node1", true);
+        let mut candidate = match common.start_election() {
+            RoleState::Candidate(c) => c,
+            _ => panic!(),
+        };
+        track!(candidate.run_once(&mut common))?;
+        let seq_no = match last_sent(&common) {
+            Some(Message::RequestVoteCall(m)) => m.header.seq_no,
+            m => panic!("{:?}", m),
+        };
+
+        // 以前の依頼への応答は数えない
+        let stale = pre_vote_reply(&common, "node2", SequenceNumber::new(seq_no.as_u64() + 1));
+        assert!(track!(candidate.handle_message(&mut common, &stale))?.is_none());
+
+        let reply = pre_vote_reply(&common, "node1", seq_no);
+        assert!(track!(candidate.handle_message(&mut common, &reply))?.is_none());
+        assert_eq!(common.term(), Term::new(0));
+
+        // 過半数の承認が得られたら、`term`を増やして立候補する
+        let reply = pre_vote_reply(&common, "node2", seq_no);
+        let next = track!(candidate.handle_message(&mut common, &reply))?;
+        assert!(matches!(next, Some(RoleState::Candidate(_))));
+        assert_eq!(common.term(), Term::new(1));
+        Ok(())
+    }
+
+    #[test]
+    fn pre_vote_rejection_with_higher_term_is_followed() -> TestResult {
+        let mut common = common("node1", true);
+        let mut candidate = match common.start_election() {
+            RoleState::Candidate(c) => c,
+            _ => panic!(),
+        };
+        track!(candidate.run_once(&mut common))?;
+        let seq_no = match last_sent(&common) {
+            Some(Message::RequestVoteCall(m)) => m.header.seq_no,
+            m => panic!("{:?}", m),
+        };
+
+        // "node2"は既により大きな`term`に進んでいるので、事前投票を拒否する
+        let rejection = RequestVoteReply {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no,
+                term: Term::new(5),
+            },
+            voted: false,
+            repeated: false,
+            pre_vote: true,
+        };
+        let result = track!(common.handle_message(rejection.into()))?;
+
+        // 拒否応答の`term`に追従して、フォロワーとなる
+        assert!(matches!(
+            result,
+            HandleMessageResult::Handled(Some(RoleState::Follower(_)))
+        ));
+        assert_eq!(common.term(), Term::new(5));
+        Ok(())
+    }
+}
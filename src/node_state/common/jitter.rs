@@ -0,0 +1,61 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::Duration;
+
+use crate::election::Role;
+use crate::node::NodeId;
+use crate::Io;
+
+/// 選挙用の(i.e., フォロワーおよび候補者用の)タイムアウトに加える揺らぎを決定する.
+///
+/// 詳細は`ReplicatedLogOptions::election_timeout_jitter`を参照のこと.
+#[derive(Debug, Clone)]
+pub struct TimeoutJitter {
+    max: Duration,
+    state: u64,
+}
+impl TimeoutJitter {
+    /// 新しい`TimeoutJitter`インスタンスを生成する.
+    ///
+    /// `seed`が`None`の場合には、ランダムなシードが使用される.
+    /// また、シードにはローカルノードのIDが混ぜ合わされるので、
+    /// 全てのノードで同じシードを指定した場合でも、各ノードの揺らぎは異なるものとなる.
+    pub fn new(local: &NodeId, max: Duration, seed: Option<u64>) -> Self {
+        let mut hasher = match seed {
+            None => RandomState::new().build_hasher(),
+            Some(seed) => {
+                let mut hasher = DefaultHasher::new();
+                seed.hash(&mut hasher);
+                hasher
+            }
+        };
+        local.hash(&mut hasher);
+
+        // 状態が`0`だと、常に`0`が生成されるようになってしまう
+        let state = hasher.finish() | 1;
+        TimeoutJitter { max, state }
+    }
+
+    /// 役割に応じたタイムアウトを生成する.
+    ///
+    /// 揺らぎが有効な場合には、フォロワーおよび候補者用のタイムアウトは、
+    /// `0`から`max`までの間で選ばれた揺らぎを伴って`Io::create_jittered_timeout`で生成される.
+    pub fn create_timeout<IO: Io>(&mut self, io: &mut IO, role: Role) -> IO::Timeout {
+        if role == Role::Leader || self.max == Duration::from_secs(0) {
+            io.create_timeout(role)
+        } else {
+            let jitter = self.next_jitter();
+            io.create_jittered_timeout(role, jitter)
+        }
+    }
+
+    fn next_jitter(&mut self) -> Duration {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let n = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        let ratio = (n >> 11) as f64 / (1u64 << 53) as f64;
+        self.max.mul_f64(ratio)
+    }
+}
@@ -1,19 +1,36 @@
 use futures::{Async, Future, Poll};
+use std::cmp;
 use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+use trackable::error::ErrorKindExt;
 
+pub use self::persistence::{PersistBallot, PersistLog};
+
+use self::jitter::TimeoutJitter;
+use self::persistence::{Persist, PersistenceQueue};
 use self::rpc_builder::{RpcCallee, RpcCaller};
 use super::candidate::Candidate;
 use super::follower::Follower;
 use super::leader::Leader;
 use super::{NextState, RoleState};
-use crate::cluster::ClusterConfig;
+use crate::cluster::{ClusterConfig, ClusterState, ConfigDiff};
 use crate::election::{Ballot, Role, Term};
-use crate::log::{Log, LogHistory, LogIndex, LogPosition, LogPrefix, LogSuffix};
-use crate::message::{Message, MessageHeader, SequenceNumber};
+use crate::log::{
+    AuditFuture, ConsistencyResult, Log, LogEntry, LogHistory, LogIndex, LogPosition, LogPrefix,
+    LogPrefixMeta, LogSuffix, ReadToken, SessionToken, SnapshotChunk, SnapshotTransferProgress,
+};
+use crate::message::{Message, MessageHeader, RequestVoteCall, RequestVoteReply, SequenceNumber};
 use crate::metrics::NodeStateMetrics;
 use crate::node::{Node, NodeId};
-use crate::{Error, ErrorKind, Event, Io, Result};
+use crate::{
+    AdminView, CommonDebugState, Error, ErrorKind, Event, EventQueueOverflowPolicy, Io, IoStatus,
+    LeadershipStatus, NodeCounters, RemovedNodePolicy, ReplicatedLogOptions, Result, SizeHistogram,
+    SnapshotInstallCallback, TransportStats,
+};
 
+mod jitter;
+mod persistence;
 mod rpc_builder;
 
 /// 全ての状態に共通する処理をまとめた構造体.
@@ -21,12 +38,47 @@ pub struct Common<IO: Io> {
     local_node: Node,
     history: LogHistory,
     timeout: IO::Timeout,
+    timeout_role: Role,
+    timeout_duration: Option<Duration>,
+    tick_timeout: IO::Timeout,
+    timer_errors: usize,
     events: VecDeque<Event>,
     io: IO,
     unread_message: Option<Message>,
     seq_no: SequenceNumber,
     load_committed: Option<IO::LoadLog>,
     install_snapshot: Option<InstallSnapshot<IO>>,
+    snapshot_transfer: Option<SnapshotTransfer<IO>>,
+    resumable_snapshot: Option<SnapshotTransferProgress>,
+    append_times: VecDeque<(LogIndex, Instant)>,
+    self_removed: bool,
+    stripped_log: bool,
+    initial_config: ClusterConfig,
+    shutting_down: bool,
+    entry_sizes: Option<SizeHistogram>,
+    counters: NodeCounters,
+    persistence: PersistenceQueue<IO>,
+    options: ReplicatedLogOptions,
+    ticks: u64,
+    role_changes: VecDeque<u64>,
+    role_flapping_backoff_until: Option<u64>,
+    last_leader_contact_tick: Option<u64>,
+    leader_contacted_since_timeout: bool,
+    leader: Option<NodeId>,
+    catching_up: bool,
+    deferred_messages: VecDeque<Message>,
+    broadcast_priority: Vec<NodeId>,
+    broadcast_sends: usize,
+    contacted_quorum: bool,
+    applied_index: LogIndex,
+    apply_backlog_high: bool,
+    apply_progress_tick: u64,
+    apply_stalled: bool,
+    retained_entries: VecDeque<(LogIndex, LogEntry)>,
+    consumption_lagging: bool,
+    quiescent: bool,
+    quiescent_missed_timeouts: u64,
+    timeout_jitter: TimeoutJitter,
     metrics: NodeStateMetrics,
 }
 impl<IO> Common<IO>
@@ -38,24 +90,151 @@ where
         node_id: NodeId,
         mut io: IO,
         config: ClusterConfig,
+        options: ReplicatedLogOptions,
         metrics: NodeStateMetrics,
     ) -> Self {
+        let mut timeout_jitter = TimeoutJitter::new(
+            &node_id,
+            options.election_timeout_jitter,
+            options.election_timeout_jitter_seed,
+        );
+
         // 最初は（仮に）フォロワーだとしておく
-        let timeout = io.create_timeout(Role::Follower);
+        let timeout = timeout_jitter.create_timeout(&mut io, Role::Follower);
+        let timeout_duration = io.current_timeout_duration();
+        let tick_timeout = io.create_timeout(Role::Leader);
         Common {
             local_node: Node::new(node_id),
             io,
-            history: LogHistory::new(config),
+            history: LogHistory::new(config.clone()),
             unread_message: None,
             seq_no: SequenceNumber::new(0),
             timeout,
+            timeout_role: Role::Follower,
+            timeout_duration,
+            tick_timeout,
+            timer_errors: 0,
             events: VecDeque::new(),
             load_committed: None,
             install_snapshot: None,
+            snapshot_transfer: None,
+            resumable_snapshot: None,
+            append_times: VecDeque::new(),
+            self_removed: false,
+            stripped_log: false,
+            initial_config: config,
+            shutting_down: false,
+            entry_sizes: if options.track_entry_sizes {
+                Some(SizeHistogram::new())
+            } else {
+                None
+            },
+            counters: NodeCounters::default(),
+            persistence: PersistenceQueue::new(),
+            options,
+            ticks: 0,
+            role_changes: VecDeque::new(),
+            role_flapping_backoff_until: None,
+            last_leader_contact_tick: None,
+            leader_contacted_since_timeout: false,
+            leader: None,
+            catching_up: false,
+            deferred_messages: VecDeque::new(),
+            broadcast_priority: Vec::new(),
+            broadcast_sends: 0,
+            contacted_quorum: false,
+            applied_index: LogIndex::new(0),
+            apply_backlog_high: false,
+            apply_progress_tick: 0,
+            apply_stalled: false,
+            retained_entries: VecDeque::new(),
+            consumption_lagging: false,
+            quiescent: false,
+            quiescent_missed_timeouts: 0,
+            timeout_jitter,
             metrics,
         }
     }
 
+    /// バックグランドで実行中のI/O処理が存在せず、`replace_io`が可能な場合には`true`を返す.
+    pub fn is_io_replaceable(&self) -> bool {
+        self.load_committed.is_none()
+            && self.install_snapshot.is_none()
+            && self.snapshot_transfer.is_none()
+            && self.persistence.is_empty()
+    }
+
+    /// I/O実装を`new_io`に差し替えた`Common`インスタンスを返す.
+    ///
+    /// ストレージの移行等に使用されることが想定されている.
+    /// タイムアウト(およびtick計時用のタイムアウト)は、現在の役割に応じたものが`new_io`を用いて設定し直される.
+    ///
+    /// # Errors
+    ///
+    /// バックグランドでI/O処理(コミット済みログの読み込みやスナップショットのインストール)が
+    /// 実行中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    pub fn replace_io<NewIo: Io>(self, mut new_io: NewIo) -> Result<Common<NewIo>> {
+        track_assert!(self.is_io_replaceable(), ErrorKind::Busy);
+
+        let role = self.local_node.role;
+        let mut timeout_jitter = self.timeout_jitter;
+        let timeout = timeout_jitter.create_timeout(&mut new_io, role);
+        let timeout_duration = new_io.current_timeout_duration();
+        let tick_timeout = new_io.create_timeout(Role::Leader);
+        Ok(Common {
+            local_node: self.local_node,
+            history: self.history,
+            timeout,
+            timeout_role: role,
+            timeout_duration,
+            tick_timeout,
+            timer_errors: 0,
+            events: self.events,
+            io: new_io,
+            unread_message: self.unread_message,
+            seq_no: self.seq_no,
+            load_committed: None,
+            install_snapshot: None,
+            snapshot_transfer: None,
+            resumable_snapshot: self.resumable_snapshot,
+            append_times: self.append_times,
+            self_removed: self.self_removed,
+            stripped_log: self.stripped_log,
+            initial_config: self.initial_config,
+            shutting_down: self.shutting_down,
+            entry_sizes: self.entry_sizes,
+            counters: self.counters,
+            persistence: PersistenceQueue::new(),
+            options: self.options,
+            ticks: self.ticks,
+            role_changes: self.role_changes,
+            role_flapping_backoff_until: self.role_flapping_backoff_until,
+            last_leader_contact_tick: self.last_leader_contact_tick,
+            leader_contacted_since_timeout: self.leader_contacted_since_timeout,
+            leader: self.leader,
+            catching_up: self.catching_up,
+            deferred_messages: self.deferred_messages,
+            broadcast_priority: self.broadcast_priority,
+            broadcast_sends: self.broadcast_sends,
+            contacted_quorum: self.contacted_quorum,
+            applied_index: self.applied_index,
+            apply_backlog_high: self.apply_backlog_high,
+            apply_progress_tick: self.apply_progress_tick,
+            apply_stalled: self.apply_stalled,
+            retained_entries: self.retained_entries,
+            consumption_lagging: self.consumption_lagging,
+            quiescent: self.quiescent,
+            quiescent_missed_timeouts: self.quiescent_missed_timeouts,
+            timeout_jitter,
+            metrics: self.metrics,
+        })
+    }
+
+    /// ノードの挙動を調整するためのオプション群を返す.
+    pub fn options(&self) -> &ReplicatedLogOptions {
+        &self.options
+    }
+
     /// 現在のクラスタの構成情報を返す.
     pub fn config(&self) -> &ClusterConfig {
         self.history.config()
@@ -76,24 +255,357 @@ where
         self.local_node.ballot.term
     }
 
+    /// 新旧両方の構成の合意が必要な状態(`ClusterState::Joint`)の場合には、
+    /// 旧構成と新構成の組を返す.
+    ///
+    /// それ以外の場合には`None`が返される.
+    pub fn joint_config(&self) -> Option<(ClusterConfig, ClusterConfig)> {
+        let config = self.config();
+        if config.state().is_joint() {
+            let old = ClusterConfig::new(config.old_members().clone());
+            let new = ClusterConfig::new(config.new_members().clone());
+            Some((old, new))
+        } else {
+            None
+        }
+    }
+
+    /// ローカルログと、他ノードのログとの整合性を判定する.
+    ///
+    /// `peer_tail`は相手のログの終端位置で、`peer_term_at`は相手のログの指定インデックスのエントリの`Term`を返す関数.
+    /// `peer_term_at`が`None`を返したエントリは、不一致として扱われる.
+    ///
+    /// なお、ローカルログの先頭(スナップショット地点)以前の領域は、一致しているものとして扱われる.
+    pub fn is_log_consistent_with<F>(
+        &self,
+        peer_tail: LogPosition,
+        peer_term_at: F,
+    ) -> ConsistencyResult
+    where
+        F: Fn(LogIndex) -> Option<Term>,
+    {
+        let local_tail = self.history.tail();
+        let head = self.history.head().index;
+        let common_tail = if local_tail.index < peer_tail.index {
+            local_tail.index
+        } else {
+            peer_tail.index
+        };
+
+        // `position`の直前までのエントリ群が一致しているかどうか.
+        // ログの一致特性(論文の「5.3 Log replication」を参照)により、
+        // ある地点で一致していれば、それ以前の地点も全て一致していることになる.
+        let matched = |position: LogIndex| {
+            if position <= head {
+                return true;
+            }
+            let peer_prev_term = if position == peer_tail.index {
+                Some(peer_tail.prev_term)
+            } else {
+                peer_term_at(LogIndex::new(position.as_u64() - 1))
+            };
+            let local_prev_term = self.history.get_record(position).map(|r| r.head.prev_term);
+            local_prev_term.is_some() && local_prev_term == peer_prev_term
+        };
+
+        if !matched(common_tail) {
+            // 一致している最後の地点を二分探索する
+            let mut lo = head.as_u64(); // 常に一致
+            let mut hi = common_tail.as_u64(); // 常に不一致
+            while lo + 1 < hi {
+                let mid = lo + (hi - lo) / 2;
+                if matched(LogIndex::new(mid)) {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            return ConsistencyResult::Diverged {
+                at: LogIndex::new(lo),
+            };
+        }
+
+        if local_tail.index < peer_tail.index {
+            ConsistencyResult::Behind
+        } else if peer_tail.index < local_tail.index {
+            ConsistencyResult::Ahead
+        } else {
+            ConsistencyResult::Consistent
+        }
+    }
+
+    /// 直近の投票の`Term`と投票先を返す.
+    ///
+    /// 立候補者として自分自身に投票している場合(ないし、まだ誰にも投票していない場合)には、
+    /// 投票先は`None`となる.
+    pub fn last_vote(&self) -> (Term, Option<NodeId>) {
+        let ballot = &self.local_node.ballot;
+        let voted_for = if ballot.voted_for == self.local_node.id {
+            None
+        } else {
+            Some(ballot.voted_for.clone())
+        };
+        (ballot.term, voted_for)
+    }
+
+    /// ローカルノードを含まないクラスタ構成がコミット済みの場合には`true`を返す.
+    ///
+    /// 最新の構成がまだコミットされていない場合には、ローカルノードが含まれていなくても`false`となる.
+    pub fn is_removed(&self) -> bool {
+        let record = self.history.last_record();
+        record.head.index <= self.history.committed_tail().index
+            && !record.config.is_known_node(&self.local_node.id)
+    }
+
+    /// ローカルノードが、ウィットネスとして振る舞う必要があるかどうかを判定する.
+    ///
+    /// 最新のクラスタ構成においてウィットネスである場合に加えて、
+    /// ローカルログがコマンドの内容の取り除かれたエントリ(ないしスナップショット)を含む場合にも`true`が返される.
+    /// (後者は、ウィットネスではなくなった後や、構成変更エントリが未復元の再起動直後にも該当し得る)
+    ///
+    /// ウィットネスは投票には参加するが、リーダにはならず、読み込みにも応じない.
+    pub fn is_witness(&self) -> bool {
+        self.stripped_log || self.config().is_witness(&self.local_node.id)
+    }
+
+    /// コミット済みの最新のクラスタ構成と、それが有効となった位置(構成変更エントリのインデックス)を返す.
+    ///
+    /// 詳細は`LogHistory::config_version`を参照のこと.
+    pub fn config_version(&self) -> (LogIndex, ClusterConfig) {
+        self.history.config_version()
+    }
+
+    /// 現在のスナップショット地点として読み込まれたスナップショットの、メタデータ(終端位置と構成)を返す.
+    ///
+    /// `Event::SnapshotLoaded`を保持しておかなくても、後からメタデータを参照できるようにするためのもの.
+    /// インストールされただけで、まだ読み込まれていない場合には、直前に読み込まれたものが返される.
+    pub fn loaded_snapshot(&self) -> Option<LogPrefixMeta> {
+        self.history.loaded_snapshot().cloned()
+    }
+
+    /// ノードの停止処理を開始する.
+    ///
+    /// 以後、ローカルノードは立候補しなくなる.
+    /// 初回の呼び出し時にのみ`Event::ShuttingDown`が発行される.
+    pub fn begin_shutdown(&mut self) {
+        if !self.shutting_down {
+            self.shutting_down = true;
+            self.push_event(Event::ShuttingDown);
+        }
+    }
+
+    /// ノードの停止処理が開始済みの場合には`true`を返す.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
+    /// ローカルノードのリーダシップの状況を返す.
+    ///
+    /// 複数の状況に該当する場合には、
+    /// リーダ、クラスタ構成からの除外、スナップショットのインストール、の順で優先される.
+    pub fn leadership_status(&self) -> LeadershipStatus {
+        match self.local_node.role {
+            Role::Leader => LeadershipStatus::Leader,
+            _ if !self.config().is_known_node(&self.local_node.id) => LeadershipStatus::Removed,
+            _ if self.is_snapshot_installing() => LeadershipStatus::InstallingSnapshot,
+            Role::Candidate => LeadershipStatus::Candidate { term: self.term() },
+            Role::Follower => LeadershipStatus::Follower {
+                leader: self.leader.clone(),
+            },
+        }
+    }
+
+    /// 現在の状態に対応する読み込みトークンを返す.
+    pub fn read_token(&self) -> ReadToken {
+        ReadToken {
+            term: self.term(),
+            read_index: self.history.committed_tail().index,
+        }
+    }
+
+    /// 以前の読み込み時に発行されたトークンを検証する.
+    ///
+    /// ローカルノードの`Term`がトークンのものよりも小さい場合には、
+    /// 古い状態を読み込んでしまう可能性があるので`false`を返す.
+    pub fn validate_read_token(&self, token: ReadToken) -> bool {
+        token.term <= self.term()
+    }
+
+    /// `token`を提示したクライアントからの読み込みに、ローカルノードが応じられる場合には`true`を返す.
+    ///
+    /// 状態機械への適用済み位置が`token`の位置に達していない場合や、ローカルノードがウィットネスの場合には`false`が返される.
+    /// その場合には、適用が進むのを待つか、読み込みを別のノードに転送する必要がある.
+    pub fn can_serve_read(&self, token: &SessionToken) -> bool {
+        !self.is_witness() && token.index <= self.applied_index
+    }
+
     /// ローカルノードの情報を返す.
     pub fn local_node(&self) -> &Node {
         &self.local_node
     }
 
+    /// `Io::now`が返す現在時刻を返す.
+    pub fn now(&self) -> Option<Instant> {
+        self.io.now()
+    }
+
+    /// 起動してから現在までに経過したtick数を返す.
+    ///
+    /// "tick"は、リーダ用のタイムアウトの時間(i.e., ハートビートの間隔)を単位とする.
+    /// 詳細は`poll_tick_timeout`を参照のこと.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// フォロー中のリーダから、最後にメッセージを受信した時点のtick数を返す.
+    ///
+    /// まだ一度も受信していない場合には`None`が返される.
+    pub fn last_leader_contact_tick(&self) -> Option<u64> {
+        self.last_leader_contact_tick
+    }
+
+    /// ローカルノードが、これまでに一度でもクラスタの過半数との通信に成功したことがある場合には`true`を返す.
+    ///
+    /// 現状では、選挙に勝利して(i.e., 過半数の票を得て)リーダとなった時点で`true`になる.
+    /// 一度`true`になった後は、役割が変わってもプロセスが終了するまで`true`のままとなる.
+    pub fn has_contacted_quorum(&self) -> bool {
+        self.contacted_quorum
+    }
+
+    /// 状態機械への適用が完了した位置を返す.
+    pub fn applied_index(&self) -> LogIndex {
+        self.applied_index
+    }
+
+    /// 状態機械への適用が完了した位置を更新する.
+    ///
+    /// 現在の位置よりも前の`index`が指定された場合には、何も行われない.
+    ///
+    /// # Errors
+    ///
+    /// `index`がコミット済み領域の終端を超えている場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn set_applied_index(&mut self, index: LogIndex) -> Result<()> {
+        let committed = self.history.committed_tail().index;
+        track_assert!(
+            index <= committed,
+            ErrorKind::InvalidInput,
+            "index={:?}, committed={:?}",
+            index,
+            committed
+        );
+        if self.applied_index < index {
+            self.applied_index = index;
+            self.handle_apply_progress();
+        }
+        Ok(())
+    }
+
+    /// コミット済みだが、まだ状態機械に適用されていないエントリの数を返す.
+    pub fn apply_backlog(&self) -> u64 {
+        let committed = self.history.committed_tail().index;
+        committed
+            .as_u64()
+            .saturating_sub(self.applied_index.as_u64())
+    }
+
+    /// 適用待ちのコミット済みエントリが存在する状態で、
+    /// 状態機械への適用が最後に進んでから経過したtick数を返す.
+    ///
+    /// 適用待ちのエントリが存在しない場合には、常に`0`が返される.
+    pub fn ticks_since_apply_progress(&self) -> u64 {
+        if self.apply_backlog() == 0 {
+            0
+        } else {
+            self.ticks - self.apply_progress_tick
+        }
+    }
+
+    /// 単一ノード構成のクラスタの起動時の選挙が、制限時間内に完了したかどうかを確認する.
+    ///
+    /// # Errors
+    ///
+    /// `ReplicatedLogOptions::bootstrap_deadline_ticks`が指定されていて、
+    /// その期間内にリーダとなれなかった場合には、`ErrorKind::Timeout`を理由としたエラーが返される.
+    pub fn check_bootstrap_deadline(&self) -> Result<()> {
+        let deadline = self.options.bootstrap_deadline_ticks;
+        if deadline == 0 || self.contacted_quorum {
+            return Ok(());
+        }
+        let local = &self.local_node.id;
+        let is_single_node = self.config().members().all(|n| n == local);
+        track_assert!(
+            !is_single_node || self.ticks < deadline,
+            ErrorKind::Timeout,
+            "Bootstrap self-election did not complete within {} ticks: node={:?}",
+            deadline,
+            local
+        );
+        Ok(())
+    }
+
+    /// 役割の振動を検知したために、立候補を控えている期間中の場合には`true`を返す.
+    pub fn is_role_flapping_backoff(&self) -> bool {
+        matches!(self.role_flapping_backoff_until, Some(until) if self.ticks < until)
+    }
+
     /// ローカルログへの追記イベントを処理する.
     pub fn handle_log_appended(&mut self, suffix: &LogSuffix) -> Result<()> {
-        track!(self.history.record_appended(suffix))
+        let was_joint = self.joint_config().is_some();
+        track!(self.history.record_appended(suffix))?;
+        if suffix
+            .entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::Stripped { .. }))
+        {
+            self.stripped_log = true;
+        }
+        self.notify_joint_consensus_transition(was_joint);
+        self.record_append_times(suffix);
+        Ok(())
+    }
+
+    /// ローカルログへの追記(`save_log_suffix`)の完了を処理する.
+    ///
+    /// `handle_log_appended`とは異なり、実際に保存処理が行われた場合にのみ呼び出される.
+    pub fn handle_log_persisted(&mut self, suffix: &LogSuffix) {
+        if self.options.append_persisted_events && !suffix.entries.is_empty() {
+            let through = suffix.tail().index;
+            self.push_event(Event::AppendPersisted { through });
+        }
     }
 
     /// ログのコミットイベントを処理する.
     pub fn handle_log_committed(&mut self, new_tail: LogIndex) -> Result<()> {
-        track!(self.history.record_committed(new_tail))
+        let old_tail = self.history.committed_tail().index;
+        let (old_config_index, old_config) = self.history.config_version();
+        track!(self.history.record_committed(new_tail))?;
+        self.counters.log_entries_committed += (new_tail - old_tail) as u64;
+        self.check_apply_backlog();
+        let (config_index, config) = self.history.config_version();
+        if config_index != old_config_index && config != old_config {
+            self.notify_config_changed(config_index, old_config.diff(&config));
+        }
+        if !self.self_removed && self.is_removed() {
+            self.self_removed = true;
+            self.push_event(Event::SelfRemoved);
+        }
+
+        let stride = self.options.commit_notify_stride;
+        if stride > 0 && old_tail.as_u64() / stride < new_tail.as_u64() / stride {
+            self.push_event(Event::CommitAdvanced {
+                committed_tail: new_tail,
+            });
+        }
+        Ok(())
     }
 
     /// ローカルログのロールバックイベントを処理する.
     pub fn handle_log_rollbacked(&mut self, new_tail: LogPosition) -> Result<()> {
-        track!(self.history.record_rollback(new_tail))
+        let was_joint = self.joint_config().is_some();
+        track!(self.history.record_rollback(new_tail))?;
+        self.notify_joint_consensus_transition(was_joint);
+        Ok(())
     }
 
     /// ログのスナップショットインストール完了イベントを処理する.
@@ -102,7 +614,14 @@ where
         new_head: LogPosition,
         config: ClusterConfig,
     ) -> Result<()> {
-        track!(self.history.record_snapshot_installed(new_head, config))
+        let was_joint = self.joint_config().is_some();
+        if config.is_witness(&self.local_node.id) {
+            // ウィットネス宛てのスナップショットは、中身が空になっている
+            self.stripped_log = true;
+        }
+        track!(self.history.record_snapshot_installed(new_head, config))?;
+        self.notify_joint_consensus_transition(was_joint);
+        Ok(())
     }
 
     /// ログのスナップショットロードイベントを処理する.
@@ -126,6 +645,12 @@ where
                 .record_snapshot_installed(prefix.tail, prefix.config.clone(),))?;
         }
         track!(self.history.record_snapshot_loaded(&prefix))?;
+
+        // スナップショットの地点までは、状態機械に適用済みとなる
+        if self.applied_index < prefix.tail.index {
+            self.applied_index = prefix.tail.index;
+            self.handle_apply_progress();
+        }
         let event = Event::SnapshotLoaded {
             new_head: prefix.tail,
             snapshot: prefix.snapshot,
@@ -136,20 +661,147 @@ where
     }
 
     /// ローカルノードの投票状況を更新する.
+    ///
+    /// `term`の単調増加性を保証するために、現在よりも小さい`term`を持つ`new_ballot`は無視され、
+    /// 代わりに`Event::TermRegressionRejected`が発行される.
     pub fn set_ballot(&mut self, new_ballot: Ballot) {
-        if self.local_node.ballot != new_ballot {
+        if new_ballot.term < self.local_node.ballot.term {
+            self.push_event(Event::TermRegressionRejected {
+                attempted: new_ballot.term,
+            });
+        } else if self.local_node.ballot != new_ballot {
             self.local_node.ballot = new_ballot.clone();
             self.metrics.event_queue_len.increment();
             self.events.push_back(Event::TermChanged { new_ballot });
         }
     }
 
+    /// 起動時に復元した投票状況が、ローカルログおよびクラスタ構成と矛盾していないかを検査する.
+    ///
+    /// 投票先が、起動時に与えられた構成とローカルログ内の構成のいずれにも含まれない場合や、
+    /// 投票状況の`Term`がローカルログの末尾のエントリの`Term`よりも古い場合に、矛盾していると判定される.
+    ///
+    /// 矛盾している場合には、投票先を取り消した(i.e., ローカルノード自身とした)投票状況に置き換え、
+    /// `Event::BallotCorrupted`を発行した上で`false`を返す.
+    /// 置き換え後の投票状況は、まだ永続化されていないことに注意.
+    pub fn validate_loaded_ballot(&mut self) -> bool {
+        let loaded = self.local_node.ballot.clone();
+        let log_term = self.history.tail().prev_term;
+        // 後の構成変更で取り除かれたノードへの投票も正当なので、過去の構成も含めて、いずれかに含まれていれば良い
+        let is_known_candidate = loaded.voted_for == self.local_node.id
+            || self.initial_config.is_known_node(&loaded.voted_for)
+            || self.history.is_known_node(&loaded.voted_for);
+        if is_known_candidate && log_term <= loaded.term {
+            return true;
+        }
+
+        let recovered = Ballot {
+            term: cmp::max(loaded.term, log_term),
+            voted_for: self.local_node.id.clone(),
+        };
+        self.set_ballot(recovered.clone());
+        self.push_event(Event::BallotCorrupted { loaded, recovered });
+        false
+    }
+
+    /// `size`バイトのスナップショットのインストールを受け付けられる場合には`true`を返す.
+    ///
+    /// `ReplicatedLogOptions::max_snapshot_size`を超えている場合には、
+    /// `Event::SnapshotRejectedTooLarge`が発行された上で`false`が返される.
+    pub fn check_snapshot_size(&mut self, size: usize) -> bool {
+        let max = self.options.max_snapshot_size;
+        if max != 0 && max < size {
+            self.push_event(Event::SnapshotRejectedTooLarge { size, max });
+            false
+        } else {
+            true
+        }
+    }
+
     /// スナップショットをインストール中の場合には`true`を返す.
     ///
     /// このメソッドが`true`を返している間は、
-    /// 新しいスナップショットのインストールを行うことはできない.
+    /// (インストール中のものより新しい地点のものを除いて)新しいスナップショットのインストールを行うことはできない.
     pub fn is_snapshot_installing(&self) -> bool {
-        self.install_snapshot.is_some()
+        self.install_snapshot.is_some() || self.snapshot_transfer.is_some()
+    }
+
+    /// 終端が`tail`のスナップショットで、インストール中のものを置き換えられる場合には`true`を返す.
+    ///
+    /// 置き換えが可能なのは、`install_snapshot`(ないし`InstallSnapshotCast`)によるインストール中で、
+    /// `tail`がその終端よりも新しい場合のみ.
+    /// 分割転送中のスナップショットは置き換えられない.
+    pub fn is_snapshot_supersedable(&self, tail: LogIndex) -> bool {
+        match self.install_snapshot {
+            Some(ref s) => self.snapshot_transfer.is_none() && s.summary.tail.index < tail,
+            None => false,
+        }
+    }
+
+    /// スナップショットを分割して転送する際の、一つのチャンクの最大サイズを返す.
+    ///
+    /// `ReplicatedLogOptions::snapshot_chunk_size`の値が使用される.
+    pub fn snapshot_chunk_size(&self) -> usize {
+        self.options.snapshot_chunk_size
+    }
+
+    /// バックグランドで実行中のI/O処理の状況を返す.
+    pub fn io_status(&self) -> IoStatus {
+        IoStatus {
+            // 読み込みの完了までは、消費済み領域の終端は更新されない
+            loading_committed_from: self
+                .load_committed
+                .as_ref()
+                .map(|_| self.history.consumed_tail().index),
+            installing_snapshot_to: self
+                .install_snapshot
+                .as_ref()
+                .map(|s| s.summary.tail)
+                .or_else(|| self.snapshot_transfer.as_ref().map(|t| t.summary.tail)),
+        }
+    }
+
+    /// コミットされたコマンドのバイト数の分布を返す.
+    ///
+    /// `ReplicatedLogOptions::track_entry_sizes`が無効な場合には、常に空の分布が返される.
+    pub fn entry_size_histogram(&self) -> SizeHistogram {
+        self.entry_sizes.clone().unwrap_or_default()
+    }
+
+    /// ローカルノードで発生した、RPCメッセージの送受信や選挙等の回数を返す.
+    pub fn counters(&self) -> NodeCounters {
+        self.counters
+    }
+
+    /// `Io`が提供するメッセージチャンネルのキューの状況を返す.
+    ///
+    /// 送信側のキューは、クラスタの(ローカルノード以外の)メンバ毎に集計される.
+    pub fn transport_stats(&self) -> TransportStats {
+        let local = &self.local_node.id;
+        TransportStats {
+            inbound_depth: self.io.inbound_depth(),
+            outbound_depths: self
+                .config()
+                .members()
+                .filter(|&peer| peer != local)
+                .map(|peer| (peer.clone(), self.io.outbound_depth(peer)))
+                .collect(),
+        }
+    }
+
+    /// インストール中のスナップショットのバイト数を返す.
+    ///
+    /// スナップショットの本体は`Io`に渡されて保存中であり、`Common`自体は保持していない.
+    /// 分割転送の受信中の場合には、受信済みのバイト数が返される.
+    /// インストール中ではない場合には`0`が返される.
+    pub fn installing_snapshot_bytes(&self) -> usize {
+        if let Some(ref s) = self.install_snapshot {
+            s.snapshot_bytes
+        } else {
+            self.snapshot_transfer
+                .as_ref()
+                .map_or(0, |t| t.received_bytes as usize)
+        }
     }
 
     /// Returns `true` if and only if a node is installing snapshot and should not do
@@ -167,25 +819,143 @@ where
             // See https://github.com/frugalos/raftlog/pull/16#discussion_r250061583.
             return self.log().tail().index < snapshot.summary.tail.index;
         }
+        if let Some(ref transfer) = self.snapshot_transfer {
+            return self.log().tail().index < transfer.summary.tail.index;
+        }
         false
     }
 
     /// `Leader`状態に遷移する.
     pub fn transit_to_leader(&mut self) -> RoleState<IO> {
         self.metrics.transit_to_leader_total.increment();
+        self.counters.elections_won += 1;
+        self.contacted_quorum = true;
         self.set_role(Role::Leader);
         self.notify_new_leader_elected();
+        let local = self.local_node.id.clone();
+        self.set_leader(Some(local));
         RoleState::Leader(Leader::new(self))
     }
 
     /// `Candidate`状態に遷移する.
+    ///
+    /// ローカルノードがウィットネスの場合には、立候補は行わず、リーダが未知の`Follower`状態に遷移する.
     pub fn transit_to_candidate(&mut self) -> RoleState<IO> {
+        if self.is_witness() {
+            // ウィットネスはコマンドを保持していないので、リーダにはなれない
+            let local = self.local_node.id.clone();
+            return self.transit_to_follower(local, None);
+        }
+        self.start_candidacy();
+        RoleState::Candidate(Candidate::new(self))
+    }
+
+    /// リーダシップの移譲先として、`Candidate`状態に遷移する.
+    ///
+    /// `transit_to_candidate`とは異なり、送信される投票依頼は、
+    /// 現在のリーダをフォロー中のノードによっても処理される.
+    pub fn transit_to_transfer_candidate(&mut self) -> RoleState<IO> {
+        self.start_candidacy();
+        RoleState::Candidate(Candidate::new_for_transfer(self))
+    }
+
+    /// 選挙のタイムアウトに伴い、新しい選挙を開始する.
+    ///
+    /// `ReplicatedLogOptions::pre_vote`が有効な場合には、`term`を増やさずに事前投票から始める.
+    pub fn start_election(&mut self) -> RoleState<IO> {
+        if self.options.pre_vote {
+            self.transit_to_pre_candidate()
+        } else {
+            self.transit_to_candidate()
+        }
+    }
+
+    /// `term`を増やさずに、事前投票(pre-vote)を行う`Candidate`状態に遷移する.
+    ///
+    /// 投票状況は変更されないので、その永続化も行われない.
+    /// 過半数から事前投票の承認が得られた場合にのみ、`transit_to_candidate`による実際の立候補が行われる.
+    ///
+    /// `transit_to_candidate`と同様に、ローカルノードがウィットネスの場合には`Follower`状態に遷移する.
+    pub fn transit_to_pre_candidate(&mut self) -> RoleState<IO> {
+        if self.is_witness() {
+            let local = self.local_node.id.clone();
+            return self.transit_to_follower(local, None);
+        }
+        self.set_role(Role::Candidate);
+        RoleState::Candidate(Candidate::new_pre_vote(self))
+    }
+
+    /// クラスタが休止状態かどうかを判定する.
+    ///
+    /// リーダの場合は自身が休止状態であるかどうか、
+    /// フォロワーの場合はリーダから最後に受信した`AppendEntriesCall`で休止状態が通知されたかどうか、を意味する.
+    pub fn is_quiescent(&self) -> bool {
+        self.quiescent
+    }
+
+    /// クラスタの休止状態を設定する.
+    ///
+    /// リーダの場合には、以後に送信される`AppendEntriesCall`に反映される.
+    pub fn set_quiescent(&mut self, quiescent: bool) {
+        self.quiescent = quiescent;
+        self.quiescent_missed_timeouts = 0;
+    }
+
+    /// 休止状態のリーダに合わせて、選挙のタイムアウトを延長する.
+    ///
+    /// リーダは休止中には`ReplicatedLogOptions::quiescent_heartbeat_interval`回に一度しかハートビートを送信しないので、
+    /// フォロワーは、その回数に達するまではタイムアウトを再設定して待機する.
+    /// 延長した場合には`true`が返される.
+    pub fn extend_quiescent_election_timeout(&mut self) -> bool {
+        if !self.quiescent {
+            return false;
+        }
+        self.quiescent_missed_timeouts += 1;
+        if self.quiescent_missed_timeouts < self.options.quiescent_heartbeat_interval {
+            self.set_timeout(Role::Follower);
+            true
+        } else {
+            self.set_quiescent(false);
+            false
+        }
+    }
+
+    /// 選挙のタイムアウトを待たずに、即座に新しい選挙を開始する.
+    ///
+    /// 挙動はタイムアウトの発火時(`start_election`)と同様で、`term`を増やして`Candidate`状態に遷移する.
+    /// (投票状況の永続化と投票依頼の送信は、遷移先の`Candidate`によって行われる)
+    ///
+    /// `ReplicatedLogOptions::pre_vote`が有効な場合には、`term`を増やさずに事前投票から始める.
+    ///
+    /// # Errors
+    ///
+    /// ローカルノードが投票権を有するメンバではない場合
+    /// (e.g., ラーナーや、構成変更中に追加されて、ログの同期待ちをしている新メンバ)や、ウィットネスの場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn trigger_election(&mut self) -> Result<RoleState<IO>> {
+        let is_witness = self.is_witness();
+        let config = self.config();
+        let local = &self.local_node.id;
+        let is_voter = match config.state() {
+            ClusterState::CatchUp => config.old_members().contains(local),
+            _ => config.is_known_node(local) && !config.is_learner(local),
+        } && !is_witness;
+        track_assert!(
+            is_voter,
+            ErrorKind::InvalidInput,
+            "Not a voting member: node={:?}, config={:?}",
+            local,
+            config
+        );
+        Ok(self.start_election())
+    }
+
+    /// `term`を増やさずに、`Candidate`状態に遷移する.
+    ///
+    /// 再起動前に、現在の`term`で既に自分に投票していた場合に使用される.
+    /// 以前に得ていた票はメモリ上にしか存在しないため、投票依頼は改めて送信される.
+    pub fn resume_candidacy(&mut self) -> RoleState<IO> {
         self.metrics.transit_to_candidate_total.increment();
-        let new_ballot = Ballot {
-            term: (self.local_node.ballot.term.as_u64() + 1).into(),
-            voted_for: self.local_node.id.clone(),
-        };
-        self.set_ballot(new_ballot);
         self.set_role(Role::Candidate);
         RoleState::Candidate(Candidate::new(self))
     }
@@ -201,9 +971,20 @@ where
             term: self.local_node.ballot.term,
             voted_for: followee,
         };
+        let is_leader_known = pending_vote.is_none() && new_ballot.voted_for != self.local_node.id;
+        let leader = if is_leader_known {
+            Some(new_ballot.voted_for.clone())
+        } else {
+            // 投票先の候補者は、まだリーダとして確定していない
+            None
+        };
         self.set_ballot(new_ballot);
         self.set_role(Role::Follower);
         self.notify_new_leader_elected();
+        self.set_leader(leader);
+
+        // 分割転送中のスナップショットの残りは、新しいリーダからは送られてこない
+        self.snapshot_transfer = None;
         RoleState::Follower(Follower::new(self, pending_vote))
     }
 
@@ -212,6 +993,19 @@ where
         self.events.push_back(Event::NewLeaderElected);
     }
 
+    /// フォロー中のリーダとの通信が途絶えたことを通知する.
+    ///
+    /// リーダが確定していた場合には`Event::LeaderLost`が発行される.
+    pub fn notify_leader_lost(&mut self) {
+        self.set_leader(None);
+    }
+
+    /// ユーザに通知するイベントを追加する.
+    pub fn push_event(&mut self, event: Event) {
+        self.metrics.event_queue_len.increment();
+        self.events.push_back(event);
+    }
+
     /// 次のメッセージ送信に使用されるシーケンス番号を返す.
     ///
     /// このメソッド自体は単に値を返すのみであり、
@@ -234,18 +1028,92 @@ where
     }
 
     /// 指定範囲のローカルログをロードする.
-    pub fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> IO::LoadLog {
-        self.io.load_log(start, end)
+    ///
+    /// `end`がローカルログの終端よりも後ろを指している場合には、終端位置に切り詰められる.
+    ///
+    /// なお`start`がローカルログの先頭よりも前を指している場合には、切り詰めは行われない.
+    /// その場合には`Io`の実装によってスナップショット(`Log::Prefix`)が返されることが期待されており、
+    /// フォロワーへのスナップショット送信等は、この挙動に依存しているため.
+    ///
+    /// # Errors
+    ///
+    /// 切り詰め後の範囲の終端が、開始位置よりも前になった場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Result<IO::LoadLog> {
+        let tail = self.history.tail().index;
+        let end = end.map(|end| if tail < end { tail } else { end });
+        if let Some(end) = end {
+            track_assert!(
+                start <= end,
+                ErrorKind::InvalidInput,
+                "start={:?}, end={:?}, tail={:?}",
+                start,
+                end,
+                tail
+            );
+        }
+        Ok(self.io.load_log(start, end))
+    }
+
+    /// コミット済みのログ全体を読み込んで、その整合性を検査する.
+    ///
+    /// ローカルノードの状態は一切変更されない.
+    pub fn audit_committed_log(&mut self) -> Result<AuditFuture<IO>> {
+        let start = self.history.head().index;
+        let end = self.history.committed_tail().index;
+        let load = if start < end {
+            Some(track!(self.load_log(start, Some(end)))?)
+        } else {
+            None
+        };
+        Ok(AuditFuture::new(load, start, end, self.history.clone()))
     }
 
     /// ローカルログの末尾部分に`suffix`を追記する.
-    pub fn save_log_suffix(&mut self, suffix: &LogSuffix) -> IO::SaveLog {
-        self.io.save_log_suffix(suffix)
+    ///
+    /// `ReplicatedLogOptions::ordered_persistence`が有効な場合には、
+    /// 先に発行された投票状況の保存やログの追記が完了するまで、実際の書き込みは開始されない.
+    pub fn save_log_suffix(&mut self, suffix: &LogSuffix) -> PersistLog<IO> {
+        if self.options.ordered_persistence {
+            Persist::Queued(self.persistence.push_log_suffix(&mut self.io, suffix))
+        } else {
+            Persist::Direct(self.io.save_log_suffix(suffix))
+        }
     }
 
     /// 現在の投票状況を保存する.
-    pub fn save_ballot(&mut self) -> IO::SaveBallot {
-        self.io.save_ballot(self.local_node.ballot.clone())
+    ///
+    /// `ReplicatedLogOptions::ordered_persistence`が有効な場合には、
+    /// 先に発行された投票状況の保存やログの追記が完了するまで、実際の書き込みは開始されない.
+    pub fn save_ballot(&mut self) -> PersistBallot<IO> {
+        let ballot = self.local_node.ballot.clone();
+        if self.options.ordered_persistence {
+            Persist::Queued(self.persistence.push_ballot(&mut self.io, ballot))
+        } else {
+            Persist::Direct(self.io.save_ballot(ballot))
+        }
+    }
+
+    /// 順序付きキューに投入された書き込みの完了を確認する.
+    fn poll_persistence(&mut self, ticket: u64) -> Poll<(), Error> {
+        track!(self.persistence.run_once(&mut self.io))?;
+        if self.persistence.is_completed(ticket) {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
+    /// 前回の停止時に保存途中だった分割スナップショットの進捗を復元する.
+    ///
+    /// 復元された進捗は、同じスナップショットのチャンクを次に受信した際に使用され、
+    /// 保存済みの範囲を読み飛ばして、その続きから保存が再開される.
+    /// ローカルログの先頭が既にスナップショットの終端を超えている場合には、進捗は無視される.
+    pub fn load_snapshot_transfer(&mut self) -> Result<()> {
+        let progress = track!(self.io.load_snapshot_transfer())?;
+        let head = self.history.head().index;
+        self.resumable_snapshot = progress.filter(|p| head <= p.tail.index);
+        Ok(())
     }
 
     /// 以前の投票状況を復元する.
@@ -253,14 +1121,118 @@ where
         self.io.load_ballot()
     }
 
+    /// 現在設定されているタイムアウトの時間を返す.
+    ///
+    /// 値はタイムアウトの生成直後に`Io::current_timeout_duration`から取得されるので、
+    /// `Io`実装がこれに対応していない場合には`None`となる.
+    /// tick計時用のタイムアウトは対象外.
+    pub fn current_timeout_duration(&self) -> Option<Duration> {
+        self.timeout_duration
+    }
+
     /// 指定されたロール用のタイムアウトを設定する.
+    ///
+    /// `ReplicatedLogOptions::election_timeout_jitter`が指定されている場合には、
+    /// フォロワーおよび候補者用のタイムアウトには、ランダムな揺らぎが加えられる.
     pub fn set_timeout(&mut self, role: Role) {
-        self.timeout = self.io.create_timeout(role);
+        self.timeout = self.timeout_jitter.create_timeout(&mut self.io, role);
+        self.timeout_role = role;
+        self.timeout_duration = self.io.current_timeout_duration();
+    }
+
+    /// 選挙用の(i.e., フォロワー用の)タイムアウトを新しく設定し直す.
+    ///
+    /// 例えば、ノード間通信とは別の経路でリーダの生存が確認できた場合等に、
+    /// 不要な選挙の開始を避けるために使用できる.
+    /// リーダの生存が確実な場合にのみ使用すべきであり、乱用するとリーダの障害検知が遅れてしまうので注意.
+    pub fn reset_election_timeout(&mut self) {
+        self.set_timeout(Role::Follower);
     }
 
     /// タイムアウトに達していないかを確認する.
+    ///
+    /// タイムアウトがエラーを返した場合には、`Event::TimerError`を発行した上で、同じ役割用のタイムアウトを作り直す.
+    /// ただし、連続したエラーの回数が`ReplicatedLogOptions::max_timer_retries`を超えた場合には、そのエラーが返される.
     pub fn poll_timeout(&mut self) -> Result<Async<()>> {
-        track!(self.timeout.poll())
+        let polled = loop {
+            match self.timeout.poll() {
+                Ok(polled) => break polled,
+                Err(e) => {
+                    track!(self.handle_timer_error(e))?;
+                    let role = self.timeout_role;
+                    self.set_timeout(role);
+                }
+            }
+        };
+        self.timer_errors = 0;
+        if polled.is_ready() {
+            self.leader_contacted_since_timeout = false;
+        }
+        Ok(polled)
+    }
+
+    /// tick計時用のタイムアウトに達していないかを確認し、達していた場合にはtick数を一つ進める.
+    ///
+    /// tick計時用のタイムアウトは、役割や選挙用のタイムアウトとは独立して、
+    /// リーダ用のタイムアウトの時間(i.e., ハートビートの間隔)毎に発火する.
+    /// (フォロワーの選挙用のタイムアウトは、リーダからのメッセージを受信する度に設定し直されるので、tickの計時には使えない)
+    ///
+    /// エラー時の挙動は`poll_timeout`と同様.
+    pub fn poll_tick_timeout(&mut self) -> Result<Async<()>> {
+        let polled = loop {
+            match self.tick_timeout.poll() {
+                Ok(polled) => break polled,
+                Err(e) => {
+                    track!(self.handle_timer_error(e))?;
+                    self.tick_timeout = self.io.create_timeout(Role::Leader);
+                }
+            }
+        };
+        self.timer_errors = 0;
+        if polled.is_ready() {
+            self.tick_timeout = self.io.create_timeout(Role::Leader);
+            self.tick();
+        }
+        Ok(polled)
+    }
+
+    /// ローカルノードの状態の要約を返す.
+    pub fn debug_state(&self) -> CommonDebugState {
+        let committed_tail = self.history.committed_tail().index;
+        CommonDebugState {
+            role: self.local_node.role,
+            term: self.term(),
+            committed_tail,
+            unconsumed_entries: committed_tail.as_u64()
+                - self.history.consumed_tail().index.as_u64(),
+        }
+    }
+
+    /// デバッグ用にメモリ上に保持されている、コミット済みエントリを返す.
+    ///
+    /// 保持されていないインデックスが指定された場合には`None`が返される.
+    pub fn peek_entry(&self, index: LogIndex) -> Option<&LogEntry> {
+        self.retained_entries
+            .iter()
+            .find(|&&(i, _)| i == index)
+            .map(|(_, e)| e)
+    }
+
+    /// 管理ツール向けに、ローカルノードの状態の一覧を返す.
+    ///
+    /// リーダ固有のフィールドは`None`となるので、必要に応じて呼び出し元で設定すること.
+    pub fn admin_view(&self) -> AdminView {
+        AdminView {
+            role: self.local_node.role,
+            term: self.term(),
+            voted_for: self.local_node.ballot.voted_for.clone(),
+            log_tail: self.history.tail().index,
+            committed_tail: self.history.committed_tail().index,
+            applied_index: self.applied_index,
+            config: self.config().clone(),
+            peers: None,
+            leadership_age_ticks: None,
+        }
     }
 
     /// ユーザに通知するイベントがある場合には、それを返す.
@@ -269,52 +1241,277 @@ where
         self.events.pop_front()
     }
 
+    /// 次に通知されるイベントを、キューから取り除かずに返す.
+    pub fn peek_event(&self) -> Option<&Event> {
+        self.events.front()
+    }
+
+    /// 未消費のイベント数が上限に達している場合には`true`を返す.
+    pub fn is_event_queue_congested(&self) -> bool {
+        let high_water_mark = self.options.event_queue_high_water_mark;
+        high_water_mark > 0 && self.events.len() >= high_water_mark
+    }
+
     /// 受信メッセージがある場合には、それを返す.
     pub fn try_recv_message(&mut self) -> Result<Option<Message>> {
         if let Some(message) = self.unread_message.take() {
-            Ok(Some(message))
-        } else {
-            track!(self.io.try_recv_message())
+            return Ok(Some(message));
+        }
+        let message = track!(self.io.try_recv_message())?;
+        match message {
+            Some(Message::RequestVoteCall(_)) => self.counters.request_vote_received += 1,
+            Some(Message::RequestVoteReply(RequestVoteReply { repeated: true, .. })) => {
+                self.counters.repeated_votes_received += 1
+            }
+            Some(Message::AppendEntriesCall(_)) => self.counters.append_entries_received += 1,
+            _ => {}
         }
+        Ok(message)
     }
 
     /// ローカルログのスナップショットのインストールを開始する.
     pub fn install_snapshot(&mut self, snapshot: LogPrefix) -> Result<()> {
+        track!(self.install_snapshot_with_callback(snapshot, None))
+    }
+
+    /// ローカルログのスナップショットのインストールを開始する.
+    ///
+    /// `callback`が指定された場合には、インストールの完了時に新しいログの先頭位置を引数として呼び出される.
+    /// インストールに失敗した場合や、完了前に破棄された場合には、エラーを引数として呼び出される.
+    ///
+    /// 別のスナップショットのインストール中の場合には、`ErrorKind::Busy`が返される.
+    /// ただし`snapshot`の方が新しい地点のものである場合には、インストール中のものは破棄され
+    /// (`Event::SnapshotSuperseded`が発行され)、代わりに`snapshot`のインストールが開始される.
+    pub fn install_snapshot_with_callback(
+        &mut self,
+        snapshot: LogPrefix,
+        callback: Option<SnapshotInstallCallback>,
+    ) -> Result<()> {
+        // リーダが自身のログを圧縮する(コミット済みの地点までのスナップショットをインストールする)のは問題ない
+        track_assert!(
+            self.local_node.role != Role::Leader
+                || snapshot.tail.index <= self.history.committed_tail().index
+                || self.options.allow_snapshot_install_on_leader,
+            ErrorKind::InvalidInput,
+            "Leaders cannot install snapshots beyond the committed tail: node={:?}, snapshot_tail={:?}, committed_tail={:?}",
+            self.local_node.id,
+            snapshot.tail,
+            self.history.committed_tail()
+        );
         track_assert!(
             self.history.head().index <= snapshot.tail.index,
             ErrorKind::InconsistentState
         );
-        track_assert!(self.install_snapshot.is_none(), ErrorKind::Busy);
+        track_assert!(
+            self.install_snapshot.is_none() || self.is_snapshot_supersedable(snapshot.tail.index),
+            ErrorKind::Busy
+        );
+        let size = snapshot.snapshot.len();
+        track_assert!(
+            self.check_snapshot_size(size),
+            ErrorKind::InvalidInput,
+            "Too large snapshot: size={}, max={}",
+            size,
+            self.options.max_snapshot_size
+        );
 
-        let future = InstallSnapshot::new(self, snapshot);
+        if let Some(old) = self.install_snapshot.take() {
+            // より新しいスナップショットが届いたので、インストール中のものは破棄する
+            // (破棄されたインストールのコールバックには、キャンセル扱いのエラーが渡される)
+            let old_head = old.summary.tail;
+            drop(old);
+            self.push_event(Event::SnapshotSuperseded {
+                old_head,
+                new_head: snapshot.tail,
+            });
+        }
+        let future = InstallSnapshot::new(self, snapshot, callback);
         self.install_snapshot = Some(future);
         Ok(())
     }
 
+    /// 分割して転送されたスナップショットのチャンクを受け取る.
+    ///
+    /// チャンク群は`offset`の順に`Io::save_snapshot_chunk`で保存され
+    /// (`Io`がこれに対応していない場合には、連結後に`Io::save_log_prefix`で保存され)、
+    /// 最後のチャンクの保存完了時に`install_snapshot`の場合と同様に、インストールの完了が処理される.
+    ///
+    /// 想定外の`offset`(ないしスナップショット)のチャンクを受け取った場合には、進行中の転送は破棄される.
+    /// その際に、受け取ったチャンクが先頭(`offset == 0`)のものであれば、新しい転送が開始される.
+    /// また、受信済みの合計サイズが`ReplicatedLogOptions::max_snapshot_size`を超えた場合にも、転送は破棄される.
+    ///
+    /// 別のスナップショットのインストール(ないし最後のチャンクの保存)が進行中の場合には、チャンクは無視される.
+    ///
+    /// `load_snapshot_transfer`で復元された進捗と同じスナップショットのチャンクを受け取った場合には、
+    /// 先頭から転送をやり直す代わりに、保存済みの範囲を読み飛ばして、その続きから保存が再開される.
+    ///
+    /// # Errors
+    ///
+    /// 現在のログの先頭よりも前の地点のスナップショットの転送を開始しようとした場合には、
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
+    pub fn receive_snapshot_chunk(&mut self, mut chunk: SnapshotChunk) -> Result<()> {
+        if self.install_snapshot.is_some() {
+            return Ok(());
+        }
+        if self.snapshot_transfer.is_none() {
+            self.resume_snapshot_transfer(&chunk);
+        }
+        if let Some(ref t) = self.snapshot_transfer {
+            if t.summary.tail == chunk.tail && chunk.offset < t.resumed_bytes {
+                // 再起動前に保存済みの範囲は、保存し直さずに読み飛ばす
+                let skip = cmp::min(t.resumed_bytes - chunk.offset, chunk.data.len() as u64);
+                if skip == chunk.data.len() as u64 && !chunk.done {
+                    return Ok(());
+                }
+                chunk.data.drain(..skip as usize);
+                chunk.offset += skip;
+            }
+        }
+        let is_continued = matches!(
+            self.snapshot_transfer,
+            Some(ref t) if t.summary.tail == chunk.tail && t.received_bytes == chunk.offset
+        );
+        if !is_continued {
+            self.snapshot_transfer = None;
+            if chunk.offset != 0 {
+                // 途中のチャンクが欠損している
+                return Ok(());
+            }
+            track_assert!(
+                self.history.head().index <= chunk.tail.index,
+                ErrorKind::InconsistentState
+            );
+            self.snapshot_transfer = Some(SnapshotTransfer::new(&chunk));
+
+            // 先頭のチャンクの保存によって、`Io`側の保存途中のチャンク群は破棄される
+            self.resumable_snapshot = None;
+        }
+
+        let received_bytes = chunk.offset + chunk.data.len() as u64;
+        if !self.check_snapshot_size(received_bytes as usize) {
+            self.snapshot_transfer = None;
+            return Ok(());
+        }
+        if let Some(ref mut t) = self.snapshot_transfer {
+            t.received_bytes = received_bytes;
+            t.pending.push_back(chunk);
+        }
+        Ok(())
+    }
+
+    /// 分割転送中のスナップショットの受信が途絶えている場合には、その転送を破棄する.
+    ///
+    /// 受信済みのチャンクの保存が全て完了しているにも関わらず、
+    /// 最後のチャンクが届いていない場合に、途絶えていると判断される.
+    /// (リーダは全てのチャンクを一度に送信するので、以後のハートビートの受信時に呼び出されることを想定している)
+    pub fn abandon_stalled_snapshot_transfer(&mut self) {
+        let is_stalled = matches!(
+            self.snapshot_transfer,
+            Some(ref t) if t.saving.is_none() && t.pending.is_empty()
+        );
+        if is_stalled {
+            self.snapshot_transfer = None;
+        }
+    }
+
     /// 受信メッセージに対する共通的な処理を実行する.
-    pub fn handle_message(&mut self, message: Message) -> HandleMessageResult<IO> {
+    ///
+    /// 役割の遷移を伴うメッセージは、遷移後の状態で処理させるために未読メッセージとしてバッファされる.
+    /// バッファできるメッセージは一つだけなので、このメソッドを呼び出す前には、
+    /// 必ず`try_recv_message`を使って、バッファ済みのメッセージを消費しておく必要がある.
+    ///
+    /// # Errors
+    ///
+    /// 未読メッセージが消費されていない状態で、新たなメッセージをバッファしようとした場合には、
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
+    pub fn handle_message(&mut self, message: Message) -> Result<HandleMessageResult<IO>> {
         if self.local_node.role == Role::Leader
             && !self.config().is_known_node(&message.header().sender)
+            && !self.is_newer_leader_message(&message)
         {
             // a) リーダは、不明なノードからのメッセージは無視
             //
             //  リーダ以外は、クラスタの構成変更を跨いで再起動が発生した場合に、
             //  停止時には知らなかった新構成を把握するために、
             //  不明なノードからもメッセージも受信する必要がある.
-            HandleMessageResult::Handled(None)
+            //
+            //  ただし、より新しい`term`のリーダからの`AppendEntriesCall`は例外とする.
+            //  自身を含まない新構成の下で、既に別のリーダが選出されていることを示しているので、
+            //  以下の分岐でフォロワーに遷移して、新構成を受け取る.
+            Ok(HandleMessageResult::Handled(None))
+        } else if self.options.removed_node_policy == RemovedNodePolicy::Passive
+            && self.is_removed()
+        {
+            // クラスタから取り除かれたので、受動モードとして振る舞う
+            //
+            // 取り除かれたことをまだ把握していないリーダには、それを通知して、以後の同期を止めて貰う.
+            // 投票の依頼を含む、それ以外のメッセージは全て無視する.
+            if let Message::AppendEntriesCall(ref m) = message {
+                self.rpc_callee(&m.header).reply_removed();
+            }
+            Ok(HandleMessageResult::Handled(None))
+        } else if let Message::RequestVoteCall(RequestVoteCall { pre_vote: true, .. }) = message {
+            // 事前投票の依頼は`term`に影響を与えないので、他の分岐よりも先に処理する
+            if let Message::RequestVoteCall(ref m) = message {
+                self.handle_pre_vote_call(m);
+            }
+            Ok(HandleMessageResult::Handled(None))
+        } else if matches!(
+            message,
+            Message::RequestVoteReply(RequestVoteReply { pre_vote: true, voted, ref header, .. })
+                if voted || header.term <= self.local_node.ballot.term
+        ) {
+            // 事前投票の結果は、それを依頼した候補者のみが扱う
+            //
+            // ただし、より大きな`term`を伴う拒否応答は、以下の分岐で通常のメッセージと同様に扱い、その`term`に追従する.
+            if self.local_node.role == Role::Candidate {
+                Ok(HandleMessageResult::Unhandled(message))
+            } else {
+                Ok(HandleMessageResult::Handled(None))
+            }
         } else if message.header().term > self.local_node.ballot.term {
             // b) 相手のtermの方が大きい => 新しい選挙が始まっているので追従する
-            let is_follower = self.local_node.ballot.voted_for != self.local_node.id;
-            if is_follower && self.local_node.ballot.voted_for != message.header().sender {
+            //
+            // NOTE: 事前投票中の候補者は、以前のリーダへの投票状況を保持しているが、
+            // 既に選挙のタイムアウトに達しているので、フォロワーとしては扱わない.
+            //
+            // また、リーダシップの移譲に伴う投票依頼は、現在のリーダの同意の下で送信されたものなので、
+            // 以下の現在の選挙を維持するための判定の対象外となる.
+            let is_follower = self.local_node.role == Role::Follower
+                && self.local_node.ballot.voted_for != self.local_node.id;
+            let is_transfer = matches!(
+                message,
+                Message::RequestVoteCall(RequestVoteCall { transfer: true, .. })
+            );
+            if is_follower
+                && !is_transfer
+                && self.local_node.ballot.voted_for != message.header().sender
+            {
                 // リーダをフォロー中(i.e., 定期的にハートビートを受信できている)の場合には、
                 // そのリーダを信じて、現在の選挙を維持する.
                 //
                 // これはクラスタ構成変更時に、旧構成のメンバによって、延々と新選挙の開始が繰り返されてしまう
                 // 可能性がある問題への対処となる.
                 // この問題の詳細は論文の「6 Cluster membership changes」の"The third issue is ..."部分を参照のこと.
-                return HandleMessageResult::Handled(None);
+                //
+                // リース(`leader_lease_ticks`)が有効な場合には、現在の選挙の維持はリース期間中に限定される.
+                let lease_ticks = self.options.leader_lease_ticks;
+                if lease_ticks == 0 {
+                    return Ok(HandleMessageResult::Handled(None));
+                }
+                if self.is_within_leader_lease() {
+                    if let Message::RequestVoteCall(_) = message {
+                        self.rpc_callee(message.header()).reply_request_vote(false);
+                    }
+                    return Ok(HandleMessageResult::Handled(None));
+                }
             }
 
+            // NOTE:
+            // ここで更新した`term`は、以下のいずれの分岐でも遷移先の状態(`FollowerInit`ないし`Candidate`)の
+            // 生成時に永続化される.
+            // 遷移先の状態は、永続化が完了するまで投票やログの追記を行わないので、
+            // 投票の有無に関わらず、新しい`term`に基づく行動は全て永続化の後となる.
             self.local_node.ballot.term = message.header().term;
             let next_state = if let Message::RequestVoteCall(m) = message {
                 if m.log_tail.is_newer_or_equal_than(self.history.tail()) {
@@ -328,7 +1525,7 @@ where
             } else if let Message::AppendEntriesCall { .. } = message {
                 // 新リーダが当選していたので、その人のフォロワーとなる
                 let leader = message.header().sender.clone();
-                self.unread_message = Some(message);
+                track!(self.buffer_unread_message(message))?;
                 self.transit_to_follower(leader, None)
             } else if self.local_node.role == Role::Leader {
                 self.transit_to_candidate()
@@ -336,45 +1533,114 @@ where
                 let local = self.local_node.id.clone();
                 self.transit_to_follower(local, None)
             };
-            HandleMessageResult::Handled(Some(next_state))
+            Ok(HandleMessageResult::Handled(Some(next_state)))
         } else if message.header().term < self.local_node.ballot.term {
             // c) 自分のtermの方が大きい => 選挙期間が古くなっていることを送信元の通知
 
             // NOTE: 返信メッセージの中身は重要ではないので、一番害の無さそうなものを送っておく
             self.rpc_callee(message.header()).reply_request_vote(false);
-            HandleMessageResult::Handled(None)
+            Ok(HandleMessageResult::Handled(None))
         } else {
             // d) 同じ選挙期間に属するノードからのメッセージ
             match message {
                 Message::RequestVoteCall { .. } if !self.is_following_sender(&message) => {
                     // 別の人をフォロー中に投票依頼が来た場合ので拒否
                     self.rpc_callee(message.header()).reply_request_vote(false);
-                    HandleMessageResult::Handled(None)
+                    Ok(HandleMessageResult::Handled(None))
+                }
+                Message::AppendEntriesCall(ref m)
+                    if !self.is_following_sender(&message)
+                        && self.is_conflicting_with_committed(m.suffix.head) =>
+                {
+                    // 同じ選挙期間内の正当なリーダは、全てのコミット済みエントリを保持しているはず
+                    // (論文の「5.4 Safety」を参照)
+                    // => コミット済み領域と矛盾する送信者は、リーダとして認めずに拒否する
+                    //
+                    // NOTE: 返信メッセージの中身は重要ではないので、一番害の無さそうなものを送っておく
+                    self.rpc_callee(message.header()).reply_request_vote(false);
+                    Ok(HandleMessageResult::Handled(None))
                 }
                 Message::AppendEntriesCall { .. } if !self.is_following_sender(&message) => {
                     // リーダが確定したので、フォロー先を変更する
+                    //
+                    // 送信者が本当に過半数の投票を得たリーダであるかは、フォロワー側では確認できないが、
+                    // 少なくともコミット済み領域との整合性は、上の分岐で確認済み.
                     let leader = message.header().sender.clone();
-                    self.unread_message = Some(message);
+                    track!(self.buffer_unread_message(message))?;
                     let next = self.transit_to_follower(leader, None);
-                    HandleMessageResult::Handled(Some(next))
+                    Ok(HandleMessageResult::Handled(Some(next)))
+                }
+                _ => {
+                    if self.local_node.role == Role::Follower && self.is_following_sender(&message)
+                    {
+                        // フォロー中のリーダとの最終通信時刻は、ここで一元的に記録する
+                        self.last_leader_contact_tick = Some(self.ticks);
+                        self.leader_contacted_since_timeout = true;
+                        match message {
+                            Message::AppendEntriesCall(ref m)
+                                if self.leader.as_ref() != Some(&m.header.sender) =>
+                            {
+                                // 投票した候補者が選挙に勝利した、ないし、見失っていたリーダとの通信が回復した
+                                self.set_leader(Some(m.header.sender.clone()));
+                            }
+                            _ => {}
+                        }
+                    }
+                    Ok(HandleMessageResult::Unhandled(message)) // 個別のロールに処理を任せる
                 }
-                _ => HandleMessageResult::Unhandled(message), // 個別のロールに処理を任せる
             }
         }
     }
 
+    /// ブロードキャスト時の送信先の優先順位を設定する.
+    ///
+    /// `ReplicatedLogOptions::max_broadcast_sends_per_run`によって送信数が制限されている場合には、
+    /// `priority`の先頭に近いノードほど先に送信される.
+    /// `priority`に含まれないノードは、末尾に位置するものとして扱われる.
+    pub fn set_broadcast_priority(&mut self, priority: Vec<NodeId>) {
+        self.broadcast_priority = priority;
+    }
+
+    /// 送信が保留されているブロードキャストメッセージの数を返す.
+    pub fn deferred_messages_len(&self) -> usize {
+        self.deferred_messages.len()
+    }
+
+    /// ブロードキャストのための送信数のカウントをリセットする.
+    ///
+    /// `NodeState`のポーリングの開始時に一度だけ呼び出される.
+    pub fn reset_broadcast_sends(&mut self) {
+        self.broadcast_sends = 0;
+    }
+
     /// バックグランド処理を一単位実行する.
     pub fn run_once(&mut self) -> Result<NextState<IO>> {
+        self.flush_deferred_messages();
+        self.check_consumption_progress();
         loop {
+            // 分割転送されたスナップショットのチャンクの保存処理
+            track!(self.poll_snapshot_transfer())?;
+
+            // 順序付きキューに投入された書き込みの処理
+            // (呼び出し元で破棄されたものも、ここで完了まで進められる)
+            track!(self.persistence.run_once(&mut self.io))?;
+
             // スナップショットのインストール処理
             if let Async::Ready(Some(summary)) = track!(self.install_snapshot.poll())? {
                 let SnapshotSummary {
                     tail: new_head,
                     config,
                 } = summary;
-                self.install_snapshot = None;
+                let callback = self
+                    .install_snapshot
+                    .take()
+                    .and_then(|mut s| s.callback.take());
                 self.events.push_back(Event::SnapshotInstalled { new_head });
-                track!(self.history.record_snapshot_installed(new_head, config))?;
+                self.counters.snapshots_installed += 1;
+                track!(self.handle_log_snapshot_installed(new_head, config))?;
+                if let Some(callback) = callback {
+                    callback(Ok(new_head));
+                }
             }
 
             // コミット済みログの処理.
@@ -387,20 +1653,31 @@ where
                 }
             }
 
-            if self.load_committed.is_some()
-                || self.history.consumed_tail().index == self.history.committed_tail().index
-            {
+            if !track!(self.prefetch_committed())? {
                 // コミット済みのログの読み込み中 or 未処理のコミット済みログ領域がない
                 break;
             }
-
-            let start = self.history.consumed_tail().index;
-            let end = self.history.committed_tail().index;
-            self.load_committed = Some(self.load_log(start, Some(end)));
         }
+        self.check_consumption_progress();
         Ok(None)
     }
 
+    /// 未処理のコミット済みログ領域の読み込みを、先行して開始する.
+    ///
+    /// 利用者がイベントを処理している間に読み込みを進めておくことで、`Io::load_log`の遅延を隠蔽できる.
+    /// 既に読み込み中の場合や、未処理のコミット済みログ領域がない場合には何も行わない.
+    ///
+    /// 新たに読み込みを開始した場合には`true`が返される.
+    pub fn prefetch_committed(&mut self) -> Result<bool> {
+        let start = self.history.consumed_tail().index;
+        let end = self.history.committed_tail().index;
+        if self.load_committed.is_some() || start == end {
+            return Ok(false);
+        }
+        self.load_committed = Some(track!(self.load_log(start, Some(end)))?);
+        Ok(true)
+    }
+
     /// RPCの要求用のインスタンスを返す.
     pub fn rpc_caller(&mut self) -> RpcCaller<IO> {
         RpcCaller::new(self)
@@ -413,24 +1690,470 @@ where
 
     fn handle_committed(&mut self, suffix: LogSuffix) -> Result<()> {
         let new_tail = suffix.tail();
-        for (index, entry) in (suffix.head.index.as_u64()..)
+        let threshold = self.options.catch_up_threshold;
+        let behind = self
+            .history
+            .committed_tail()
+            .index
+            .as_u64()
+            .saturating_sub(suffix.head.index.as_u64());
+        if threshold > 0 && behind >= threshold {
+            // 大幅に遅れている
+            // => 追い付くまでの間は、個々のコミット済みエントリの通知を省略する
+            self.catching_up = true;
+        }
+
+        self.retain_entries_for_debug(&suffix);
+        if let Some(ref mut histogram) = self.entry_sizes {
+            for e in &suffix.entries {
+                if let LogEntry::Command { ref command, .. } = *e {
+                    histogram.record(command.len() as u64);
+                }
+            }
+        }
+
+        let now = self.io.now();
+        let skip_noop = self.options.skip_noop_committed_events;
+        let entries = (suffix.head.index.as_u64()..)
             .map(LogIndex::new)
             .zip(suffix.entries.into_iter())
+            .map(|(index, entry)| (index, entry, self.take_append_to_commit(index, now)))
+            .filter(|(_, entry, _)| !(skip_noop && matches!(entry, LogEntry::Noop { .. })))
+            .collect::<Vec<_>>();
+        if self.catching_up {
+            for (index, entry, append_to_commit) in entries {
+                if let LogEntry::Config { .. } = entry {
+                    // 構成変更エントリは省略しない
+                    self.events.push_back(Event::Committed {
+                        index,
+                        entry,
+                        append_to_commit,
+                    });
+                }
+            }
+        } else if self.options.batch_committed_events {
+            let mut batch = Vec::new();
+            for (index, entry, append_to_commit) in entries {
+                if let LogEntry::Config { .. } = entry {
+                    // 構成変更エントリはバッチの境界となる
+                    self.flush_committed_batch(&mut batch);
+                    self.events.push_back(Event::Committed {
+                        index,
+                        entry,
+                        append_to_commit,
+                    });
+                } else {
+                    batch.push((index, entry, append_to_commit));
+                }
+            }
+            self.flush_committed_batch(&mut batch);
+        } else {
+            for (index, entry, append_to_commit) in entries {
+                let event = Event::Committed {
+                    index,
+                    entry,
+                    append_to_commit,
+                };
+                self.events.push_back(event);
+            }
+        }
+        if self.options.event_queue_overflow_policy == EventQueueOverflowPolicy::Coalesce
+            && self.is_event_queue_congested()
         {
-            let event = Event::Committed { index, entry };
-            self.events.push_back(event);
+            self.coalesce_committed_events();
         }
         if new_tail.index >= self.log().head().index {
             // 「ローカルログの終端よりも先の地点のスナップショット」をインストールした後、
             // そのスナップショットのロードが行われるまでの間には、上の条件が`false`になる可能性がある.
             track!(self.history.record_consumed(new_tail.index))?;
         }
+        if self.catching_up
+            && self.history.consumed_tail().index == self.history.committed_tail().index
+        {
+            self.catching_up = false;
+            self.push_event(Event::CaughtUp {
+                through: new_tail.index,
+            });
+        }
+        Ok(())
+    }
+    fn record_append_times(&mut self, suffix: &LogSuffix) {
+        let now = if let Some(now) = self.io.now() {
+            now
+        } else {
+            return;
+        };
+
+        // 上書きされたエントリの追記時刻は破棄する
+        while matches!(self.append_times.back(), Some(&(i, _)) if suffix.head.index <= i) {
+            self.append_times.pop_back();
+        }
+        for i in 0..suffix.entries.len() {
+            self.append_times.push_back((suffix.head.index + i, now));
+        }
+    }
+    fn take_append_to_commit(&mut self, index: LogIndex, now: Option<Instant>) -> Option<Duration> {
+        while matches!(self.append_times.front(), Some(&(i, _)) if i < index) {
+            self.append_times.pop_front();
+        }
+        match self.append_times.front() {
+            Some(&(i, appended)) if i == index => {
+                self.append_times.pop_front();
+                now.map(|now| now.saturating_duration_since(appended))
+            }
+            _ => None,
+        }
+    }
+    fn retain_entries_for_debug(&mut self, suffix: &LogSuffix) {
+        let retain = self.options.debug_retain_entries;
+        if retain == 0 {
+            return;
+        }
+        let skip = suffix.entries.len().saturating_sub(retain);
+        for (i, e) in suffix.entries.iter().enumerate().skip(skip) {
+            self.retained_entries
+                .push_back((suffix.head.index + i, e.clone()));
+        }
+        while self.retained_entries.len() > retain {
+            self.retained_entries.pop_front();
+        }
+    }
+    fn coalesce_committed_events(&mut self) {
+        let mut events = VecDeque::with_capacity(self.events.len());
+        let mut dropped = 0;
+        let mut committed_tail = None;
+        for event in mem::take(&mut self.events) {
+            match event {
+                Event::Committed {
+                    index, ref entry, ..
+                } if !matches!(entry, LogEntry::Config { .. }) => {
+                    dropped += 1;
+                    committed_tail = Some(LogIndex::new(index.as_u64() + 1));
+                }
+                _ => {
+                    if let Some(committed_tail) = committed_tail.take() {
+                        events.push_back(Event::EventsDropped { count: dropped });
+                        events.push_back(Event::CommitAdvanced { committed_tail });
+                        dropped = 0;
+                    }
+                    events.push_back(event);
+                }
+            }
+        }
+        if let Some(committed_tail) = committed_tail {
+            events.push_back(Event::EventsDropped { count: dropped });
+            events.push_back(Event::CommitAdvanced { committed_tail });
+        }
+        self.events = events;
+    }
+    fn flush_committed_batch(&mut self, batch: &mut Vec<(LogIndex, LogEntry, Option<Duration>)>) {
+        if !batch.is_empty() {
+            let append_to_commit = batch.iter().filter_map(|&(_, _, d)| d).max();
+            let entries = mem::take(batch)
+                .into_iter()
+                .map(|(index, entry, _)| (index, entry))
+                .collect();
+            self.events.push_back(Event::CommittedBatch {
+                entries,
+                append_to_commit,
+            });
+        }
+    }
+    /// `position`がローカルのコミット済み領域内を指しており、かつ、その`term`が一致しない場合には`true`を返す.
+    fn is_conflicting_with_committed(&self, position: LogPosition) -> bool {
+        if self.history.committed_tail().index < position.index {
+            return false;
+        }
+        matches!(
+            self.history.get_record(position.index),
+            Some(r) if r.head.prev_term != position.prev_term
+        )
+    }
+    fn handle_apply_progress(&mut self) {
+        self.apply_progress_tick = self.ticks;
+        self.apply_stalled = false;
+        self.check_apply_backlog();
+    }
+    fn check_apply_stall(&mut self) {
+        // 適用待ちが無い間は、停滞の計測は開始されない
+        if self.apply_backlog() == 0 {
+            self.apply_progress_tick = self.ticks;
+            self.apply_stalled = false;
+            return;
+        }
+
+        let threshold = self.options.apply_stall_threshold_ticks;
+        let ticks = self.ticks_since_apply_progress();
+        if threshold > 0 && ticks >= threshold && !self.apply_stalled {
+            self.apply_stalled = true;
+            let backlog = self.apply_backlog();
+            self.push_event(Event::ApplyStalled { ticks, backlog });
+        }
+    }
+    fn check_apply_backlog(&mut self) {
+        let threshold = self.options.apply_backlog_threshold;
+        if threshold == 0 {
+            return;
+        }
+        let backlog = self.apply_backlog();
+        let is_high = backlog > threshold;
+        if is_high && !self.apply_backlog_high {
+            self.push_event(Event::ApplyBacklogHigh { backlog });
+        }
+        self.apply_backlog_high = is_high;
+    }
+    fn start_candidacy(&mut self) {
+        self.metrics.transit_to_candidate_total.increment();
+        self.counters.elections_started += 1;
+        let new_ballot = Ballot {
+            term: (self.local_node.ballot.term.as_u64() + 1).into(),
+            voted_for: self.local_node.id.clone(),
+        };
+        self.set_ballot(new_ballot);
+        self.set_role(Role::Candidate);
+    }
+    fn check_consumption_progress(&mut self) {
+        let consumed = self.history.consumed_tail().index;
+        if consumed < self.history.committed_tail().index {
+            self.consumption_lagging = true;
+        } else if self.consumption_lagging {
+            self.consumption_lagging = false;
+            self.push_event(Event::FullyConsumed { at: consumed });
+        }
+    }
+    fn is_newer_leader_message(&self, message: &Message) -> bool {
+        matches!(*message, Message::AppendEntriesCall(_))
+            && message.header().term > self.local_node.ballot.term
+    }
+    fn notify_config_changed(&mut self, from_index: LogIndex, diff: ConfigDiff) {
+        let promoted = diff.promoted_learners.clone();
+        let demoted = diff.demoted_voters.clone();
+        self.push_event(Event::ConfigChanged { from_index, diff });
+        if self.options.config_change_detail_events {
+            for node in promoted {
+                self.push_event(Event::NodePromoted { node });
+            }
+            for node in demoted {
+                self.push_event(Event::NodeDemoted { node });
+            }
+        }
+    }
+    fn notify_joint_consensus_transition(&mut self, was_joint: bool) {
+        let is_joint = self.joint_config().is_some();
+        if !was_joint && is_joint {
+            self.push_event(Event::JointConsensusEntered);
+        } else if was_joint && !is_joint {
+            self.push_event(Event::JointConsensusExited);
+        }
+    }
+    fn handle_pre_vote_call(&mut self, call: &RequestVoteCall) {
+        // 以下の全てを満たす場合にのみ承認する:
+        // - 依頼者が次に立候補する際の`term`が、ローカルの`term`よりも大きい
+        // - 依頼者のログが、ローカルログ以上に新しい
+        // - ローカルノードがリーダではなく、かつ、最後にリーダと通信してから選挙のタイムアウトに達している
+        //
+        // 最後の条件により、ネットワーク分断から復帰したノードが、健全なリーダを妨害することを防ぐ.
+        let is_leader_alive = match self.local_node.role {
+            Role::Leader => true,
+            Role::Follower => self.leader_contacted_since_timeout,
+            Role::Candidate => false,
+        };
+        let granted = !is_leader_alive
+            && call.header.term > self.local_node.ballot.term
+            && call.log_tail.is_newer_or_equal_than(self.history.tail());
+        self.rpc_callee(&call.header).reply_pre_vote(granted);
+    }
+    fn is_within_leader_lease(&self) -> bool {
+        let lease_ticks = self.options.leader_lease_ticks;
+        matches!(self.last_leader_contact_tick, Some(t) if self.ticks < t + lease_ticks)
+    }
+    fn handle_timer_error(&mut self, e: Error) -> Result<()> {
+        if self.timer_errors >= self.options.max_timer_retries {
+            return Err(track!(e));
+        }
+        self.timer_errors += 1;
+        let attempt = self.timer_errors;
+        let reason = e.to_string();
+        self.push_event(Event::TimerError { attempt, reason });
+        Ok(())
+    }
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.check_apply_stall();
+
+        let interval = self.options.status_event_interval_ticks;
+        if self.ticks.checked_rem(interval) == Some(0) {
+            let state = self.debug_state();
+            self.push_event(Event::Status(state));
+        }
+    }
+    fn buffer_unread_message(&mut self, message: Message) -> Result<()> {
+        track_assert!(
+            self.unread_message.is_none(),
+            ErrorKind::InconsistentState,
+            "Unread message is not consumed: unread={:?}, new={:?}",
+            self.unread_message,
+            message
+        );
+        self.unread_message = Some(message);
         Ok(())
     }
+    /// メッセージを送信する.
+    fn send_message(&mut self, message: Message) {
+        match message {
+            Message::RequestVoteCall(_) => self.counters.request_vote_sent += 1,
+            Message::AppendEntriesCall(_) => self.counters.append_entries_sent += 1,
+            _ => {}
+        }
+        self.io.send_message(message);
+    }
+    /// ブロードキャスト用のメッセージを送信する.
+    ///
+    /// 送信数の上限に達している場合には、送信は保留される.
+    /// 同じ宛先に対して既に保留中のメッセージがある場合には、それが新しいメッセージで置き換えられる.
+    /// (欠落したログエントリは、フォロワーからの応答を受けて、通常のログ同期処理によって補われる)
+    fn send_broadcast_message(&mut self, message: Message) {
+        let limit = self.options.max_broadcast_sends_per_run;
+        if limit == 0 || self.broadcast_sends < limit {
+            self.broadcast_sends += 1;
+            self.send_message(message);
+            return;
+        }
+
+        let destination = &message.header().destination;
+        if let Some(m) = self
+            .deferred_messages
+            .iter_mut()
+            .find(|m| m.header().destination == *destination)
+        {
+            *m = message;
+        } else {
+            self.deferred_messages.push_back(message);
+        }
+    }
+    fn flush_deferred_messages(&mut self) {
+        let limit = self.options.max_broadcast_sends_per_run;
+        while limit == 0 || self.broadcast_sends < limit {
+            if let Some(message) = self.deferred_messages.pop_front() {
+                self.broadcast_sends += 1;
+                self.send_message(message);
+            } else {
+                break;
+            }
+        }
+    }
+    // NOTE:
+    // 以前の役割の下で積まれたイベント(e.g., `Event::Committed`)は、役割の遷移後も破棄せずに、
+    // そのままの順番で利用者に通知する必要がある.
+    fn resume_snapshot_transfer(&mut self, chunk: &SnapshotChunk) {
+        let is_resumable = matches!(
+            self.resumable_snapshot,
+            Some(ref p) if p.tail == chunk.tail && chunk.offset <= p.saved_bytes
+        );
+        if is_resumable {
+            // 再開に使用できるのは一度だけ (以後は、保存済みのバイト数が変わってしまうため)
+            let progress = self.resumable_snapshot.take().expect("Never fails");
+            self.snapshot_transfer = Some(SnapshotTransfer::resume(progress));
+        }
+    }
+    fn poll_snapshot_transfer(&mut self) -> Result<()> {
+        loop {
+            let transfer = if let Some(ref mut t) = self.snapshot_transfer {
+                t
+            } else {
+                return Ok(());
+            };
+            if let Async::NotReady = track!(transfer.saving.poll())? {
+                return Ok(());
+            }
+            transfer.saving = None;
+
+            let chunk = if let Some(chunk) = transfer.pending.pop_front() {
+                chunk
+            } else {
+                return Ok(());
+            };
+            if !self.io.supports_snapshot_chunks() {
+                // チャンク単位では保存できないので、最後のチャンクまで連結してから保存する
+                transfer.buffer.extend(chunk.data);
+                if chunk.done {
+                    let prefix = LogPrefix {
+                        tail: transfer.summary.tail,
+                        config: transfer.summary.config.clone(),
+                        snapshot: mem::take(&mut transfer.buffer),
+                    };
+                    self.snapshot_transfer = None;
+                    self.install_snapshot = Some(InstallSnapshot::new(self, prefix, None));
+                    return Ok(());
+                }
+                continue;
+            }
+            if chunk.done {
+                // 最後のチャンクの保存完了をもって、インストールが完了する
+                let summary = transfer.summary.clone();
+                let snapshot_bytes = transfer.received_bytes as usize;
+                self.snapshot_transfer = None;
+                let future = self.io.save_snapshot_chunk(chunk);
+                self.install_snapshot = Some(InstallSnapshot {
+                    future,
+                    summary,
+                    snapshot_bytes,
+                    callback: None,
+                });
+                return Ok(());
+            }
+            transfer.saving = Some(self.io.save_snapshot_chunk(chunk));
+        }
+    }
     fn set_role(&mut self, new_role: Role) {
         if self.local_node.role != new_role {
+            // 以前の役割で保留していたブロードキャストは不要
+            self.deferred_messages.clear();
+            self.broadcast_priority.clear();
+            self.quiescent = false;
+            self.quiescent_missed_timeouts = 0;
+            self.snapshot_transfer = None;
             self.local_node.role = new_role;
             self.events.push_back(Event::RoleChanged { new_role });
+            self.detect_role_flapping();
+        }
+        if new_role == Role::Candidate {
+            self.set_leader(None);
+        }
+    }
+    fn set_leader(&mut self, leader: Option<NodeId>) {
+        if self.leader == leader {
+            return;
+        }
+        match leader {
+            Some(ref leader) => self.push_event(Event::LeaderElected {
+                leader: leader.clone(),
+                term: self.local_node.ballot.term,
+            }),
+            None => self.push_event(Event::LeaderLost),
+        }
+        self.leader = leader;
+    }
+    fn detect_role_flapping(&mut self) {
+        let threshold = self.options.role_flapping_threshold;
+        if threshold == 0 || self.is_role_flapping_backoff() {
+            return;
+        }
+
+        let window = self.options.role_flapping_window_ticks;
+        self.role_changes.push_back(self.ticks);
+        while let Some(&tick) = self.role_changes.front() {
+            if self.ticks <= tick + window {
+                break;
+            }
+            self.role_changes.pop_front();
+        }
+        if self.role_changes.len() > threshold {
+            let count = self.role_changes.len();
+            self.role_changes.clear();
+            self.role_flapping_backoff_until =
+                Some(self.ticks + self.options.role_flapping_backoff_ticks);
+            self.push_event(Event::RoleFlappingDetected { count });
         }
     }
     fn is_following_sender(&self, message: &Message) -> bool {
@@ -438,6 +2161,7 @@ where
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum HandleMessageResult<IO: Io> {
     Handled(Option<RoleState<IO>>),
     Unhandled(Message),
@@ -449,37 +2173,126 @@ struct SnapshotSummary {
     config: ClusterConfig,
 }
 
+/// 分割して転送されているスナップショットの受信状況.
+///
+/// 受信したチャンク群は、`Io::save_snapshot_chunk`を用いて一つずつ順番に保存される.
+/// ただし`Io::supports_snapshot_chunks`が`false`の場合には、メモリ上で連結された後に、
+/// `Io::save_log_prefix`を用いてまとめて保存される.
+struct SnapshotTransfer<IO: Io> {
+    summary: SnapshotSummary,
+
+    // 受信済みのバイト数 (i.e., 次に受け付けるチャンクの`offset`).
+    received_bytes: u64,
+
+    // 再起動前に保存済みだったバイト数 (再開された転送以外では`0`).
+    resumed_bytes: u64,
+
+    // 保存中のチャンク.
+    saving: Option<IO::SaveLog>,
+
+    // 保存待ちのチャンク群.
+    pending: VecDeque<SnapshotChunk>,
+
+    // 連結済みのチャンク群 (`Io`がチャンク単位での保存に対応していない場合にのみ使用される).
+    buffer: Vec<u8>,
+}
+impl<IO: Io> SnapshotTransfer<IO> {
+    fn new(chunk: &SnapshotChunk) -> Self {
+        SnapshotTransfer {
+            summary: SnapshotSummary {
+                tail: chunk.tail,
+                config: chunk.config.clone(),
+            },
+            received_bytes: 0,
+            resumed_bytes: 0,
+            saving: None,
+            pending: VecDeque::new(),
+            buffer: Vec::new(),
+        }
+    }
+    fn resume(progress: SnapshotTransferProgress) -> Self {
+        SnapshotTransfer {
+            summary: SnapshotSummary {
+                tail: progress.tail,
+                config: progress.config,
+            },
+            received_bytes: progress.saved_bytes,
+            resumed_bytes: progress.saved_bytes,
+            saving: None,
+            pending: VecDeque::new(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// スナップショットのインストール処理.
+///
+/// スナップショットの本体(バイト列)の所有権は、生成時に`Io::save_log_prefix`に渡してしまい、
+/// この構造体自体はその大きさのみを保持する.
+/// (巨大なスナップショットをメモリ上に二重に保持してしまうことを避けるため)
 struct InstallSnapshot<IO: Io> {
     future: IO::SaveLog,
     summary: SnapshotSummary,
+    snapshot_bytes: usize,
+    callback: Option<SnapshotInstallCallback>,
 }
 impl<IO: Io> InstallSnapshot<IO> {
-    pub fn new(common: &mut Common<IO>, prefix: LogPrefix) -> Self {
+    pub fn new(
+        common: &mut Common<IO>,
+        prefix: LogPrefix,
+        callback: Option<SnapshotInstallCallback>,
+    ) -> Self {
         let summary = SnapshotSummary {
             tail: prefix.tail,
             config: prefix.config.clone(),
         };
+        let snapshot_bytes = prefix.snapshot.len();
         let future = common.io.save_log_prefix(prefix);
-        InstallSnapshot { future, summary }
+        InstallSnapshot {
+            future,
+            summary,
+            snapshot_bytes,
+            callback,
+        }
     }
 }
 impl<IO: Io> Future for InstallSnapshot<IO> {
     type Item = SnapshotSummary;
     type Error = Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        Ok(track!(self.future.poll())?.map(|()| self.summary.clone()))
+        match track!(self.future.poll()) {
+            Err(e) => {
+                if let Some(callback) = self.callback.take() {
+                    callback(Err(e.clone()));
+                }
+                Err(e)
+            }
+            Ok(a) => Ok(a.map(|()| self.summary.clone())),
+        }
     }
 }
-
-#[cfg(test)]
+impl<IO: Io> Drop for InstallSnapshot<IO> {
+    fn drop(&mut self) {
+        // 完了前に破棄された場合には、キャンセル扱いとする
+        if let Some(callback) = self.callback.take() {
+            let e = ErrorKind::Other.cause("Snapshot installation was cancelled");
+            callback(Err(track!(Error::from(e))));
+        }
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
     use prometrics::metrics::MetricBuilder;
+    use std::time::Duration;
     use trackable::result::TestResult;
 
-    use crate::log::{LogEntry, LogPrefix};
+    use crate::cluster::ClusterMembers;
+    use crate::log::{AuditError, LogPrefix};
+    use crate::message::AppendEntriesCall;
     use crate::metrics::NodeStateMetrics;
-    use crate::test_util::tests::TestIoBuilder;
+    use crate::test_util::tests::{ManualClock, TestIo, TestIoBuilder};
 
     #[test]
     fn is_snapshot_installing_works() -> TestResult {
@@ -491,7 +2304,7 @@ mod tests {
             .add_member("node3".into())
             .finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
         let prefix = LogPrefix {
             tail: LogPosition::default(),
             config: cluster,
@@ -505,6 +2318,92 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn config_version_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        assert_eq!(common.config_version(), (LogIndex::new(0), cluster.clone()));
+
+        // 位置`1`に構成変更エントリを追記する
+        let term = Term::new(1);
+        let new_config = cluster.add_learner("node4".into());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term },
+                LogEntry::Config {
+                    term,
+                    config: new_config.clone(),
+                },
+                LogEntry::Noop { term },
+            ],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        assert_eq!(common.config(), &new_config);
+
+        // コミットされるまでは、以前の構成のまま
+        track!(common.handle_log_committed(LogIndex::new(1)))?;
+        assert_eq!(common.config_version(), (LogIndex::new(0), cluster));
+        track!(common.handle_log_committed(LogIndex::new(3)))?;
+        assert_eq!(
+            common.config_version(),
+            (LogIndex::new(1), new_config.clone())
+        );
+
+        // `Term`が変わっても、構成が変わらなければ位置は同じ
+        let suffix = LogSuffix {
+            head: suffix.tail(),
+            entries: vec![LogEntry::Noop { term: Term::new(2) }],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(LogIndex::new(4)))?;
+        assert_eq!(common.config_version(), (LogIndex::new(1), new_config));
+        Ok(())
+    }
+
+    #[test]
+    fn loaded_snapshot_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        assert_eq!(common.loaded_snapshot(), None);
+
+        let prefix = |index: u64, config: &ClusterConfig| LogPrefix {
+            tail: LogPosition {
+                prev_term: Term::new(0),
+                index: LogIndex::new(index),
+            },
+            config: config.clone(),
+            snapshot: vec![1, 2, 3],
+        };
+        track!(common.handle_log_snapshot_loaded(prefix(3, &cluster)))?;
+        let meta = track_assert_some!(common.loaded_snapshot(), ErrorKind::Other);
+        assert_eq!(meta, prefix(3, &cluster).meta());
+        assert_eq!(meta.tail.index, LogIndex::new(3));
+        assert_eq!(meta.config, cluster);
+
+        // より新しいスナップショットが読み込まれると、そちらに置き換わる
+        let new_config = cluster.add_learner("node4".into());
+        track!(common.handle_log_snapshot_loaded(prefix(5, &new_config)))?;
+        let meta = track_assert_some!(common.loaded_snapshot(), ErrorKind::Other);
+        assert_eq!(meta.tail.index, LogIndex::new(5));
+        assert_eq!(meta.config, new_config);
+        Ok(())
+    }
+
     #[test]
     fn is_focusing_on_installing_snapshot_works() -> TestResult {
         let node_id: NodeId = "node1".into();
@@ -515,7 +2414,7 @@ mod tests {
             .add_member("node3".into())
             .finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
         let prev_term = Term::new(0);
         let node_prefix = LogPrefix {
             tail: LogPosition {
@@ -576,4 +2475,2732 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn committed_batch_splits_at_config_entry() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            batch_committed_events: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster.clone(), options, metrics);
+
+        let term = Term::new(1);
+        let command = |n: u8| LogEntry::Command {
+            term,
+            command: vec![n],
+        };
+        let config = LogEntry::Config {
+            term,
+            config: cluster,
+        };
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![command(0), command(1), config.clone(), command(2)],
+        };
+        common.handle_log_appended(&suffix)?;
+        common.handle_log_committed(suffix.tail().index)?;
+        common.handle_committed(suffix)?;
+
+        assert_eq!(
+            common.next_event(),
+            Some(Event::CommittedBatch {
+                entries: vec![
+                    (LogIndex::new(0), command(0)),
+                    (LogIndex::new(1), command(1))
+                ],
+                append_to_commit: None,
+            })
+        );
+        assert_eq!(
+            common.next_event(),
+            Some(Event::Committed {
+                index: LogIndex::new(2),
+                entry: config,
+                append_to_commit: None,
+            })
+        );
+        assert_eq!(
+            common.next_event(),
+            Some(Event::CommittedBatch {
+                entries: vec![(LogIndex::new(3), command(2))],
+                append_to_commit: None,
+            })
+        );
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn role_flapping_backoff_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            role_flapping_threshold: 3,
+            role_flapping_window_ticks: 5,
+            role_flapping_backoff_ticks: 5,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id.clone(), io, cluster, options, metrics);
+
+        // フォロワーと立候補者の間を行き来させる
+        for _ in 0..2 {
+            common.transit_to_candidate();
+            common.transit_to_follower(node_id.clone(), None);
+        }
+        assert!(common.is_role_flapping_backoff());
+
+        let mut detected = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::RoleFlappingDetected { count } = e {
+                detected.push(count);
+            }
+        }
+        assert_eq!(detected, vec![4]);
+
+        // 猶予期間が過ぎれば、再び立候補可能になる
+        common.ticks += 5;
+        assert!(!common.is_role_flapping_backoff());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_read_token_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        let token = common.read_token();
+        assert!(common.validate_read_token(token));
+
+        // より新しい`Term`で発行されたトークンは拒否される
+        let token = ReadToken {
+            term: Term::new(common.term().as_u64() + 1),
+            read_index: token.read_index,
+        };
+        assert!(!common.validate_read_token(token));
+        Ok(())
+    }
+
+    #[test]
+    fn double_buffering_unread_message_is_rejected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        let append = |sender: &str, term: u64| -> Message {
+            AppendEntriesCall {
+                header: MessageHeader {
+                    sender: sender.into(),
+                    destination: node_id.clone(),
+                    seq_no: SequenceNumber::new(0),
+                    term: Term::new(term),
+                },
+                committed_log_tail: LogIndex::new(0),
+                suffix: LogSuffix::default(),
+                quiescent: false,
+            }
+            .into()
+        };
+
+        // "node2"を新リーダとして認識し、メッセージがバッファされる
+        let result = track!(common.handle_message(append("node2", 1)))?;
+        assert!(matches!(result, HandleMessageResult::Handled(Some(_))));
+
+        // バッファ済みのメッセージを消費せずに、別のリーダからのメッセージを処理するとエラーになる
+        let result = common.handle_message(append("node3", 1));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InconsistentState)
+        );
+
+        // 最初にバッファされたメッセージは失われていない
+        let message = track!(common.try_recv_message())?;
+        assert_eq!(
+            message.map(|m| m.header().sender.clone()),
+            Some("node2".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn peek_event_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        assert_eq!(common.peek_event(), None);
+
+        common.transit_to_candidate();
+        let peeked = common.peek_event().map(|e| format!("{:?}", e));
+        assert!(peeked.is_some());
+        assert_eq!(common.next_event().map(|e| format!("{:?}", e)), peeked);
+        Ok(())
+    }
+
+    #[test]
+    fn commit_advanced_fires_at_stride_boundaries() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            commit_notify_stride: 4,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        let term = Term::new(1);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: (0..10)
+                .map(|n| LogEntry::Command {
+                    term,
+                    command: vec![n],
+                })
+                .collect(),
+        };
+        common.handle_log_appended(&suffix)?;
+        for i in 1..=10 {
+            common.handle_log_committed(LogIndex::new(i))?;
+        }
+
+        let mut advanced = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::CommitAdvanced { committed_tail } = e {
+                advanced.push(committed_tail.as_u64());
+            }
+        }
+        assert_eq!(advanced, vec![4, 8]);
+        Ok(())
+    }
+
+    #[test]
+    fn higher_term_is_persisted_without_vote() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let ballots = io.ballots.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+
+        // 立候補中に、より大きな`term`を持つ投票結果を受信する (投票先は変わらない)
+        let message = RequestVoteReply {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id.clone(),
+                seq_no: SequenceNumber::new(0),
+                term: Term::new(5),
+            },
+            voted: false,
+            repeated: false,
+            pre_vote: false,
+        }
+        .into();
+        let result = track!(common.handle_message(message))?;
+        assert!(matches!(
+            result,
+            HandleMessageResult::Handled(Some(RoleState::Follower(_)))
+        ));
+
+        // ここでクラッシュしたとしても、新しい`term`は既に永続化されている
+        let persisted = ballots.lock().expect("Never fails").pop();
+        assert_eq!(
+            persisted,
+            Some(Ballot {
+                term: Term::new(5),
+                voted_for: node_id
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn leadership_age_ticks_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        common.transit_to_candidate();
+        let leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => unreachable!(),
+        };
+        assert_eq!(leader.leadership_age_ticks(&common), 0);
+
+        common.ticks += 3;
+        assert_eq!(leader.leadership_age_ticks(&common), 3);
+
+        // 再選出されると、リセットされる
+        common.transit_to_candidate();
+        let leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => unreachable!(),
+        };
+        assert_eq!(leader.leadership_age_ticks(&common), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn io_status_reflects_snapshot_installation() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        assert_eq!(
+            common.io_status(),
+            IoStatus {
+                loading_committed_from: None,
+                installing_snapshot_to: None,
+            }
+        );
+
+        let tail = LogPosition {
+            prev_term: Term::new(1),
+            index: LogIndex::new(3),
+        };
+        let prefix = LogPrefix {
+            tail,
+            config: cluster,
+            snapshot: Vec::default(),
+        };
+        common.install_snapshot(prefix)?;
+        assert_eq!(common.io_status().installing_snapshot_to, Some(tail));
+        assert_eq!(common.io_status().loading_committed_from, None);
+        Ok(())
+    }
+
+    #[test]
+    fn installing_snapshot_bytes_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        assert_eq!(common.installing_snapshot_bytes(), 0);
+
+        let prefix = LogPrefix {
+            tail: LogPosition::default(),
+            config: cluster,
+            snapshot: vec![0; 1024],
+        };
+        common.install_snapshot(prefix)?;
+        assert_eq!(common.installing_snapshot_bytes(), 1024);
+
+        // 保存が完了すれば、インストール中の扱いではなくなる
+        track!(common.run_once())?;
+        assert!(!common.is_snapshot_installing());
+        assert_eq!(common.installing_snapshot_bytes(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn append_conflicting_with_committed_is_rejected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: Term::new(0) }; 3],
+        };
+        common.handle_log_appended(&suffix)?;
+        common.handle_log_committed(suffix.tail().index)?;
+
+        // コミット済み領域と矛盾する`prev`を持つ、同じ`term`の`AppendEntriesCall`
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node3".into(),
+                destination: node_id.clone(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix {
+                head: LogPosition {
+                    prev_term: Term::new(7),
+                    index: LogIndex::new(2),
+                },
+                entries: Vec::new(),
+            },
+            quiescent: false,
+        }
+        .into();
+        let result = track!(common.handle_message(message))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+
+        // フォロー先は変わらず、拒否の応答が返される
+        assert_eq!(common.local_node().ballot.voted_for, node_id);
+        assert!(track!(common.try_recv_message())?.is_none());
+        let sent = sent_messages.lock().expect("Never fails");
+        assert!(matches!(
+            sent.last(),
+            Some(Message::RequestVoteReply(RequestVoteReply {
+                voted: false,
+                ..
+            }))
+        ));
+        Ok(())
+    }
+
+    fn commit_commands(common: &mut Common<TestIo>, n: u8) -> TestResult {
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: (0..n)
+                .map(|n| LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![n],
+                })
+                .collect(),
+        };
+        common.handle_log_appended(&suffix)?;
+        common.handle_log_committed(suffix.tail().index)?;
+        common.handle_committed(suffix)?;
+        Ok(())
+    }
+
+    #[test]
+    fn skip_noop_committed_events_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            skip_noop_committed_events: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: Term::new(0) },
+                LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![0],
+                },
+            ],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(suffix.tail().index))?;
+        track!(common.handle_committed(suffix))?;
+
+        let mut committed = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::Committed { index, .. } = e {
+                committed.push(index);
+            }
+        }
+        assert_eq!(committed, [LogIndex::new(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn entry_size_histogram_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            track_entry_sizes: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        assert_eq!(common.entry_size_histogram().count, 0);
+
+        let sizes = [0, 64, 65, 1000, 5000, 2 << 20];
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: sizes
+                .iter()
+                .map(|&n| LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![0; n],
+                })
+                .chain(Some(LogEntry::Noop { term: Term::new(0) }))
+                .collect(),
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(suffix.tail().index))?;
+        track!(common.handle_committed(suffix))?;
+
+        // コマンド以外のエントリは記録されない
+        let histogram = common.entry_size_histogram();
+        assert_eq!(histogram.count, 6);
+        assert_eq!(histogram.max, 2 << 20);
+        assert_eq!(
+            histogram.buckets,
+            [
+                (64, 2),
+                (256, 1),
+                (1024, 1),
+                (4096, 0),
+                (16384, 1),
+                (65536, 0),
+                (262_144, 0),
+                (1_048_576, 0),
+                (u64::MAX, 1)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn append_to_commit_latency_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let clock = io.clock.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let suffix = |head: u64, n: u8| LogSuffix {
+            head: LogPosition {
+                prev_term: Term::new(0),
+                index: LogIndex::new(head),
+            },
+            entries: (0..n)
+                .map(|n| LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![n],
+                })
+                .collect(),
+        };
+        let latencies = |common: &mut Common<TestIo>| {
+            let mut latencies = Vec::new();
+            while let Some(e) = common.next_event() {
+                if let Event::Committed {
+                    append_to_commit, ..
+                } = e
+                {
+                    latencies.push(append_to_commit);
+                }
+            }
+            latencies
+        };
+
+        // `Io::now`が時刻を返さない場合には、計時されない
+        track!(common.handle_log_appended(&suffix(0, 1)))?;
+        track!(common.handle_log_committed(LogIndex::new(1)))?;
+        track!(common.handle_committed(suffix(0, 1)))?;
+        assert_eq!(latencies(&mut common), [None]);
+
+        // 追記からコミットの通知までの所要時間が付与される
+        let t0 = Instant::now();
+        *clock.lock().expect("Never fails") = Some(t0);
+        track!(common.handle_log_appended(&suffix(1, 2)))?;
+        *clock.lock().expect("Never fails") = Some(t0 + Duration::from_millis(5));
+        track!(common.handle_log_appended(&suffix(3, 1)))?;
+        *clock.lock().expect("Never fails") = Some(t0 + Duration::from_millis(8));
+        track!(common.handle_log_committed(LogIndex::new(4)))?;
+        track!(common.handle_committed(suffix(1, 3)))?;
+        assert_eq!(
+            latencies(&mut common),
+            [
+                Some(Duration::from_millis(8)),
+                Some(Duration::from_millis(8)),
+                Some(Duration::from_millis(3))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn committed_batch_reports_append_to_commit_latency() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let clock = io.clock.clone();
+        let options = ReplicatedLogOptions {
+            batch_committed_events: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let suffix = |head: u64, n: u8| LogSuffix {
+            head: LogPosition {
+                prev_term: Term::new(0),
+                index: LogIndex::new(head),
+            },
+            entries: (0..n)
+                .map(|n| LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![n],
+                })
+                .collect(),
+        };
+
+        // `Io::now`が時刻を返さない場合には、計時されない
+        track!(common.handle_log_appended(&suffix(0, 1)))?;
+        track!(common.handle_log_committed(LogIndex::new(1)))?;
+        track!(common.handle_committed(suffix(0, 1)))?;
+        assert!(matches!(
+            common.next_event(),
+            Some(Event::CommittedBatch {
+                append_to_commit: None,
+                ..
+            })
+        ));
+
+        // バッチ内で最も長く待たされたエントリの所要時間が付与される
+        let t0 = Instant::now();
+        *clock.lock().expect("Never fails") = Some(t0);
+        track!(common.handle_log_appended(&suffix(1, 2)))?;
+        *clock.lock().expect("Never fails") = Some(t0 + Duration::from_millis(5));
+        track!(common.handle_log_appended(&suffix(3, 1)))?;
+        *clock.lock().expect("Never fails") = Some(t0 + Duration::from_millis(8));
+        track!(common.handle_log_committed(LogIndex::new(4)))?;
+        track!(common.handle_committed(suffix(1, 3)))?;
+        match common.next_event() {
+            Some(Event::CommittedBatch {
+                entries,
+                append_to_commit,
+            }) => {
+                assert_eq!(entries.len(), 3);
+                assert_eq!(append_to_commit, Some(Duration::from_millis(8)));
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_committed_log_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let logs = io.logs.clone();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        commit_commands(&mut common, 5)?;
+
+        let stored = |corrupted: Option<usize>| {
+            let entries = (0..5)
+                .map(|i| LogEntry::Command {
+                    term: Term::new(if Some(i) == corrupted { 2 } else { 0 }),
+                    command: vec![i as u8],
+                })
+                .collect();
+            let suffix = LogSuffix {
+                head: LogPosition::default(),
+                entries,
+            };
+            let mut logs = logs.lock().expect("Never fails");
+            logs.insert((LogIndex::new(0), Some(LogIndex::new(5))), suffix.into());
+        };
+
+        // 正常なログ
+        stored(None);
+        let report = track!(track!(common.audit_committed_log())?.wait())?;
+        assert_eq!(report.entries_checked, 5);
+        assert_eq!(report.errors, Vec::new());
+
+        // 3番目のエントリの`Term`が壊れている
+        stored(Some(3));
+        let report = track!(track!(common.audit_committed_log())?.wait())?;
+        assert_eq!(report.entries_checked, 5);
+        assert_eq!(
+            report.errors,
+            vec![
+                (
+                    LogIndex::new(3),
+                    AuditError::TermMismatch {
+                        expected: Term::new(0),
+                        actual: Term::new(2),
+                    }
+                ),
+                (
+                    LogIndex::new(4),
+                    AuditError::TermRegression {
+                        prev: Term::new(2),
+                        actual: Term::new(0),
+                    }
+                ),
+            ]
+        );
+
+        // 監査によってローカルノードの状態は変わらない
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(5));
+        Ok(())
+    }
+
+    #[test]
+    fn event_queue_backpressure_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            event_queue_high_water_mark: 3,
+            event_queue_overflow_policy: EventQueueOverflowPolicy::Backpressure,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        commit_commands(&mut common, 5)?;
+        assert!(common.is_event_queue_congested());
+
+        // イベントは一つも失われない
+        let mut count = 0;
+        while let Some(e) = common.next_event() {
+            assert!(matches!(e, Event::Committed { .. }));
+            count += 1;
+        }
+        assert_eq!(count, 5);
+        assert!(!common.is_event_queue_congested());
+        Ok(())
+    }
+
+    #[test]
+    fn event_queue_coalesce_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            event_queue_high_water_mark: 3,
+            event_queue_overflow_policy: EventQueueOverflowPolicy::Coalesce,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        commit_commands(&mut common, 5)?;
+        assert_eq!(common.next_event(), Some(Event::EventsDropped { count: 5 }));
+        assert_eq!(
+            common.next_event(),
+            Some(Event::CommitAdvanced {
+                committed_tail: LogIndex::new(5)
+            })
+        );
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn last_vote_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+
+        common.transit_to_candidate();
+        assert_eq!(common.last_vote(), (Term::new(1), None));
+
+        // "node2"からの投票依頼に応じる
+        let message = RequestVoteCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id,
+                seq_no: SequenceNumber::new(0),
+                term: Term::new(3),
+            },
+            log_tail: common.log().tail(),
+            pre_vote: false,
+            transfer: false,
+        }
+        .into();
+        track!(common.handle_message(message))?;
+        assert_eq!(common.last_vote(), (Term::new(3), Some("node2".into())));
+        Ok(())
+    }
+
+    #[test]
+    fn is_log_consistent_with_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        let local_terms = [1, 1, 2, 2];
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: local_terms
+                .iter()
+                .map(|&t| LogEntry::Noop { term: Term::new(t) })
+                .collect(),
+        };
+        common.handle_log_appended(&suffix)?;
+
+        let check = |peer_terms: &[u64]| {
+            let peer_tail = LogPosition {
+                prev_term: Term::new(*peer_terms.last().unwrap_or(&0)),
+                index: LogIndex::new(peer_terms.len() as u64),
+            };
+            common.is_log_consistent_with(peer_tail, |i| {
+                peer_terms.get(i.as_u64() as usize).map(|&t| Term::new(t))
+            })
+        };
+        assert_eq!(check(&[1, 1, 2, 2]), ConsistencyResult::Consistent);
+        assert_eq!(check(&[1, 1, 2, 2, 3, 3]), ConsistencyResult::Behind);
+        assert_eq!(check(&[1, 1]), ConsistencyResult::Ahead);
+        assert_eq!(
+            check(&[1, 1, 3, 3]),
+            ConsistencyResult::Diverged {
+                at: LogIndex::new(2)
+            }
+        );
+        assert_eq!(
+            check(&[1, 3, 3, 3, 3]),
+            ConsistencyResult::Diverged {
+                at: LogIndex::new(1)
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn status_event_fires_at_interval() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            status_event_interval_ticks: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        let mut fired = Vec::new();
+        for _ in 0..7 {
+            common.tick();
+            while let Some(e) = common.next_event() {
+                if let Event::Status(state) = e {
+                    assert_eq!(state, common.debug_state());
+                    fired.push(common.ticks());
+                }
+            }
+        }
+        assert_eq!(fired, vec![3, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn load_log_clamps_end_to_tail() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let mut handle = io.handle();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: Term::new(0) }; 3],
+        };
+        common.handle_log_appended(&suffix)?;
+        let tail = common.log().tail().index;
+        handle.append_log(LogIndex::new(0), tail, Log::Suffix(suffix.clone()));
+
+        // 終端より後ろの`end`は、終端に切り詰められる
+        let mut future = track!(common.load_log(LogIndex::new(0), Some(tail + 5)))?;
+        match track!(future.poll())? {
+            Async::Ready(Log::Suffix(loaded)) => assert_eq!(loaded.entries, suffix.entries),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // 切り詰めの結果、範囲が逆転する場合にはエラー
+        let result = common.load_log(tail + 1, Some(tail + 2));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_log_keeps_start_below_head() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let mut handle = io.handle();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+
+        let head = LogPosition {
+            prev_term: Term::new(0),
+            index: LogIndex::new(3),
+        };
+        common.handle_log_snapshot_installed(head, cluster.clone())?;
+        let prefix = LogPrefix {
+            tail: head,
+            config: cluster,
+            snapshot: vec![1, 2, 3],
+        };
+        handle.append_log(LogIndex::new(0), head.index, Log::Prefix(prefix.clone()));
+
+        // 先頭より前の`start`はそのまま渡され、スナップショットが取得される
+        let mut future = track!(common.load_log(LogIndex::new(0), Some(head.index)))?;
+        match track!(future.poll())? {
+            Async::Ready(Log::Prefix(loaded)) => assert_eq!(loaded.snapshot, prefix.snapshot),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn last_leader_contact_is_updated_by_append_from_leader() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        common.transit_to_follower("node2".into(), None);
+        assert_eq!(common.last_leader_contact_tick(), None);
+
+        common.ticks = 4;
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id,
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix::default(),
+            quiescent: false,
+        }
+        .into();
+        let result = track!(common.handle_message(message))?;
+        assert!(matches!(result, HandleMessageResult::Unhandled(_)));
+        assert_eq!(common.last_leader_contact_tick(), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn reset_election_timeout_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let clock = ManualClock::new();
+        io.manual_clock = Some(clock.clone());
+        io.follower_timeout = Duration::from_secs(3600);
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        common.set_timeout(Role::Leader);
+        assert!(track!(common.poll_timeout())?.is_not_ready());
+        clock.advance(Duration::from_millis(5));
+        assert!(track!(common.poll_timeout())?.is_ready());
+
+        // 新しいタイムアウトが設定されるので、すぐには発火しない
+        common.reset_election_timeout();
+        assert!(track!(common.poll_timeout())?.is_not_ready());
+        Ok(())
+    }
+
+    #[test]
+    fn timer_errors_are_retried() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let clock = ManualClock::new();
+        io.manual_clock = Some(clock.clone());
+        io.failing_timeouts = 1;
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+
+        // 最初のタイムアウトは失敗するが、作り直されたタイムアウトは正常に発火する
+        assert!(track!(common.poll_timeout())?.is_not_ready());
+        clock.advance(Duration::from_millis(10));
+        assert!(track!(common.poll_timeout())?.is_ready());
+        let mut errors = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::TimerError { attempt, .. } = e {
+                errors.push(attempt);
+            }
+        }
+        assert_eq!(errors, vec![1]);
+
+        // 再試行が許可されていない場合には、エラーがそのまま返される
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        io.failing_timeouts = 1;
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            max_timer_retries: 0,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        assert!(common.poll_timeout().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn vote_request_within_leader_lease_is_rejected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            leader_lease_ticks: 2,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id.clone(), io, cluster, options, metrics);
+        common.transit_to_follower("node2".into(), None);
+
+        // リーダ("node2")からのメッセージを受信する
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id.clone(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix::default(),
+            quiescent: false,
+        }
+        .into();
+        track!(common.handle_message(message))?;
+
+        let vote_request = |common: &Common<TestIo>| -> Message {
+            RequestVoteCall {
+                header: MessageHeader {
+                    sender: "node3".into(),
+                    destination: node_id.clone(),
+                    seq_no: SequenceNumber::new(0),
+                    term: Term::new(common.term().as_u64() + 1),
+                },
+                log_tail: common.log().tail(),
+                pre_vote: false,
+                transfer: false,
+            }
+            .into()
+        };
+
+        // リース期間中の投票依頼は拒否される
+        common.ticks += 1;
+        let result = track!(common.handle_message(vote_request(&common)))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+        assert!(matches!(
+            sent_messages.lock().expect("Never fails").last(),
+            Some(Message::RequestVoteReply(RequestVoteReply {
+                voted: false,
+                ..
+            }))
+        ));
+        assert_eq!(common.local_node().ballot.voted_for, "node2".into());
+
+        // リース期間後の投票依頼には応じる
+        common.ticks += 1;
+        let result = track!(common.handle_message(vote_request(&common)))?;
+        assert!(matches!(
+            result,
+            HandleMessageResult::Handled(Some(RoleState::Follower(_)))
+        ));
+        assert_eq!(common.local_node().ballot.voted_for, "node3".into());
+        Ok(())
+    }
+
+    #[test]
+    fn joint_config_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        assert_eq!(common.joint_config(), None);
+
+        let new_members = ["node1", "node2"].iter().map(|&n| n.into()).collect();
+        let catch_up = cluster.start_config_change(new_members);
+        let joint = catch_up.to_next_state();
+        let stable = joint.to_next_state();
+        let term = Term::new(0);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Config {
+                    term,
+                    config: catch_up,
+                },
+                LogEntry::Config {
+                    term,
+                    config: joint.clone(),
+                },
+            ],
+        };
+        common.handle_log_appended(&suffix)?;
+        assert_eq!(
+            common.joint_config(),
+            Some((
+                ClusterConfig::new(joint.old_members().clone()),
+                ClusterConfig::new(joint.new_members().clone())
+            ))
+        );
+        assert_eq!(common.next_event(), Some(Event::JointConsensusEntered));
+
+        let suffix = LogSuffix {
+            head: suffix.tail(),
+            entries: vec![LogEntry::Config {
+                term,
+                config: stable,
+            }],
+        };
+        common.handle_log_appended(&suffix)?;
+        assert_eq!(common.joint_config(), None);
+        assert_eq!(common.next_event(), Some(Event::JointConsensusExited));
+        Ok(())
+    }
+
+    #[test]
+    fn committed_config_change_emits_events_in_order() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let members =
+            |names: &[&str]| -> ClusterMembers { names.iter().map(|&n| n.into()).collect() };
+        let cluster = io
+            .cluster
+            .clone()
+            .with_learners(members(&["node4", "node5"]));
+        let options = ReplicatedLogOptions {
+            config_change_detail_events: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        // "node4"と"node5"を昇格させ、"node3"をラーナーに降格させる
+        let catch_up = ClusterConfig::with_state(
+            members(&["node1", "node2", "node4", "node5"]),
+            members(&["node1", "node2", "node3"]),
+            ClusterState::CatchUp,
+        );
+        let joint = catch_up.to_next_state();
+        let stable = joint.to_next_state().with_learners(members(&["node3"]));
+        let term = Term::new(0);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Config {
+                    term,
+                    config: catch_up,
+                },
+                LogEntry::Config {
+                    term,
+                    config: joint,
+                },
+                LogEntry::Config {
+                    term,
+                    config: stable,
+                },
+            ],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        while common.next_event().is_some() {}
+
+        // 一度にコミットされた構成変更は、一つの要約イベントと、それに続く詳細なイベント群となる
+        track!(common.handle_log_committed(suffix.tail().index))?;
+        let diff = match common.next_event() {
+            Some(Event::ConfigChanged { from_index, diff }) => {
+                assert_eq!(from_index, LogIndex::new(2));
+                diff
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        };
+        assert_eq!(diff.promoted_learners, members(&["node4", "node5"]));
+        assert_eq!(diff.demoted_voters, members(&["node3"]));
+        assert_eq!(
+            common.next_event(),
+            Some(Event::NodePromoted {
+                node: "node4".into()
+            })
+        );
+        assert_eq!(
+            common.next_event(),
+            Some(Event::NodePromoted {
+                node: "node5".into()
+            })
+        );
+        assert_eq!(
+            common.next_event(),
+            Some(Event::NodeDemoted {
+                node: "node3".into()
+            })
+        );
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn catch_up_emits_bulk_event() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            catch_up_threshold: 100,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: Term::new(0) }; 1000],
+        };
+        common.handle_log_appended(&suffix)?;
+        common.handle_log_committed(suffix.tail().index)?;
+
+        // 二回に分けて読み込まれる
+        let mut first = suffix.clone();
+        first.truncate(LogIndex::new(600))?;
+        let mut second = suffix;
+        second.skip_to(LogIndex::new(600))?;
+        common.handle_committed(first)?;
+        assert_eq!(common.next_event(), None);
+        common.handle_committed(second)?;
+        assert_eq!(
+            common.next_event(),
+            Some(Event::CaughtUp {
+                through: LogIndex::new(1000)
+            })
+        );
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn replace_io_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+        common.transit_to_candidate();
+
+        // アイドル状態なら差し替え可能
+        let new_io = TestIoBuilder::new().add_member("node1".into()).finish();
+        let sent_messages = new_io.sent_messages.clone();
+        let mut common = track!(common.replace_io(new_io))?;
+        assert_eq!(common.term(), Term::new(1));
+        common.rpc_caller().broadcast_request_vote();
+        assert!(sent_messages.lock().expect("Never fails").is_empty()); // 単一ノード構成なので自分宛て
+        assert!(track!(common.try_recv_message())?.is_some());
+
+        // スナップショットのインストール中は差し替え不可
+        let prefix = LogPrefix {
+            tail: LogPosition::default(),
+            config: cluster,
+            snapshot: Vec::new(),
+        };
+        common.install_snapshot(prefix)?;
+        let new_io = TestIoBuilder::new().add_member("node1".into()).finish();
+        let result = common.replace_io(new_io);
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Busy));
+        Ok(())
+    }
+
+    #[test]
+    fn set_ballot_rejects_term_regression() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        common.set_ballot(Ballot {
+            term: Term::new(5),
+            voted_for: node_id.clone(),
+        });
+        while common.next_event().is_some() {}
+
+        common.set_ballot(Ballot {
+            term: Term::new(3),
+            voted_for: node_id,
+        });
+        assert_eq!(common.term(), Term::new(5));
+        assert_eq!(
+            common.next_event(),
+            Some(Event::TermRegressionRejected {
+                attempted: Term::new(3)
+            })
+        );
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn install_snapshot_callback_works() -> TestResult {
+        use std::sync::{Arc, Mutex};
+
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+
+        // 完了時には、新しい先頭位置を引数として呼び出される
+        let result = Arc::new(Mutex::new(None));
+        let result0 = result.clone();
+        let tail = LogPosition::default();
+        let prefix = LogPrefix {
+            tail,
+            config: cluster.clone(),
+            snapshot: Vec::new(),
+        };
+        let callback = Box::new(move |r: Result<LogPosition>| {
+            *result0.lock().expect("Never fails") = Some(r.map_err(|e| *e.kind()));
+        });
+        common.install_snapshot_with_callback(prefix, Some(callback))?;
+        assert!(result.lock().expect("Never fails").is_none());
+        track!(common.run_once())?;
+        assert_eq!(*result.lock().expect("Never fails"), Some(Ok(tail)));
+
+        // 完了前に破棄された場合には、エラーを引数として呼び出される
+        let result = Arc::new(Mutex::new(None));
+        let result0 = result.clone();
+        let prefix = LogPrefix {
+            tail,
+            config: cluster,
+            snapshot: Vec::new(),
+        };
+        let callback = Box::new(move |r: Result<LogPosition>| {
+            *result0.lock().expect("Never fails") = Some(r.map_err(|e| *e.kind()));
+        });
+        common.install_snapshot_with_callback(prefix, Some(callback))?;
+        drop(common);
+        assert_eq!(
+            *result.lock().expect("Never fails"),
+            Some(Err(ErrorKind::Other))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn newer_snapshot_supersedes_installing_one() -> TestResult {
+        use std::sync::{Arc, Mutex};
+
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let logs = io.logs.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+
+        let prefix = |index: u64| LogPrefix {
+            tail: LogPosition {
+                prev_term: Term::new(1),
+                index: LogIndex::new(index),
+            },
+            config: cluster.clone(),
+            snapshot: vec![0; index as usize],
+        };
+        // インストール後の、コミット済み領域の読み込み用
+        logs.lock().expect("Never fails").insert(
+            (LogIndex::new(0), Some(LogIndex::new(10))),
+            prefix(10).into(),
+        );
+
+        let result = Arc::new(Mutex::new(None));
+        let result0 = result.clone();
+        let callback = Box::new(move |r: Result<LogPosition>| {
+            *result0.lock().expect("Never fails") = Some(r.map_err(|e| *e.kind()));
+        });
+        track!(common.install_snapshot_with_callback(prefix(5), Some(callback)))?;
+
+        // インストール中のもの以前の地点のスナップショットは拒否される
+        assert!(!common.is_snapshot_supersedable(LogIndex::new(5)));
+        let e = track_assert_some!(common.install_snapshot(prefix(5)).err(), ErrorKind::Other);
+        assert_eq!(*e.kind(), ErrorKind::Busy);
+
+        // より新しい地点のスナップショットは、インストール中のものを置き換える
+        assert!(common.is_snapshot_supersedable(LogIndex::new(10)));
+        track!(common.install_snapshot(prefix(10)))?;
+        assert_eq!(
+            *result.lock().expect("Never fails"),
+            Some(Err(ErrorKind::Other))
+        );
+        assert_eq!(common.installing_snapshot_bytes(), 10);
+        assert_eq!(
+            common.next_event(),
+            Some(Event::SnapshotSuperseded {
+                old_head: prefix(5).tail,
+                new_head: prefix(10).tail,
+            })
+        );
+
+        track!(common.run_once())?;
+        assert!(!common.is_snapshot_installing());
+        assert_eq!(common.log().head(), prefix(10).tail);
+        assert_eq!(
+            common.next_event(),
+            Some(Event::SnapshotInstalled {
+                new_head: prefix(10).tail
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn has_contacted_quorum_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        assert!(!common.has_contacted_quorum());
+
+        // 他のノードと通信できないまま立候補しても`false`のまま
+        common.transit_to_candidate();
+        assert!(!common.has_contacted_quorum());
+
+        // 選挙に勝利すれば`true`となり、その後に役割が変わっても維持される
+        common.transit_to_leader();
+        assert!(common.has_contacted_quorum());
+        common.transit_to_follower(node_id, None);
+        assert!(common.has_contacted_quorum());
+        Ok(())
+    }
+
+    #[test]
+    fn leader_steps_down_on_removal_by_newer_leader() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+        common.transit_to_leader();
+        assert!(!common.is_removed());
+
+        // ローカルノードを含まない新構成の下で選出されたリーダ(旧構成には含まれない)からのメッセージ
+        let term = Term::new(common.term().as_u64() + 1);
+        let new_config = ClusterConfig::new(
+            vec!["node2".into(), "node3".into(), "node4".into()]
+                .into_iter()
+                .collect(),
+        );
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Config {
+                term,
+                config: new_config,
+            }],
+        };
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node4".into(),
+                destination: node_id,
+                seq_no: SequenceNumber::new(0),
+                term,
+            },
+            committed_log_tail: suffix.tail().index,
+            suffix: suffix.clone(),
+            quiescent: false,
+        };
+
+        // 不明なノードからのメッセージだが、より新しい`term`のリーダなので、フォロワーに遷移する
+        let next = match track!(common.handle_message(message.into()))? {
+            HandleMessageResult::Handled(Some(next)) => next,
+            _ => panic!("Not handled"),
+        };
+        let mut follower = match next {
+            RoleState::Follower(follower) => follower,
+            _ => panic!("Not a follower"),
+        };
+        assert_eq!(common.local_node().role, Role::Follower);
+        assert!(common.unread_message.is_some());
+
+        // 新構成がコミットされると、取り除かれたことが通知される
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(suffix.tail().index))?;
+        assert!(common.is_removed());
+        let mut removed = 0;
+        while let Some(e) = common.next_event() {
+            if e == Event::SelfRemoved {
+                removed += 1;
+            }
+        }
+        assert_eq!(removed, 1);
+
+        // 以後は、タイムアウトを迎えても立候補しない
+        assert!(track!(follower.handle_timeout(&mut common))?.is_none());
+        assert_eq!(common.local_node().role, Role::Follower);
+        Ok(())
+    }
+
+    #[test]
+    fn witness_never_becomes_candidate() -> TestResult {
+        let node_id: NodeId = "node3".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member(node_id.clone())
+            .finish();
+        let cluster = io
+            .cluster
+            .clone()
+            .with_witnesses(vec![node_id.clone()].into_iter().collect());
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        assert!(common.is_witness());
+
+        let term = common.term();
+        match common.transit_to_candidate() {
+            RoleState::Follower(_) => {}
+            _ => panic!("A witness must not become a candidate"),
+        }
+        assert_eq!(common.term(), term);
+        assert!(common.trigger_election().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn removed_node_replies_in_passive_mode() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            removed_node_policy: RemovedNodePolicy::Passive,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id.clone(), io, cluster.clone(), options, metrics);
+        common.transit_to_follower("node2".into(), None);
+
+        // ローカルノードを含まない新構成がコミットされる
+        let new_config =
+            ClusterConfig::new(vec!["node2".into(), "node3".into()].into_iter().collect());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Config {
+                term: common.term(),
+                config: new_config,
+            }],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(suffix.tail().index))?;
+        assert!(common.is_removed());
+        let tail = common.log().tail();
+
+        // 取り除かれたことを把握していないリーダからの追記依頼には、追従せずに`removed`を応答する
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id.clone(),
+                seq_no: SequenceNumber::new(3),
+                term: common.term(),
+            },
+            committed_log_tail: tail.index,
+            suffix: LogSuffix {
+                head: tail,
+                entries: vec![LogEntry::Noop {
+                    term: common.term(),
+                }],
+            },
+            quiescent: false,
+        };
+        let result = track!(common.handle_message(message.into()))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+        assert!(common.unread_message.is_none());
+        assert_eq!(common.log().tail(), tail);
+        let reply = match sent_messages.lock().expect("Never fails").pop() {
+            Some(Message::AppendEntriesReply(reply)) => reply,
+            m => panic!("Unexpected message: {:?}", m),
+        };
+        assert!(reply.removed);
+        assert_eq!(reply.header.destination, "node2".into());
+        assert_eq!(reply.header.seq_no, SequenceNumber::new(3));
+
+        // 取り除かれたノードのIDは再利用できないので、再び追加する構成変更エントリにも追従しない
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id.clone(),
+                seq_no: SequenceNumber::new(4),
+                term: common.term(),
+            },
+            committed_log_tail: tail.index,
+            suffix: LogSuffix {
+                head: tail,
+                entries: vec![LogEntry::Config {
+                    term: common.term(),
+                    config: cluster.clone(),
+                }],
+            },
+            quiescent: false,
+        };
+        let result = track!(common.handle_message(message.into()))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+        assert_eq!(common.log().tail(), tail);
+        assert!(common.is_removed());
+        let reply = match sent_messages.lock().expect("Never fails").pop() {
+            Some(Message::AppendEntriesReply(reply)) => reply,
+            m => panic!("Unexpected message: {:?}", m),
+        };
+        assert!(reply.removed);
+
+        // 投票の依頼は(より新しい`term`であっても)破棄される
+        let term = common.term();
+        let message = RequestVoteCall {
+            header: MessageHeader {
+                sender: "node3".into(),
+                destination: node_id,
+                seq_no: SequenceNumber::new(0),
+                term: Term::new(term.as_u64() + 1),
+            },
+            log_tail: common.log().tail(),
+            pre_vote: false,
+            transfer: false,
+        };
+        let result = track!(common.handle_message(message.into()))?;
+        assert!(matches!(result, HandleMessageResult::Handled(None)));
+        assert_eq!(common.term(), term);
+        assert!(sent_messages.lock().expect("Never fails").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn leader_elected_events_work() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        let append = |sender: &str, term: u64| -> Message {
+            AppendEntriesCall {
+                header: MessageHeader {
+                    sender: sender.into(),
+                    destination: node_id.clone(),
+                    seq_no: SequenceNumber::new(0),
+                    term: Term::new(term),
+                },
+                committed_log_tail: LogIndex::new(0),
+                suffix: LogSuffix::default(),
+                quiescent: false,
+            }
+            .into()
+        };
+        let leader_events = |common: &mut Common<TestIo>| {
+            let mut events = Vec::new();
+            while let Some(e) = common.next_event() {
+                match e {
+                    Event::TermChanged { .. } | Event::LeaderElected { .. } | Event::LeaderLost => {
+                        events.push(e)
+                    }
+                    _ => {}
+                }
+            }
+            events
+        };
+
+        // 新しい`term`のリーダからの`AppendEntriesCall`を受信: `TermChanged`の後に`LeaderElected`
+        track!(common.handle_message(append("node2", 1)))?;
+        let ballot = common.local_node().ballot.clone();
+        assert_eq!(
+            leader_events(&mut common),
+            vec![
+                Event::TermChanged { new_ballot: ballot },
+                Event::LeaderElected {
+                    leader: "node2".into(),
+                    term: Term::new(1)
+                },
+            ]
+        );
+
+        // 同じリーダからの後続のメッセージでは発行されない
+        let _ = common.unread_message.take();
+        track!(common.handle_message(append("node2", 1)))?;
+        assert_eq!(leader_events(&mut common), Vec::new());
+
+        // 立候補すると、リーダを見失う
+        common.transit_to_candidate();
+        let ballot = common.local_node().ballot.clone();
+        assert_eq!(
+            leader_events(&mut common),
+            vec![Event::TermChanged { new_ballot: ballot }, Event::LeaderLost]
+        );
+
+        // 選挙に勝利すると、自身がリーダとなる
+        common.transit_to_leader();
+        assert_eq!(
+            leader_events(&mut common),
+            vec![Event::LeaderElected {
+                leader: node_id.clone(),
+                term: Term::new(2)
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn leadership_status_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(
+            node_id.clone(),
+            io,
+            cluster.clone(),
+            Default::default(),
+            metrics,
+        );
+        assert_eq!(
+            common.leadership_status(),
+            LeadershipStatus::Follower { leader: None }
+        );
+
+        // 投票しただけの候補者は、まだリーダとして報告されない
+        let vote = MessageHeader {
+            sender: "node3".into(),
+            destination: node_id.clone(),
+            seq_no: SequenceNumber::new(0),
+            term: common.term(),
+        };
+        common.transit_to_follower("node3".into(), Some(vote));
+        assert_eq!(
+            common.leadership_status(),
+            LeadershipStatus::Follower { leader: None }
+        );
+
+        common.transit_to_follower("node2".into(), None);
+        assert_eq!(
+            common.leadership_status(),
+            LeadershipStatus::Follower {
+                leader: Some("node2".into())
+            }
+        );
+
+        common.transit_to_candidate();
+        let term = common.term();
+        assert_eq!(
+            common.leadership_status(),
+            LeadershipStatus::Candidate { term }
+        );
+
+        common.transit_to_leader();
+        assert_eq!(common.leadership_status(), LeadershipStatus::Leader);
+
+        // フォロワーがスナップショットをインストール中
+        common.transit_to_follower("node2".into(), None);
+        let prefix = LogPrefix {
+            tail: LogPosition::default(),
+            config: cluster,
+            snapshot: Vec::new(),
+        };
+        track!(common.install_snapshot(prefix))?;
+        assert_eq!(
+            common.leadership_status(),
+            LeadershipStatus::InstallingSnapshot
+        );
+
+        // クラスタ構成から除外されたノード
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member("node2".into()).finish();
+        let cluster = io.cluster.clone();
+        let common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        assert_eq!(common.leadership_status(), LeadershipStatus::Removed);
+        Ok(())
+    }
+
+    #[test]
+    fn bootstrap_times_out_if_ballot_save_stalls() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        io.stall_ballot_saves = true;
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            bootstrap_deadline_ticks: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        // 投票状況の保存が完了しないので、いつまでもリーダになれない
+        let mut candidate = Candidate::new(&mut common);
+        track!(candidate.run_once(&mut common))?;
+        assert!(track!(common.try_recv_message())?.is_none());
+
+        common.ticks = 2;
+        assert!(track!(candidate.handle_timeout(&mut common))?.is_some());
+
+        common.ticks = 3;
+        let result = candidate.handle_timeout(&mut common);
+        assert_eq!(result.err().map(|e| *e.kind()), Some(ErrorKind::Timeout));
+        Ok(())
+    }
+
+    #[test]
+    fn ordered_persistence_survives_failed_ballot_save() -> TestResult {
+        let run = |ordered_persistence: bool| -> Result<Vec<LogIndex>> {
+            let node_id: NodeId = "node1".into();
+            let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+            let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+            io.fail_ballot_saves = true;
+            let saved_log_suffixes = io.saved_log_suffixes.clone();
+            let cluster = io.cluster.clone();
+            let options = ReplicatedLogOptions {
+                ordered_persistence,
+                ..Default::default()
+            };
+            let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+            // 新しい`term`の投票状況を保存し、続けて、その`term`のエントリを追記する
+            common.transit_to_candidate();
+            let mut ballot = common.save_ballot();
+            let suffix = LogSuffix {
+                head: common.log().tail(),
+                entries: vec![LogEntry::Noop {
+                    term: common.term(),
+                }],
+            };
+            let mut log = common.save_log_suffix(&suffix);
+
+            // 二つの書き込みの間で障害が発生する
+            assert!(ballot.poll_with(&mut common).is_err());
+            let _ = log.poll_with(&mut common);
+            let _ = common.run_once();
+
+            let suffixes = saved_log_suffixes.lock().expect("Never fails").clone();
+            Ok(suffixes)
+        };
+
+        // 順序付けが有効な場合には、投票状況の保存に失敗したら、ログの追記は発行されない
+        assert_eq!(track!(run(true))?, Vec::new());
+
+        // 無効な場合には、投票状況の保存の完了を待たずに、ログの追記が発行される
+        assert_eq!(track!(run(false))?, vec![LogIndex::new(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn apply_backlog_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            apply_backlog_threshold: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        assert_eq!(common.apply_backlog(), 0);
+
+        // 適用されないままコミットが進むと、適用待ちの数が増えていく
+        commit_commands(&mut common, 5)?;
+        assert_eq!(common.apply_backlog(), 5);
+        let mut backlogs = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::ApplyBacklogHigh { backlog } = e {
+                backlogs.push(backlog);
+            }
+        }
+        assert_eq!(backlogs, vec![5]);
+
+        // 適用が進めば、適用待ちの数は減る
+        common.set_applied_index(LogIndex::new(4))?;
+        assert_eq!(common.apply_backlog(), 1);
+
+        // コミット済み領域を超えた位置は指定できない
+        let result = common.set_applied_index(LogIndex::new(6));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_stall_detection_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            apply_stall_threshold_ticks: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        fn stalls(common: &mut Common<TestIo>) -> Vec<(u64, u64)> {
+            let mut stalls = Vec::new();
+            while let Some(e) = common.next_event() {
+                if let Event::ApplyStalled { ticks, backlog } = e {
+                    stalls.push((ticks, backlog));
+                }
+            }
+            stalls
+        }
+
+        // 適用待ちが無い間は、停滞とはみなされない
+        for _ in 0..5 {
+            common.tick();
+        }
+        assert_eq!(common.ticks_since_apply_progress(), 0);
+        assert!(stalls(&mut common).is_empty());
+
+        // 適用が通知されないまま閾値に達すると、イベントが一度だけ発行される
+        commit_commands(&mut common, 5)?;
+        for _ in 0..2 {
+            common.tick();
+        }
+        assert_eq!(common.ticks_since_apply_progress(), 2);
+        assert!(stalls(&mut common).is_empty());
+        common.tick();
+        assert_eq!(stalls(&mut common), vec![(3, 5)]);
+        common.tick();
+        assert_eq!(common.ticks_since_apply_progress(), 4);
+        assert!(stalls(&mut common).is_empty());
+
+        // 適用が進めば、計測はやり直しとなる
+        common.set_applied_index(LogIndex::new(2))?;
+        assert_eq!(common.ticks_since_apply_progress(), 0);
+        for _ in 0..3 {
+            common.tick();
+        }
+        assert_eq!(stalls(&mut common), vec![(3, 3)]);
+
+        // 全て適用されれば、停滞は解消される
+        common.set_applied_index(LogIndex::new(5))?;
+        common.tick();
+        assert_eq!(common.ticks_since_apply_progress(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn transport_stats_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let inbound = io.inbound_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+
+        // 受信側: 二つのメッセージが未処理
+        let header = MessageHeader {
+            sender: "node2".into(),
+            destination: "node1".into(),
+            seq_no: SequenceNumber::new(0),
+            term: Term::new(0),
+        };
+        for _ in 0..2 {
+            let reply = RequestVoteReply {
+                header: header.clone(),
+                voted: false,
+                repeated: false,
+                pre_vote: false,
+            };
+            inbound.lock().expect("Never fails").push_back(reply.into());
+        }
+
+        // 送信側: 各ピア宛てに一つずつ
+        common.rpc_caller().broadcast_request_vote();
+        assert_eq!(
+            common.transport_stats(),
+            TransportStats {
+                inbound_depth: 2,
+                outbound_depths: vec![("node2".into(), 1), ("node3".into(), 1)],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn counters_work() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let inbound = io.inbound_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        assert_eq!(common.counters(), NodeCounters::default());
+
+        // 立候補して、各ピアに投票を依頼する
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let call = RequestVoteCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            log_tail: LogPosition::default(),
+            pre_vote: false,
+            transfer: false,
+        };
+        inbound.lock().expect("Never fails").push_back(call.into());
+
+        // "node3"は、再送された投票依頼に重複した投票を返す
+        let repeated = RequestVoteReply {
+            header: MessageHeader {
+                sender: "node3".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            voted: true,
+            repeated: true,
+            pre_vote: false,
+        };
+        inbound
+            .lock()
+            .expect("Never fails")
+            .push_back(repeated.into());
+
+        // 自分自身への投票は、受信数には数えない
+        for _ in 0..3 {
+            assert!(track!(common.try_recv_message())?.is_some());
+        }
+
+        // 当選して、最初のエントリを複製・コミットする
+        common.transit_to_leader();
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop {
+                term: common.term(),
+            }],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        common.rpc_caller().broadcast_append_entries(suffix, &[]);
+        track!(common.handle_log_committed(LogIndex::new(1)))?;
+        assert_eq!(
+            common.counters(),
+            NodeCounters {
+                request_vote_sent: 2,
+                request_vote_received: 1,
+                repeated_votes_received: 1,
+                append_entries_sent: 2,
+                append_entries_received: 0,
+                elections_started: 1,
+                elections_won: 1,
+                snapshots_installed: 0,
+                log_entries_committed: 1,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn current_timeout_duration_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            election_timeout_jitter: Duration::from_millis(5),
+            election_timeout_jitter_seed: Some(0),
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+
+        // 役割毎のタイムアウト時間に、乱数で選ばれた揺らぎが加わった値が返される
+        let in_range = |d: Option<Duration>, base: u64, jitter: u64| {
+            let d = d.expect("TestIo reports the duration");
+            Duration::from_millis(base) <= d && d <= Duration::from_millis(base + jitter)
+        };
+        assert!(in_range(common.current_timeout_duration(), 10, 5));
+        let mut durations = std::collections::BTreeSet::new();
+        for _ in 0..10 {
+            common.set_timeout(Role::Follower);
+            let d = common.current_timeout_duration();
+            assert!(in_range(d, 10, 5), "{:?}", d);
+            durations.insert(d);
+        }
+        assert!(durations.len() > 1);
+
+        common.set_timeout(Role::Candidate);
+        assert!(in_range(common.current_timeout_duration(), 15, 5));
+
+        // リーダ用のタイムアウトには、揺らぎは加えられない
+        common.set_timeout(Role::Leader);
+        assert!(in_range(common.current_timeout_duration(), 5, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn injected_message_drops_work() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .drop_messages_matching(|m| matches!(m, Message::RequestVoteCall(_)))
+            .link_drop_probability("node3".into(), node_id.clone(), 1.0)
+            .finish();
+        let cluster = io.cluster.clone();
+        let inbound = io.inbound_messages.clone();
+        let dropped = io.dropped_messages.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+
+        let header = |sender: &str| MessageHeader {
+            sender: sender.into(),
+            destination: node_id.clone(),
+            seq_no: SequenceNumber::new(0),
+            term: Term::new(0),
+        };
+        let vote = RequestVoteCall {
+            header: header("node2"),
+            log_tail: LogPosition::default(),
+            pre_vote: false,
+            transfer: false,
+        };
+        let reply = |sender: &str| RequestVoteReply {
+            header: header(sender),
+            voted: true,
+            repeated: false,
+            pre_vote: false,
+        };
+        {
+            let mut inbound = inbound.lock().expect("Never fails");
+            inbound.push_back(vote.into());
+            inbound.push_back(reply("node3").into());
+            inbound.push_back(reply("node2").into());
+        }
+
+        // 条件に該当するメッセージと、"node3"からの経路のメッセージは破棄される
+        let message = track!(common.try_recv_message())?;
+        assert_eq!(
+            message.map(|m| m.header().sender.clone()),
+            Some("node2".into())
+        );
+        assert!(track!(common.try_recv_message())?.is_none());
+        assert_eq!(*dropped.lock().expect("Never fails"), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn can_serve_read_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        common.transit_to_follower("node2".into(), None);
+
+        // クライアントは、インデックス`3`までの書き込みを行っている
+        let token = SessionToken {
+            index: LogIndex::new(3),
+        };
+        commit_commands(&mut common, 5)?;
+
+        // コミット済みでも、適用が追い付くまでは読み込みに応じられない
+        assert!(!common.can_serve_read(&token));
+        common.set_applied_index(LogIndex::new(2))?;
+        assert!(!common.can_serve_read(&token));
+        common.set_applied_index(LogIndex::new(3))?;
+        assert!(common.can_serve_read(&token));
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_election_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(
+            node_id.clone(),
+            io,
+            cluster,
+            Default::default(),
+            metrics.clone(),
+        );
+        common.transit_to_follower("node2".into(), None);
+        let term = common.term();
+
+        let next = track!(common.trigger_election())?;
+        assert!(next.is_candidate());
+        assert_eq!(common.term().as_u64(), term.as_u64() + 1);
+        assert_eq!(common.local_node().role, Role::Candidate);
+
+        // 事前投票が有効な場合には、`term`を増やさずに事前投票から始める
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            pre_vote: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics.clone());
+        common.transit_to_follower("node2".into(), None);
+        let term = common.term();
+
+        let next = track!(common.trigger_election())?;
+        let mut candidate = match next {
+            RoleState::Candidate(c) => c,
+            _ => panic!(),
+        };
+        assert_eq!(common.term(), term);
+        track!(candidate.run_once(&mut common))?;
+        assert!(matches!(
+            sent_messages.lock().expect("Never fails").last(),
+            Some(Message::RequestVoteCall(RequestVoteCall {
+                pre_vote: true,
+                ..
+            }))
+        ));
+
+        // 投票権を持たないノードでは選挙を開始できない
+        let io = TestIoBuilder::new().add_member("node2".into()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new("node1".into(), io, cluster, Default::default(), metrics);
+        let result = common.trigger_election();
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn too_large_snapshot_is_rejected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            max_snapshot_size: 16,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster.clone(), options, metrics);
+
+        let prefix = LogPrefix {
+            tail: LogPosition::default(),
+            config: cluster.clone(),
+            snapshot: vec![0; 17],
+        };
+        let result = common.install_snapshot(prefix);
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert!(!common.is_snapshot_installing()); // 保存は開始されていない
+        assert_eq!(
+            common.next_event(),
+            Some(Event::SnapshotRejectedTooLarge { size: 17, max: 16 })
+        );
+
+        // 上限以下なら受け付けられる
+        let prefix = LogPrefix {
+            tail: LogPosition::default(),
+            config: cluster,
+            snapshot: vec![0; 16],
+        };
+        track!(common.install_snapshot(prefix))?;
+        assert!(common.is_snapshot_installing());
+        Ok(())
+    }
+
+    #[test]
+    fn leader_rejects_snapshot_install_beyond_committed_tail_by_default() -> TestResult {
+        let new_leader = |options: ReplicatedLogOptions| -> Result<Common<TestIo>> {
+            let node_id: NodeId = "node1".into();
+            let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+            let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+            let cluster = io.cluster.clone();
+            let mut common = Common::new(node_id, io, cluster, options, metrics);
+            common.transit_to_candidate();
+            common.transit_to_leader();
+            Ok(common)
+        };
+        let prefix = |common: &Common<TestIo>, index: u64| LogPrefix {
+            tail: LogPosition {
+                prev_term: Term::new(0),
+                index: LogIndex::new(index),
+            },
+            config: common.config().clone(),
+            snapshot: Vec::new(),
+        };
+
+        // コミット済みの地点までのスナップショット(i.e., リーダ自身のログの圧縮)は、デフォルトでも許可される
+        let mut common = new_leader(Default::default())?;
+        track!(common.install_snapshot(prefix(&common, 0)))?;
+        assert!(common.is_snapshot_installing());
+
+        // コミット済みの地点を超えるスナップショットは拒否される
+        let mut common = new_leader(Default::default())?;
+        let result = common.install_snapshot(prefix(&common, 1));
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert!(!common.is_snapshot_installing());
+
+        let options = ReplicatedLogOptions {
+            allow_snapshot_install_on_leader: true,
+            ..Default::default()
+        };
+        let mut common = new_leader(options)?;
+        track!(common.install_snapshot(prefix(&common, 1)))?;
+        assert!(common.is_snapshot_installing());
+        Ok(())
+    }
+
+    #[test]
+    fn events_are_preserved_across_role_transitions() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        commit_commands(&mut common, 3)?;
+
+        common.transit_to_candidate();
+        common.transit_to_follower("node2".into(), None);
+
+        let mut committed = Vec::new();
+        let mut role_changes = Vec::new();
+        while let Some(e) = common.next_event() {
+            match e {
+                Event::Committed { index, .. } => {
+                    assert!(role_changes.is_empty()); // 遷移前のイベントが先に通知される
+                    committed.push(index.as_u64());
+                }
+                Event::RoleChanged { new_role } => role_changes.push(new_role),
+                _ => {}
+            }
+        }
+        assert_eq!(committed, vec![0, 1, 2]);
+        assert_eq!(role_changes, vec![Role::Candidate, Role::Follower]);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_retain_entries_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            debug_retain_entries: 2,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        commit_commands(&mut common, 5)?;
+        common.set_applied_index(LogIndex::new(5))?;
+
+        // 適用済みでも、直近の二つのエントリは参照可能
+        let command = |i: u8| LogEntry::Command {
+            term: Term::new(0),
+            command: vec![i],
+        };
+        assert_eq!(common.peek_entry(LogIndex::new(2)), None);
+        assert_eq!(common.peek_entry(LogIndex::new(3)), Some(&command(3)));
+        assert_eq!(common.peek_entry(LogIndex::new(4)), Some(&command(4)));
+        assert_eq!(common.peek_entry(LogIndex::new(5)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn rpc_caller_returns_assigned_seq_no() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        let seq_nos = vec![
+            common.rpc_caller().broadcast_request_vote(),
+            common
+                .rpc_caller()
+                .broadcast_append_entries(LogSuffix::default(), &[]),
+            common
+                .rpc_caller()
+                .send_append_entries(&"node2".into(), LogSuffix::default()),
+        ];
+        assert!(seq_nos.windows(2).all(|w| w[0] < w[1]));
+
+        // 実際に送信されたメッセージのシーケンス番号と一致する
+        let sent = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .map(|m| m.header().seq_no)
+            .collect::<Vec<_>>();
+        assert_eq!(sent, seq_nos);
+        Ok(())
+    }
+
+    #[test]
+    fn prefetch_committed_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        // 未処理のコミット済みログ領域がない
+        assert!(!track!(common.prefetch_committed())?);
+        assert_eq!(common.io_status().loading_committed_from, None);
+
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: Term::new(0) }; 3],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(suffix.tail().index))?;
+
+        // 読み込みが開始される
+        assert!(track!(common.prefetch_committed())?);
+        assert_eq!(
+            common.io_status().loading_committed_from,
+            Some(LogIndex::new(0))
+        );
+
+        // 既に読み込み中
+        assert!(!track!(common.prefetch_committed())?);
+        Ok(())
+    }
+
+    #[test]
+    fn fully_consumed_event_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let logs = io.logs.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        let commit = |common: &mut Common<TestIo>, head: LogPosition, n: usize| {
+            let suffix = LogSuffix {
+                head,
+                entries: vec![LogEntry::Noop { term: Term::new(0) }; n],
+            };
+            let (start, end) = (suffix.head.index, suffix.tail().index);
+            logs.lock()
+                .expect("Never fails")
+                .insert((start, Some(end)), Log::Suffix(suffix.clone()));
+            common.handle_log_appended(&suffix)?;
+            common.handle_log_committed(end)
+        };
+        let fully_consumed = |common: &mut Common<TestIo>| {
+            let mut events = Vec::new();
+            while let Some(e) = common.next_event() {
+                events.push(e);
+            }
+            match events.last() {
+                Some(Event::FullyConsumed { at }) => Some(*at),
+                _ => None,
+            }
+        };
+
+        // 未処理のエントリがない状態では発行されない
+        track!(common.run_once())?;
+        assert_eq!(fully_consumed(&mut common), None);
+
+        // 溜まっていたコミット済みエントリの処理が完了した時点で発行される
+        track!(commit(&mut common, LogPosition::default(), 3))?;
+        track!(common.run_once())?;
+        assert_eq!(common.history.consumed_tail().index, LogIndex::new(3));
+        assert_eq!(fully_consumed(&mut common), Some(LogIndex::new(3)));
+
+        track!(common.run_once())?;
+        assert_eq!(fully_consumed(&mut common), None);
+
+        // 再度遅れた後に追い付いた場合にも発行される
+        let head = common.log().tail();
+        track!(commit(&mut common, head, 2))?;
+        track!(common.run_once())?;
+        assert_eq!(fully_consumed(&mut common), Some(LogIndex::new(5)));
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_snapshot_transfer_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let saved_chunks = io.saved_snapshot_chunks.clone();
+        let logs = io.logs.clone();
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 4,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster.clone(), options, metrics);
+        assert_eq!(common.snapshot_chunk_size(), 4);
+
+        // 送信側: スナップショットはチャンクサイズ毎に分割される
+        let tail = LogPosition {
+            prev_term: Term::new(0),
+            index: LogIndex::new(3),
+        };
+        let prefix = LogPrefix {
+            tail,
+            config: cluster,
+            snapshot: (0..10).collect(),
+        };
+        // インストール後の、コミット済み領域の読み込み用
+        logs.lock()
+            .expect("Never fails")
+            .insert((LogIndex::new(0), Some(tail.index)), prefix.clone().into());
+        common
+            .rpc_caller()
+            .send_install_snapshot(&"node2".into(), prefix);
+        let chunks = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .map(|m| match m {
+                Message::InstallSnapshotCall(m) => m.chunk.clone(),
+                _ => panic!("Unexpected message: {:?}", m),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.offset, c.data.len(), c.done))
+                .collect::<Vec<_>>(),
+            [(0, 4, false), (4, 4, false), (8, 2, true)]
+        );
+
+        // 受信側: 想定外のオフセットのチャンクを受け取ると、転送は破棄される
+        track!(common.receive_snapshot_chunk(chunks[0].clone()))?;
+        assert!(common.is_snapshot_installing());
+        track!(common.receive_snapshot_chunk(chunks[2].clone()))?;
+        assert!(!common.is_snapshot_installing());
+
+        // 最後のチャンクが届かないまま、保存が完了した転送は破棄できる
+        for chunk in &chunks[..2] {
+            track!(common.receive_snapshot_chunk(chunk.clone()))?;
+        }
+        assert_eq!(common.installing_snapshot_bytes(), 8);
+        track!(common.run_once())?;
+        assert!(common.is_snapshot_installing());
+        common.abandon_stalled_snapshot_transfer();
+        assert!(!common.is_snapshot_installing());
+
+        // 全てのチャンクを順番に受け取ると、最後のチャンクの保存完了時にインストールされる
+        for chunk in chunks {
+            track!(common.receive_snapshot_chunk(chunk))?;
+        }
+        track!(common.run_once())?;
+        assert!(!common.is_snapshot_installing());
+        assert_eq!(common.log().head(), tail);
+        assert_eq!(
+            *saved_chunks.lock().expect("Never fails"),
+            [(0, false), (4, false), (0, false), (4, false), (8, true)]
+        );
+        let mut installed = false;
+        while let Some(e) = common.next_event() {
+            if let Event::SnapshotInstalled { new_head } = e {
+                assert_eq!(new_head, tail);
+                installed = true;
+            }
+        }
+        assert!(installed);
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_snapshot_is_saved_as_whole_if_io_does_not_support_chunks() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        io.supports_snapshot_chunks = false;
+        let cluster = io.cluster.clone();
+        let saved_chunks = io.saved_snapshot_chunks.clone();
+        let saved_prefixes = io.saved_log_prefixes.clone();
+        let tail = LogPosition {
+            prev_term: Term::new(0),
+            index: LogIndex::new(3),
+        };
+        let prefix = LogPrefix {
+            tail,
+            config: cluster.clone(),
+            snapshot: (0..10).collect(),
+        };
+        io.logs
+            .lock()
+            .expect("Never fails")
+            .insert((LogIndex::new(0), Some(tail.index)), prefix.clone().into());
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        // チャンク群は連結されて、`save_log_prefix`でまとめて保存される
+        for chunk in prefix.clone().into_chunks(4) {
+            track!(common.receive_snapshot_chunk(chunk))?;
+        }
+        track!(common.run_once())?;
+        assert!(!common.is_snapshot_installing());
+        assert_eq!(common.log().head(), tail);
+        assert!(saved_chunks.lock().expect("Never fails").is_empty());
+        let saved_prefixes = saved_prefixes.lock().expect("Never fails");
+        assert_eq!(saved_prefixes.len(), 1);
+        assert_eq!(saved_prefixes[0].tail, tail);
+        assert_eq!(saved_prefixes[0].snapshot, prefix.snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_snapshot_transfer_resumes() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let transfer = io.snapshot_transfer.clone();
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 4,
+            ..Default::default()
+        };
+        let mut common = Common::new(
+            node_id.clone(),
+            io,
+            cluster.clone(),
+            options.clone(),
+            metrics.clone(),
+        );
+
+        let tail = LogPosition {
+            prev_term: Term::new(0),
+            index: LogIndex::new(3),
+        };
+        let prefix = LogPrefix {
+            tail,
+            config: cluster.clone(),
+            snapshot: (0..10).collect(),
+        };
+        let chunks = prefix.clone().into_chunks(4);
+
+        // 最後のチャンクを受け取る前に、ノードがクラッシュする
+        for chunk in &chunks[..2] {
+            track!(common.receive_snapshot_chunk(chunk.clone()))?;
+        }
+        track!(common.run_once())?;
+        mem::drop(common);
+        let saved_bytes = transfer
+            .lock()
+            .expect("Never fails")
+            .as_ref()
+            .map(|t| t.saved_bytes);
+        assert_eq!(saved_bytes, Some(8));
+
+        // 再起動後は、保存済みのオフセットから保存が再開される
+        let mut io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        io.snapshot_transfer = transfer;
+        let saved_chunks = io.saved_snapshot_chunks.clone();
+        io.logs
+            .lock()
+            .expect("Never fails")
+            .insert((LogIndex::new(0), Some(tail.index)), prefix.into());
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        track!(common.load_snapshot_transfer())?;
+        for chunk in chunks {
+            track!(common.receive_snapshot_chunk(chunk))?;
+        }
+        track!(common.run_once())?;
+        assert!(!common.is_snapshot_installing());
+        assert_eq!(common.log().head(), tail);
+        assert_eq!(*saved_chunks.lock().expect("Never fails"), [(8, true)]);
+        Ok(())
+    }
 }
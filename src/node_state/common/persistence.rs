@@ -0,0 +1,143 @@
+use futures::{Future, Poll};
+use std::collections::VecDeque;
+
+use super::Common;
+use crate::election::Ballot;
+use crate::log::LogSuffix;
+use crate::{Error, ErrorKind, Io, Result};
+
+/// 投票状況の保存処理.
+pub type PersistBallot<IO> = Persist<<IO as Io>::SaveBallot>;
+
+/// ローカルログへの追記処理.
+pub type PersistLog<IO> = Persist<<IO as Io>::SaveLog>;
+
+/// 投票状況ないしローカルログの永続化処理.
+///
+/// `ReplicatedLogOptions::ordered_persistence`が有効な場合には、
+/// 書き込みは`Common`が保持するキューを経由して、発行された順に一つずつ実行される.
+pub enum Persist<F> {
+    /// I/O実装が返した`Future`をそのまま保持している.
+    Direct(F),
+
+    /// 順序付きキューに投入済み.
+    Queued(u64),
+}
+impl<F> Persist<F>
+where
+    F: Future<Item = (), Error = Error>,
+{
+    /// 永続化処理の完了を確認する.
+    pub fn poll_with<IO: Io>(&mut self, common: &mut Common<IO>) -> Poll<(), Error> {
+        match *self {
+            Persist::Direct(ref mut f) => track!(f.poll()),
+            Persist::Queued(ticket) => track!(common.poll_persistence(ticket)),
+        }
+    }
+}
+
+/// 投票状況とローカルログの書き込みを、発行順に一つずつ実行するためのキュー.
+///
+/// 呼び出し元が`Persist`を破棄した場合でも、キューに投入済みの書き込みは中断されない
+/// (後続の書き込みが、それを追い越すことはない).
+pub struct PersistenceQueue<IO: Io> {
+    next_ticket: u64,
+
+    // これより前のチケットの書き込みは完了済み.
+    completed: u64,
+
+    writes: VecDeque<Write<IO>>,
+    broken: bool,
+}
+impl<IO: Io> PersistenceQueue<IO> {
+    pub fn new() -> Self {
+        PersistenceQueue {
+            next_ticket: 0,
+            completed: 0,
+            writes: VecDeque::new(),
+            broken: false,
+        }
+    }
+
+    /// 未完了の書き込みが存在しない場合には`true`を返す.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn push_ballot(&mut self, io: &mut IO, ballot: Ballot) -> u64 {
+        let write = if self.writes.is_empty() {
+            Write::SavingBallot(io.save_ballot(ballot))
+        } else {
+            Write::Ballot(ballot)
+        };
+        self.push(write)
+    }
+
+    pub fn push_log_suffix(&mut self, io: &mut IO, suffix: &LogSuffix) -> u64 {
+        let write = if self.writes.is_empty() {
+            Write::SavingLog(io.save_log_suffix(suffix))
+        } else {
+            Write::LogSuffix(suffix.clone())
+        };
+        self.push(write)
+    }
+
+    /// `ticket`の書き込みが完了している場合には`true`を返す.
+    pub fn is_completed(&self, ticket: u64) -> bool {
+        ticket < self.completed
+    }
+
+    /// キューの先頭から順に、書き込みを進める.
+    ///
+    /// 一度でも書き込みに失敗した場合には、以後の書き込みは一切行われず、常にエラーが返される.
+    pub fn run_once(&mut self, io: &mut IO) -> Result<()> {
+        track_assert!(
+            !self.broken,
+            ErrorKind::InconsistentState,
+            "A preceding write has failed"
+        );
+        if let Err(e) = self.write_next(io) {
+            self.broken = true;
+            self.writes.clear();
+            return Err(track!(e));
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, write: Write<IO>) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.writes.push_back(write);
+        ticket
+    }
+
+    fn write_next(&mut self, io: &mut IO) -> Result<()> {
+        while let Some(write) = self.writes.front_mut() {
+            let ready = match *write {
+                Write::Ballot(ref ballot) => {
+                    *write = Write::SavingBallot(io.save_ballot(ballot.clone()));
+                    continue;
+                }
+                Write::LogSuffix(ref suffix) => {
+                    *write = Write::SavingLog(io.save_log_suffix(suffix));
+                    continue;
+                }
+                Write::SavingBallot(ref mut f) => track!(f.poll())?.is_ready(),
+                Write::SavingLog(ref mut f) => track!(f.poll())?.is_ready(),
+            };
+            if !ready {
+                break;
+            }
+            self.writes.pop_front();
+            self.completed += 1;
+        }
+        Ok(())
+    }
+}
+
+enum Write<IO: Io> {
+    Ballot(Ballot),
+    LogSuffix(LogSuffix),
+    SavingBallot(IO::SaveBallot),
+    SavingLog(IO::SaveLog),
+}
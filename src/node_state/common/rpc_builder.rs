@@ -12,50 +12,165 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
     pub fn new(common: &'a mut Common<IO>) -> Self {
         RpcCaller { common }
     }
-    pub fn broadcast_request_vote(mut self) {
+    /// 投票依頼をブロードキャストして、使用したシーケンス番号を返す.
+    pub fn broadcast_request_vote(self) -> SequenceNumber {
+        self.broadcast_request_vote_call(false)
+    }
+
+    /// リーダシップの移譲に伴う投票依頼をブロードキャストして、使用したシーケンス番号を返す.
+    pub fn broadcast_transfer_request_vote(self) -> SequenceNumber {
+        self.broadcast_request_vote_call(true)
+    }
+
+    /// 即座に選挙を開始するように`peer`に指示して、使用したシーケンス番号を返す.
+    pub fn send_timeout_now(mut self, peer: &NodeId) -> SequenceNumber {
+        let header = self.make_header(peer);
+        let seq_no = header.seq_no;
+        let message = message::TimeoutNowCast { header }.into();
+        self.common.send_message(message);
+        seq_no
+    }
+
+    fn broadcast_request_vote_call(mut self, transfer: bool) -> SequenceNumber {
         let header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
         let log_tail = self.common.history.tail();
         let request = message::RequestVoteCall {
             header: header.clone(),
             log_tail,
+            pre_vote: false,
+            transfer,
+        }
+        .into();
+        let self_reply = message::RequestVoteReply {
+            header,
+            voted: true,
+            repeated: false,
+            pre_vote: false,
+        }
+        .into();
+        self.broadcast(request, self_reply, None)
+    }
+
+    /// 事前投票の依頼をブロードキャストして、使用したシーケンス番号を返す.
+    ///
+    /// ヘッダの`term`には、ローカルノードが次に立候補する際の`term`が設定される.
+    pub fn broadcast_pre_vote(mut self) -> SequenceNumber {
+        let mut header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
+        header.term = (header.term.as_u64() + 1).into();
+        let log_tail = self.common.history.tail();
+        let request = message::RequestVoteCall {
+            header: header.clone(),
+            log_tail,
+            pre_vote: true,
+            transfer: false,
         }
         .into();
         let self_reply = message::RequestVoteReply {
             header,
             voted: true,
+            repeated: false,
+            pre_vote: true,
         }
         .into();
-        self.broadcast(request, self_reply);
+        self.broadcast(request, self_reply, None)
     }
-    pub fn broadcast_append_entries(mut self, suffix: LogSuffix) {
+
+    /// `suffix`をブロードキャストして、使用したシーケンス番号を返す.
+    ///
+    /// ただし`throttled`に含まれるフォロワーには、`suffix`の代わりに、
+    /// エントリ群を含まないメッセージ(i.e., ハートビート)が送信される.
+    pub fn broadcast_append_entries(
+        mut self,
+        suffix: LogSuffix,
+        throttled: &[NodeId],
+    ) -> SequenceNumber {
         let header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
-        let request = message::AppendEntriesCall {
+        let heartbeat = message::AppendEntriesCall {
             header: header.clone(),
             committed_log_tail: self.common.history.committed_tail().index,
+            suffix: LogSuffix {
+                head: self.common.history.tail(),
+                entries: Vec::new(),
+            },
+            quiescent: self.common.quiescent,
+        };
+        let request = message::AppendEntriesCall {
             suffix,
+            ..heartbeat.clone()
         }
         .into();
         let self_reply = AppendEntriesReply {
             header,
             log_tail: self.common.history.tail(),
             busy: false,
+            removed: false,
+            conflict_term: None,
+            conflict_index: None,
         }
         .into();
-        self.broadcast(request, self_reply);
+        let substitute = if throttled.is_empty() {
+            None
+        } else {
+            Some((throttled, heartbeat.into()))
+        };
+        self.broadcast(request, self_reply, substitute)
     }
-    pub fn send_append_entries(mut self, peer: &NodeId, suffix: LogSuffix) {
+
+    /// `suffix`を`peer`に送信して、使用したシーケンス番号を返す.
+    ///
+    /// `peer`がウィットネスの場合には、コマンドの内容を取り除いたエントリ群が送信される.
+    pub fn send_append_entries(mut self, peer: &NodeId, suffix: LogSuffix) -> SequenceNumber {
+        let suffix = if self.common.config().is_witness(peer) {
+            suffix.without_commands()
+        } else {
+            suffix
+        };
+        let header = self.make_header(peer);
+        let seq_no = header.seq_no;
         let message = message::AppendEntriesCall {
-            header: self.make_header(peer),
+            header,
             committed_log_tail: self.common.history.committed_tail().index,
             suffix,
+            quiescent: self.common.quiescent,
         }
         .into();
-        self.common.io.send_message(message);
+        self.common.send_message(message);
+        seq_no
     }
-    pub fn send_install_snapshot(mut self, peer: &NodeId, prefix: LogPrefix) {
-        let header = self.make_header(peer);
-        let message = message::InstallSnapshotCast { header, prefix }.into();
-        self.common.io.send_message(message);
+
+    /// スナップショットを`peer`に送信して、使用したシーケンス番号を返す.
+    ///
+    /// スナップショットが`ReplicatedLogOptions::snapshot_chunk_size`を超える場合には、
+    /// 複数のチャンクに分割され、それぞれが`InstallSnapshotCall`として順番に送信される.
+    /// その場合には、最後のチャンクの送信に使用したシーケンス番号が返される.
+    ///
+    /// チャンク群は、メモリ上の`prefix`から一度に生成されるため、分割時には一時的に`prefix`の二倍程度のメモリが使用される.
+    /// (`Io`からスナップショットを逐次的に読み込みながら送信することは行われない)
+    ///
+    /// スナップショットに含まれるクラスタ構成において`peer`がウィットネスの場合には、
+    /// スナップショットの中身は空にして送信される.
+    /// (受信側は、その構成を見ることで、中身が取り除かれていることを判別できる)
+    pub fn send_install_snapshot(mut self, peer: &NodeId, mut prefix: LogPrefix) -> SequenceNumber {
+        if prefix.config.is_witness(peer) {
+            prefix.snapshot = Vec::new();
+        }
+        let chunk_size = self.common.snapshot_chunk_size();
+        if prefix.snapshot.len() <= chunk_size {
+            let header = self.make_header(peer);
+            let seq_no = header.seq_no;
+            let message = message::InstallSnapshotCast { header, prefix }.into();
+            self.common.send_message(message);
+            return seq_no;
+        }
+
+        let mut seq_no = self.common.seq_no;
+        for chunk in prefix.into_chunks(chunk_size) {
+            let header = self.make_header(peer);
+            seq_no = header.seq_no;
+            let message = message::InstallSnapshotCall { header, chunk }.into();
+            self.common.send_message(message);
+        }
+        seq_no
     }
 
     fn make_header(&mut self, destination: &NodeId) -> MessageHeader {
@@ -68,19 +183,59 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
             term: self.common.local_node.ballot.term,
         }
     }
-    fn broadcast(&mut self, mut message: Message, self_reply: Message) {
+    // `substitute`が指定された場合には、それに含まれるピアには、`message`の代わりに、その代替メッセージが送信される.
+    // ウィットネスには、`message`に含まれるエントリ群からコマンドの内容を取り除いたものが送信される.
+    fn broadcast(
+        &mut self,
+        mut message: Message,
+        self_reply: Message,
+        substitute: Option<(&[NodeId], Message)>,
+    ) -> SequenceNumber {
+        let seq_no = message.header().seq_no;
         let mut do_self_reply = false;
+        let mut peers = Vec::new();
         for peer in self.common.history.config().members() {
             if *peer == self.common.local_node.id {
                 do_self_reply = true;
             } else {
-                message.set_destination(peer);
-                self.common.io.send_message(message.clone());
+                peers.push(peer.clone());
+            }
+        }
+
+        let priority = &self.common.broadcast_priority;
+        peers.sort_by_key(|peer| {
+            priority
+                .iter()
+                .position(|p| p == peer)
+                .unwrap_or(priority.len())
+        });
+        for peer in &peers {
+            match substitute {
+                Some((targets, ref substitute)) if targets.contains(peer) => {
+                    let mut substitute = substitute.clone();
+                    substitute.set_destination(peer);
+                    self.common.send_broadcast_message(substitute);
+                }
+                _ => {
+                    message.set_destination(peer);
+                    let message = match message {
+                        Message::AppendEntriesCall(ref m)
+                            if self.common.config().is_witness(peer) =>
+                        {
+                            let mut m = m.clone();
+                            m.suffix = m.suffix.without_commands();
+                            m.into()
+                        }
+                        _ => message.clone(),
+                    };
+                    self.common.send_broadcast_message(message);
+                }
             }
         }
         if do_self_reply {
             self.common.unread_message = Some(self_reply);
         }
+        seq_no
     }
 }
 
@@ -95,26 +250,95 @@ impl<'a, IO: 'a + Io> RpcCallee<'a, IO> {
     }
     pub fn reply_request_vote(self, voted: bool) {
         let header = self.make_header();
-        let message = message::RequestVoteReply { header, voted }.into();
-        self.common.io.send_message(message);
+        let message = message::RequestVoteReply {
+            header,
+            voted,
+            repeated: false,
+            pre_vote: false,
+        }
+        .into();
+        self.common.send_message(message);
+    }
+    /// 既に投票済みの候補者から再送された投票依頼に応答する.
+    pub fn reply_repeated_vote(self) {
+        let header = self.make_header();
+        let message = message::RequestVoteReply {
+            header,
+            voted: true,
+            repeated: true,
+            pre_vote: false,
+        }
+        .into();
+        self.common.send_message(message);
+    }
+    /// 事前投票の依頼に応答する.
+    pub fn reply_pre_vote(self, voted: bool) {
+        let header = self.make_header();
+        let message = message::RequestVoteReply {
+            header,
+            voted,
+            repeated: false,
+            pre_vote: true,
+        }
+        .into();
+        self.common.send_message(message);
     }
     pub fn reply_append_entries(self, log_tail: LogPosition) {
         let message = AppendEntriesReply {
             header: self.make_header(),
             log_tail,
             busy: false,
+            removed: false,
+            conflict_term: None,
+            conflict_index: None,
+        }
+        .into();
+        self.common.send_message(message);
+    }
+    /// リーダのログとの分岐を検知し、`log_tail`までロールバックしたことを応答する.
+    ///
+    /// リーダが同期点を効率的に探索できるように、`log_tail`地点の`Term`とその開始位置も通知する.
+    pub fn reply_log_conflict(self, log_tail: LogPosition) {
+        let conflict_index = self
+            .common
+            .history
+            .term_range(log_tail.prev_term)
+            .map(|(start, _)| start);
+        let message = AppendEntriesReply {
+            header: self.make_header(),
+            log_tail,
+            busy: false,
+            removed: false,
+            conflict_term: conflict_index.map(|_| log_tail.prev_term),
+            conflict_index,
+        }
+        .into();
+        self.common.send_message(message);
+    }
+    /// ローカルノードがクラスタから取り除かれており、ログの追記を行わないことを応答する.
+    pub fn reply_removed(self) {
+        let message = AppendEntriesReply {
+            header: self.make_header(),
+            log_tail: self.common.history.tail(),
+            busy: false,
+            removed: true,
+            conflict_term: None,
+            conflict_index: None,
         }
         .into();
-        self.common.io.send_message(message);
+        self.common.send_message(message);
     }
     pub fn reply_busy(self) {
         let message = AppendEntriesReply {
             header: self.make_header(),
             log_tail: self.common.history.tail(),
             busy: true,
+            removed: false,
+            conflict_term: None,
+            conflict_index: None,
         }
         .into();
-        self.common.io.send_message(message);
+        self.common.send_message(message);
     }
 
     fn make_header(&self) -> MessageHeader {
@@ -1,5 +1,6 @@
-use futures::{Async, Future};
+use futures::Async;
 
+use super::super::common::PersistLog;
 use super::super::{Common, NextState, RoleState};
 use super::{Follower, FollowerIdle};
 use crate::log::LogPosition;
@@ -14,7 +15,7 @@ use crate::{Io, Result};
 /// 細かい調整処理は`FollowerIdle`内で行われ、
 /// ここが担当するのは、あくまでもログ追記処理のみ.
 pub struct FollowerAppend<IO: Io> {
-    future: Option<IO::SaveLog>,
+    future: Option<PersistLog<IO>>,
     new_log_tail: LogPosition,
     message: AppendEntriesCall,
 }
@@ -57,9 +58,16 @@ impl<IO: Io> FollowerAppend<IO> {
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        if let Async::Ready(_) = track!(self.future.poll())? {
+        let is_saved = match self.future {
+            Some(ref mut f) => track!(f.poll_with(common))?,
+            None => Async::Ready(()),
+        };
+        if let Async::Ready(()) = is_saved {
             if self.new_log_tail == self.message.suffix.tail() {
                 track!(common.handle_log_appended(&self.message.suffix))?;
+                if self.future.is_some() {
+                    common.handle_log_persisted(&self.message.suffix);
+                }
             }
             track!(common.handle_log_committed(self.message.committed_log_tail))?;
             common
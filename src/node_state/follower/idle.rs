@@ -9,7 +9,7 @@ use crate::{ErrorKind, Io, Result};
 
 /// 待機中(i.e., 受信メッセージ処理が可能)なフォロワーのサブ状態.
 ///
-/// リーダから送られてきた`AppendEntriesCall`および`InstallSnapshotCast`を処理する.
+/// リーダから送られてきた`AppendEntriesCall`、`InstallSnapshotCast`および`InstallSnapshotCall`を処理する.
 pub struct FollowerIdle<IO: Io> {
     _phantom: PhantomData<IO>,
 }
@@ -33,8 +33,13 @@ impl<IO: Io> FollowerIdle<IO> {
                     // 既にコミット済みの地点のスナップショットは無視する
                     // (必要なら、ローカルノードで独自にスナップショットを取れば良い)
                     Ok(None)
-                } else if common.is_snapshot_installing() {
-                    // 別のスナップショットをインストール中
+                } else if common.is_snapshot_installing()
+                    && !common.is_snapshot_supersedable(m.prefix.tail.index)
+                {
+                    // 別の(より新しい地点の)スナップショットをインストール中
+                    Ok(None)
+                } else if !common.check_snapshot_size(m.prefix.snapshot.len()) {
+                    // 大きすぎるスナップショットは受け付けない
                     Ok(None)
                 } else {
                     // 未コミット地点のスナップショットが送られてきた
@@ -45,6 +50,25 @@ impl<IO: Io> FollowerIdle<IO> {
                     Ok(Some(RoleState::Follower(Follower::Snapshot(next))))
                 }
             }
+            Message::InstallSnapshotCall(m) => {
+                if m.chunk.tail.index <= common.log_committed_tail().index {
+                    // 既にコミット済みの地点のスナップショットは無視する
+                    Ok(None)
+                } else if common.is_snapshot_installing() {
+                    // 別のスナップショットをインストール中
+                    Ok(None)
+                } else {
+                    // 未コミット地点のスナップショットの、分割転送が開始された
+                    track!(common.receive_snapshot_chunk(m.chunk))?;
+                    if common.is_snapshot_installing() {
+                        let next = FollowerSnapshot::new();
+                        Ok(Some(RoleState::Follower(Follower::Snapshot(next))))
+                    } else {
+                        // 先頭以外のチャンクや、大きすぎるスナップショットのチャンクだった
+                        Ok(None)
+                    }
+                }
+            }
             _ => Ok(None),
         }
     }
@@ -97,7 +121,7 @@ impl<IO: Io> FollowerIdle<IO> {
             track!(common.handle_log_rollbacked(new_log_tail))?;
             common
                 .rpc_callee(&message.header)
-                .reply_append_entries(new_log_tail);
+                .reply_log_conflict(new_log_tail);
             Ok(None)
         } else {
             // 両者は包含関係にあるので、追記が可能
@@ -1,5 +1,4 @@
-use futures::Future;
-
+use super::super::common::PersistBallot;
 use super::super::{Common, NextState, RoleState};
 use super::{Follower, FollowerIdle, FollowerSnapshot};
 use crate::message::{Message, MessageHeader};
@@ -12,7 +11,7 @@ use crate::{Io, Result};
 /// - 1. 投票状況を保存
 /// - 2. もし保存処理中に投票先から`RequestVoteCall`を受信したら、保存後にそれに返答(投票)
 pub struct FollowerInit<IO: Io> {
-    future: IO::SaveBallot,
+    future: PersistBallot<IO>,
     pending_vote: Option<MessageHeader>,
 }
 impl<IO: Io> FollowerInit<IO> {
@@ -41,7 +40,7 @@ impl<IO: Io> FollowerInit<IO> {
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        let item = track!(self.future.poll())?;
+        let item = track!(self.future.poll_with(common))?;
         if item.is_ready() {
             if let Some(header) = self.pending_vote.take() {
                 common.rpc_callee(&header).reply_request_vote(true);
@@ -37,22 +37,82 @@ impl<IO: Io> Follower<IO> {
         let follower = FollowerInit::new(common, pending_vote);
         Follower::Init(follower)
     }
+    /// I/O実装の差し替え用に、異なるI/O実装用のフォロワーに変換する.
+    ///
+    /// I/O処理を伴わない`Idle`状態の場合にのみ変換可能.
+    pub fn convert_io<NewIo: Io>(self) -> Option<Follower<NewIo>> {
+        if let Follower::Idle(_) = self {
+            Some(Follower::Idle(FollowerIdle::new()))
+        } else {
+            None
+        }
+    }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        Ok(Some(common.transit_to_candidate()))
+        if common.config().is_learner(&common.local_node().id) {
+            // ラーナーは投票権を持たないので、立候補しない
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        if common.is_witness() {
+            // ウィットネスはリーダにはなれないので、立候補しない
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        if common.is_removed() {
+            // クラスタから取り除かれたので、以後は立候補しない
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        if common.is_shutting_down() {
+            // 停止処理中なので、以後は立候補しない
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        if common.extend_quiescent_election_timeout() {
+            // リーダが休止中なので、ハートビートの間隔が延びている
+            return Ok(None);
+        }
+        common.notify_leader_lost();
+        if common.is_role_flapping_backoff() {
+            // 役割の振動が収まるまでは、立候補を控える
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        Ok(Some(common.start_election()))
     }
     pub fn handle_message(
         &mut self,
         common: &mut Common<IO>,
         message: Message,
     ) -> Result<NextState<IO>> {
-        if let Message::AppendEntriesCall { .. } = message {
+        if let Message::TimeoutNowCast(ref m) = message {
+            if common.local_node().ballot.voted_for == m.header.sender
+                && !common.is_shutting_down()
+                && !common.is_witness()
+            {
+                // リーダからリーダシップの移譲を指示されたので、タイムアウトを待たずに立候補する
+                return Ok(Some(common.transit_to_transfer_candidate()));
+            }
+            return Ok(None);
+        }
+        if let Message::AppendEntriesCall(ref m) = message {
             common.set_timeout(Role::Follower);
+            common.set_quiescent(m.quiescent);
             if unsafe { common.io_mut().is_busy() } {
                 common.rpc_callee(message.header()).reply_busy();
                 return Ok(None);
             }
         }
 
+        if let Message::RequestVoteCall(ref m) = message {
+            let is_init = matches!(self, Follower::Init(_));
+            if !is_init && common.local_node().ballot.voted_for == m.header.sender {
+                // 投票済みの候補者からの再送された依頼 (初期化中の場合は、保存完了後に返答される)
+                common.rpc_callee(&m.header).reply_repeated_vote();
+                return Ok(None);
+            }
+        }
+
         match *self {
             Follower::Init(ref mut t) => track!(t.handle_message(common, message)),
             Follower::Idle(ref mut t) => track!(t.handle_message(common, message)),
@@ -69,3 +129,233 @@ impl<IO: Io> Follower<IO> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometrics::metrics::MetricBuilder;
+    use trackable::result::TestResult;
+
+    use crate::election::Term;
+    use crate::log::{LogEntry, LogIndex, LogPosition, LogSuffix};
+    use crate::message::{
+        AppendEntriesCall, AppendEntriesReply, RequestVoteCall, RequestVoteReply, SequenceNumber,
+        TimeoutNowCast,
+    };
+    use crate::metrics::NodeStateMetrics;
+    use crate::node::NodeId;
+    use crate::node_state::RoleState;
+    use crate::test_util::tests::{TestIo, TestIoBuilder};
+    use crate::ReplicatedLogOptions;
+
+    #[test]
+    fn retransmitted_vote_request_is_replied_as_repeated() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        common.transit_to_follower("node2".into(), None);
+        let mut follower = Follower::Idle(FollowerIdle::new());
+
+        // 投票済みの候補者("node2")から、同じ投票依頼が再送される
+        let request = RequestVoteCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: node_id,
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            log_tail: common.log().tail(),
+            pre_vote: false,
+            transfer: false,
+        };
+        track!(follower.handle_message(&mut common, request.into()))?;
+        assert!(matches!(
+            sent_messages.lock().expect("Never fails").last(),
+            Some(Message::RequestVoteReply(RequestVoteReply {
+                voted: true,
+                repeated: true,
+                ..
+            }))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_now_starts_transfer_election() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new("node1".into(), io, cluster, Default::default(), metrics);
+        common.transit_to_follower("node2".into(), None);
+        let mut follower = Follower::Idle(FollowerIdle::new());
+
+        let timeout_now = |sender: &str, common: &Common<TestIo>| -> Message {
+            TimeoutNowCast {
+                header: MessageHeader {
+                    sender: sender.into(),
+                    destination: "node1".into(),
+                    seq_no: SequenceNumber::new(0),
+                    term: common.term(),
+                },
+            }
+            .into()
+        };
+
+        // リーダ以外からの指示は無視する
+        let message = timeout_now("node3", &common);
+        assert!(track!(follower.handle_message(&mut common, message))?.is_none());
+
+        // リーダ("node2")からの指示で、即座に立候補する
+        let message = timeout_now("node2", &common);
+        let mut candidate = match track!(follower.handle_message(&mut common, message))? {
+            Some(RoleState::Candidate(c)) => c,
+            _ => panic!(),
+        };
+        assert_eq!(common.term(), Term::new(1));
+        track!(candidate.run_once(&mut common))?;
+        let request = match sent_messages.lock().expect("Never fails").last() {
+            Some(Message::RequestVoteCall(m)) => m.clone(),
+            m => panic!("{:?}", m),
+        };
+        assert!(request.transfer);
+
+        // "node2"をフォロー中の"node3"も、移譲に伴う投票依頼には応じる
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut other = Common::new("node3".into(), io, cluster, Default::default(), metrics);
+        other.transit_to_follower("node2".into(), None);
+
+        let mut normal = request.clone();
+        normal.transfer = false;
+        track!(other.handle_message(normal.into()))?;
+        assert_eq!(other.local_node().ballot.voted_for, "node2".into());
+
+        track!(other.handle_message(request.into()))?;
+        assert_eq!(other.local_node().ballot.voted_for, "node1".into());
+        assert_eq!(other.term(), Term::new(1));
+        Ok(())
+    }
+
+    #[test]
+    fn quiescent_leader_extends_election_timeout() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            quiescent_heartbeat_interval: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new("node1".into(), io, cluster, options, metrics);
+        common.transit_to_follower("node2".into(), None);
+        let mut follower = Follower::Idle(FollowerIdle::new());
+
+        // 休止中のリーダ("node2")からのハートビート
+        let heartbeat = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: common.log().committed_tail().index,
+            suffix: LogSuffix::default(),
+            quiescent: true,
+        };
+        track!(follower.handle_message(&mut common, heartbeat.into()))?;
+        assert!(common.is_quiescent());
+
+        // ハートビートの間隔分のタイムアウトまでは立候補しない
+        for _ in 0..2 {
+            assert!(track!(follower.handle_timeout(&mut common))?.is_none());
+        }
+        assert!(matches!(
+            track!(follower.handle_timeout(&mut common))?,
+            Some(RoleState::Candidate(_))
+        ));
+        assert!(!common.is_quiescent());
+        Ok(())
+    }
+
+    #[test]
+    fn log_conflict_is_replied_with_hints() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new("node1".into(), io, cluster, Default::default(), metrics);
+        common.transit_to_follower("node2".into(), None);
+        let mut follower = Follower::Idle(FollowerIdle::new());
+
+        // ローカルログ: `Term(0)`のエントリが三つと、(リーダが持たない)`Term(1)`のエントリが五つ
+        let entries = (0..8)
+            .map(|i| LogEntry::Noop {
+                term: Term::new(if i < 3 { 0 } else { 1 }),
+            })
+            .collect();
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        };
+        track!(common.handle_log_appended(&suffix))?;
+
+        // `Term(2)`のリーダのログ終端と分岐している
+        let call = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(0),
+                term: common.term(),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix {
+                head: LogPosition {
+                    prev_term: Term::new(2),
+                    index: LogIndex::new(8),
+                },
+                entries: Vec::new(),
+            },
+            quiescent: false,
+        };
+        track!(follower.handle_message(&mut common, call.into()))?;
+
+        // 分岐地点の`Term`と、その開始位置が通知される
+        let reply = match sent_messages.lock().expect("Never fails").last() {
+            Some(Message::AppendEntriesReply(reply)) => reply.clone(),
+            m => panic!("Unexpected message: {:?}", m),
+        };
+        let AppendEntriesReply {
+            log_tail,
+            conflict_term,
+            conflict_index,
+            ..
+        } = reply;
+        assert_eq!(log_tail.index, LogIndex::new(7));
+        assert_eq!(conflict_term, Some(Term::new(1)));
+        assert_eq!(conflict_index, Some(LogIndex::new(3)));
+        Ok(())
+    }
+}
@@ -7,7 +7,8 @@ use crate::{Io, Result};
 
 /// ローカルログへのスナップショット保存を処理するためのフォロワーのサブ状態.
 ///
-/// `InstallSnapshotCast`で送られてきたスナップショットを処理する.
+/// `InstallSnapshotCast`ないし`InstallSnapshotCall`で送られてきたスナップショットを処理する.
+/// 分割転送の場合には、後続のチャンク群もこのサブ状態で受け取る.
 ///
 /// 正確には、スナップショットの処理自体は共通モジュールで行われるため、
 /// ここでの目的は「スナップショットの保存中に、新たなログ追記等が行われないようにする」
@@ -31,8 +32,24 @@ impl<IO: Io> FollowerSnapshot<IO> {
         common: &mut Common<IO>,
         message: Message,
     ) -> Result<NextState<IO>> {
-        if let Message::AppendEntriesCall(m) = message {
-            common.rpc_callee(&m.header).reply_busy();
+        match message {
+            Message::AppendEntriesCall(m) => {
+                // 全チャンクの送信後に届いたハートビートなので、
+                // この時点で残りのチャンクを待っている転送は、途中のチャンクが欠損している
+                common.abandon_stalled_snapshot_transfer();
+                common.rpc_callee(&m.header).reply_busy();
+            }
+            Message::InstallSnapshotCall(m) => {
+                track!(common.receive_snapshot_chunk(m.chunk))?;
+            }
+            Message::InstallSnapshotCast(m)
+                if common.is_snapshot_supersedable(m.prefix.tail.index)
+                    && common.check_snapshot_size(m.prefix.snapshot.len()) =>
+            {
+                // インストール中のものよりも新しい地点のスナップショットが届いたので、置き換える
+                track!(common.install_snapshot(m.prefix))?;
+            }
+            _ => {}
         }
         Ok(None)
     }
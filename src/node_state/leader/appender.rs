@@ -1,8 +1,8 @@
-use futures::{Async, Future};
 use std::mem;
 
+use super::super::common::PersistLog;
 use super::super::Common;
-use crate::log::{LogEntry, LogIndex, LogSuffix};
+use crate::log::{LogEntry, LogIndex, LogSuffix, ProposalId};
 use crate::{Io, Result};
 
 /// リーダのローカルログへの追記の管理を行う.
@@ -10,8 +10,11 @@ use crate::{Io, Result};
 /// メモリ上のバッファを保持しており、
 /// ストレージへの追記中に新たな追加要求が発行された場合には、
 /// 新規分はそのバッファに積まれていく.
+///
+/// `buffer`メソッドを使用した場合には、追記中でなくとも要求はバッファに積まれ、
+/// `flush`メソッドの呼び出し時に、まとめて一つの`LogSuffix`として追記される.
 pub struct LogAppender<IO: Io> {
-    task: Option<IO::SaveLog>,
+    task: Option<PersistLog<IO>>,
     in_progress: Option<LogSuffix>,
     pendings: Vec<LogEntry>,
 }
@@ -36,11 +39,53 @@ impl<IO: Io> LogAppender<IO> {
             self.pendings.extend(entries)
         }
     }
+
+    /// `entries`をバッファに積む.
+    ///
+    /// バッファ内のエントリ数が`ReplicatedLogOptions::proposal_batch_size`に達した場合には、
+    /// 即座に`flush`が行われる.
+    pub fn buffer(&mut self, common: &mut Common<IO>, entries: Vec<LogEntry>) {
+        self.pendings.extend(entries);
+        if self.pendings.len() >= common.options().proposal_batch_size {
+            self.flush(common);
+        }
+    }
+
+    /// 追記中でなければ、バッファ内のエントリ群の追記を開始する.
+    pub fn flush(&mut self, common: &mut Common<IO>) {
+        if self.task.is_none() && !self.pendings.is_empty() {
+            let pendings = mem::take(&mut self.pendings);
+            self.append(common, pendings);
+        }
+    }
+
+    /// 追記が完了していない(i.e., まだフォロワーに送信されていない)エントリ群の提案IDを返す.
+    pub fn unappended_proposals(&self, common: &Common<IO>) -> Vec<ProposalId> {
+        let head = self
+            .in_progress
+            .as_ref()
+            .map_or(common.log().tail().index, |s| s.head.index);
+        let in_progress = self.in_progress.iter().flat_map(|s| s.entries.iter());
+        in_progress
+            .chain(self.pendings.iter())
+            .enumerate()
+            .map(|(i, e)| ProposalId {
+                term: e.term(),
+                index: head + i,
+            })
+            .collect()
+    }
+
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<Option<LogSuffix>> {
-        if let Async::Ready(Some(())) = track!(self.task.poll())? {
+        let is_saved = match self.task {
+            Some(ref mut f) => track!(f.poll_with(common))?.is_ready(),
+            None => false,
+        };
+        if is_saved {
             self.task = None;
             let suffix = self.in_progress.take().expect("Never fails");
             track!(common.handle_log_appended(&suffix))?;
+            common.handle_log_persisted(&suffix);
 
             let pendings = mem::replace(&mut self.pendings, Vec::new());
             if !pendings.is_empty() {
@@ -1,14 +1,22 @@
 use futures::{Async, Future};
-use std::collections::BTreeMap;
+use std::cmp::{self, Reverse};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::mem;
+use std::time::{Duration, Instant};
 use trackable::error::ErrorKindExt;
 
 use super::super::Common;
 use crate::cluster::ClusterConfig;
-use crate::log::{Log, LogIndex};
+use crate::log::{Log, LogIndex, LogSuffix};
 use crate::message::{AppendEntriesReply, SequenceNumber};
 use crate::node::NodeId;
-use crate::{ErrorKind, Io, Result};
+use crate::{ErrorKind, Event, FollowerProgress, Io, PeerLag, ReplicatedLogOptions, Result};
+
+// ラウンドトリップ時間の計測のために保持する、ブロードキャストの送信時刻の最大数.
+const MAX_BROADCAST_TIMES: usize = 64;
+
+// 未計測のラウンドトリップ時間を表す値 (合意値の計算用).
+const UNKNOWN_RTT: Duration = Duration::from_secs(u64::MAX);
 
 /// フォロワーの管理者.
 ///
@@ -20,6 +28,10 @@ pub struct FollowersManager<IO: Io> {
     config: ClusterConfig,
     latest_hearbeat_ack: SequenceNumber,
     last_broadcast_seq_no: SequenceNumber,
+    last_heartbeat_seq_no: Option<SequenceNumber>,
+
+    // 直近のブロードキャストの送信時刻 (ラウンドトリップ時間の計測用).
+    broadcast_times: VecDeque<(SequenceNumber, Instant)>,
 
     // `raft_test_simu`のために非決定的な要素は排除したいので、
     // `HashMap`ではなく`BTreeMap`を使用している.
@@ -37,6 +49,8 @@ impl<IO: Io> FollowersManager<IO> {
             tasks: BTreeMap::new(),
             latest_hearbeat_ack: SequenceNumber::new(0),
             last_broadcast_seq_no: SequenceNumber::new(0),
+            last_heartbeat_seq_no: None,
+            broadcast_times: VecDeque::new(),
         }
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<()> {
@@ -48,12 +62,34 @@ impl<IO: Io> FollowersManager<IO> {
             }
         }
         for (follower, log) in dones {
-            let rpc = common.rpc_caller();
+            self.tasks.remove(&follower);
             match log {
-                Log::Prefix(snapshot) => rpc.send_install_snapshot(&follower, snapshot),
-                Log::Suffix(slice) => rpc.send_append_entries(&follower, slice),
+                Log::Prefix(snapshot) => {
+                    common
+                        .rpc_caller()
+                        .send_install_snapshot(&follower, snapshot);
+                }
+                Log::Suffix(slice) if !is_flow_controlled(common.options()) => {
+                    common.rpc_caller().send_append_entries(&follower, slice);
+                }
+                Log::Suffix(mut slice) => {
+                    if !slice.entries.is_empty() {
+                        let len = self.followers[&follower].fit_len(common.options(), &slice);
+                        if len == 0 {
+                            // 読み込み中に送信枠が埋まった
+                            // (この場合は、次の応答を受け取った時点で、改めて同期処理が行われる)
+                            continue;
+                        }
+                        slice.entries.truncate(len);
+                    }
+                    let seq_no = common
+                        .rpc_caller()
+                        .send_append_entries(&follower, slice.clone());
+                    if let Some(f) = self.followers.get_mut(&follower) {
+                        f.record_sent(&slice, seq_no);
+                    }
+                }
             }
-            self.tasks.remove(&follower);
         }
         Ok(())
     }
@@ -61,6 +97,198 @@ impl<IO: Io> FollowersManager<IO> {
         self.latest_hearbeat_ack
     }
 
+    /// 各フォロワーのハートビートの応答率を、千分率で返す.
+    ///
+    /// まだ一度も計測が行われていないフォロワーの応答率は`1000`となる.
+    pub fn heartbeat_health(&self, local: &NodeId) -> Vec<(NodeId, u32)> {
+        self.followers
+            .iter()
+            .filter(|&(id, _)| id != local)
+            .map(|(id, f)| (id.clone(), f.heartbeat_ratio()))
+            .collect()
+    }
+
+    /// 各フォロワーの複製の遅れを返す.
+    ///
+    /// `bytes_behind`は、指定のインデックス以降のエントリ群の合計バイト数(の推定値)を返す関数.
+    pub fn replication_lag<F>(
+        &self,
+        local: &NodeId,
+        leader_tail: LogIndex,
+        bytes_behind: F,
+    ) -> Vec<PeerLag>
+    where
+        F: Fn(LogIndex) -> u64,
+    {
+        self.followers
+            .iter()
+            .filter(|&(id, _)| id != local)
+            .map(|(id, f)| PeerLag {
+                node: id.clone(),
+                entries_behind: leader_tail.as_u64().saturating_sub(f.log_tail.as_u64()),
+                bytes_behind_estimate: bytes_behind(f.log_tail),
+            })
+            .collect()
+    }
+
+    /// 各フォロワーへの複製の進み具合を返す.
+    ///
+    /// `head`はリーダのローカルログの先頭位置.
+    pub fn replication_progress(
+        &self,
+        local: &NodeId,
+        head: LogIndex,
+    ) -> HashMap<NodeId, FollowerProgress> {
+        self.followers
+            .iter()
+            .filter(|&(id, _)| id != local)
+            .map(|(id, f)| {
+                let progress = FollowerProgress {
+                    match_index: if f.synced {
+                        f.log_tail
+                    } else {
+                        LogIndex::new(0)
+                    },
+                    next_index: f.log_tail,
+                    // `log_sync`は、先頭より遅れているフォロワーに対してはスナップショットを読み込む
+                    sending_snapshot: self.tasks.contains_key(id) && f.log_tail < head,
+                    is_learner: self.config.is_learner(id),
+                };
+                (id.clone(), progress)
+            })
+            .collect()
+    }
+
+    /// `node`のローカルログが`head`よりも遅れていて、スナップショットの送信が必要な場合には`true`を返す.
+    pub fn needs_snapshot(&self, node: &NodeId, head: LogIndex) -> bool {
+        matches!(self.followers.get(node), Some(f) if f.log_tail < head)
+    }
+
+    /// `node`のローカルログが`tail`まで追い付いている場合には`true`を返す.
+    pub fn is_caught_up(&self, node: &NodeId, tail: LogIndex) -> bool {
+        matches!(self.followers.get(node), Some(f) if tail <= f.log_tail)
+    }
+
+    /// 投票権を有するフォロワーの中で、ローカルログの複製が最も進んでいるものを返す.
+    ///
+    /// 同期(合流)点が確定しているフォロワーが優先される.
+    /// リーダになれないウィットネスは除外される.
+    pub fn most_up_to_date_follower(&self, local: &NodeId) -> Option<NodeId> {
+        self.followers
+            .iter()
+            .filter(|&(id, _)| {
+                id != local && !self.config.is_learner(id) && !self.config.is_witness(id)
+            })
+            .max_by_key(|&(_, f)| (f.synced, f.log_tail))
+            .map(|(id, _)| id.clone())
+    }
+
+    /// 全フォロワーの中で、最も遅れているローカルログの終端を返す.
+    pub fn min_log_tail(&self) -> LogIndex {
+        self.followers
+            .values()
+            .map(|f| f.log_tail)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// ブロードキャスト時の送信先の優先順位を返す.
+    ///
+    /// ローカルログが遅れているフォロワーほど優先され、
+    /// 遅れが同じ場合には、最後に応答を受け取ったのがより古いフォロワーが優先される.
+    pub fn broadcast_priority(&self, local: &NodeId) -> Vec<NodeId> {
+        let mut followers = self
+            .followers
+            .iter()
+            .filter(|&(id, _)| id != local)
+            .collect::<Vec<_>>();
+        followers.sort_by_key(|&(_, f)| (f.log_tail, f.last_seq_no));
+        followers.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// 前回のハートビートに対する各フォロワーの応答有無を記録する.
+    ///
+    /// 新しいハートビートのブロードキャスト直前に呼び出されることが想定されており、
+    /// 前回のハートビート以降に一度も応答を返していないフォロワーは、
+    /// ハートビートへの応答に失敗したものとして扱われる.
+    pub fn record_heartbeat_round(&mut self, common: &mut Common<IO>) {
+        let window = common.options().heartbeat_health_window;
+        let threshold = common.options().peer_unhealthy_threshold_per_mille;
+        let local = common.local_node().id.clone();
+        if self.last_heartbeat_seq_no.is_some() {
+            for (id, f) in self.followers.iter_mut().filter(|&(id, _)| *id != local) {
+                if f.heartbeat_acked {
+                    f.missed_heartbeats = 0;
+                } else {
+                    f.missed_heartbeats += 1;
+                }
+                f.heartbeats.push_back(f.heartbeat_acked);
+                f.heartbeat_acked = false;
+                while f.heartbeats.len() > window {
+                    f.heartbeats.pop_front();
+                }
+
+                let ratio_per_mille = f.heartbeat_ratio();
+                let unhealthy = ratio_per_mille < threshold;
+                if unhealthy && !f.unhealthy {
+                    let node = id.clone();
+                    common.push_event(Event::PeerUnhealthy {
+                        node,
+                        ratio_per_mille,
+                    });
+                }
+                f.unhealthy = unhealthy;
+            }
+        }
+        self.last_heartbeat_seq_no = Some(common.next_seq_no());
+    }
+
+    /// リーダのタイムアウト(i.e., tick)の経過を記録する.
+    ///
+    /// 休止状態でハートビートの送信を見送った場合も含めて、タイムアウトの度に呼び出される.
+    /// 直近のハートビートに応答していないフォロワーは、応答の無いまま経過したtick数が一つ進められる.
+    pub fn record_elapsed_tick(&mut self, local: &NodeId) {
+        if self.last_heartbeat_seq_no.is_none() {
+            return;
+        }
+        for (_, f) in self.followers.iter_mut().filter(|&(id, _)| id != local) {
+            if f.heartbeat_acked {
+                f.ticks_since_ack = 0;
+            } else {
+                f.ticks_since_ack += 1;
+            }
+        }
+    }
+
+    /// 投票権を有するメンバの過半数が、直近`ticks`tickの間に、ハートビートへの応答を返している場合には`true`を返す.
+    ///
+    /// 経過tick数は`record_elapsed_tick`の呼び出し時に記録される.
+    /// ローカルノードは、常に応答済みとして扱われる.
+    pub fn has_recent_quorum_ack(&self, local: &NodeId, ticks: u64) -> bool {
+        self.config.consensus_value(|node_id| {
+            node_id == local
+                || matches!(self.followers.get(node_id), Some(f) if f.ticks_since_ack < ticks)
+        })
+    }
+
+    /// 投票権を有するメンバの過半数が、直近のハートビートに応答済みの場合には`true`を返す.
+    pub fn has_quorum_heartbeat_ack(&self, local: &NodeId) -> bool {
+        self.config.consensus_value(|node_id| {
+            node_id == local || matches!(self.followers.get(node_id), Some(f) if f.heartbeat_acked)
+        })
+    }
+
+    /// 投票権を有するメンバの過半数が、直近`rounds`回のハートビートのいずれかに応答している場合には`true`を返す.
+    ///
+    /// 各フォロワーの応答有無は`record_heartbeat_round`の呼び出し時に記録される.
+    /// ローカルノードは、常に応答済みとして扱われる.
+    pub fn has_quorum_contact(&self, local: &NodeId, rounds: u64) -> bool {
+        self.config.consensus_value(|node_id| {
+            node_id == local
+                || matches!(self.followers.get(node_id), Some(f) if f.missed_heartbeats < rounds)
+        })
+    }
+
     /// コミット済みログ領域の終端を返す.
     ///
     /// "コミット済み"とは「投票権を有するメンバの過半数以上のローカルログに存在する」ということを意味する.
@@ -94,10 +322,42 @@ impl<IO: Io> FollowersManager<IO> {
 
     pub fn handle_append_entries_reply(
         &mut self,
-        common: &Common<IO>,
+        common: &mut Common<IO>,
         reply: &AppendEntriesReply,
     ) -> bool {
+        if self.is_ignored(common, &reply.header.sender) {
+            return false;
+        }
+        let was_suspected = matches!(
+            self.followers.get(&reply.header.sender),
+            Some(f) if f.duplicate_suspected
+        );
+
         let updated = self.update_follower_state(common, reply);
+        if let Some(f) = self.followers.get_mut(&reply.header.sender) {
+            f.handle_inflight_ack(reply);
+        }
+        if !was_suspected && self.followers[&reply.header.sender].duplicate_suspected {
+            if common.options().ignore_suspected_duplicate_nodes {
+                // 以後は、このノードの応答をコミットの判定に使用しない
+                let f = self
+                    .followers
+                    .get_mut(&reply.header.sender)
+                    .expect("Never fails");
+                f.synced = false;
+                f.log_tail = LogIndex::new(0);
+            }
+            let node = reply.header.sender.clone();
+            common.push_event(Event::DuplicateNodeIdSuspected { node });
+        }
+        if let Some(now) = common.now() {
+            self.sample_rtt(reply, now);
+        }
+        if matches!(self.last_heartbeat_seq_no, Some(s) if s <= reply.header.seq_no) {
+            if let Some(f) = self.followers.get_mut(&reply.header.sender) {
+                f.heartbeat_acked = true;
+            }
+        }
         if self.latest_hearbeat_ack < reply.header.seq_no {
             self.latest_hearbeat_ack = self
                 .config
@@ -110,12 +370,77 @@ impl<IO: Io> FollowersManager<IO> {
         self.last_broadcast_seq_no = seq_no;
     }
 
+    /// フロー制御のために、`suffix`のブロードキャストを抑制すべきフォロワーの一覧を返す.
+    ///
+    /// 返されたフォロワーには、`suffix`の代わりにハートビートが送信されることが想定されており、
+    /// それ以外のフォロワーに対しては、`seq_no`を用いて`suffix`が送信されたものとして記録される.
+    ///
+    /// フロー制御が無効な場合(i.e., `max_inflight_entries`と`max_append_bytes`の両方が`0`の場合)には、
+    /// 常に空のリストが返される.
+    pub fn throttle_broadcast(
+        &mut self,
+        common: &Common<IO>,
+        suffix: &LogSuffix,
+        seq_no: SequenceNumber,
+    ) -> Vec<NodeId> {
+        let options = common.options();
+        if !is_flow_controlled(options) || suffix.entries.is_empty() {
+            return Vec::new();
+        }
+        let local = &common.local_node().id;
+        let mut throttled = Vec::new();
+        for (id, f) in self.followers.iter_mut().filter(|&(id, _)| id != local) {
+            if f.fit_len(options, suffix) == suffix.entries.len() {
+                f.record_sent(suffix, seq_no);
+            } else {
+                throttled.push(id.clone());
+            }
+        }
+        throttled
+    }
+
+    /// ブロードキャストの送信時刻を記録する.
+    ///
+    /// 記録された時刻は、対応する応答の受信時に、各フォロワーとのラウンドトリップ時間の計測に使用される.
+    pub fn record_broadcast_time(&mut self, seq_no: SequenceNumber, now: Instant) {
+        self.broadcast_times.push_back((seq_no, now));
+        while self.broadcast_times.len() > MAX_BROADCAST_TIMES {
+            self.broadcast_times.pop_front();
+        }
+    }
+
+    /// 投票権を有するメンバの過半数との間の、ラウンドトリップ時間(の平滑値)を返す.
+    ///
+    /// 過半数のメンバとの計測が済んでいない場合には`None`が返される.
+    /// ローカルノードとのラウンドトリップ時間は`0`として扱われる.
+    pub fn quorum_rtt(&self, local: &NodeId) -> Option<Duration> {
+        let Reverse(rtt) = self.config.consensus_value(|node_id| {
+            if node_id == local {
+                Reverse(Duration::from_secs(0))
+            } else {
+                Reverse(self.followers[node_id].rtt.unwrap_or(UNKNOWN_RTT))
+            }
+        });
+        if rtt == UNKNOWN_RTT {
+            None
+        } else {
+            Some(rtt)
+        }
+    }
+
     /// フォロワーのローカルログとの同期処理を実行する.
     pub fn log_sync(&mut self, common: &mut Common<IO>, reply: &AppendEntriesReply) -> Result<()> {
+        if self.is_ignored(common, &reply.header.sender) {
+            return Ok(());
+        }
         if reply.busy || self.tasks.contains_key(&reply.header.sender) {
             // フォロワーが忙しい or 既に同期処理が進行中
             return Ok(());
         }
+        if matches!(self.followers.get(&reply.header.sender), Some(f) if f.removed) {
+            // フォロワーは、既にクラスタから取り除かれたことを把握している
+            return Ok(());
+        }
 
         let follower = track!(self
             .followers
@@ -134,14 +459,36 @@ impl<IO: Io> FollowersManager<IO> {
             return Ok(());
         }
 
+        let head = common.log().head().index;
+        if follower.log_tail < head {
+            // フォロワーが必要とするエントリは、既にリーダのローカルログから削除されているので、
+            // エントリ群の代わりにスナップショットを送信する.
+            // (同期点の探索を続けても、削除済みの領域では合流できないため)
+            let future = track!(common.load_log(follower.log_tail, Some(head)))?;
+            self.tasks.insert(reply.header.sender.clone(), future);
+            return Ok(());
+        }
+
+        let mut start = follower.log_tail;
         let end = if follower.synced {
             // フォロワーのログとリーダのログの差分を送信
-            common.log().tail().index
+            let tail = common.log().tail().index;
+            if is_flow_controlled(common.options()) {
+                // 送信済みの部分は除いて、送信枠に収まる分だけを送る
+                let (entries, bytes) = follower.window_room(common.options());
+                start = follower.next_index();
+                if entries == 0 || bytes == 0 || tail <= start {
+                    return Ok(());
+                }
+                cmp::min(tail, LogIndex::new(start.as_u64().saturating_add(entries)))
+            } else {
+                tail
+            }
         } else {
             // フォロワーのログとリーダのログの同期(合流)点を探索中
             follower.log_tail
         };
-        let future = common.load_log(follower.log_tail, Some(end));
+        let future = track!(common.load_log(start, Some(end)))?;
         self.tasks.insert(reply.header.sender.clone(), future);
         Ok(())
     }
@@ -164,20 +511,51 @@ impl<IO: Io> FollowersManager<IO> {
         self.config = config.clone();
     }
 
+    /// 同じIDを持つ複数のノードが存在する疑いがあり、その応答を信用すべきではない場合には`true`を返す.
+    fn is_ignored(&self, common: &Common<IO>, node: &NodeId) -> bool {
+        common.options().ignore_suspected_duplicate_nodes
+            && matches!(self.followers.get(node), Some(f) if f.duplicate_suspected)
+    }
+    fn sample_rtt(&mut self, reply: &AppendEntriesReply, now: Instant) {
+        let sent_at = self
+            .broadcast_times
+            .iter()
+            .find(|&&(seq_no, _)| seq_no == reply.header.seq_no)
+            .map(|&(_, t)| t);
+        let f = self.followers.get_mut(&reply.header.sender);
+        if let (Some(sent_at), Some(f)) = (sent_at, f) {
+            // TCPのSRTTと同様に、直近の計測値の重みを1/8とした移動平均を取る
+            let sample = now.saturating_duration_since(sent_at);
+            f.rtt = Some(f.rtt.map_or(sample, |rtt| (rtt * 7 + sample) / 8));
+        }
+    }
     fn update_follower_state(&mut self, common: &Common<IO>, reply: &AppendEntriesReply) -> bool {
         let follower = &mut self
             .followers
             .get_mut(&reply.header.sender)
             .expect("Never fails");
-        if follower.last_seq_no < reply.header.seq_no {
+        let is_newest = follower.last_seq_no < reply.header.seq_no;
+        if is_newest {
             follower.last_seq_no = reply.header.seq_no;
         }
         match *reply {
+            AppendEntriesReply { removed: true, .. } => {
+                // フォロワーはログの追記を行っていないので、報告された終端位置は信用しない
+                follower.removed = true;
+                false
+            }
             AppendEntriesReply { busy: true, .. } => false,
             AppendEntriesReply { log_tail, .. } if follower.synced => {
                 let updated = follower.log_tail < log_tail.index;
                 if updated {
                     follower.log_tail = log_tail.index;
+                } else if is_newest
+                    && log_tail.index < follower.log_tail
+                    && log_tail.index.as_u64() != 0
+                {
+                    // 同じ選挙期間中に、より新しい応答で報告されたログの終端が後退することはあり得ない
+                    // => 同じIDを持つ別のノードが存在する可能性がある
+                    follower.duplicate_suspected = true;
                 } else if log_tail.index.as_u64() == 0 && follower.log_tail.as_u64() != 0 {
                     // NOTE: followerのデータがクリアされたものと判断する
                     // FIXME: ちゃんとした実装にする(e.g., ノードに再起動毎に替わるようなIDを付与して、その一致を確認する)
@@ -185,7 +563,12 @@ impl<IO: Io> FollowersManager<IO> {
                 }
                 updated
             }
-            AppendEntriesReply { log_tail, .. } => {
+            AppendEntriesReply {
+                log_tail,
+                conflict_term,
+                conflict_index,
+                ..
+            } => {
                 let leader_term = common
                     .log()
                     .get_record(log_tail.index)
@@ -194,7 +577,17 @@ impl<IO: Io> FollowersManager<IO> {
                 if follower.synced {
                     follower.log_tail = log_tail.index;
                 } else {
-                    follower.log_tail = log_tail.index.as_u64().saturating_sub(1).into();
+                    let prev = log_tail.index.as_u64().saturating_sub(1).into();
+                    follower.log_tail = match (conflict_term, conflict_index) {
+                        (Some(term), Some(index)) => {
+                            // 分岐している`Term`単位で、同期点の探索を進める:
+                            // - リーダのログにもその`Term`が存在する場合には、その終端から
+                            // - 存在しない場合には、フォロワーのログ内でのその`Term`の開始位置から
+                            let hint = common.log().term_range(term).map_or(index, |(_, end)| end);
+                            cmp::min(hint, prev)
+                        }
+                        _ => prev,
+                    };
                 }
                 follower.synced
             }
@@ -202,6 +595,11 @@ impl<IO: Io> FollowersManager<IO> {
     }
 }
 
+// フロー制御が有効な場合には`true`を返す.
+fn is_flow_controlled(options: &ReplicatedLogOptions) -> bool {
+    options.max_inflight_entries != 0 || options.max_append_bytes != 0
+}
+
 #[derive(Debug)]
 struct Follower {
     pub obsolete_seq_no: SequenceNumber,
@@ -209,6 +607,34 @@ struct Follower {
     pub log_tail: LogIndex,
     pub last_seq_no: SequenceNumber,
     pub synced: bool,
+
+    // 直近のハートビートに対する応答有無 (`true`なら応答あり).
+    pub heartbeats: VecDeque<bool>,
+    pub heartbeat_acked: bool,
+
+    // 連続して応答が得られなかったハートビートの回数.
+    pub missed_heartbeats: u64,
+
+    // 直近のハートビートへの応答が得られないまま経過したtick数 (ハートビートを送信しなかったtickも含む).
+    pub ticks_since_ack: u64,
+    pub unhealthy: bool,
+
+    // 同じIDを持つ複数のノードが存在する疑いがあるかどうか.
+    pub duplicate_suspected: bool,
+
+    // ラウンドトリップ時間の平滑値 (未計測の場合は`None`).
+    pub rtt: Option<Duration>,
+
+    // 送信済みで、まだ応答を受け取っていないエントリ群 (フロー制御用).
+    //
+    // 要素は、送信したエントリ群の終端位置と、その合計バイト数.
+    pub inflight: VecDeque<(LogIndex, u64)>,
+
+    // `inflight`に含まれるエントリ群の送信に、最後に使用したシーケンス番号.
+    pub inflight_seq_no: SequenceNumber,
+
+    // フォロワーから、クラスタから取り除かれた旨の応答を受け取ったかどうか.
+    pub removed: bool,
 }
 impl Follower {
     pub fn new() -> Self {
@@ -218,6 +644,93 @@ impl Follower {
             log_tail: LogIndex::new(0),
             last_seq_no: SequenceNumber::new(0),
             synced: false,
+
+            heartbeats: VecDeque::new(),
+            heartbeat_acked: false,
+            missed_heartbeats: 0,
+            ticks_since_ack: 0,
+            unhealthy: false,
+
+            duplicate_suspected: false,
+
+            rtt: None,
+
+            inflight: VecDeque::new(),
+            inflight_seq_no: SequenceNumber::new(0),
+
+            removed: false,
+        }
+    }
+    // 次に送信すべきエントリの位置を返す.
+    pub fn next_index(&self) -> LogIndex {
+        self.inflight.back().map_or(self.log_tail, |&(end, _)| end)
+    }
+
+    // 送信枠の残り(エントリ数とバイト数)を返す.
+    pub fn window_room(&self, options: &ReplicatedLogOptions) -> (u64, u64) {
+        let room = |limit: usize, used: u64| match limit {
+            0 => u64::MAX,
+            n => (n as u64).saturating_sub(used),
+        };
+        let entries = self
+            .next_index()
+            .as_u64()
+            .saturating_sub(self.log_tail.as_u64());
+        let bytes = self.inflight.iter().map(|&(_, size)| size).sum();
+        (
+            room(options.max_inflight_entries, entries),
+            room(options.max_append_bytes, bytes),
+        )
+    }
+
+    // `suffix`の先頭から、送信枠に収まるエントリ数を返す.
+    //
+    // `suffix`の開始位置が`next_index`と一致しない場合には`0`が返される.
+    // ただし、応答待ちのエントリ群が存在しない場合には、送信枠を超える場合でも、最低一つのエントリは収まるものとして扱う.
+    pub fn fit_len(&self, options: &ReplicatedLogOptions, suffix: &LogSuffix) -> usize {
+        if suffix.head.index != self.next_index() {
+            return 0;
+        }
+        let (entries, bytes) = self.window_room(options);
+        let mut len = 0;
+        let mut total = 0;
+        for e in &suffix.entries {
+            total += super::entry_size(e);
+            if len as u64 >= entries || (bytes < total && (len != 0 || !self.inflight.is_empty())) {
+                break;
+            }
+            len += 1;
+        }
+        len
+    }
+
+    // 送信したエントリ群を、応答待ちとして記録する.
+    pub fn record_sent(&mut self, suffix: &LogSuffix, seq_no: SequenceNumber) {
+        if suffix.entries.is_empty() || suffix.tail().index <= self.next_index() {
+            return;
+        }
+        let bytes = suffix.entries.iter().map(super::entry_size).sum();
+        self.inflight.push_back((suffix.tail().index, bytes));
+        self.inflight_seq_no = seq_no;
+    }
+
+    // 応答に合わせて、応答待ちのエントリ群を更新する.
+    pub fn handle_inflight_ack(&mut self, reply: &AppendEntriesReply) {
+        while matches!(self.inflight.front(), Some(&(end, _)) if end <= self.log_tail) {
+            self.inflight.pop_front();
+        }
+        if !self.synced || (!reply.busy && self.inflight_seq_no <= reply.header.seq_no) {
+            // 最後の送信以降のメッセージも処理済みなので、残りのエントリ群は(欠損等により)届かなかったものと判断する
+            self.inflight.clear();
+        }
+    }
+
+    pub fn heartbeat_ratio(&self) -> u32 {
+        if self.heartbeats.is_empty() {
+            1000
+        } else {
+            let acked = self.heartbeats.iter().filter(|&&acked| acked).count();
+            (acked * 1000 / self.heartbeats.len()) as u32
         }
     }
 }
@@ -1,14 +1,22 @@
+use futures::sync::oneshot;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
 use self::appender::LogAppender;
 use self::follower::FollowersManager;
 use super::{Common, NextState};
 use crate::election::Role;
 use crate::log::{LogEntry, LogIndex, LogSuffix, ProposalId};
 use crate::message::{Message, SequenceNumber};
-use crate::{ErrorKind, Io, Result};
+use crate::node::NodeId;
+use crate::{CommandTrace, ErrorKind, Event, FollowerProgress, Io, PeerLag, ReadIndex, Result};
 
 mod appender;
 mod follower;
 
+/// コミットのスループットの計測に用いる、直近のコミット地点の記録数.
+const COMMIT_SAMPLES: usize = 16;
+
 /// 選挙で選ばれたリーダ.
 ///
 /// 主に、以下のようなことを行う:
@@ -27,6 +35,27 @@ pub struct Leader<IO: Io> {
     followers: FollowersManager<IO>,
     appender: LogAppender<IO>,
     commit_lower_bound: LogIndex,
+    elected_at_tick: u64,
+
+    // 選挙期間中に追記したエントリ群のサイズ(複製の遅れの推定用).
+    // 全フォロワーに複製済みのものは、順次破棄される.
+    entry_sizes: VecDeque<(LogIndex, u64)>,
+
+    // 実行中のリーダシップの移譲.
+    transfer: Option<LeadershipTransfer>,
+
+    // 非休止中は連続したアイドル状態のハートビートの回数、
+    // 休止中は次のハートビートの送信までに見送るタイムアウトの回数.
+    quiescence_rounds: u64,
+
+    // ReadIndex方式の読み込みの、過半数の確認待ちの要求群.
+    pending_reads: VecDeque<PendingRead>,
+
+    // 提案の追跡情報(古いものから順に並ぶ).
+    command_traces: VecDeque<(ProposalId, CommandTrace)>,
+
+    // 直近のコミット地点とその時刻(コミットのスループットの推定用).
+    commit_samples: VecDeque<(Instant, LogIndex)>,
 }
 impl<IO: Io> Leader<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -46,10 +75,42 @@ impl<IO: Io> Leader<IO> {
             followers,
             appender,
             commit_lower_bound: term_start_index,
+            elected_at_tick: common.ticks(),
+            entry_sizes: VecDeque::new(),
+            transfer: None,
+            quiescence_rounds: 0,
+            pending_reads: VecDeque::new(),
+            command_traces: VecDeque::new(),
+            commit_samples: VecDeque::new(),
         }
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        self.broadcast_empty_entries(common);
+        // 経過時間は、休止状態でハートビートを見送る場合も含めて、タイムアウトの度に数える
+        let local = common.local_node().id.clone();
+        self.followers.record_elapsed_tick(&local);
+        let threshold = common.options().quorum_step_down_ticks;
+        if threshold != 0 && !self.followers.has_recent_quorum_ack(&local, threshold) {
+            // 過半数と通信できないまま選挙のタイムアウト相当の期間が経過したので、リーダから退く
+            // (ネットワーク分断によって取り残されたリーダが、古い読み込み結果を返し続けることを防ぐ)
+            return Ok(Some(common.transit_to_follower(local, None)));
+        }
+
+        if self.skip_quiescent_heartbeat(common) {
+            common.set_timeout(Role::Leader);
+        } else {
+            self.followers.record_heartbeat_round(common);
+            self.broadcast_empty_entries(common);
+        }
+        if let Some(mut transfer) = self.transfer.take() {
+            transfer.rounds += 1;
+            if transfer.rounds < common.options().leadership_transfer_max_rounds {
+                self.transfer = Some(transfer);
+            } else {
+                // 移譲先が追い付かない、ないし、移譲先の選挙が完了しなかった
+                let target = transfer.target;
+                common.push_event(Event::LeadershipTransferAborted { target });
+            }
+        }
         Ok(None)
     }
     pub fn handle_message(
@@ -58,19 +119,36 @@ impl<IO: Io> Leader<IO> {
         message: Message,
     ) -> Result<NextState<IO>> {
         if let Message::AppendEntriesReply(reply) = message {
-            let updated = self.followers.handle_append_entries_reply(&common, &reply);
+            let updated = self.followers.handle_append_entries_reply(common, &reply);
 
             track!(self.followers.log_sync(common, &reply))?;
 
             if updated {
                 track!(self.handle_committed_log(common))?;
             }
+            self.check_leadership_transfer(common);
+            self.check_pending_reads(common);
+            self.update_command_traces(common);
         }
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
+        if common.is_removed() {
+            // 自身を含まない構成がコミットされたので、リーダから退く
+            let local = common.local_node().id.clone();
+            return Ok(Some(common.transit_to_follower(local, None)));
+        }
+        if common.is_shutting_down() && self.transfer.is_none() {
+            // 停止処理中で、リーダシップの移譲が行えなかった(ないし、中止された)ので、移譲を待たずにリーダから退く
+            let local = common.local_node().id.clone();
+            return Ok(Some(common.transit_to_follower(local, None)));
+        }
+        self.appender.flush(common);
         while let Some(appended) = track!(self.appender.run_once(common))? {
-            for e in &appended.entries {
+            for (i, e) in appended.entries.iter().enumerate() {
+                self.entry_sizes
+                    .push_back((appended.head.index + i, entry_size(e)));
+
                 if let LogEntry::Config { ref config, .. } = *e {
                     self.followers.handle_config_updated(config);
 
@@ -101,25 +179,334 @@ impl<IO: Io> Leader<IO> {
         }
         track!(self.handle_change_config(common))?;
         track!(self.followers.run_once(common))?;
+
+        let min_log_tail = self.followers.min_log_tail();
+        while matches!(self.entry_sizes.front(), Some(&(i, _)) if i < min_log_tail) {
+            self.entry_sizes.pop_front();
+        }
+        self.update_command_traces(common);
         Ok(None)
     }
     pub fn propose(&mut self, common: &mut Common<IO>, entry: LogEntry) -> ProposalId {
-        let proposal_id = self.next_proposal_id(common);
-        self.appender.append(common, vec![entry]);
-        proposal_id
+        self.propose_entries(common, vec![entry])[0]
+    }
+
+    /// バッファに積まれている提案の追記を、次のポーリングを待たずに開始する.
+    ///
+    /// 既に追記中の場合には、その完了後に追記される.
+    pub fn flush_proposals(&mut self, common: &mut Common<IO>) {
+        self.appender.flush(common);
+    }
+
+    /// 複数のコマンドをまとめて提案する.
+    ///
+    /// コマンド群は、一つの`LogSuffix`としてローカルログに追記され、フォロワーに送信される.
+    pub fn propose_batch(
+        &mut self,
+        common: &mut Common<IO>,
+        commands: Vec<Vec<u8>>,
+    ) -> Vec<ProposalId> {
+        let term = common.term();
+        let entries = commands
+            .into_iter()
+            .map(|command| LogEntry::Command { term, command })
+            .collect();
+        self.propose_entries(common, entries)
+    }
+
+    /// 追記が完了していないために、リーダシップを失うと破棄される提案群を返す.
+    pub fn unappended_proposals(&self, common: &Common<IO>) -> Vec<ProposalId> {
+        self.appender.unappended_proposals(common)
     }
     pub fn heartbeat_syn(&mut self, common: &mut Common<IO>) -> SequenceNumber {
         let seq_no = common.next_seq_no();
         self.broadcast_empty_entries(common);
         seq_no
     }
+
+    /// ReadIndex方式の読み込みのための、リーダシップの確認を開始する.
+    ///
+    /// 詳細は`ReplicatedLog::confirm_read_index`を参照のこと.
+    pub fn confirm_read_index(&mut self, common: &mut Common<IO>) -> ReadIndex {
+        let (reply, rx) = oneshot::channel();
+        self.pending_reads.push_back(PendingRead {
+            read_index: LogIndex::new(0),
+            seq_no: None,
+            reply,
+        });
+        self.check_pending_reads(common);
+        ReadIndex::new(rx)
+    }
+
     pub fn proposal_queue_len(&self, common: &Common<IO>) -> usize {
         self.appender.unappended_log_tail(common) - common.log().tail().index
     }
     pub fn last_heartbeat_ack(&self) -> SequenceNumber {
         self.followers.latest_hearbeat_ack()
     }
+    pub fn heartbeat_health(&self, common: &Common<IO>) -> Vec<(NodeId, u32)> {
+        self.followers.heartbeat_health(&common.local_node().id)
+    }
+
+    /// 各フォロワーの複製の遅れを返す.
+    pub fn replication_lag(&self, common: &Common<IO>) -> Vec<PeerLag> {
+        let bytes_behind = |log_tail: LogIndex| {
+            self.entry_sizes
+                .iter()
+                .filter(|&&(i, _)| log_tail <= i)
+                .map(|&(_, size)| size)
+                .sum()
+        };
+        self.followers.replication_lag(
+            &common.local_node().id,
+            common.log().tail().index,
+            bytes_behind,
+        )
+    }
+
+    /// 新しい提案がコミットされるまでに要する時間の、大まかな推定値を返す.
+    ///
+    /// 投票権を有するメンバの過半数とのラウンドトリップ時間(`Io::now`を用いて計測される)を基準とし、
+    /// 未コミットのエントリ群が存在する場合には、それらの消化を待つ分を加算する.
+    ///
+    /// 消化を待つ分は、未コミットのエントリ数を、直近に観測されたコミットのスループット(エントリ数/時間)で
+    /// 割った値となる. ただし、最低でも一往復分は加算される.
+    /// スループットが未観測の場合(e.g., リーダ選出直後)には一往復分のみが加算されるため、
+    /// 大量のエントリが滞留している状況では、実際の所要時間よりも短い値(下限値)となる.
+    ///
+    /// 過半数のメンバとのラウンドトリップ時間が未計測の場合には`None`が返される.
+    pub fn estimated_commit_latency(&self, common: &Common<IO>) -> Option<Duration> {
+        let rtt = self.followers.quorum_rtt(&common.local_node().id)?;
+        let backlog = common.log().tail().index - common.log().committed_tail().index;
+        if backlog == 0 && !self.appender.is_busy() {
+            return Some(rtt);
+        }
+
+        let drain = self
+            .commit_throughput()
+            .map(|(entries, elapsed)| {
+                let nanos = elapsed.as_nanos() * backlog as u128 / entries as u128;
+                Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64)
+            })
+            .unwrap_or(rtt);
+        Some(rtt + drain.max(rtt))
+    }
+
+    /// 直近に観測されたコミットのスループットを、(コミットされたエントリ数, 所要時間)の形式で返す.
+    fn commit_throughput(&self) -> Option<(usize, Duration)> {
+        let (first_time, first_index) = *self.commit_samples.front()?;
+        let (last_time, last_index) = *self.commit_samples.back()?;
+        let entries = last_index - first_index;
+        let elapsed = last_time.saturating_duration_since(first_time);
+        if entries == 0 || elapsed == Duration::from_secs(0) {
+            None
+        } else {
+            Some((entries, elapsed))
+        }
+    }
+
+    /// 各フォロワーへの複製の進み具合を返す.
+    pub fn replication_progress(&self, common: &Common<IO>) -> HashMap<NodeId, FollowerProgress> {
+        self.followers
+            .replication_progress(&common.local_node().id, common.log().head().index)
+    }
+
+    /// `proposal`の追跡情報を返す.
+    pub fn command_trace(&self, proposal: &ProposalId) -> Option<CommandTrace> {
+        self.command_traces
+            .iter()
+            .find(|(id, _)| id == proposal)
+            .map(|(_, trace)| trace.clone())
+    }
+
+    /// 現在の状態に基づいて、未完了の追跡情報の各時点を記録する.
+    pub fn update_command_traces(&mut self, common: &Common<IO>) {
+        let tick = common.ticks();
+        let log_tail = common.log().tail().index;
+        let committed_tail = common.log().committed_tail().index;
+        let applied_index = common.applied_index();
+        for (id, trace) in self.command_traces.iter_mut() {
+            if trace.applied_tick.is_some() {
+                continue;
+            }
+            if trace.appended_tick.is_none() && id.index < log_tail {
+                trace.appended_tick = Some(tick);
+            }
+            for node in common.config().members() {
+                if self.followers.is_caught_up(node, id.index + 1)
+                    && !trace.replicated_to.iter().any(|(n, _)| n == node)
+                {
+                    trace.replicated_to.push((node.clone(), tick));
+                }
+            }
+            if trace.committed_tick.is_none() && id.index < committed_tail {
+                trace.committed_tick = Some(tick);
+            }
+            if id.index < applied_index {
+                trace.applied_tick = Some(tick);
+            }
+        }
+    }
+
+    pub fn peer_needs_snapshot(&self, common: &Common<IO>, node: &NodeId) -> bool {
+        self.followers
+            .needs_snapshot(node, common.log().head().index)
+    }
+
+    /// リーダに選出されてから経過したtick数を返す.
+    pub fn leadership_age_ticks(&self, common: &Common<IO>) -> u64 {
+        common.ticks() - self.elected_at_tick
+    }
+
+    /// リーダシップを`target`に移譲する.
+    ///
+    /// 移譲中は新規の提案を受け付けない.
+    /// `target`のローカルログが、リーダのログの末尾(未追記の提案を含む)に追い付いた時点で、
+    /// `target`に即座に選挙を開始するように指示する.
+    /// 移譲先が選挙に勝利すると、ローカルノードは、より大きな`term`を持つ投票依頼を受けてフォロワーに遷移する.
+    ///
+    /// `ReplicatedLogOptions::leadership_transfer_max_rounds`で指定された回数のハートビートを送信しても
+    /// 移譲が完了しない場合には、移譲は中止され`Event::LeadershipTransferAborted`が発行される.
+    pub fn transfer_leadership(&mut self, common: &mut Common<IO>, target: NodeId) -> Result<()> {
+        track_assert!(
+            target != common.local_node().id
+                && common.config().members().any(|n| *n == target)
+                && !common.config().is_witness(&target),
+            ErrorKind::InvalidInput,
+            "Not a transferable member: target={:?}, config={:?}",
+            target,
+            common.config()
+        );
+        track_assert!(
+            self.transfer.is_none(),
+            ErrorKind::Busy,
+            "Another leadership transfer is in progress: target={:?}",
+            self.transfer.as_ref().map(|t| &t.target)
+        );
+        self.transfer = Some(LeadershipTransfer {
+            target,
+            rounds: 0,
+            timeout_now_sent: false,
+        });
+        self.check_leadership_transfer(common);
+        Ok(())
+    }
+
+    /// ノードの停止に備えて、ローカルログの複製が最も進んでいるフォロワーに、リーダシップを移譲する.
+    ///
+    /// 移譲先となるフォロワーが存在しない場合や、移譲が中止された場合には、次の`run_once`の呼び出しでリーダから退く.
+    /// 既に別の移譲が進行中の場合には、そちらの完了を待つ.
+    pub fn begin_shutdown(&mut self, common: &mut Common<IO>) -> Result<()> {
+        if self.transfer.is_some() {
+            return Ok(());
+        }
+        let local = common.local_node().id.clone();
+        if let Some(target) = self.followers.most_up_to_date_follower(&local) {
+            track!(self.transfer_leadership(common, target))?;
+        }
+        Ok(())
+    }
+
+    /// リーダシップの移譲中かどうかを判定する.
+    pub fn is_transferring_leadership(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// 投票権を有するメンバの過半数との通信を失っている場合には`true`を返す.
+    ///
+    /// 直近`ReplicatedLogOptions::quorum_loss_ticks`回のハートビートのいずれにも応答しなかったメンバが、
+    /// 過半数に達した場合に、通信を失ったものと判定される.
+    pub fn has_lost_quorum(&self, common: &Common<IO>) -> bool {
+        let threshold = common.options().quorum_loss_ticks;
+        threshold != 0
+            && !self
+                .followers
+                .has_quorum_contact(&common.local_node().id, threshold)
+    }
+
+    /// 休止状態の判定を行い、今回のハートビートの送信を見送るべき場合には`true`を返す.
+    ///
+    /// `ReplicatedLogOptions::quiescence_idle_rounds`回連続でアイドル状態だった場合に休止状態に移行し、
+    /// 以後は`ReplicatedLogOptions::quiescent_heartbeat_interval`回に一度だけハートビートを送信する.
+    /// ただし、直前のハートビートに過半数が応答していない場合には、休止状態を抜ける.
+    fn skip_quiescent_heartbeat(&mut self, common: &mut Common<IO>) -> bool {
+        let idle_rounds = common.options().quiescence_idle_rounds;
+        if idle_rounds == 0 {
+            return false;
+        }
+
+        let tail = self.appender.unappended_log_tail(common);
+        let is_idle = self.transfer.is_none() && tail <= self.followers.min_log_tail();
+        if !is_idle {
+            self.exit_quiescence(common);
+            return false;
+        }
 
+        if common.is_quiescent() {
+            if !self
+                .followers
+                .has_quorum_heartbeat_ack(&common.local_node().id)
+            {
+                // 直前のハートビートに過半数が応答していないので、休止状態を抜けて通信状況を確認する
+                self.exit_quiescence(common);
+                return false;
+            }
+            if self.quiescence_rounds > 0 {
+                self.quiescence_rounds -= 1;
+                return true;
+            }
+        } else {
+            self.quiescence_rounds += 1;
+            if self.quiescence_rounds < idle_rounds {
+                return false;
+            }
+            // 休止状態への移行は、このハートビートによってフォロワーに通知される
+            common.set_quiescent(true);
+        }
+        self.quiescence_rounds = common
+            .options()
+            .quiescent_heartbeat_interval
+            .saturating_sub(1);
+        false
+    }
+    fn exit_quiescence(&mut self, common: &mut Common<IO>) {
+        self.quiescence_rounds = 0;
+        if common.is_quiescent() {
+            common.set_quiescent(false);
+        }
+    }
+    fn check_pending_reads(&mut self, common: &mut Common<IO>) {
+        let committed = common.log().committed_tail();
+        if committed.prev_term != common.term() {
+            // 現在の選挙期間のエントリがコミットされるまでは、
+            // 前の選挙期間にコミット済みの領域を正確には把握できない
+            return;
+        }
+
+        if self.pending_reads.iter().any(|r| r.seq_no.is_none()) {
+            // 新規の要求群は、一回のハートビートでまとめて確認する
+            let seq_no = self.heartbeat_syn(common);
+            for r in self.pending_reads.iter_mut().filter(|r| r.seq_no.is_none()) {
+                r.read_index = committed.index;
+                r.seq_no = Some(seq_no);
+            }
+        }
+
+        let ack = self.last_heartbeat_ack();
+        while matches!(self.pending_reads.front(), Some(r) if r.seq_no <= Some(ack)) {
+            let r = self.pending_reads.pop_front().expect("Never fails");
+            let _ = r.reply.send(r.read_index);
+        }
+    }
+    fn check_leadership_transfer(&mut self, common: &mut Common<IO>) {
+        let tail = self.appender.unappended_log_tail(common);
+        if let Some(ref mut transfer) = self.transfer {
+            if !transfer.timeout_now_sent && self.followers.is_caught_up(&transfer.target, tail) {
+                common.rpc_caller().send_timeout_now(&transfer.target);
+                transfer.timeout_now_sent = true;
+            }
+        }
+    }
     fn handle_change_config(&mut self, common: &mut Common<IO>) -> Result<()> {
         if common.config().state().is_stable() {
             return Ok(());
@@ -152,11 +539,64 @@ impl<IO: Io> Leader<IO> {
         let index = self.appender.unappended_log_tail(common);
         ProposalId { term, index }
     }
+    fn propose_entries(
+        &mut self,
+        common: &mut Common<IO>,
+        entries: Vec<LogEntry>,
+    ) -> Vec<ProposalId> {
+        if entries.is_empty() {
+            return Vec::new();
+        }
+        self.exit_quiescence(common);
+        let head = self.next_proposal_id(common);
+        let proposal_ids = (0..entries.len())
+            .map(|i| ProposalId {
+                term: head.term,
+                index: head.index + i,
+            })
+            .collect::<Vec<_>>();
+        if common.options().proposal_batch_size == 0 {
+            self.appender.append(common, entries);
+        } else {
+            self.appender.buffer(common, entries);
+        }
+        for &proposal_id in &proposal_ids {
+            self.start_command_trace(common, proposal_id);
+        }
+        proposal_ids
+    }
+    fn start_command_trace(&mut self, common: &Common<IO>, proposal_id: ProposalId) {
+        let capacity = common.options().command_trace_capacity;
+        if capacity == 0 {
+            return;
+        }
+        let trace = CommandTrace {
+            proposed_tick: common.ticks(),
+            appended_tick: None,
+            replicated_to: Vec::new(),
+            committed_tick: None,
+            applied_tick: None,
+        };
+        self.command_traces.push_back((proposal_id, trace));
+        while self.command_traces.len() > capacity {
+            self.command_traces.pop_front();
+        }
+    }
     fn broadcast_slice(&mut self, common: &mut Common<IO>, slice: LogSuffix) {
-        self.followers
-            .set_last_broadcast_seq_no(common.next_seq_no());
+        let seq_no = common.next_seq_no();
+        self.followers.set_last_broadcast_seq_no(seq_no);
+        if let Some(now) = common.now() {
+            self.followers.record_broadcast_time(seq_no, now);
+        }
         common.set_timeout(Role::Leader);
-        common.rpc_caller().broadcast_append_entries(slice);
+        if common.options().max_broadcast_sends_per_run != 0 {
+            let priority = self.followers.broadcast_priority(&common.local_node().id);
+            common.set_broadcast_priority(priority);
+        }
+        let throttled = self.followers.throttle_broadcast(common, &slice, seq_no);
+        common
+            .rpc_caller()
+            .broadcast_append_entries(slice, &throttled);
     }
     fn broadcast_empty_entries(&mut self, common: &mut Common<IO>) {
         let head = common.log().tail();
@@ -166,8 +606,9 @@ impl<IO: Io> Leader<IO> {
     }
     fn handle_committed_log(&mut self, common: &mut Common<IO>) -> Result<()> {
         let committed = self.followers.committed_log_tail();
-        if committed < self.commit_lower_bound {
+        if committed <= self.commit_lower_bound {
             // コミット済みのログ領域でも、現在のtermよりも前に追加されたものはまだコミットできない.
+            // (現在のtermの最初のエントリ(i.e., `Noop`)が過半数に複製された時点で、まとめてコミット可能となる)
             // 詳細は論文の「5.4.2 Committing entries from previous terms」を参照のこと.
             return Ok(());
         }
@@ -188,6 +629,1460 @@ impl<IO: Io> Leader<IO> {
         // 履歴に新しいコミット済み領域を記録する.
         // 新規コミット済み領域の処理は`Common::run_once`関数の中で行われる.
         track!(common.handle_log_committed(committed))?;
+        if let Some(now) = common.now() {
+            self.commit_samples.push_back((now, committed));
+            if self.commit_samples.len() > COMMIT_SAMPLES {
+                self.commit_samples.pop_front();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ReadIndex方式の読み込みの要求.
+///
+/// リーダでなくなった(i.e., `Leader`が破棄された)場合には`reply`も破棄され、
+/// 対応する`ReadIndex`は`ErrorKind::NotLeader`エラーで完了する.
+struct PendingRead {
+    read_index: LogIndex,
+
+    // 確認用のハートビートのシーケンス番号 (未送信の場合は`None`).
+    seq_no: Option<SequenceNumber>,
+
+    reply: oneshot::Sender<LogIndex>,
+}
+
+struct LeadershipTransfer {
+    target: NodeId,
+
+    // 移譲の開始後に経過したハートビートの回数.
+    rounds: usize,
+
+    // 移譲先に`TimeoutNowCast`を送信済みかどうか.
+    timeout_now_sent: bool,
+}
+
+// フロー制御等で使用される、エントリのサイズ(の推定値)を返す.
+fn entry_size(entry: &LogEntry) -> u64 {
+    match *entry {
+        LogEntry::Command { ref command, .. } => command.len() as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor;
+    use futures::Async;
+    use prometrics::metrics::MetricBuilder;
+    use std::mem;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use trackable::result::TestResult;
+
+    use crate::election::Term;
+    use crate::log::{Log, LogPosition, LogPrefix};
+    use crate::message::{AppendEntriesCall, AppendEntriesReply, MessageHeader};
+    use crate::metrics::NodeStateMetrics;
+    use crate::node_state::RoleState;
+    use crate::test_util::tests::{NoopNotify, TestIo, TestIoBuilder};
+    use crate::{Event, ReplicatedLogOptions};
+
+    fn reply(common: &Common<TestIo>, sender: &str, seq_no: SequenceNumber) -> Message {
+        AppendEntriesReply {
+            header: MessageHeader {
+                sender: sender.into(),
+                destination: common.local_node().id.clone(),
+                seq_no,
+                term: common.term(),
+            },
+            log_tail: common.log().tail(),
+            busy: false,
+            removed: false,
+            conflict_term: None,
+            conflict_index: None,
+        }
+        .into()
+    }
+
+    #[test]
+    fn heartbeat_health_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            heartbeat_health_window: 4,
+            peer_unhealthy_threshold_per_mille: 500,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+
+        // "node3"だけがハートビートに応答しない
+        for _ in 0..4 {
+            let seq_no = common.next_seq_no();
+            track!(leader.handle_timeout(&mut common))?;
+            let message = reply(&common, "node2", seq_no);
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        track!(leader.handle_timeout(&mut common))?;
+
+        assert_eq!(
+            leader.heartbeat_health(&common),
+            vec![("node2".into(), 1000), ("node3".into(), 0)]
+        );
+
+        let mut unhealthy = Vec::new();
+        while let Some(e) = common.next_event() {
+            if let Event::PeerUnhealthy { .. } = e {
+                unhealthy.push(e);
+            }
+        }
+        assert_eq!(
+            unhealthy,
+            vec![Event::PeerUnhealthy {
+                node: "node3".into(),
+                ratio_per_mille: 0,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn heartbeat_syn_reaches_every_peer() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        sent_messages.lock().expect("Never fails").clear();
+
+        leader.heartbeat_syn(&mut common);
+
+        let committed = common.log().committed_tail().index;
+        let destinations = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .map(|m| match m {
+                Message::AppendEntriesCall(AppendEntriesCall {
+                    header,
+                    committed_log_tail,
+                    ..
+                }) => {
+                    assert_eq!(*committed_log_tail, committed);
+                    header.destination.clone()
+                }
+                _ => panic!("Unexpected message: {:?}", m),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(destinations, vec!["node2".into(), "node3".into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn replication_lag_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        let term = common.term();
+        leader.propose(
+            &mut common,
+            LogEntry::Command {
+                term,
+                command: vec![0; 10],
+            },
+        );
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(2));
+
+        let lag = |leader: &Leader<TestIo>, common: &Common<TestIo>| {
+            let lag = leader.replication_lag(common);
+            assert_eq!(lag.len(), 1);
+            (lag[0].entries_behind, lag[0].bytes_behind_estimate)
+        };
+        assert_eq!(lag(&leader, &common), (2, 10));
+
+        // NOOPエントリ分だけ追い付く
+        let mut message = reply(&common, "node2", SequenceNumber::new(0));
+        if let Message::AppendEntriesReply(ref mut m) = message {
+            m.log_tail.index = LogIndex::new(1);
+        }
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(lag(&leader, &common), (1, 10));
+
+        // 完全に追い付く
+        let message = reply(&common, "node2", SequenceNumber::new(1));
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(lag(&leader, &common), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn replication_progress_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        io.cluster = io
+            .cluster
+            .with_learners(vec!["node3".into()].into_iter().collect());
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+
+        let progress = leader.replication_progress(&common);
+        assert_eq!(progress.len(), 2);
+        let initial = FollowerProgress {
+            match_index: LogIndex::new(0),
+            next_index: LogIndex::new(0),
+            sending_snapshot: false,
+            is_learner: false,
+        };
+        assert_eq!(progress[&"node2".into()], initial);
+        assert!(progress[&"node3".into()].is_learner);
+
+        // フォロワーの応答に応じて更新される
+        let message = reply(&common, "node2", SequenceNumber::new(0));
+        track!(leader.handle_message(&mut common, message))?;
+        let progress = leader.replication_progress(&common);
+        assert_eq!(
+            progress[&"node2".into()],
+            FollowerProgress {
+                match_index: LogIndex::new(1),
+                next_index: LogIndex::new(1),
+                ..initial
+            }
+        );
+        assert_eq!(progress[&"node3".into()].match_index, LogIndex::new(0));
+        Ok(())
+    }
+
+    #[test]
+    fn log_conflict_hints_skip_whole_terms() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: (0..3)
+                .map(|_| LogEntry::Noop { term: Term::new(0) })
+                .collect(),
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        common.transit_to_candidate();
+        common.transit_to_candidate();
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.term(), Term::new(2));
+
+        // フォロワーのログは、リーダが持たない`Term(1)`のエントリ群(位置`3`から開始)で分岐している
+        let conflict = |common: &Common<TestIo>, hints: bool| -> Message {
+            AppendEntriesReply {
+                header: MessageHeader {
+                    sender: "node2".into(),
+                    destination: common.local_node().id.clone(),
+                    seq_no: SequenceNumber::new(0),
+                    term: common.term(),
+                },
+                log_tail: LogPosition {
+                    prev_term: Term::new(1),
+                    index: LogIndex::new(7),
+                },
+                busy: false,
+                removed: false,
+                conflict_term: if hints { Some(Term::new(1)) } else { None },
+                conflict_index: if hints { Some(LogIndex::new(3)) } else { None },
+            }
+            .into()
+        };
+
+        // ヒントが無い場合には、一つずつ戻る
+        let message = conflict(&common, false);
+        track!(leader.handle_message(&mut common, message))?;
+        let progress = leader.replication_progress(&common);
+        assert_eq!(progress[&"node2".into()].next_index, LogIndex::new(6));
+
+        // ヒントがある場合には、`Term(1)`の開始位置まで一度に戻る
+        let message = conflict(&common, true);
+        track!(leader.handle_message(&mut common, message))?;
+        let progress = leader.replication_progress(&common);
+        assert_eq!(progress[&"node2".into()].next_index, LogIndex::new(3));
+        Ok(())
+    }
+
+    #[test]
+    fn quorum_loss_is_detected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            quorum_loss_ticks: 2,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        let mut leader = Leader::new(&mut common);
+        track!(leader.handle_timeout(&mut common))?;
+        assert!(!leader.has_lost_quorum(&common));
+
+        // 二回続けて、過半数からハートビートの応答が得られなかった
+        track!(leader.handle_timeout(&mut common))?;
+        assert!(!leader.has_lost_quorum(&common));
+        track!(leader.handle_timeout(&mut common))?;
+        assert!(leader.has_lost_quorum(&common));
+
+        // 過半数(自身と"node2")からの応答が得られれば、回復する
+        let seq_no = match sent_messages.lock().expect("Never fails").last() {
+            Some(Message::AppendEntriesCall(m)) => m.header.seq_no,
+            m => panic!("Unexpected message: {:?}", m),
+        };
+        let message = reply(&common, "node2", seq_no);
+        track!(leader.handle_message(&mut common, message))?;
+        track!(leader.handle_timeout(&mut common))?;
+        assert!(!leader.has_lost_quorum(&common));
+        Ok(())
+    }
+
+    #[test]
+    fn leader_steps_down_on_quorum_loss() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .add_member("node4".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            quorum_step_down_ticks: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        let mut leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => panic!("Not a leader"),
+        };
+        let last_heartbeat = || match sent_messages.lock().expect("Never fails").last() {
+            Some(Message::AppendEntriesCall(m)) => m.header.seq_no,
+            m => panic!("Unexpected message: {:?}", m),
+        };
+
+        // 三台のフォロワーのうち、"node3"と"node4"との通信が途絶えた ("node2"は応答を続ける)
+        assert!(track!(leader.handle_timeout(&mut common))?.is_none());
+        for _ in 0..2 {
+            let message = reply(&common, "node2", last_heartbeat());
+            track!(leader.handle_message(&mut common, message))?;
+            assert!(track!(leader.handle_timeout(&mut common))?.is_none());
+            assert_eq!(common.local_node().role, Role::Leader);
+        }
+
+        // 過半数(三台)からの応答が得られないまま閾値に達したので、リーダから退く
+        let message = reply(&common, "node2", last_heartbeat());
+        track!(leader.handle_message(&mut common, message))?;
+        let next = track!(leader.handle_timeout(&mut common))?;
+        assert!(matches!(next, Some(RoleState::Follower(_))));
+        assert_eq!(common.local_node().role, Role::Follower);
+        Ok(())
+    }
+
+    #[test]
+    fn quiescent_leader_steps_down_on_quorum_loss() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            quiescence_idle_rounds: 2,
+            quiescent_heartbeat_interval: 10,
+            quorum_step_down_ticks: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        let mut leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => panic!("Not a leader"),
+        };
+        track!(leader.run_once(&mut common))?;
+        let message = reply(&common, "node1", SequenceNumber::new(0));
+        track!(leader.handle_message(&mut common, message))?;
+
+        // フォロワーが応答している間は、休止状態に移行し、リーダであり続ける
+        for _ in 0..20 {
+            sent_messages.lock().expect("Never fails").clear();
+            assert!(track!(leader.handle_timeout(&mut common))?.is_none());
+            let calls = mem::take(&mut *sent_messages.lock().expect("Never fails"));
+            for m in calls {
+                if let Message::AppendEntriesCall(m) = m {
+                    let message = reply(&common, m.header.destination.as_str(), m.header.seq_no);
+                    track!(leader.handle_message(&mut common, message))?;
+                }
+            }
+        }
+        assert!(common.is_quiescent());
+        assert_eq!(common.local_node().role, Role::Leader);
+
+        // フォロワーとの通信が途絶えると、休止状態を抜けて、ハートビートの回数ではなく経過tick数で判定されるので、
+        // 遅くとも閾値(3)に休止中のハートビートの間隔(10)を加えたtick数以内に、リーダから退く
+        let mut ticks = 0;
+        while common.local_node().role == Role::Leader {
+            ticks += 1;
+            assert!(ticks <= 3 + 10, "The leader did not step down");
+            track!(leader.handle_timeout(&mut common))?;
+        }
+        assert!(!common.is_quiescent());
+        Ok(())
+    }
+
+    #[test]
+    fn broadcast_sends_are_spread_across_runs() -> TestResult {
+        let node_id: NodeId = "node00".into();
+        let mut builder = TestIoBuilder::new();
+        for i in 0..15 {
+            builder = builder.add_member(format!("node{:02}", i).into());
+        }
+        let io = builder.finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            max_broadcast_sends_per_run: 4,
+            ..Default::default()
+        };
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let mut leader = Leader::new(&mut common);
+        track!(common.try_recv_message())?;
+        while common.deferred_messages_len() > 0 {
+            common.reset_broadcast_sends();
+            track!(common.run_once())?;
+        }
+
+        // "node14"のみが最新のログを保持している
+        let seq_no = common.next_seq_no();
+        leader.heartbeat_syn(&mut common);
+        let message = reply(&common, "node14", seq_no);
+        track!(leader.handle_message(&mut common, message))?;
+        while common.deferred_messages_len() > 0 {
+            common.reset_broadcast_sends();
+            track!(common.run_once())?;
+        }
+        common.reset_broadcast_sends();
+        sent_messages.lock().expect("Never fails").clear();
+
+        // 14台のフォロワーへの送信が、4件ずつに分散される
+        leader.heartbeat_syn(&mut common);
+        let mut counts = vec![sent_messages.lock().expect("Never fails").len()];
+        while common.deferred_messages_len() > 0 {
+            common.reset_broadcast_sends();
+            track!(common.run_once())?;
+            counts.push(sent_messages.lock().expect("Never fails").len());
+        }
+        assert_eq!(counts, vec![4, 8, 12, 14]);
+
+        // 最新のログを保持しているフォロワーへの送信は最後になる
+        let sent_messages = sent_messages.lock().expect("Never fails");
+        assert_eq!(
+            sent_messages.last().map(|m| m.header().destination.clone()),
+            Some("node14".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_is_sent_to_follower_behind_log_head() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut handle = io.handle();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
+
+        // ローカルログの先頭は、スナップショットによって`5`まで切り詰められている
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: (0..10)
+                .map(|n| LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![n],
+                })
+                .collect(),
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        track!(common.handle_log_committed(LogIndex::new(10)))?;
+        let snapshot_tail = LogPosition {
+            prev_term: Term::new(0),
+            index: LogIndex::new(5),
+        };
+        track!(common.handle_log_snapshot_installed(snapshot_tail, cluster.clone()))?;
+
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let mut leader = Leader::new(&mut common);
+
+        // "node2"のローカルログは、リーダのログの先頭よりも遅れている
+        let mut message = reply(&common, "node2", common.next_seq_no());
+        if let Message::AppendEntriesReply(ref mut m) = message {
+            m.log_tail = LogPosition {
+                prev_term: Term::new(0),
+                index: LogIndex::new(3),
+            };
+        }
+        let prefix = LogPrefix {
+            tail: snapshot_tail,
+            config: cluster,
+            snapshot: vec![1, 2, 3],
+        };
+        handle.append_log(LogIndex::new(2), LogIndex::new(5), Log::Prefix(prefix));
+        track!(leader.handle_message(&mut common, message))?;
+        assert!(leader.peer_needs_snapshot(&common, &"node2".into()));
+
+        // エントリ群の代わりに、スナップショットが送信される
+        sent_messages.lock().expect("Never fails").clear();
+        track!(leader.run_once(&mut common))?;
+        let snapshots = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .filter_map(|m| match m {
+                Message::InstallSnapshotCast(m) => {
+                    Some((m.header.destination.clone(), m.prefix.tail))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(snapshots, vec![("node2".into(), snapshot_tail)]);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_node_id_is_suspected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            ignore_suspected_duplicate_nodes: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+        let term = common.term();
+        leader.propose(
+            &mut common,
+            LogEntry::Command {
+                term,
+                command: vec![0],
+            },
+        );
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(2));
+        while common.next_event().is_some() {}
+
+        // 一台目の"node2"は、リーダのログに追い付いている
+        let message = reply(&common, "node2", SequenceNumber::new(1));
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(common.next_event(), None);
+
+        // 二台目の"node2"は遅れているので、より新しい応答でログの終端が後退する
+        let mut message = reply(&common, "node2", SequenceNumber::new(2));
+        if let Message::AppendEntriesReply(ref mut m) = message {
+            m.log_tail.index = LogIndex::new(1);
+        }
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(
+            common.next_event(),
+            Some(Event::DuplicateNodeIdSuspected {
+                node: "node2".into()
+            })
+        );
+
+        // 以後の"node2"の応答は、コミットの判定に使用されない
+        let message = reply(&common, "node2", SequenceNumber::new(3));
+        track!(leader.handle_message(&mut common, message))?;
+        let message = reply(&common, "node1", SequenceNumber::new(3));
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(0));
+        assert_eq!(common.next_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn append_persisted_events_work() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            append_persisted_events: true,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+        let persisted = |common: &mut Common<TestIo>| {
+            let mut through = Vec::new();
+            while let Some(e) = common.next_event() {
+                if let Event::AppendPersisted { through: t } = e {
+                    through.push(t);
+                }
+            }
+            through
+        };
+
+        // 保存が完了するまでは発行されない
+        assert!(persisted(&mut common).is_empty());
+
+        // `Noop`エントリの保存完了
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(persisted(&mut common), [LogIndex::new(1)]);
+
+        // 提案されたコマンドの保存完了毎に、水位が進む
+        for i in 0..2 {
+            let term = common.term();
+            let command = vec![i];
+            leader.propose(&mut common, LogEntry::Command { term, command });
+            track!(leader.run_once(&mut common))?;
+        }
+        assert_eq!(persisted(&mut common), [LogIndex::new(2), LogIndex::new(3)]);
+        assert_eq!(common.log().tail().index, LogIndex::new(3));
+        Ok(())
+    }
+
+    #[test]
+    fn estimated_commit_latency_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let clock = io.clock.clone();
+        let sent_messages = io.sent_messages.clone();
+        let start = Instant::now();
+        *clock.lock().expect("Never fails") = Some(start);
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        let seq_no = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .filter_map(|m| match m {
+                Message::AppendEntriesCall(m) => Some(m.header.seq_no),
+                _ => None,
+            })
+            .next_back();
+        let seq_no = track_assert_some!(seq_no, ErrorKind::Other);
+
+        // 過半数のメンバとのラウンドトリップ時間が未計測
+        assert_eq!(leader.estimated_commit_latency(&common), None);
+
+        // "node2"だけが10ミリ秒後に応答する ("node3"は未計測のまま)
+        *clock.lock().expect("Never fails") = Some(start + Duration::from_millis(10));
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, seq_no);
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().tail(), common.log().committed_tail());
+        assert_eq!(
+            leader.estimated_commit_latency(&common),
+            Some(Duration::from_millis(10))
+        );
+
+        // 未コミットのエントリが存在する場合には、最低でも一往復分が加算される
+        // (この時点では、スループットは未観測)
+        let term = common.term();
+        leader.propose(
+            &mut common,
+            LogEntry::Command {
+                term,
+                command: vec![0],
+            },
+        );
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(
+            leader.estimated_commit_latency(&common),
+            Some(Duration::from_millis(20))
+        );
+
+        // 20ミリ秒で一エントリがコミットされる (ラウンドトリップ時間の移動平均は12.5ミリ秒となる)
+        *clock.lock().expect("Never fails") = Some(start + Duration::from_millis(30));
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, seq_no);
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().tail(), common.log().committed_tail());
+        assert_eq!(
+            leader.estimated_commit_latency(&common),
+            Some(Duration::from_micros(12_500))
+        );
+
+        // 四エントリが滞留している場合には、観測されたスループットに基づき、その消化に80ミリ秒を要すると推定される
+        let entries = (0..4)
+            .map(|i| LogEntry::Command {
+                term,
+                command: vec![i],
+            })
+            .collect();
+        leader.propose_entries(&mut common, entries);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(
+            leader.estimated_commit_latency(&common),
+            Some(Duration::from_micros(92_500))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inflight_entries_are_limited_per_follower() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let logs = io.logs.clone();
+        let options = ReplicatedLogOptions {
+            max_inflight_entries: 2,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+
+        let sent_to = |node: &str| -> Vec<(SequenceNumber, LogSuffix)> {
+            sent_messages
+                .lock()
+                .expect("Never fails")
+                .iter()
+                .filter_map(|m| match m {
+                    Message::AppendEntriesCall(m) if m.header.destination == node.into() => {
+                        Some((m.header.seq_no, m.suffix.clone()))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // "node2"は即座に応答するが、"node3"は応答しない
+        let term = common.term();
+        for i in 0..5 {
+            if i != 0 {
+                leader.propose(
+                    &mut common,
+                    LogEntry::Command {
+                        term,
+                        command: vec![i],
+                    },
+                );
+                track!(leader.run_once(&mut common))?;
+            }
+            let seq_no = track_assert_some!(sent_to("node2").last(), ErrorKind::Other).0;
+            for sender in &["node1", "node2"] {
+                let message = reply(&common, sender, seq_no);
+                track!(leader.handle_message(&mut common, message))?;
+            }
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(5));
+
+        // "node3"には、応答待ちのエントリ数が上限に達した後は、ハートビートのみが送信される
+        let sent = sent_to("node3");
+        let entries = sent.iter().map(|(_, s)| s.entries.len()).sum::<usize>();
+        assert_eq!(entries, 2);
+        assert!(sent[2..].iter().all(|(_, s)| s.entries.is_empty()));
+
+        // "node3"が応答すると、送信枠に収まる分だけ、続きのエントリ群が送信される
+        let head = LogPosition {
+            prev_term: term,
+            index: LogIndex::new(2),
+        };
+        let entries = (2..4)
+            .map(|i| LogEntry::Command {
+                term,
+                command: vec![i - 1],
+            })
+            .collect();
+        let suffix = LogSuffix { head, entries };
+        logs.lock()
+            .expect("Never fails")
+            .insert((head.index, Some(LogIndex::new(4))), Log::Suffix(suffix));
+
+        let mut message = reply(&common, "node3", sent[1].0);
+        if let Message::AppendEntriesReply(ref mut m) = message {
+            m.log_tail = head;
+        }
+        track!(leader.handle_message(&mut common, message))?;
+        track!(leader.run_once(&mut common))?;
+        let sent = sent_to("node3");
+        let (_, last) = track_assert_some!(sent.last(), ErrorKind::Other);
+        assert_eq!(last.head.index, LogIndex::new(2));
+        assert_eq!(last.entries.len(), 2);
+
+        // 再び送信枠が埋まったので、新しいエントリはブロードキャストされない
+        leader.propose(
+            &mut common,
+            LogEntry::Command {
+                term,
+                command: vec![5],
+            },
+        );
+        track!(leader.run_once(&mut common))?;
+        let (_, last) = track_assert_some!(sent_to("node3").pop(), ErrorKind::Other);
+        assert!(last.entries.is_empty());
+        let (_, last) = track_assert_some!(sent_to("node2").pop(), ErrorKind::Other);
+        assert_eq!(last.entries.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn transfer_leadership_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        let term = common.term();
+        leader.propose(
+            &mut common,
+            LogEntry::Command {
+                term,
+                command: vec![0],
+            },
+        );
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(2));
+
+        // メンバ以外やローカルノード自身には移譲できない
+        for target in &["node4", "node1"] {
+            let e = leader
+                .transfer_leadership(&mut common, (*target).into())
+                .err();
+            assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        }
+
+        track!(leader.transfer_leadership(&mut common, "node2".into()))?;
+        assert!(leader.is_transferring_leadership());
+        let e = leader
+            .transfer_leadership(&mut common, "node3".into())
+            .err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Busy));
+
+        let is_timeout_now_sent = || {
+            sent_messages
+                .lock()
+                .expect("Never fails")
+                .iter()
+                .any(|m| match m {
+                    Message::TimeoutNowCast(m) => m.header.destination == "node2".into(),
+                    _ => false,
+                })
+        };
+
+        // "node2"のログが追い付くまでは、選挙の開始を指示しない
+        let mut message = reply(&common, "node2", SequenceNumber::new(1));
+        if let Message::AppendEntriesReply(ref mut m) = message {
+            m.log_tail.index = LogIndex::new(1);
+        }
+        track!(leader.handle_message(&mut common, message))?;
+        assert!(!is_timeout_now_sent());
+
+        let message = reply(&common, "node2", SequenceNumber::new(2));
+        track!(leader.handle_message(&mut common, message))?;
+        assert!(is_timeout_now_sent());
+        Ok(())
+    }
+
+    #[test]
+    fn leadership_transfer_is_aborted_after_max_rounds() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            leadership_transfer_max_rounds: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        while common.next_event().is_some() {}
+
+        // "node3"は応答しないので、ログが追い付かない
+        track!(leader.transfer_leadership(&mut common, "node3".into()))?;
+        for _ in 0..2 {
+            track!(leader.handle_timeout(&mut common))?;
+            assert!(leader.is_transferring_leadership());
+        }
+        track!(leader.handle_timeout(&mut common))?;
+        assert!(!leader.is_transferring_leadership());
+        assert_eq!(
+            common.next_event(),
+            Some(Event::LeadershipTransferAborted {
+                target: "node3".into()
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn entries_from_previous_terms_are_committed_via_noop() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+
+        // 以前の`Term`に追記されたまま、コミットされていないエントリ群
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![0],
+                },
+                LogEntry::Command {
+                    term: Term::new(0),
+                    command: vec![1],
+                },
+            ],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+
+        // 新しい`Term`のリーダとなり、`Noop`エントリを追記する
+        common.transit_to_candidate();
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(3));
+
+        // 過半数に複製されていても、以前の`Term`のエントリだけでは、コミットされない
+        // (論文の「Figure 8」のように、後続のリーダによって上書きされる可能性があるため)
+        for sender in &["node1", "node2"] {
+            let message: Message = AppendEntriesReply {
+                header: MessageHeader {
+                    sender: (*sender).into(),
+                    destination: common.local_node().id.clone(),
+                    seq_no: SequenceNumber::new(0),
+                    term: common.term(),
+                },
+                log_tail: suffix.tail(),
+                busy: false,
+                removed: false,
+                conflict_term: None,
+                conflict_index: None,
+            }
+            .into();
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(0));
+
+        // 現在の`Term`の`Noop`エントリが過半数に複製された時点で、以前のエントリもまとめてコミットされる
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(3));
+        Ok(())
+    }
+
+    #[test]
+    fn learners_do_not_count_toward_commit() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io
+            .cluster
+            .add_learner("node4".into())
+            .add_learner("node5".into());
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(1));
+
+        // ラーナーにもログは複製される
+        let destinations = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .filter_map(|m| match m {
+                Message::AppendEntriesCall(m) => Some(m.header.destination.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for node in &["node2", "node3", "node4", "node5"] {
+            assert!(destinations.contains(&(*node).into()));
+        }
+
+        // ラーナーの承認は、コミットの判定には使われない
+        for sender in &["node1", "node4", "node5"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(0));
+
+        // 投票権を有するメンバ二台の承認でコミットされる
+        let message = reply(&common, "node2", SequenceNumber::new(0));
+        track!(leader.handle_message(&mut common, message))?;
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(1));
+        Ok(())
+    }
+
+    #[test]
+    fn witness_counts_toward_commit_without_commands() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io
+            .cluster
+            .clone()
+            .with_witnesses(vec!["node3".into()].into_iter().collect());
+        assert!(cluster.is_witness(&"node3".into()));
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            proposal_batch_size: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => unreachable!(),
+        };
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(1));
+
+        // ウィットネスには、コマンドの内容を取り除いたエントリ群が送信される
+        leader.propose_batch(&mut common, vec![b"foo".to_vec(), b"bar".to_vec()]);
+        let before = sent_messages.lock().expect("Never fails").len();
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(3));
+        let sent = sent_messages.lock().expect("Never fails")[before..].to_vec();
+        assert_eq!(sent.len(), 2);
+        for m in sent {
+            match m {
+                Message::AppendEntriesCall(m) => {
+                    assert_eq!(m.suffix.entries.len(), 2);
+                    let is_witness = m.header.destination == "node3".into();
+                    for e in &m.suffix.entries {
+                        assert_eq!(matches!(e, LogEntry::Stripped { .. }), is_witness);
+                    }
+                }
+                m => panic!("Unexpected message: {:?}", m),
+            }
+        }
+
+        // ローカルノードとウィットネスの承認でコミットされる
+        for sender in &["node1", "node3"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(3));
+
+        // ウィットネスにはリーダシップを移譲できない
+        assert!(leader
+            .transfer_leadership(&mut common, "node3".into())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn quiescent_heartbeats_work() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            quiescence_idle_rounds: 2,
+            quiescent_heartbeat_interval: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        for sender in &["node1", "node2", "node3"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+
+        // 送信されたハートビートには、各フォロワーが即座に応答する
+        let heartbeat = |leader: &mut Leader<TestIo>, common: &mut Common<TestIo>| -> Result<_> {
+            sent_messages.lock().expect("Never fails").clear();
+            track!(leader.handle_timeout(common))?;
+            let calls = sent_messages
+                .lock()
+                .expect("Never fails")
+                .iter()
+                .filter_map(|m| match m {
+                    Message::AppendEntriesCall(m) => Some(m.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            for m in &calls {
+                let sender = m.header.destination.as_str();
+                let message = reply(common, sender, m.header.seq_no);
+                track!(leader.handle_message(common, message))?;
+            }
+            Ok(calls.into_iter().map(|m| m.quiescent).collect::<Vec<_>>())
+        };
+
+        // アイドル状態が続くと休止状態に移行し、ハートビートの頻度が下がる
+        let mut sent = Vec::new();
+        for _ in 0..8 {
+            sent.push(track!(heartbeat(&mut leader, &mut common))?);
+        }
+        assert_eq!(
+            sent,
+            vec![
+                vec![false, false],
+                vec![true, true],
+                vec![],
+                vec![],
+                vec![true, true],
+                vec![],
+                vec![],
+                vec![true, true],
+            ]
+        );
+        assert!(common.is_quiescent());
+
+        // 提案があると、即座に通常のハートビートに戻る
+        let term = common.term();
+        leader.propose(&mut common, LogEntry::Noop { term });
+        assert!(!common.is_quiescent());
+        assert_eq!(
+            track!(heartbeat(&mut leader, &mut common))?,
+            vec![false, false]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn confirm_read_index_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let mut leader = Leader::new(&mut common);
+        let sent_count = || sent_messages.lock().expect("Never fails").len();
+        let notify = Arc::new(NoopNotify);
+
+        // 選出直後の`Noop`エントリがコミットされるまでは、確認は保留される
+        let before = sent_count();
+        let mut read = executor::spawn(leader.confirm_read_index(&mut common));
+        assert_eq!(sent_count(), before);
+        assert_eq!(read.poll_future_notify(&notify, 0)?, Async::NotReady);
+
+        // `Noop`エントリのコミット後に、確認用のハートビートが送信される
+        track!(leader.run_once(&mut common))?;
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(common.log().committed_tail().index, LogIndex::new(1));
+        let heartbeat = match sent_messages.lock().expect("Never fails").last() {
+            Some(Message::AppendEntriesCall(m)) => m.header.seq_no,
+            m => panic!("Unexpected message: {:?}", m),
+        };
+        assert_eq!(read.poll_future_notify(&notify, 0)?, Async::NotReady);
+
+        // 過半数からハートビートの応答が得られた時点で、記録されたread indexを返して完了する
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, heartbeat);
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        assert_eq!(
+            read.poll_future_notify(&notify, 0)?,
+            Async::Ready(LogIndex::new(1))
+        );
+
+        // 確認の完了前にリーダでなくなった場合には、エラーとなる
+        let mut read = executor::spawn(leader.confirm_read_index(&mut common));
+        drop(leader);
+        assert_eq!(
+            read.poll_future_notify(&notify, 0).map_err(|e| *e.kind()),
+            Err(ErrorKind::NotLeader)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn propose_batch_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            proposal_batch_size: 3,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let mut leader = Leader::new(&mut common);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(1));
+
+        // 上限未満の提案は、バッファに積まれる
+        let commands = vec![b"foo".to_vec(), b"bar".to_vec()];
+        let proposals = leader.propose_batch(&mut common, commands.clone());
+        let indices = proposals.iter().map(|p| p.index).collect::<Vec<_>>();
+        assert_eq!(indices, vec![LogIndex::new(1), LogIndex::new(2)]);
+        assert!(!leader.appender.is_busy());
+        assert_eq!(leader.unappended_proposals(&common), proposals);
+
+        // 次の`run_once`で、一つの`LogSuffix`としてまとめて追記・送信される
+        let before = sent_messages.lock().expect("Never fails").len();
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(3));
+        assert!(leader.unappended_proposals(&common).is_empty());
+        let sent = sent_messages.lock().expect("Never fails")[before..].to_vec();
+        assert_eq!(sent.len(), 2);
+        for m in sent {
+            match m {
+                Message::AppendEntriesCall(m) => {
+                    assert_eq!(m.suffix.head.index, LogIndex::new(1));
+                    let sent_commands = m
+                        .suffix
+                        .entries
+                        .into_iter()
+                        .map(|e| match e {
+                            LogEntry::Command { command, .. } => command,
+                            e => panic!("Unexpected entry: {:?}", e),
+                        })
+                        .collect::<Vec<_>>();
+                    assert_eq!(sent_commands, commands);
+                }
+                m => panic!("Unexpected message: {:?}", m),
+            }
+        }
+
+        // 上限に達した場合には、即座に追記が開始される
+        let commands = vec![vec![0], vec![1], vec![2]];
+        leader.propose_batch(&mut common, commands);
+        assert!(leader.appender.is_busy());
+        Ok(())
+    }
+
+    #[test]
+    fn flush_proposals_appends_buffered_proposals_at_once() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let saved_log_suffixes = io.saved_log_suffixes.clone();
+        let options = ReplicatedLogOptions {
+            proposal_batch_size: 100,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        let mut leader = match common.transit_to_leader() {
+            RoleState::Leader(leader) => leader,
+            _ => unreachable!(),
+        };
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(1));
+
+        // 一連の提案には、即座に連続したインデックスが割り当てられる
+        let proposals = (0..10)
+            .map(|i| {
+                let entry = LogEntry::Command {
+                    term: common.term(),
+                    command: vec![i],
+                };
+                leader.propose(&mut common, entry)
+            })
+            .collect::<Vec<_>>();
+        let indices = proposals
+            .iter()
+            .map(|p| p.index.as_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(indices, (1..=10).collect::<Vec<_>>());
+        assert_eq!(leader.unappended_proposals(&common), proposals);
+
+        // 明示的な`flush_proposals`で、一度の追記にまとめられる
+        let saved_before = saved_log_suffixes.lock().expect("Never fails").len();
+        let sent_before = sent_messages.lock().expect("Never fails").len();
+        leader.flush_proposals(&mut common);
+        track!(leader.run_once(&mut common))?;
+        assert_eq!(common.log().tail().index, LogIndex::new(11));
+        let saved = saved_log_suffixes.lock().expect("Never fails")[saved_before..].to_vec();
+        assert_eq!(saved, vec![LogIndex::new(1)]);
+        let sent = sent_messages.lock().expect("Never fails")[sent_before..].to_vec();
+        assert_eq!(sent.len(), 2);
+        for m in sent {
+            match m {
+                Message::AppendEntriesCall(m) => assert_eq!(m.suffix.entries.len(), 10),
+                m => panic!("Unexpected message: {:?}", m),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn command_trace_works() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            command_trace_capacity: 1,
+            ..Default::default()
+        };
+        let mut common = Common::new(node_id, io, cluster, options, metrics);
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let mut leader = Leader::new(&mut common);
+
+        let command = LogEntry::Command {
+            term: common.term(),
+            command: b"foo".to_vec(),
+        };
+        let proposal = leader.propose(&mut common, command);
+        let trace = leader.command_trace(&proposal).expect("Never fails");
+        assert_eq!(trace.appended_tick, None);
+
+        // ローカルログへの追記
+        track!(leader.run_once(&mut common))?;
+        let trace = leader.command_trace(&proposal).expect("Never fails");
+        assert_eq!(trace.appended_tick, Some(0));
+        assert!(trace.replicated_to.is_empty());
+        assert_eq!(trace.committed_tick, None);
+
+        // 過半数への複製とコミット
+        for sender in &["node1", "node2"] {
+            let message = reply(&common, sender, SequenceNumber::new(0));
+            track!(leader.handle_message(&mut common, message))?;
+        }
+        let trace = leader.command_trace(&proposal).expect("Never fails");
+        assert_eq!(
+            trace.replicated_to,
+            vec![("node1".into(), 0), ("node2".into(), 0)]
+        );
+        assert_eq!(trace.committed_tick, Some(0));
+        assert_eq!(trace.applied_tick, None);
+
+        // 状態機械への適用
+        track!(common.set_applied_index(LogIndex::new(2)))?;
+        leader.update_command_traces(&common);
+        let trace = leader.command_trace(&proposal).expect("Never fails");
+        assert_eq!(trace.applied_tick, Some(0));
+
+        // 上限を超えた分は古いものから破棄される
+        let command = LogEntry::Command {
+            term: common.term(),
+            command: b"bar".to_vec(),
+        };
+        let next = leader.propose(&mut common, command);
+        assert!(leader.command_trace(&proposal).is_none());
+        assert!(leader.command_trace(&next).is_some());
         Ok(())
     }
 }
@@ -8,11 +8,15 @@ use crate::{Error, Io, Result};
 /// ノード起動時に、前回の状況を復元(ロード)を行う.
 pub struct Loader<IO: Io> {
     phase: Phase<IO::LoadBallot, IO::LoadLog>,
+    ballot_loaded: bool,
 }
 impl<IO: Io> Loader<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
         let phase = Phase::A(common.load_ballot());
-        Loader { phase }
+        Loader {
+            phase,
+            ballot_loaded: false,
+        }
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
         // ロードにはタイムアウトは存在しないので、無条件で延長し続ける
@@ -26,8 +30,9 @@ impl<IO: Io> Loader<IO> {
                     // 1) 前回の投票状況を復元
                     if let Some(ballot) = ballot {
                         common.set_ballot(ballot);
+                        self.ballot_loaded = true;
                     }
-                    let future = common.load_log(LogIndex::new(0), None);
+                    let future = track!(common.load_log(LogIndex::new(0), None))?;
                     Phase::B(future) // => ログ復元へ
                 }
                 Phase::B(log) => {
@@ -42,7 +47,7 @@ impl<IO: Io> Loader<IO> {
                             track!(common.handle_log_snapshot_loaded(prefix))?;
 
                             let suffix_head = common.log().tail().index;
-                            let future = common.load_log(suffix_head, None);
+                            let future = track!(common.load_log(suffix_head, None))?;
                             Phase::B(future) // => スナップショット以降のログ取得へ
                         }
                         Log::Suffix(suffix) => {
@@ -57,6 +62,13 @@ impl<IO: Io> Loader<IO> {
                             // `Io`トレイトに追加しても良いかもしれない.
                             track!(common.handle_log_appended(&suffix))?;
 
+                            // 3) 保存途中だった分割スナップショットがあれば、その進捗を復元
+                            track!(common.load_snapshot_transfer())?;
+
+                            // 4) 復元した投票状況が、ログやクラスタ構成と矛盾していないかを検査
+                            let is_ballot_valid =
+                                !self.ballot_loaded || common.validate_loaded_ballot();
+
                             // FIXME:
                             // 起動直後にcandidate状態に遷移してしまうと、
                             // 前回停止時からtermが変わっていない場合に、
@@ -66,7 +78,30 @@ impl<IO: Io> Loader<IO> {
                             // candidateに遷移するのは`index==0`の場合のみ、とか？
                             // 若干起動時の待ちが増える可能性はあるが、全部follower、として起動する、
                             // というのもありかもしれない.
-                            let next = common.transit_to_candidate();
+                            let is_hinted_to_others = matches!(
+                                common.options().initial_leader_hint,
+                                Some(ref hint) if *hint != common.local_node().id
+                            );
+                            let ballot = &common.local_node().ballot;
+                            let has_voted_for_self = ballot.term.as_u64() > 0
+                                && ballot.voted_for == common.local_node().id;
+                            let next = if !is_ballot_valid {
+                                // 投票状況が壊れていたので、修復したものの永続化が完了するまでは、
+                                // 投票もログの追記も行わないフォロワーとして起動する.
+                                let local = common.local_node().id.clone();
+                                common.transit_to_follower(local, None)
+                            } else if has_voted_for_self {
+                                // 前回停止時に自分に投票していた場合には、同じtermのまま立候補し直す.
+                                // (投票状況はメモリ上にしか存在しないので、投票依頼は再送する)
+                                common.resume_candidacy()
+                            } else if is_hinted_to_others {
+                                // 別のノードが最初のリーダとなるように、自分はフォロワーとして起動する.
+                                // (そのノードが停止している場合でも、フォロワーのタイムアウト後に選挙が始まる)
+                                let local = common.local_node().id.clone();
+                                common.transit_to_follower(local, None)
+                            } else {
+                                common.transit_to_candidate()
+                            };
                             return Ok(Some(next));
                         }
                     }
@@ -104,11 +139,15 @@ mod tests {
     use super::*;
     use prometrics::metrics::MetricBuilder;
 
-    use crate::election::Term;
+    use crate::cluster::ClusterConfig;
+    use crate::election::{Ballot, Term};
     use crate::log::{LogEntry, LogPosition, LogPrefix, LogSuffix};
+    use crate::message::Message;
     use crate::metrics::NodeStateMetrics;
     use crate::node::NodeId;
-    use crate::test_util::tests::TestIoBuilder;
+    use crate::node_state::RoleState;
+    use crate::test_util::tests::{TestIo, TestIoBuilder};
+    use crate::{Event, ReplicatedLogOptions};
     use trackable::result::TestResult;
 
     #[test]
@@ -118,7 +157,7 @@ mod tests {
         let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
         let mut handle = io.handle();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
         let mut loader = Loader::new(&mut common);
 
         // prefix には空の snapshot があり、tail は 1 を指している。
@@ -169,7 +208,7 @@ mod tests {
         let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
         let mut handle = io.handle();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), Default::default(), metrics);
         let mut loader = Loader::new(&mut common);
 
         // 古い term のログが紛れ込んでいるとエラーになる
@@ -210,4 +249,176 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn initial_leader_hint_works() -> TestResult {
+        let load = |node_id: &str| -> Result<RoleState<TestIo>> {
+            let node_id: NodeId = node_id.into();
+            let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+            let io = TestIoBuilder::new()
+                .add_member("node1".into())
+                .add_member("node2".into())
+                .add_member("node3".into())
+                .finish();
+            let cluster = io.cluster.clone();
+            let options = ReplicatedLogOptions {
+                initial_leader_hint: Some("node1".into()),
+                ..Default::default()
+            };
+            let mut common = Common::new(node_id, io, cluster, options, metrics);
+            let mut loader = Loader::new(&mut common);
+            loop {
+                if let Some(next) = track!(loader.run_once(&mut common))? {
+                    return Ok(next);
+                }
+            }
+        };
+
+        // 指定されたノードのみが、起動直後に立候補する
+        assert!(track!(load("node1"))?.is_candidate());
+        assert!(track!(load("node2"))?.is_follower());
+        assert!(track!(load("node3"))?.is_follower());
+        Ok(())
+    }
+
+    #[test]
+    fn restart_during_candidacy_does_not_inflate_term() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        io.ballots.lock().expect("Never fails").push(Ballot {
+            term: Term::new(5),
+            voted_for: node_id.clone(),
+        });
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut loader = Loader::new(&mut common);
+        let mut next = loop {
+            if let Some(next) = track!(loader.run_once(&mut common))? {
+                break next;
+            }
+        };
+        assert!(next.is_candidate());
+        assert_eq!(common.term(), Term::new(5));
+
+        // 同じtermのまま、投票依頼が再送される
+        if let RoleState::Candidate(ref mut candidate) = next {
+            track!(candidate.run_once(&mut common))?;
+        }
+        let sent_messages = sent_messages.lock().expect("Never fails");
+        assert_eq!(sent_messages.len(), 2);
+        for m in sent_messages.iter() {
+            assert!(matches!(m, Message::RequestVoteCall(_)));
+            assert_eq!(m.header().term, Term::new(5));
+        }
+        Ok(())
+    }
+    #[test]
+    fn corrupted_ballot_is_recovered() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let loaded = Ballot {
+            term: Term::new(5),
+            voted_for: "node9".into(),
+        };
+        io.ballots.lock().expect("Never fails").push(loaded.clone());
+        let ballots = io.ballots.clone();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, Default::default(), metrics);
+        let mut loader = Loader::new(&mut common);
+        let next = loop {
+            if let Some(next) = track!(loader.run_once(&mut common))? {
+                break next;
+            }
+        };
+
+        // クラスタ外のノードへの投票は取り消され、`term`は維持される
+        let recovered = Ballot {
+            term: Term::new(5),
+            voted_for: node_id,
+        };
+        assert!(next.is_follower());
+        assert_eq!(common.local_node().ballot, recovered);
+        let mut events = Vec::new();
+        while let Some(e) = common.next_event() {
+            events.push(e);
+        }
+        assert!(events.contains(&Event::BallotCorrupted {
+            loaded,
+            recovered: recovered.clone()
+        }));
+
+        // 修復された投票状況は、フォロワーへの遷移時に永続化される
+        assert_eq!(
+            ballots.lock().expect("Never fails").last(),
+            Some(&recovered)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ballot_for_node_removed_by_later_config_is_valid() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member(node_id.clone())
+            .add_member("node2".into())
+            .finish();
+
+        // "node3"に投票した後に、"node3"を取り除く構成変更がローカルログに追記された
+        let old = ClusterConfig::new(
+            vec![node_id.clone(), "node2".into(), "node3".into()]
+                .into_iter()
+                .collect(),
+        );
+        let new = io.cluster.clone();
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Config {
+                    term: Term::new(5),
+                    config: old,
+                },
+                LogEntry::Config {
+                    term: Term::new(5),
+                    config: new,
+                },
+            ],
+        };
+        io.logs
+            .lock()
+            .expect("Never fails")
+            .insert((LogIndex::new(0), None), suffix.into());
+        let loaded = Ballot {
+            term: Term::new(5),
+            voted_for: "node3".into(),
+        };
+        io.ballots.lock().expect("Never fails").push(loaded);
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, Default::default(), metrics);
+        let mut loader = Loader::new(&mut common);
+        let next = loop {
+            if let Some(next) = track!(loader.run_once(&mut common))? {
+                break next;
+            }
+        };
+
+        // 過去の構成に含まれていたノードへの投票は、破損とはみなされずに、通常通り起動する
+        assert!(next.is_candidate());
+        assert_eq!(common.term(), Term::new(6));
+        while let Some(e) = common.next_event() {
+            assert!(!matches!(e, Event::BallotCorrupted { .. }), "{:?}", e);
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use futures::{Async, Poll, Stream};
+use futures::{task, Async, Poll, Stream};
 use std::time::Instant;
 
 pub use self::common::Common;
@@ -12,7 +12,7 @@ use crate::cluster::ClusterConfig;
 use crate::message::Message;
 use crate::metrics::NodeStateMetrics;
 use crate::node::NodeId;
-use crate::{Error, Event, Io, Result};
+use crate::{AdminView, Error, ErrorKind, Event, Io, ReplicatedLogOptions, Result};
 
 mod candidate;
 mod common;
@@ -31,10 +31,19 @@ pub struct NodeState<IO: Io> {
     pub role: RoleState<IO>,
     started_at: Instant,
     pub metrics: NodeStateMetrics,
+
+    // 現在のポーリング中に、リーダとして処理した`AppendEntriesReply`の数.
+    handled_replies: usize,
 }
 impl<IO: Io> NodeState<IO> {
-    pub fn load(node_id: NodeId, config: ClusterConfig, io: IO, metrics: NodeStateMetrics) -> Self {
-        let mut common = Common::new(node_id, io, config, metrics.clone());
+    pub fn load(
+        node_id: NodeId,
+        config: ClusterConfig,
+        io: IO,
+        options: ReplicatedLogOptions,
+        metrics: NodeStateMetrics,
+    ) -> Self {
+        let mut common = Common::new(node_id, io, config, options, metrics.clone());
         let role = RoleState::Loader(Loader::new(&mut common));
         let started_at = Instant::now();
         NodeState {
@@ -42,17 +51,75 @@ impl<IO: Io> NodeState<IO> {
             role,
             started_at,
             metrics,
+            handled_replies: 0,
         }
     }
     pub fn is_loading(&self) -> bool {
         self.role.is_loader()
     }
+    pub fn is_io_replaceable(&self) -> bool {
+        matches!(self.role, RoleState::Follower(Follower::Idle(_)))
+            && self.common.is_io_replaceable()
+    }
+    pub fn replace_io<NewIo: Io>(self, new_io: NewIo) -> Result<NodeState<NewIo>> {
+        track_assert!(self.is_io_replaceable(), ErrorKind::Busy);
+        let role = match self.role {
+            RoleState::Follower(follower) => {
+                RoleState::Follower(follower.convert_io().expect("Never fails"))
+            }
+            _ => unreachable!(),
+        };
+        let common = track!(self.common.replace_io(new_io))?;
+        Ok(NodeState {
+            common,
+            role,
+            started_at: self.started_at,
+            metrics: self.metrics,
+            handled_replies: 0,
+        })
+    }
+    pub fn admin_view(&self) -> AdminView {
+        let mut view = self.common.admin_view();
+        if let RoleState::Leader(ref leader) = self.role {
+            view.peers = Some(leader.replication_lag(&self.common));
+            view.leadership_age_ticks = Some(leader.leadership_age_ticks(&self.common));
+        }
+        view
+    }
     pub fn start_election(&mut self) {
+        if self.common.is_shutting_down() {
+            return;
+        }
         if let RoleState::Follower(_) = self.role {
             let next = self.common.transit_to_candidate();
             self.handle_role_change(next);
         }
     }
+    pub fn trigger_election(&mut self) -> Result<()> {
+        track_assert!(
+            !self.role.is_loader(),
+            ErrorKind::Busy,
+            "Loading node state"
+        );
+        if let RoleState::Leader(_) = self.role {
+            track_panic!(ErrorKind::InvalidInput, "Already a leader");
+        }
+        track_assert!(
+            !self.common.is_shutting_down(),
+            ErrorKind::InvalidInput,
+            "Shutting down"
+        );
+        let next = track!(self.common.trigger_election())?;
+        self.handle_role_change(next);
+        Ok(())
+    }
+    pub fn begin_shutdown(&mut self) -> Result<()> {
+        self.common.begin_shutdown();
+        if let RoleState::Leader(ref mut leader) = self.role {
+            track!(leader.begin_shutdown(&mut self.common))?;
+        }
+        Ok(())
+    }
     fn handle_timeout(&mut self) -> Result<Option<RoleState<IO>>> {
         match self.role {
             RoleState::Loader(ref mut t) => track!(t.handle_timeout(&mut self.common)),
@@ -66,7 +133,7 @@ impl<IO: Io> NodeState<IO> {
             // ロード中に届いたメッセージは全て破棄
             return Ok(None);
         }
-        match self.common.handle_message(message) {
+        match track!(self.common.handle_message(message))? {
             HandleMessageResult::Handled(next) => Ok(next),
             HandleMessageResult::Unhandled(message) => match self.role {
                 RoleState::Loader(_) => unreachable!(),
@@ -80,6 +147,10 @@ impl<IO: Io> NodeState<IO> {
             },
         }
     }
+    fn is_reply_budget_exhausted(&self) -> bool {
+        let max = self.common.options().max_replies_per_run;
+        max != 0 && self.handled_replies >= max
+    }
     fn handle_role_change(&mut self, next: RoleState<IO>) {
         // For now, we don't require the metrics of other state transitions.
         match (&self.role, &next) {
@@ -110,6 +181,15 @@ impl<IO: Io> NodeState<IO> {
             | (RoleState::Loader(_), RoleState::Loader(_)) => {}
             _ => self.started_at = Instant::now(),
         }
+        if let RoleState::Leader(ref leader) = self.role {
+            if !matches!(next, RoleState::Leader(_)) {
+                let proposals = leader.unappended_proposals(&self.common);
+                if !proposals.is_empty() {
+                    self.common
+                        .push_event(Event::ProposalsAbandoned { proposals });
+                }
+            }
+        }
         self.role = next;
     }
 }
@@ -117,6 +197,8 @@ impl<IO: Io> Stream for NodeState<IO> {
     type Item = Event;
     type Error = Error;
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.handled_replies = 0;
+        self.common.reset_broadcast_sends();
         let mut did_something = true;
         while did_something {
             did_something = false;
@@ -125,6 +207,14 @@ impl<IO: Io> Stream for NodeState<IO> {
                 return Ok(Async::Ready(Some(e)));
             }
 
+            // tickの計時
+            if let Async::Ready(()) = track!(self.common.poll_tick_timeout())? {
+                did_something = true;
+                if let Some(e) = self.common.next_event() {
+                    return Ok(Async::Ready(Some(e)));
+                }
+            }
+
             // タイムアウト処理
             if let Async::Ready(()) = track!(self.common.poll_timeout())? {
                 did_something = true;
@@ -162,8 +252,18 @@ impl<IO: Io> Stream for NodeState<IO> {
             }
 
             // 受信メッセージ処理
+            if self.is_reply_budget_exhausted() {
+                // 一度のポーリングでの処理時間が延びないように、残りのメッセージは次回に処理する
+                task::current().notify();
+                break;
+            }
             if let Some(message) = track!(self.common.try_recv_message())? {
                 did_something = true;
+                if self.role.is_leader() {
+                    if let Message::AppendEntriesReply(_) = message {
+                        self.handled_replies += 1;
+                    }
+                }
                 if let Some(next) = track!(self.handle_message(message))? {
                     self.handle_role_change(next);
                 }
@@ -172,11 +272,16 @@ impl<IO: Io> Stream for NodeState<IO> {
                 }
             }
         }
+        if self.common.deferred_messages_len() > 0 {
+            // 保留中のブロードキャストメッセージは、次回のポーリングで送信する
+            task::current().notify();
+        }
         Ok(Async::NotReady)
     }
 }
 
 /// 各役割固有の状態.
+#[allow(clippy::large_enum_variant)]
 pub enum RoleState<IO: Io> {
     /// ノード起動時にストレージから前回の状況を復元するための状態
     Loader(Loader<IO>),
@@ -197,6 +302,17 @@ impl<IO: Io> RoleState<IO> {
         matches!(self, RoleState::Loader(_))
     }
 
+    /// Returns true if this role state is `Leader`.
+    pub fn is_leader(&self) -> bool {
+        matches!(self, RoleState::Leader(_))
+    }
+
+    /// Returns true if this role state is `Follower`.
+    #[cfg(test)]
+    pub fn is_follower(&self) -> bool {
+        matches!(self, RoleState::Follower(_))
+    }
+
     /// Returns true if this role state is `Candidate`.
     #[cfg(test)]
     pub fn is_candidate(&self) -> bool {
@@ -207,16 +323,23 @@ impl<IO: Io> RoleState<IO> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::executor::{self, Notify, Spawn};
     use prometrics::metrics::MetricBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use trackable::result::TestResult;
 
-    use crate::test_util::tests::TestIoBuilder;
+    use crate::log::{Log, LogEntry, LogIndex, LogPosition, LogSuffix};
+    use crate::message::{AppendEntriesReply, MessageHeader, SequenceNumber};
+    use crate::test_util::tests::{NoopNotify, TestIo, TestIoBuilder};
+    use crate::ReplicatedLogOptions;
 
     #[test]
     fn node_state_is_loading_works() {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let node = NodeState::load("test".into(), cluster, io, metrics);
+        let node = NodeState::load("test".into(), cluster, io, Default::default(), metrics);
         assert!(node.is_loading());
     }
 
@@ -225,7 +348,7 @@ mod tests {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new("test".into(), io, cluster, metrics);
+        let mut common = Common::new("test".into(), io, cluster, Default::default(), metrics);
         let state = RoleState::Loader(Loader::new(&mut common));
         assert!(state.is_loader());
         assert!(!state.is_candidate());
@@ -236,9 +359,362 @@ mod tests {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new("test".into(), io, cluster, metrics);
+        let mut common = Common::new("test".into(), io, cluster, Default::default(), metrics);
         let state = RoleState::Candidate(Candidate::new(&mut common));
         assert!(!state.is_loader());
         assert!(state.is_candidate());
     }
+
+    #[test]
+    fn admin_view_works() {
+        let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(
+            "node1".into(),
+            io,
+            cluster.clone(),
+            Default::default(),
+            metrics.clone(),
+        );
+        common.transit_to_candidate();
+        let role = common.transit_to_leader();
+        let node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics,
+            handled_replies: 0,
+        };
+
+        let view = node.admin_view();
+        assert_eq!(view.role, node.common.local_node().role);
+        assert_eq!(view.term, node.common.term());
+        assert_eq!(view.voted_for, node.common.local_node().ballot.voted_for);
+        assert_eq!(view.log_tail, node.common.log().tail().index);
+        assert_eq!(
+            view.committed_tail,
+            node.common.log().committed_tail().index
+        );
+        assert_eq!(view.config, cluster);
+        if let RoleState::Leader(ref leader) = node.role {
+            assert_eq!(view.peers, Some(leader.replication_lag(&node.common)));
+            assert_eq!(
+                view.leadership_age_ticks,
+                Some(leader.leadership_age_ticks(&node.common))
+            );
+        } else {
+            panic!("Not a leader");
+        }
+    }
+
+    #[test]
+    fn unappended_proposals_are_reported_on_leadership_loss() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            proposal_batch_size: 10,
+            ..Default::default()
+        };
+        let mut common = Common::new("node1".into(), io, cluster, options, metrics.clone());
+        common.transit_to_candidate();
+        let role = common.transit_to_leader();
+        let mut node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics,
+            handled_replies: 0,
+        };
+
+        // バッファに積まれたまま(i.e., 未追記)の提案
+        let proposals = match node.role {
+            RoleState::Leader(ref mut leader) => {
+                leader.propose_batch(&mut node.common, vec![b"foo".to_vec(), b"bar".to_vec()])
+            }
+            _ => panic!("Not a leader"),
+        };
+        assert_eq!(proposals.len(), 2);
+
+        // リーダシップを失うと、`Noop`エントリと合わせて破棄されたことが通知される
+        let next = node.common.transit_to_follower("node2".into(), None);
+        node.handle_role_change(next);
+        let mut abandoned = None;
+        while let Some(e) = node.common.next_event() {
+            if let Event::ProposalsAbandoned { proposals } = e {
+                abandoned = Some(proposals);
+            }
+        }
+        let abandoned = abandoned.expect("Never fails");
+        assert_eq!(abandoned.len(), 3);
+        assert_eq!(&abandoned[1..], &proposals[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn max_replies_per_run_works() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let inbound = io.inbound_messages.clone();
+        let options = ReplicatedLogOptions {
+            max_replies_per_run: 2,
+            ..Default::default()
+        };
+        let mut common = Common::new("node1".into(), io, cluster, options, metrics.clone());
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let role = common.transit_to_leader();
+        while common.next_event().is_some() {}
+        let node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics,
+            handled_replies: 0,
+        };
+        let mut node = executor::spawn(node);
+        let notify = Arc::new(NoopNotify);
+        let poll = |node: &mut Spawn<NodeState<TestIo>>| -> Result<()> {
+            while let Async::Ready(_) = track!(node.poll_stream_notify(&notify, 0))? {}
+            Ok(())
+        };
+        track!(poll(&mut node))?;
+
+        // 一度に大量の応答が届く
+        let term = node.get_ref().common.term();
+        for i in 0..5 {
+            let reply = AppendEntriesReply {
+                header: MessageHeader {
+                    sender: if i % 2 == 0 { "node2" } else { "node3" }.into(),
+                    destination: "node1".into(),
+                    seq_no: SequenceNumber::new(i),
+                    term,
+                },
+                log_tail: LogPosition::default(),
+                busy: false,
+                removed: false,
+                conflict_term: None,
+                conflict_index: None,
+            };
+            inbound.lock().expect("Never fails").push_back(reply.into());
+        }
+
+        // 一度のポーリングで処理されるのは、最大で二つまで
+        let inbound_depth =
+            |node: &Spawn<NodeState<TestIo>>| node.get_ref().common.io().inbound_depth();
+        track!(poll(&mut node))?;
+        assert_eq!(inbound_depth(&node), 3);
+        track!(poll(&mut node))?;
+        assert_eq!(inbound_depth(&node), 1);
+        track!(poll(&mut node))?;
+        assert_eq!(inbound_depth(&node), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn deferred_broadcasts_are_sent_on_subsequent_polls() -> TestResult {
+        struct CountingNotify(AtomicUsize);
+        impl Notify for CountingNotify {
+            fn notify(&self, _id: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut builder = TestIoBuilder::new();
+        for i in 0..15 {
+            builder = builder.add_member(format!("node{:02}", i).into());
+        }
+        let io = builder.finish();
+        let cluster = io.cluster.clone();
+        let sent_messages = io.sent_messages.clone();
+        let options = ReplicatedLogOptions {
+            max_broadcast_sends_per_run: 4,
+            ..Default::default()
+        };
+        let mut common = Common::new("node00".into(), io, cluster, options, metrics.clone());
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        common.reset_broadcast_sends();
+        sent_messages.lock().expect("Never fails").clear();
+
+        let role = common.transit_to_leader();
+        while common.next_event().is_some() {}
+        let node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics,
+            handled_replies: 0,
+        };
+        let mut node = executor::spawn(node);
+        let notify = Arc::new(CountingNotify(AtomicUsize::new(0)));
+
+        // リーダ就任時のブロードキャストは、ポーリングの度に4件ずつ送信され、
+        // 保留中のものがある間はタスクの再ポーリングが要求される
+        let mut counts = Vec::new();
+        for _ in 0..4 {
+            assert!(track!(node.poll_stream_notify(&notify, 0))?.is_not_ready());
+            counts.push(sent_messages.lock().expect("Never fails").len());
+        }
+        assert_eq!(counts, vec![4, 8, 12, 14]);
+        assert_eq!(node.get_ref().common.deferred_messages_len(), 0);
+        assert_eq!(notify.0.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_transfers_leadership() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let inbound = io.inbound_messages.clone();
+        let sent_messages = io.sent_messages.clone();
+        let logs = io.logs.clone();
+        let mut common = Common::new(
+            "node1".into(),
+            io,
+            cluster.clone(),
+            Default::default(),
+            metrics.clone(),
+        );
+        common.transit_to_candidate();
+        common.rpc_caller().broadcast_request_vote();
+        let role = common.transit_to_leader();
+        let node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics: metrics.clone(),
+            handled_replies: 0,
+        };
+        let mut node = executor::spawn(node);
+        let notify = Arc::new(NoopNotify);
+        let poll = |node: &mut Spawn<NodeState<TestIo>>| -> Result<Vec<Event>> {
+            let mut events = Vec::new();
+            while let Async::Ready(Some(e)) = track!(node.poll_stream_notify(&notify, 0))? {
+                events.push(e);
+            }
+            Ok(events)
+        };
+        track!(poll(&mut node))?;
+
+        // "node2"のローカルログは、リーダに追い付いている (i.e., `Noop`エントリがコミットされる)
+        let term = node.get_ref().common.term();
+        let committed = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term }],
+        };
+        logs.lock().expect("Never fails").insert(
+            (LogIndex::new(0), Some(LogIndex::new(1))),
+            Log::Suffix(committed),
+        );
+        let reply = AppendEntriesReply {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(10),
+                term,
+            },
+            log_tail: node.get_ref().common.log().tail(),
+            busy: false,
+            removed: false,
+            conflict_term: None,
+            conflict_index: None,
+        };
+        inbound.lock().expect("Never fails").push_back(reply.into());
+        track!(poll(&mut node))?;
+
+        // 停止処理を開始すると、タイムアウトを待たずに"node2"に選挙の開始が指示される
+        track!(node.get_mut().begin_shutdown())?;
+        let events = track!(poll(&mut node))?;
+        assert!(events.contains(&Event::ShuttingDown));
+        assert!(node.get_ref().role.is_leader());
+        let timeout_now = sent_messages
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .find(|m| matches!(m, Message::TimeoutNowCast(m) if m.header.destination == "node2".into()))
+            .cloned();
+        let timeout_now = track_assert_some!(timeout_now, ErrorKind::Other);
+
+        // 指示を受けた"node2"は、即座に立候補する
+        let io = TestIoBuilder::new().finish();
+        let mut common = Common::new("node2".into(), io, cluster, Default::default(), metrics);
+        common.transit_to_candidate();
+        let role = common.transit_to_follower("node1".into(), None);
+        assert_eq!(common.term(), term);
+        let mut follower = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics: track!(NodeStateMetrics::new(&MetricBuilder::new()))?,
+            handled_replies: 0,
+        };
+        let next = track!(follower.handle_message(timeout_now))?;
+        let next = track_assert_some!(next, ErrorKind::Other);
+        follower.handle_role_change(next);
+        assert!(follower.role.is_candidate());
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_steps_down_if_transfer_is_aborted() -> TestResult {
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new()
+            .add_member("node1".into())
+            .add_member("node2".into())
+            .add_member("node3".into())
+            .finish();
+        let cluster = io.cluster.clone();
+        let options = ReplicatedLogOptions {
+            leadership_transfer_max_rounds: 1,
+            ..Default::default()
+        };
+        let mut common = Common::new("node1".into(), io, cluster, options, metrics.clone());
+        common.transit_to_candidate();
+        let role = common.transit_to_leader();
+        let mut node = NodeState {
+            common,
+            role,
+            started_at: Instant::now(),
+            metrics,
+            handled_replies: 0,
+        };
+
+        // どのフォロワーも追い付かないまま、移譲が中止される
+        track!(node.begin_shutdown())?;
+        assert!(node.common.is_shutting_down());
+        assert!(track!(node.handle_timeout())?.is_none());
+        let next = match node.role {
+            RoleState::Leader(ref mut leader) => track!(leader.run_once(&mut node.common))?,
+            _ => panic!("Not a leader"),
+        };
+        let next = track_assert_some!(next, ErrorKind::Other);
+        node.handle_role_change(next);
+        assert!(node.role.is_follower());
+
+        // 停止処理中のフォロワーは、タイムアウトしても立候補しない
+        assert!(track!(node.handle_timeout())?.is_none());
+        assert!(node.role.is_follower());
+        assert!(node.trigger_election().is_err());
+        Ok(())
+    }
 }
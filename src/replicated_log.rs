@@ -1,12 +1,20 @@
-use futures::{Poll, Stream};
+use futures::sync::oneshot;
+use futures::{Future, Poll, Stream};
 use prometrics::metrics::MetricBuilder;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 use trackable::error::ErrorKindExt;
 
-use crate::cluster::{ClusterConfig, ClusterMembers};
-use crate::election::{Ballot, Role};
+use crate::cluster::{ClusterConfig, ClusterMembers, ConfigDiff, Quorum};
+use crate::election::{Ballot, Role, Term};
 use crate::io::Io;
-use crate::log::{LogEntry, LogHistory, LogIndex, LogPosition, LogPrefix, ProposalId};
+use crate::log::{
+    AuditFuture, ConsistencyResult, LogEntry, LogHistory, LogIndex, LogPosition, LogPrefix,
+    LogPrefixMeta, ProposalId, ReadToken, SessionToken,
+};
 use crate::message::SequenceNumber;
 use crate::metrics::RaftlogMetrics;
 use crate::node::{Node, NodeId};
@@ -53,18 +61,112 @@ impl<IO: Io> ReplicatedLog<IO> {
         members: ClusterMembers,
         io: IO,
         metric_builder: &MetricBuilder,
+    ) -> Result<Self> {
+        let options = ReplicatedLogOptions::default();
+        track!(Self::with_options(
+            node_id,
+            members,
+            io,
+            options,
+            metric_builder
+        ))
+    }
+
+    /// オプションを指定して`ReplicatedLog`のローカルインスタンスを生成する.
+    ///
+    /// `options`以外の引数に関しては`new`メソッドと同様.
+    pub fn with_options(
+        node_id: NodeId,
+        members: ClusterMembers,
+        io: IO,
+        options: ReplicatedLogOptions,
+        metric_builder: &MetricBuilder,
     ) -> Result<Self> {
         let config = ClusterConfig::new(members);
+        track_assert!(
+            !options.require_local_membership || config.is_known_node(&node_id),
+            ErrorKind::InvalidInput,
+            "The local node is not a member of the cluster: node_id={:?}, config={:?}",
+            node_id,
+            config
+        );
+        track_assert!(
+            options.snapshot_chunk_size != 0
+                && (options.max_message_size == 0
+                    || options.snapshot_chunk_size <= options.max_message_size),
+            ErrorKind::InvalidInput,
+            "snapshot_chunk_size={}, max_message_size={}",
+            options.snapshot_chunk_size,
+            options.max_message_size
+        );
+        track_assert!(
+            options.election_timeout_jitter == Duration::from_secs(0)
+                || io.supports_jittered_timeout(),
+            ErrorKind::InvalidInput,
+            "The I/O implementation does not support jittered timeouts: election_timeout_jitter={:?}",
+            options.election_timeout_jitter
+        );
         let mut metric_builder = metric_builder.clone();
         metric_builder.namespace("raftlog");
         let metrics = track!(RaftlogMetrics::new(&metric_builder))?;
-        let node = NodeState::load(node_id, config, io, metrics.node_state.clone());
+        let node = NodeState::load(node_id, config, io, options, metrics.node_state.clone());
         Ok(ReplicatedLog {
             node,
             metrics: Arc::new(metrics),
         })
     }
 
+    /// I/O実装を差し替え可能な状態かどうかを判定する.
+    ///
+    /// ローカルノードがフォロワーで、かつ、バックグランドで実行中のI/O処理が存在しない場合にのみ`true`が返される.
+    pub fn is_io_replaceable(&self) -> bool {
+        self.node.is_io_replaceable()
+    }
+
+    /// I/O実装を`new_io`に差し替えた`ReplicatedLog`インスタンスを返す.
+    ///
+    /// ストレージの移行等に使用されることが想定されている.
+    /// `new_io`は、現在のI/O実装と同じ永続化状態(投票状況やローカルログ)を保持している必要がある.
+    ///
+    /// # Errors
+    ///
+    /// `is_io_replaceable`が`false`を返す状態で、このメソッドが実行された場合には、
+    /// `ErrorKind::Busy`を理由としたエラーが返される.
+    /// `ReplicatedLogOptions::election_timeout_jitter`が指定されているにも関わらず、
+    /// `new_io`が揺らぎに対応していない場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// エラーには、このインスタンスと`new_io`がそのまま含まれているので、利用者は処理を継続できる.
+    pub fn replace_io<NewIo: Io>(
+        self,
+        new_io: NewIo,
+    ) -> ::std::result::Result<ReplicatedLog<NewIo>, ReplaceIoError<IO, NewIo>> {
+        let jitter = self.node.common.options().election_timeout_jitter;
+        if jitter != Duration::from_secs(0) && !new_io.supports_jittered_timeout() {
+            let error = track!(ErrorKind::InvalidInput.cause(format!(
+                "The I/O implementation does not support jittered timeouts: election_timeout_jitter={:?}",
+                jitter
+            )))
+            .into();
+            return Err(ReplaceIoError {
+                error,
+                log: Box::new(self),
+                new_io,
+            });
+        }
+        if !self.is_io_replaceable() {
+            let error = track!(ErrorKind::Busy.error()).into();
+            return Err(ReplaceIoError {
+                error,
+                log: Box::new(self),
+                new_io,
+            });
+        }
+        let node = self.node.replace_io(new_io).expect("Never fails");
+        Ok(ReplicatedLog {
+            node,
+            metrics: self.metrics,
+        })
+    }
+
     /// `raftlog` のメトリクスを返す。
     pub fn metrics(&self) -> &Arc<RaftlogMetrics> {
         &self.metrics
@@ -82,8 +184,22 @@ impl<IO: Io> ReplicatedLog<IO> {
     ///
     /// 非リーダノードに対して、このメソッドが実行された場合には、
     /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// リーダが過半数との通信を失っている場合には、`ErrorKind::NoQuorum`を理由としたエラーが返される.
     pub fn propose_command(&mut self, command: Vec<u8>) -> Result<ProposalId> {
         if let RoleState::Leader(ref mut leader) = self.node.role {
+            track_assert!(
+                !leader.is_transferring_leadership(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            track_assert!(
+                !leader.has_lost_quorum(&self.node.common),
+                ErrorKind::NoQuorum,
+                "The leader has lost contact with a majority"
+            );
             let term = self.node.common.term();
             let entry = LogEntry::Command { term, command };
             let proposal_id = leader.propose(&mut self.node.common, entry);
@@ -93,6 +209,59 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// バッファに積まれている提案の追記およびブロードキャストを、次のポーリングを待たずに開始する.
+    ///
+    /// `ReplicatedLogOptions::proposal_batch_size`が`0`より大きい場合に、
+    /// 一連の提案を終えた時点で呼び出すことで、それらを一つの`LogSuffix`にまとめて追記できる.
+    /// バッファが空の場合には何も行われない.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    pub fn flush_proposals(&mut self) -> Result<()> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            leader.flush_proposals(&mut self.node.common);
+            Ok(())
+        } else {
+            track_panic!(ErrorKind::NotLeader)
+        }
+    }
+
+    /// 複数のコマンドをまとめて提案する.
+    ///
+    /// コマンド群は、一つの`LogSuffix`としてローカルログに追記され、フォロワーに送信される.
+    /// 返り値の`ProposalId`群は`commands`の順に並び、それぞれが`propose_command`の返り値と同様に扱える.
+    ///
+    /// 追記が完了する前にリーダシップを失った場合には、
+    /// 破棄された提案を含む`Event::ProposalsAbandoned`が発行される.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// リーダが過半数との通信を失っている場合には、`ErrorKind::NoQuorum`を理由としたエラーが返される.
+    pub fn propose_batch(&mut self, commands: Vec<Vec<u8>>) -> Result<Vec<ProposalId>> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            track_assert!(
+                !leader.is_transferring_leadership(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            track_assert!(
+                !leader.has_lost_quorum(&self.node.common),
+                ErrorKind::NoQuorum,
+                "The leader has lost contact with a majority"
+            );
+            Ok(leader.propose_batch(&mut self.node.common, commands))
+        } else {
+            track_panic!(ErrorKind::NotLeader)
+        }
+    }
+
     /// 新しいクラスタ構成(新メンバ群)を提案する.
     ///
     /// 提案が承認(コミット)された場合には、返り値の`LogPosition`を含む
@@ -111,9 +280,139 @@ impl<IO: Io> ReplicatedLog<IO> {
     ///
     /// 非リーダノードに対して、このメソッドが実行された場合には、
     /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// リーダが過半数との通信を失っている場合には、`ErrorKind::NoQuorum`を理由としたエラーが返される.
     pub fn propose_config(&mut self, new_members: ClusterMembers) -> Result<ProposalId> {
+        let config = self.node.common.config().start_config_change(new_members);
+        track!(self.propose_config_entry(config))
+    }
+
+    /// `node`を投票権を持たないメンバ(ラーナー)として追加する構成を提案する.
+    ///
+    /// ラーナーにはログが複製されるが、ログのコミットやリーダ選出のための過半数の計算には含まれない.
+    /// 新メンバを投票権を有するメンバとして追加する前に、ログを同期させておきたい場合に有用.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `node`が既にクラスタのメンバである場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn add_learner(&mut self, node: NodeId) -> Result<ProposalId> {
+        track_assert!(self.node.role.is_leader(), ErrorKind::NotLeader);
+        let config = self.node.common.config();
+        track_assert!(
+            !config.is_known_node(&node),
+            ErrorKind::InvalidInput,
+            "Already a member: node={:?}, config={:?}",
+            node,
+            config
+        );
+        let config = config.add_learner(node);
+        track!(self.propose_config_entry(config))
+    }
+
+    /// ラーナーの`node`を、投票権を有するメンバに昇格させる構成を提案する.
+    ///
+    /// 昇格は`propose_config`による構成変更と同様に、新旧混合状態を経て行われる.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `node`がラーナーではない場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn promote_learner(&mut self, node: &NodeId) -> Result<ProposalId> {
+        track_assert!(self.node.role.is_leader(), ErrorKind::NotLeader);
+        let config = self.node.common.config();
+        track_assert!(
+            config.is_learner(node),
+            ErrorKind::InvalidInput,
+            "Not a learner: node={:?}, config={:?}",
+            node,
+            config
+        );
+        let config = config.promote_learner(node);
+        track!(self.propose_config_entry(config))
+    }
+
+    /// ウィットネスの集合を`witnesses`に置き換える構成を提案する.
+    ///
+    /// ウィットネスの指定は、クラスタ構成の一部として構成変更エントリに記録されるので、
+    /// 各ノードの再起動後も維持される.
+    ///
+    /// 一度ウィットネスとなったノードは、コマンドの内容を保持していないので、
+    /// メンバのままで通常の投票メンバに戻すことはできない.
+    /// その場合には、一度クラスタから取り除き、ローカルログを破棄した上で、改めて追加すること.
+    ///
+    /// ウィットネスの承認のみでコミットされたエントリは、リーダしかその内容を保持していないので、
+    /// リーダの停止中は、他の投票メンバが選出されず、クラスタが利用不能となり得る点に注意が必要
+    /// (詳細は`ClusterConfig`を参照).
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `witnesses`に投票権を有するメンバ以外のノードやローカルノードが含まれる場合や、
+    /// 現在のウィットネスが`witnesses`に含まれない場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn propose_witnesses(&mut self, witnesses: ClusterMembers) -> Result<ProposalId> {
+        track_assert!(self.node.role.is_leader(), ErrorKind::NotLeader);
+        let config = self.node.common.config();
+        let local = &self.node.common.local_node().id;
+        let is_voter = |n: &NodeId| {
+            (config.new_members().contains(n) || config.old_members().contains(n))
+                && !config.is_learner(n)
+        };
+        track_assert!(
+            witnesses.iter().all(|n| is_voter(n) && n != local),
+            ErrorKind::InvalidInput,
+            "Witnesses must be voting members other than the leader: witnesses={:?}, config={:?}",
+            witnesses,
+            config
+        );
+        track_assert!(
+            config.witnesses().is_subset(&witnesses),
+            ErrorKind::InvalidInput,
+            "A witness cannot be demoted while it is a member: witnesses={:?}, config={:?}",
+            witnesses,
+            config
+        );
+        let config = config.clone().with_witnesses(witnesses);
+        track!(self.propose_config_entry(config))
+    }
+
+    /// 合意に必要な承認数の決め方を`quorum`に置き換える構成を提案する.
+    ///
+    /// ウィットネスと同様に、承認数の決め方はクラスタ構成の一部として構成変更エントリに記録される.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `quorum`が現在のメンバ群に対して妥当ではない場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// (詳細は`ClusterConfig::with_quorum`を参照)
+    pub fn propose_quorum(&mut self, quorum: Quorum) -> Result<ProposalId> {
+        track_assert!(self.node.role.is_leader(), ErrorKind::NotLeader);
+        let config = track!(self.node.common.config().clone().with_quorum(quorum))?;
+        track!(self.propose_config_entry(config))
+    }
+
+    fn propose_config_entry(&mut self, config: ClusterConfig) -> Result<ProposalId> {
         if let RoleState::Leader(ref mut leader) = self.node.role {
-            let config = self.node.common.config().start_config_change(new_members);
+            track_assert!(
+                !leader.is_transferring_leadership(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            track_assert!(
+                !leader.has_lost_quorum(&self.node.common),
+                ErrorKind::NoQuorum,
+                "The leader has lost contact with a majority"
+            );
             let term = self.node.common.term();
             let entry = LogEntry::Config { term, config };
             let proposal_id = leader.propose(&mut self.node.common, entry);
@@ -123,6 +422,33 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// リーダシップを`target`に移譲する.
+    ///
+    /// 選挙のタイムアウトを待たずにリーダを交代させたい場合(e.g., ローリング再起動)に使用する.
+    /// `target`のローカルログが追い付くのを待ってから、`target`に即座に選挙を開始させるので、
+    /// 移譲の完了は、このノードがフォロワーに遷移したこと(`Event::RoleChanged`)によって把握できる.
+    ///
+    /// 移譲中は新規の提案が拒否される.
+    /// `ReplicatedLogOptions::leadership_transfer_max_rounds`回のハートビートの間に移譲が完了しなかった場合には、
+    /// 移譲は中止されて`Event::LeadershipTransferAborted`が発行される.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `target`がクラスタのメンバではない、ウィットネスである、ないし、ローカルノード自身の場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    ///
+    /// 既に別の移譲が進行中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    pub fn transfer_leadership(&mut self, target: NodeId) -> Result<()> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            track!(leader.transfer_leadership(&mut self.node.common, target))
+        } else {
+            track_panic!(ErrorKind::NotLeader);
+        }
+    }
+
     /// 強制的にハートビートメッセージ(i.e., AppendEntriesCall)をブロードキャストする.
     ///
     /// 返り値は、送信メッセージのシーケンス番号.
@@ -151,6 +477,47 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// 次のハートビートを待たずに、最新のコミット状況を全フォロワーに即座に送信する.
+    ///
+    /// `heartbeat`メソッドと同じだが、送信したメッセージのシーケンス番号は返さない.
+    /// 重要なエントリ(e.g., 構成変更)のコミットを、素早くフォロワーに伝えたい場合に有用.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    pub fn broadcast_now(&mut self) -> Result<()> {
+        track!(self.heartbeat())?;
+        Ok(())
+    }
+
+    /// ReadIndex方式で、ログへの書き込みを行わずに線形化可能な読み込みを行うための確認を開始する.
+    ///
+    /// 呼び出し時点のコミット済み領域の終端(read index)を記録した上で、ハートビートを送信し、
+    /// 過半数のノードからリーダシップが確認された時点で、記録したread indexを返す`Future`が完了する.
+    /// 利用者は、状態機械への適用済み位置がread indexに達するのを待ってから、読み込みを行う必要がある.
+    ///
+    /// なお、選出後のリーダが、まだ現在の選挙期間のエントリ(i.e., 選出直後に追加される`LogEntry::Noop`)を
+    /// コミットしていない場合には、read indexの記録とハートビートの送信は、そのコミットまで保留される.
+    ///
+    /// 読み込みの度に過半数の確認を行うので、選出直後のリーダや、既に退位させられているリーダが、
+    /// 古い状態を読み込ませてしまうことはない.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// また、確認の完了前にリーダではなくなった場合には、
+    /// 返された`Future`が`ErrorKind::NotLeader`を理由としたエラーで完了する.
+    pub fn confirm_read_index(&mut self) -> Result<ReadIndex> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            Ok(leader.confirm_read_index(&mut self.node.common))
+        } else {
+            track_panic!(ErrorKind::NotLeader);
+        }
+    }
+
     /// ローカルログにスナップショットをインストールする.
     ///
     /// `new_head`が新しいローカルログの先頭位置となり、
@@ -161,9 +528,40 @@ impl<IO: Io> ReplicatedLog<IO> {
     /// 既にローカルログに対するスナップショットのインストールが進行中の場合には、
     /// `ErrorKind::Busy`を理由としてエラーが返される.
     ///
-    /// また現在のログの先頭よりも前の地点のスナップショットをインストールしようとした場合には、
+    /// また現在のログの先頭よりも前の地点のスナップショットをインストールしようとした場合や、
+    /// `snapshot`の大きさが`ReplicatedLogOptions::max_snapshot_size`を超えている場合、
+    /// `ReplicatedLogOptions::allow_snapshot_install_on_leader`が指定されていないリーダ上で、
+    /// コミット済みの地点を超えるスナップショットをインストールしようとした場合には、
     /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
     pub fn install_snapshot(&mut self, new_head: LogIndex, snapshot: Vec<u8>) -> Result<()> {
+        track!(self.install_snapshot_inner(new_head, snapshot, None))
+    }
+
+    /// `install_snapshot`と同様だが、インストールの完了時に呼び出されるコールバックを指定可能.
+    ///
+    /// `callback`は、インストールが完了した場合には新しいログの先頭位置を引数として、
+    /// インストールに失敗した場合や完了前に中断された場合にはエラーを引数として、一度だけ呼び出される.
+    ///
+    /// このメソッド自体がエラーを返した場合には、`callback`は呼び出されない.
+    ///
+    /// # Errors
+    ///
+    /// `install_snapshot`と同様.
+    pub fn install_snapshot_with_callback(
+        &mut self,
+        new_head: LogIndex,
+        snapshot: Vec<u8>,
+        callback: SnapshotInstallCallback,
+    ) -> Result<()> {
+        track!(self.install_snapshot_inner(new_head, snapshot, Some(callback)))
+    }
+
+    fn install_snapshot_inner(
+        &mut self,
+        new_head: LogIndex,
+        snapshot: Vec<u8>,
+        callback: Option<SnapshotInstallCallback>,
+    ) -> Result<()> {
         track_assert!(
             !self.node.is_loading(),
             ErrorKind::Busy,
@@ -192,7 +590,10 @@ impl<IO: Io> ReplicatedLog<IO> {
             config,
             snapshot,
         };
-        track!(self.node.common.install_snapshot(prefix))?;
+        track!(self
+            .node
+            .common
+            .install_snapshot_with_callback(prefix, callback))?;
         Ok(())
     }
 
@@ -203,6 +604,65 @@ impl<IO: Io> ReplicatedLog<IO> {
         self.node.start_election();
     }
 
+    /// ノードの停止に備えて、クラスタへの参加を穏やかに終了する.
+    ///
+    /// ローカルノードがリーダの場合には、ローカルログの複製が最も進んでいるフォロワーへのリーダシップの移譲を試みる.
+    /// 移譲が`ReplicatedLogOptions::leadership_transfer_max_rounds`回のハートビートの間に完了しなかった場合や、
+    /// 移譲先となるフォロワーが存在しない場合には、移譲を待たずにフォロワーに退く
+    /// (この場合、新しいリーダは、他のノードの選挙のタイムアウトを待って選出される).
+    ///
+    /// 停止処理の開始後も、他のノードからのRPCには応答し続けるが、選挙のタイムアウトを迎えても立候補はしない.
+    /// 開始時には`Event::ShuttingDown`が発行される.
+    ///
+    /// # Errors
+    ///
+    /// リーダシップの移譲の開始に失敗した場合には、そのエラーが返される.
+    pub fn begin_shutdown(&mut self) -> Result<()> {
+        track!(self.node.begin_shutdown())
+    }
+
+    /// ノードの停止処理が開始済みの場合には`true`を返す.
+    pub fn is_shutting_down(&self) -> bool {
+        self.node.common.is_shutting_down()
+    }
+
+    /// 選挙のタイムアウトを待たずに、即座に新しい選挙を開始する.
+    ///
+    /// `start_election`とは異なり、ローカルノードが立候補者の場合にも新しい選挙が開始され、
+    /// 選挙を開始できない場合にはエラーが返される.
+    ///
+    /// `ReplicatedLogOptions::pre_vote`が有効な場合には、タイムアウト時と同様に事前投票から始める.
+    ///
+    /// # Errors
+    ///
+    /// ローカルノードが状態の復元中の場合には`ErrorKind::Busy`が、
+    /// リーダ、投票権を有しないメンバ、ないしウィットネスの場合には`ErrorKind::InvalidInput`が、
+    /// それぞれエラーの理由として返される.
+    pub fn trigger_election(&mut self) -> Result<()> {
+        track!(self.node.trigger_election())
+    }
+
+    /// 次に通知されるイベントが既に確定している場合には、それを消費せずに返す.
+    ///
+    /// このメソッドが返すのは、既にキューに積まれているイベントのみであり、
+    /// `poll`の呼び出しによって新たに生成されるイベントは含まれない.
+    pub fn peek_event(&self) -> Option<&Event> {
+        self.node.common.peek_event()
+    }
+
+    /// 選挙用のタイムアウトを新しく設定し直す.
+    ///
+    /// ノード間通信とは別の経路でリーダの生存が確認できた場合等に、
+    /// 不要な選挙の開始を避けるために使用できる.
+    /// リーダの生存が確実な場合にのみ使用すべきであり、乱用するとリーダの障害検知が遅れてしまうので注意.
+    ///
+    /// ローカルノードがフォロワー以外の場合には、何も行われない.
+    pub fn reset_election_timeout(&mut self) {
+        if let RoleState::Follower(_) = self.node.role {
+            self.node.common.reset_election_timeout();
+        }
+    }
+
     /// ローカルノードの情報を返す.
     pub fn local_node(&self) -> &Node {
         self.node.common.local_node()
@@ -213,6 +673,89 @@ impl<IO: Io> ReplicatedLog<IO> {
         self.node.common.log()
     }
 
+    /// コミット済みの最新のクラスタ構成と、それが有効となった位置(構成変更エントリのインデックス)を返す.
+    ///
+    /// 構成の変遷を追跡するツール等での使用が想定されている.
+    /// 詳細は`LogHistory::config_version`を参照のこと.
+    pub fn config_version(&self) -> (LogIndex, ClusterConfig) {
+        self.node.common.config_version()
+    }
+
+    /// 最後に読み込まれたスナップショットの、メタデータ(終端位置と構成)を返す.
+    ///
+    /// スナップショットが一度も読み込まれていない場合には`None`が返される.
+    pub fn loaded_snapshot(&self) -> Option<LogPrefixMeta> {
+        self.node.common.loaded_snapshot()
+    }
+
+    /// ローカルログと、他ノードのログとの整合性を判定する.
+    ///
+    /// 詳細は`ConsistencyResult`のドキュメントを参照のこと.
+    /// `peer_term_at`は、相手のログの指定インデックスのエントリの`Term`を返す関数.
+    pub fn is_log_consistent_with<F>(
+        &self,
+        peer_tail: LogPosition,
+        peer_term_at: F,
+    ) -> ConsistencyResult
+    where
+        F: Fn(LogIndex) -> Option<Term>,
+    {
+        self.node
+            .common
+            .is_log_consistent_with(peer_tail, peer_term_at)
+    }
+
+    /// 直近の投票の`Term`と投票先を返す.
+    ///
+    /// 立候補者として自分自身に投票している場合には、投票先は`None`となる.
+    /// 票割れ(split vote)の調査等に有用.
+    pub fn last_vote(&self) -> (Term, Option<NodeId>) {
+        self.node.common.last_vote()
+    }
+
+    /// 整合性の監査のために、コミット済みのログ全体を読み込んで検査する.
+    ///
+    /// スナップショット以降のコミット済み領域を`Io::load_log`で読み込み、
+    /// 各エントリの`Term`が単調増加していること、および、ローカルログの歴史と一致することを確認する.
+    /// 検査は通常の処理と並行して行われ、ローカルノードの状態は一切変更されない.
+    ///
+    /// 検出された問題は、返された`Future`の結果(`AuditReport`)に含まれる.
+    pub fn audit_committed_log(&mut self) -> Result<AuditFuture<IO>> {
+        track!(self.node.common.audit_committed_log())
+    }
+
+    /// ローカルノードのリーダシップの状況を返す.
+    ///
+    /// ローカルノードが書き込みに応じられない場合には、その理由が返される.
+    pub fn leadership_status(&self) -> LeadershipStatus {
+        self.node.common.leadership_status()
+    }
+
+    /// 現在の状態に対応する読み込みトークンを返す.
+    ///
+    /// 読み込みを行ったクライアントにこのトークンを渡しておき、
+    /// 次回の読み込み時に`validate_read_token`メソッドで検証することで、
+    /// 古い状態を読み込んでしまう可能性を検知することができる.
+    pub fn read_token(&self) -> ReadToken {
+        self.node.common.read_token()
+    }
+
+    /// 以前の読み込み時に発行されたトークンを検証する.
+    ///
+    /// ローカルノードの`Term`がトークンのものよりも小さい場合には`false`が返される.
+    pub fn validate_read_token(&self, token: ReadToken) -> bool {
+        self.node.common.validate_read_token(token)
+    }
+
+    /// `token`を提示したクライアントからの読み込みに、ローカルノードが応じられる場合には`true`を返す.
+    ///
+    /// 状態機械への適用済み位置(`notify_applied`で通知されたもの)が、
+    /// `token`の位置に達している場合にのみ`true`が返される.
+    /// `false`の場合には、適用が進むのを待つか、読み込みを別のノード(e.g., リーダ)に転送すること.
+    pub fn can_serve_read(&self, token: &SessionToken) -> bool {
+        self.node.common.can_serve_read(token)
+    }
+
     /// ローカルログへの書き込み待ちの状態の提案群の数を返す.
     ///
     /// この値は、ローカルストレージの詰まり具合を把握するために有用である.
@@ -229,60 +772,1064 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
-    /// スナップショットをインストール中の場合には`true`を返す.
+    /// 送信が保留されているブロードキャストメッセージの数を返す.
     ///
-    /// このメソッドが`true`を返している間は、
-    /// 新しいスナップショットのインストールを行うことはできない.
-    pub fn is_snapshot_installing(&self) -> bool {
-        // 起動後のロードフェーズの間もスナップショットのインストールは行えないので、
-        // その場合も`true`を返しておく.
-        self.node.is_loading() || self.node.common.is_snapshot_installing()
+    /// `ReplicatedLogOptions::max_broadcast_sends_per_run`が指定されていない場合には、常に`0`が返される.
+    pub fn deferred_broadcast_len(&self) -> usize {
+        self.node.common.deferred_messages_len()
     }
 
-    /// 過半数以上の応答を得られた最新のハートビート(i.e., AppendEntriesCall) のシーケンス番号を返す.
-    ///
-    /// この値は、同じ選挙期間に関しては減少することはないことが保証されている.
+    /// 各フォロワーのハートビートの応答率を、千分率(`0..=1000`)で返す.
     ///
-    /// # 注意
+    /// 応答率は、直近の`ReplicatedLogOptions::heartbeat_health_window`回のハートビートの中で、
+    /// 次のハートビートまでに応答が得られたものの割合となる.
     ///
-    /// ハートビートを行うのはリーダノードのみなので、それ以外のノードに関しては、
-    /// このメソッドが返す値は意味を持たない.
-    pub fn last_heartbeat_ack(&self) -> SequenceNumber {
+    /// ローカルノードが非リーダである場合には、常に空の`Vec`が返される.
+    pub fn heartbeat_health(&self) -> Vec<(NodeId, u32)> {
         if let RoleState::Leader(ref leader) = self.node.role {
-            leader.last_heartbeat_ack()
+            leader.heartbeat_health(&self.node.common)
         } else {
-            SequenceNumber::new(0)
+            Vec::new()
         }
     }
 
-    /// 現在のクラスタ構成を返す.
-    pub fn cluster_config(&self) -> &ClusterConfig {
-        self.node.common.config()
+    /// フォロー中のリーダから、最後にメッセージを受信した時点のtick数を返す.
+    ///
+    /// ローカルノードがまだ一度もリーダからメッセージを受信していない場合には`None`が返される.
+    pub fn last_leader_contact_tick(&self) -> Option<u64> {
+        self.node.common.last_leader_contact_tick()
     }
 
-    /// I/O実装に対する参照を返す.
-    pub fn io(&self) -> &IO {
-        self.node.common.io()
+    /// ローカルノードが、これまでに一度でもクラスタの過半数との通信に成功したことがある場合には`true`を返す.
+    ///
+    /// 一度もクラスタに参加できていないノード(e.g., 設定ミス)と、
+    /// 正常に稼働していたがネットワーク分断等によって孤立したノード、を区別するために有用である.
+    pub fn has_contacted_quorum(&self) -> bool {
+        self.node.common.has_contacted_quorum()
     }
 
-    /// I/O実装に対する破壊的な参照を返す.
+    /// 状態機械への適用が完了した位置を通知する.
     ///
-    /// # Safety
-    /// 破壊的な操作は、Raftの管理外の挙動となり、
-    /// 整合性を崩してしまう可能性もあるので、
-    /// 注意を喚起する意味で`unsafe`と設定されている.
-    pub unsafe fn io_mut(&mut self) -> &mut IO {
-        self.node.common.io_mut()
+    /// `index`は、適用済みのエントリ群の終端(i.e., 次に適用されるエントリのインデックス)となる.
+    /// 現在の適用済み位置よりも前の`index`が指定された場合には、何も行われない.
+    ///
+    /// # Errors
+    ///
+    /// `index`がコミット済み領域の終端を超えている場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn notify_applied(&mut self, index: LogIndex) -> Result<()> {
+        track!(self.node.common.set_applied_index(index))?;
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            leader.update_command_traces(&self.node.common);
+        }
+        Ok(())
     }
-}
-impl<IO: Io> Stream for ReplicatedLog<IO> {
-    type Item = Event;
-    type Error = Error;
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+
+    /// コミット済みだが、まだ状態機械に適用されていないエントリの数を返す.
+    ///
+    /// 適用済みの位置は`notify_applied`によって通知されたものが使用される.
+    pub fn apply_backlog(&self) -> u64 {
+        self.node.common.apply_backlog()
+    }
+
+    /// フォロワー`node`のローカルログが、リーダのローカルログの先頭よりも遅れている場合には`true`を返す.
+    ///
+    /// その場合、必要なエントリは既に削除されているので、エントリ群の代わりにスナップショットが送信される.
+    ///
+    /// ローカルノードが非リーダである場合には、常に`false`が返される.
+    pub fn peer_needs_snapshot(&self, node: &NodeId) -> bool {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.peer_needs_snapshot(&self.node.common, node)
+        } else {
+            false
+        }
+    }
+
+    /// ローカルノードがリーダに選出されてから経過したtick数を返す.
+    ///
+    /// "tick"はリーダ用のタイムアウトの時間(i.e., ハートビートの間隔)を単位とする.
+    /// クラスタ全体でこの値が小さい状態が続く場合には、リーダが安定していないことを示している.
+    ///
+    /// ローカルノードが非リーダである場合には、常に`0`が返される.
+    pub fn leadership_age_ticks(&self) -> u64 {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.leadership_age_ticks(&self.node.common)
+        } else {
+            0
+        }
+    }
+
+    /// 未消費のイベント数が`ReplicatedLogOptions::event_queue_high_water_mark`に達している場合には`true`を返す.
+    pub fn is_event_queue_congested(&self) -> bool {
+        self.node.common.is_event_queue_congested()
+    }
+
+    /// インストール中のスナップショットのバイト数を返す.
+    ///
+    /// インストール中ではない場合には`0`が返される.
+    pub fn installing_snapshot_bytes(&self) -> usize {
+        self.node.common.installing_snapshot_bytes()
+    }
+
+    /// バックグランドで実行中のI/O処理の状況を返す.
+    pub fn io_status(&self) -> IoStatus {
+        self.node.common.io_status()
+    }
+
+    /// `Io`が提供するメッセージチャンネルのキューの状況を返す.
+    pub fn transport_stats(&self) -> TransportStats {
+        self.node.common.transport_stats()
+    }
+
+    /// 現在設定されているタイムアウトの時間を返す.
+    ///
+    /// `Io::current_timeout_duration`に対応していない`Io`実装の場合には`None`が返される.
+    pub fn current_timeout_duration(&self) -> Option<Duration> {
+        self.node.common.current_timeout_duration()
+    }
+
+    /// ローカルノードで発生した、RPCメッセージの送受信や選挙等の回数を返す.
+    pub fn counters(&self) -> NodeCounters {
+        self.node.common.counters()
+    }
+
+    /// コミットされたコマンドのバイト数の分布を返す.
+    ///
+    /// `ReplicatedLogOptions::track_entry_sizes`が無効な場合には、常に空の分布が返される.
+    pub fn entry_size_histogram(&self) -> SizeHistogram {
+        self.node.common.entry_size_histogram()
+    }
+
+    /// デバッグ用にメモリ上に保持されている、コミット済みエントリを返す.
+    ///
+    /// `ReplicatedLogOptions::debug_retain_entries`で指定された数の直近のエントリのみが保持されており、
+    /// それ以外のインデックスが指定された場合には`None`が返される.
+    pub fn peek_entry(&self, index: LogIndex) -> Option<&LogEntry> {
+        self.node.common.peek_entry(index)
+    }
+
+    /// 未処理のコミット済みログ領域の読み込みを、先行して開始する.
+    ///
+    /// 通常は`Stream::poll`の中で自動的に読み込みが開始されるが、
+    /// 利用者が`Event::Committed`の処理に時間を要する場合には、
+    /// 事前にこのメソッドを呼び出しておくことで、次のエントリ群の読み込みを重ねて行うことができる.
+    ///
+    /// 新たに読み込みを開始した場合には`true`が返される.
+    pub fn prefetch_committed(&mut self) -> Result<bool> {
+        track!(self.node.common.prefetch_committed())
+    }
+
+    /// 管理ツール向けに、ローカルノードの状態の一覧を返す.
+    ///
+    /// 個別のアクセサを順に呼び出す場合とは異なり、全ての値が同じ時点の状態から取得される.
+    pub fn admin_view(&self) -> AdminView {
+        self.node.admin_view()
+    }
+
+    /// 各フォロワーの複製の遅れを返す.
+    ///
+    /// ローカルノードが非リーダである場合には、常に空の`Vec`が返される.
+    pub fn replication_lag(&self) -> Vec<PeerLag> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.replication_lag(&self.node.common)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// 新しい提案がコミットされるまでに要する時間の、大まかな推定値を返す.
+    ///
+    /// クライアントのタイムアウトを決める際の目安として使用できる.
+    /// 未コミットのエントリ群が滞留している場合には、直近のコミットのスループットに応じて値が大きくなる.
+    /// 推定の詳細は`Leader::estimated_commit_latency`を参照のこと.
+    ///
+    /// 推定に必要な情報が揃っていない場合(i.e., `Io::now`が時刻を返さない場合や、
+    /// フォロワーからの応答が未受信の場合)や、ローカルノードが非リーダである場合には`None`が返される.
+    pub fn estimated_commit_latency(&self) -> Option<Duration> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.estimated_commit_latency(&self.node.common)
+        } else {
+            None
+        }
+    }
+
+    /// 各フォロワー(ラーナーを含む)への複製の進み具合を返す.
+    ///
+    /// ローカルノードが非リーダである場合には、常に空の`HashMap`が返される.
+    pub fn replication_progress(&self) -> HashMap<NodeId, FollowerProgress> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.replication_progress(&self.node.common)
+        } else {
+            HashMap::new()
+        }
+    }
+
+    /// `proposal`の追跡情報を返す.
+    ///
+    /// `ReplicatedLogOptions::command_trace_capacity`が`0`の場合、
+    /// 追跡情報が既に破棄されている場合、あるいは、ローカルノードが非リーダである場合には`None`が返される.
+    pub fn command_trace(&self, proposal: &ProposalId) -> Option<CommandTrace> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.command_trace(proposal)
+        } else {
+            None
+        }
+    }
+
+    /// スナップショットをインストール中の場合には`true`を返す.
+    ///
+    /// このメソッドが`true`を返している間は、
+    /// 新しいスナップショットのインストールを行うことはできない.
+    pub fn is_snapshot_installing(&self) -> bool {
+        // 起動後のロードフェーズの間もスナップショットのインストールは行えないので、
+        // その場合も`true`を返しておく.
+        self.node.is_loading() || self.node.common.is_snapshot_installing()
+    }
+
+    /// 新旧両方の構成の合意が必要な状態の場合には、旧構成と新構成の組を返す.
+    ///
+    /// それ以外の場合には`None`が返される.
+    pub fn joint_config(&self) -> Option<(ClusterConfig, ClusterConfig)> {
+        self.node.common.joint_config()
+    }
+
+    /// 過半数以上の応答を得られた最新のハートビート(i.e., AppendEntriesCall) のシーケンス番号を返す.
+    ///
+    /// この値は、同じ選挙期間に関しては減少することはないことが保証されている.
+    ///
+    /// # 注意
+    ///
+    /// ハートビートを行うのはリーダノードのみなので、それ以外のノードに関しては、
+    /// このメソッドが返す値は意味を持たない.
+    pub fn last_heartbeat_ack(&self) -> SequenceNumber {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.last_heartbeat_ack()
+        } else {
+            SequenceNumber::new(0)
+        }
+    }
+
+    /// 現在のクラスタ構成を返す.
+    pub fn cluster_config(&self) -> &ClusterConfig {
+        self.node.common.config()
+    }
+
+    /// I/O実装に対する参照を返す.
+    pub fn io(&self) -> &IO {
+        self.node.common.io()
+    }
+
+    /// I/O実装に対する破壊的な参照を返す.
+    ///
+    /// # Safety
+    /// 破壊的な操作は、Raftの管理外の挙動となり、
+    /// 整合性を崩してしまう可能性もあるので、
+    /// 注意を喚起する意味で`unsafe`と設定されている.
+    pub unsafe fn io_mut(&mut self) -> &mut IO {
+        self.node.common.io_mut()
+    }
+}
+/// `ReplicatedLog::replace_io`が失敗した場合のエラー.
+///
+/// 差し替え前の`ReplicatedLog`インスタンスと、差し替えに使われなかったI/O実装を保持している.
+pub struct ReplaceIoError<IO: Io, NewIo> {
+    /// エラーの理由.
+    pub error: Error,
+
+    /// 差し替え前の`ReplicatedLog`インスタンス.
+    pub log: Box<ReplicatedLog<IO>>,
+
+    /// 差し替えに使われなかったI/O実装.
+    pub new_io: NewIo,
+}
+impl<IO: Io, NewIo> fmt::Debug for ReplaceIoError<IO, NewIo> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReplaceIoError {{ error: {:?}, .. }}", self.error)
+    }
+}
+impl<IO: Io, NewIo> From<ReplaceIoError<IO, NewIo>> for Error {
+    fn from(f: ReplaceIoError<IO, NewIo>) -> Self {
+        f.error
+    }
+}
+
+impl<IO: Io> Stream for ReplicatedLog<IO> {
+    type Item = Event;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         track!(self.node.poll(), "node={:?}", self.local_node())
     }
 }
 
+/// バックグランドで実行中のI/O処理の状況.
+///
+/// ノードの処理が停滞している場合に、その原因を調査するために有用.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoStatus {
+    /// コミット済みログの読み込み中の場合には、その開始位置.
+    pub loading_committed_from: Option<LogIndex>,
+
+    /// スナップショットのインストール中の場合には、そのスナップショットの終端位置.
+    pub installing_snapshot_to: Option<LogPosition>,
+}
+
+/// ローカルノードで発生した、RPCメッセージの送受信や選挙等の回数.
+///
+/// `ReplicatedLog::counters`によって取得される.
+/// `RaftlogMetrics`とは異なり、特定のメトリクスライブラリには依存しないので、
+/// 利用者は任意の方法で、これらの値を収集することができる.
+/// 各値は、ノードの起動時からの累計となる.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCounters {
+    /// 送信した`RequestVoteCall`の数(事前投票の依頼を含む).
+    pub request_vote_sent: u64,
+
+    /// 受信した`RequestVoteCall`の数(事前投票の依頼を含む).
+    pub request_vote_received: u64,
+
+    /// 受信した`RequestVoteReply`の内、再送された投票依頼に対する重複した投票(`repeated`)の数.
+    ///
+    /// 値が大きい場合には、投票依頼ないしその応答が、ネットワーク上で頻繁に失われていることを示している.
+    pub repeated_votes_received: u64,
+
+    /// 送信した`AppendEntriesCall`の数(ハートビートを含む).
+    pub append_entries_sent: u64,
+
+    /// 受信した`AppendEntriesCall`の数(ハートビートを含む).
+    pub append_entries_received: u64,
+
+    /// 立候補した回数(事前投票は含まない).
+    pub elections_started: u64,
+
+    /// リーダに選出された回数.
+    pub elections_won: u64,
+
+    /// スナップショットのインストールが完了した回数.
+    pub snapshots_installed: u64,
+
+    /// コミットされたエントリの数.
+    pub log_entries_committed: u64,
+}
+
+/// `Io`が提供するメッセージチャンネルのキューの状況.
+///
+/// 各値が大きい場合には、チャンネルがraftlogの処理速度に追い付けていないことを示している.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportStats {
+    /// 受信済みで、まだ処理されていないメッセージの数.
+    pub inbound_depth: usize,
+
+    /// 各ピア宛ての、まだ送信が完了していないメッセージの数.
+    pub outbound_depths: Vec<(NodeId, usize)>,
+}
+
+/// コミットされたコマンドのバイト数の分布.
+///
+/// `ReplicatedLogOptions::track_entry_sizes`が有効な場合に記録される.
+/// 記録に使用されるメモリ量は、コミット数に依らず一定である.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// 各バケットの上限(バイト数)と、そのバケットに属するコマンドの数.
+    ///
+    /// バケットは上限の昇順に並んでおり、各コマンドは、そのバイト数以上の上限を持つ最初のバケットに数えられる.
+    /// 最後のバケットの上限は`u64::MAX`となる.
+    pub buckets: Vec<(u64, u64)>,
+
+    /// 記録されたコマンドの数.
+    pub count: u64,
+
+    /// 記録されたコマンドの最大バイト数.
+    pub max: u64,
+}
+impl SizeHistogram {
+    /// 空の分布を生成する.
+    ///
+    /// バケットの上限は`64`バイトから`1MiB`までの4倍毎と、`u64::MAX`となる.
+    pub fn new() -> Self {
+        let buckets = (0..8)
+            .map(|i| 64 << (2 * i))
+            .chain(Some(u64::MAX))
+            .map(|upper| (upper, 0))
+            .collect();
+        SizeHistogram {
+            buckets,
+            count: 0,
+            max: 0,
+        }
+    }
+
+    /// `size`バイトのコマンドを記録する.
+    pub fn record(&mut self, size: u64) {
+        if let Some(bucket) = self.buckets.iter_mut().find(|b| size <= b.0) {
+            bucket.1 += 1;
+        }
+        self.count += 1;
+        self.max = cmp::max(self.max, size);
+    }
+}
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ローカルノードの状態の要約.
+///
+/// `ReplicatedLogOptions::status_event_interval_ticks`が指定されている場合に、
+/// `Event::Status`として定期的に通知される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommonDebugState {
+    /// ローカルノードの役割.
+    pub role: Role,
+
+    /// 現在の選挙期間.
+    pub term: Term,
+
+    /// コミット済みログ領域の終端.
+    pub committed_tail: LogIndex,
+
+    /// コミット済みだが、まだ消費(i.e., `Event::Committed`として通知)されていないエントリの数.
+    pub unconsumed_entries: u64,
+}
+
+/// 管理ツール向けの、ある時点におけるローカルノードの状態の一覧.
+///
+/// 全てのフィールドは同じ時点の状態から取得されているので、
+/// 個別のアクセサを順に呼び出す場合とは異なり、役割の遷移途中の状態が混在することはない.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminView {
+    /// ローカルノードの役割.
+    pub role: Role,
+
+    /// 現在の選挙期間.
+    pub term: Term,
+
+    /// 現在の選挙期間における投票先.
+    pub voted_for: NodeId,
+
+    /// ローカルログの終端.
+    pub log_tail: LogIndex,
+
+    /// コミット済みログ領域の終端.
+    pub committed_tail: LogIndex,
+
+    /// 状態機械への適用済みの位置.
+    pub applied_index: LogIndex,
+
+    /// クラスタの構成.
+    pub config: ClusterConfig,
+
+    /// 各フォロワーの複製の遅れ.
+    ///
+    /// ローカルノードがリーダの場合にのみ`Some`となる.
+    pub peers: Option<Vec<PeerLag>>,
+
+    /// リーダに選出されてから経過したtick数.
+    ///
+    /// ローカルノードがリーダの場合にのみ`Some`となる.
+    pub leadership_age_ticks: Option<u64>,
+}
+
+/// フォロワーの複製の遅れ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerLag {
+    /// フォロワーのID.
+    pub node: NodeId,
+
+    /// リーダのログ終端に対して遅れているエントリ数.
+    pub entries_behind: u64,
+
+    /// 遅れているエントリ群の合計バイト数の推定値.
+    ///
+    /// リーダが自身の選挙期間中に追記したエントリのみが計上される.
+    /// (それ以前のエントリのサイズは、リーダのメモリ上には存在しないため)
+    pub bytes_behind_estimate: u64,
+}
+
+/// リーダから見た、フォロワーへの複製の進み具合.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FollowerProgress {
+    /// リーダのログとの一致が確認済みの、フォロワーのローカルログの終端.
+    ///
+    /// 一致する地点を探索中の場合には`0`となる.
+    pub match_index: LogIndex,
+
+    /// 次にフォロワーに送信されるエントリの開始位置.
+    ///
+    /// 一致する地点を探索中の場合には、探索中の位置を示す.
+    pub next_index: LogIndex,
+
+    /// フォロワーに送信するスナップショットを準備中の場合には`true`.
+    pub sending_snapshot: bool,
+
+    /// フォロワーが投票権を持たないメンバ(ラーナー)である場合には`true`.
+    pub is_learner: bool,
+}
+
+/// リーダに提案されたエントリの処理の進み具合の追跡情報.
+///
+/// 各時点は`ReplicatedLog`の起動からのtick数(ハートビートの間隔を単位とする)で記録される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTrace {
+    /// 提案された時点.
+    pub proposed_tick: u64,
+
+    /// リーダのローカルログへの追記が完了した時点.
+    pub appended_tick: Option<u64>,
+
+    /// 各ノード(リーダ自身を含む)のローカルログへの複製が確認された時点.
+    ///
+    /// 要素は確認された順に並ぶ.
+    pub replicated_to: Vec<(NodeId, u64)>,
+
+    /// コミットされた時点.
+    pub committed_tick: Option<u64>,
+
+    /// 状態機械への適用(`ReplicatedLog::notify_applied`)が通知された時点.
+    pub applied_tick: Option<u64>,
+}
+
+/// `ReplicatedLog`の挙動を調整するためのオプション群.
+#[derive(Debug, Clone)]
+pub struct ReplicatedLogOptions {
+    /// ハートビートの応答率の計測に用いる窓の大きさ(ハートビートの回数).
+    ///
+    /// デフォルト値は`10`.
+    pub heartbeat_health_window: usize,
+
+    /// ハートビートの応答率(千分率)がこの値を下回ったフォロワーが存在する場合には、
+    /// リーダから`Event::PeerUnhealthy`が発行される.
+    ///
+    /// デフォルト値は`0` (i.e., イベントは発行されない).
+    pub peer_unhealthy_threshold_per_mille: u32,
+
+    /// `true`の場合には、連続するコミット済みエントリ群が`Event::CommittedBatch`としてまとめて通知される.
+    ///
+    /// ただし構成変更エントリはバッチの境界として扱われ、常に単独の`Event::Committed`として通知される.
+    ///
+    /// デフォルト値は`false`.
+    pub batch_committed_events: bool,
+
+    /// `true`の場合には、`LogEntry::Noop`のコミットは`Event::Committed`(ないし`Event::CommittedBatch`)として通知されない.
+    ///
+    /// `Noop`は新しいリーダの選出直後に追記されるエントリで、状態機械に適用すべき内容を持たない.
+    /// ただし、この場合には`Event::Committed`のインデックスが連続しなくなる点には注意が必要.
+    ///
+    /// デフォルト値は`false`.
+    pub skip_noop_committed_events: bool,
+
+    /// `role_flapping_window_ticks`の期間内に、この値を超える回数の役割変更が発生した場合には、
+    /// 役割が振動しているものと判断される.
+    ///
+    /// 振動を検知したノードは`Event::RoleFlappingDetected`を発行した上で、
+    /// `role_flapping_backoff_ticks`の間は、フォロワーのまま新しい選挙を開始しなくなる.
+    ///
+    /// なお"tick"はリーダ用のタイムアウトの時間(i.e., ハートビートの間隔)を単位とする.
+    ///
+    /// デフォルト値は`0` (i.e., 検知は行わない).
+    pub role_flapping_threshold: usize,
+
+    /// 役割の振動を検知するための窓の大きさ(tick数).
+    ///
+    /// デフォルト値は`10`.
+    pub role_flapping_window_ticks: u64,
+
+    /// 役割の振動を検知した後に、立候補を控える期間(tick数).
+    ///
+    /// デフォルト値は`10`.
+    pub role_flapping_backoff_ticks: u64,
+
+    /// コミット済みログ領域の終端が、この値の倍数を跨いで進んだ場合に`Event::CommitAdvanced`が発行される.
+    ///
+    /// 複数の倍数を一度に跨いだ場合でも、発行されるイベントは一つのみとなる.
+    /// なお、エントリ毎の`Event::Committed`の発行には影響しない.
+    ///
+    /// デフォルト値は`0` (i.e., `Event::CommitAdvanced`は発行されない).
+    pub commit_notify_stride: u64,
+
+    /// 未消費のイベント数の上限(高水位).
+    ///
+    /// 未消費のイベント数がこの値に達した場合の挙動は`event_queue_overflow_policy`で指定する.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub event_queue_high_water_mark: usize,
+
+    /// 未消費のイベント数が`event_queue_high_water_mark`に達した場合の挙動.
+    ///
+    /// デフォルト値は`EventQueueOverflowPolicy::Backpressure`.
+    pub event_queue_overflow_policy: EventQueueOverflowPolicy,
+
+    /// 最初の選挙でリーダとなるべきノードのヒント.
+    ///
+    /// これが指定されている場合には、起動時(ロード完了直後)に立候補するのは指定のノードのみとなり、
+    /// それ以外のノードはフォロワーとして起動する.
+    /// 指定のノードが停止している場合でも、フォロワーのタイムアウト後には通常通り選挙が行われるので、
+    /// 安全性が損なわれることはない.
+    ///
+    /// クラスタの全ノードで同じ値を指定する必要がある.
+    ///
+    /// デフォルト値は`None`.
+    pub initial_leader_hint: Option<NodeId>,
+
+    /// この値のtick数毎に、`Event::Status`が発行される.
+    ///
+    /// なお"tick"はリーダ用のタイムアウトの時間(i.e., ハートビートの間隔)を単位とする.
+    ///
+    /// デフォルト値は`0` (i.e., `Event::Status`は発行されない).
+    pub status_event_interval_ticks: u64,
+
+    /// リーダのリース期間(tick数).
+    ///
+    /// デフォルトでは、リーダをフォロー中のノードは、他のノードから新しい選挙期間のメッセージを受信しても、
+    /// 自身のタイムアウトが発火するまでは、それを無視し続ける.
+    ///
+    /// この値が指定されている場合には、その挙動がリース期間に限定される.
+    /// つまり、フォロー中のリーダから最後にメッセージを受信してから、この値のtick数が経過するまでの間は、
+    /// 投票依頼(`RequestVoteCall`)を明示的に拒否し、リース期間の経過後は通常通りに応じるようになる.
+    ///
+    /// リース期間中は、リーダの障害時であっても投票は行われないため、
+    /// 最大でリース期間分、正当なフェイルオーバーが遅延する可能性がある点には注意が必要.
+    ///
+    /// なお"tick"はリーダ用のタイムアウトの時間(i.e., ハートビートの間隔)を単位とする.
+    ///
+    /// デフォルト値は`0` (i.e., リースは使用しない).
+    pub leader_lease_ticks: u64,
+
+    /// コミット済みだが未消費のエントリ数がこの値以上の場合には、大幅に遅れているものと判断される.
+    ///
+    /// 大幅に遅れている間は、(構成変更エントリ以外の)`Event::Committed`の発行は省略され、
+    /// 追い付いた時点で`Event::CaughtUp`がまとめて一つだけ発行される.
+    /// 利用者は、スナップショットとローカルログを用いて、状態機械を自前で再構築する必要がある.
+    ///
+    /// デフォルト値は`0` (i.e., 省略は行わない).
+    pub catch_up_threshold: u64,
+
+    /// 一回のポーリング(`ReplicatedLog::poll`)の中で、ブロードキャストのために送信可能なメッセージ数の上限.
+    ///
+    /// 上限を超えた分の送信は保留され、次回以降のポーリングに持ち越される.
+    /// (保留中のメッセージがある間は、タスクの再ポーリングが要求される)
+    /// 大規模なクラスタで、遅い`Io::send_message`によって一度の処理が長時間停止してしまうことを防ぐために使用される.
+    /// 送信順は、ログの複製が遅れているフォロワーほど優先される.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub max_broadcast_sends_per_run: usize,
+
+    /// リーダが各フォロワーに送信済みで、まだ応答(複製完了の通知)を受け取っていないエントリ数の上限.
+    ///
+    /// 上限に達したフォロワーには、応答が返ってくるまでの間、新しいエントリ群の代わりにハートビートのみが送信される.
+    /// 大幅に遅れているフォロワーや、低速な経路の先にあるフォロワーに、大量のエントリ群を一度に送り付けることを防ぐために使用される.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub max_inflight_entries: usize,
+
+    /// リーダが各フォロワーに送信済みで、まだ応答を受け取っていないエントリ群の合計バイト数の上限.
+    ///
+    /// 挙動は`max_inflight_entries`と同様だが、こちらはエントリ(コマンド)のサイズを基準に判定を行う.
+    /// ただし、応答待ちのエントリが存在しない場合には、上限を超えるエントリであっても、一つだけは送信が許可される.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub max_append_bytes: usize,
+
+    /// 単一ノード構成のクラスタの起動時に、自分自身をリーダとする選挙が完了するまでの制限時間(tick数).
+    ///
+    /// 投票状況の保存が完了しない等の理由で、この期間内にリーダとなれなかった場合には、
+    /// 立候補を繰り返す代わりに、`ErrorKind::Timeout`を理由としたエラーが返される.
+    ///
+    /// デフォルト値は`0` (i.e., 制限なし).
+    pub bootstrap_deadline_ticks: u64,
+
+    /// 適用待ちのコミット済みエントリ数が、この値を超えた場合に`Event::ApplyBacklogHigh`が発行される.
+    ///
+    /// 適用済みの位置は`ReplicatedLog::notify_applied`によって利用者から通知される.
+    ///
+    /// デフォルト値は`0` (i.e., イベントは発行されない).
+    pub apply_backlog_threshold: u64,
+
+    /// 適用待ちのコミット済みエントリが存在するにも関わらず、
+    /// 状態機械への適用がこのtick数の間進まなかった場合に`Event::ApplyStalled`が発行される.
+    ///
+    /// 利用者が`ReplicatedLog::notify_applied`を呼び出さなくなった(e.g., 状態機械が停止した)ことの検知に使用できる.
+    ///
+    /// デフォルト値は`0` (i.e., イベントは発行されない).
+    pub apply_stall_threshold_ticks: u64,
+
+    /// インストールを受け付けるスナップショットの最大バイト数.
+    ///
+    /// これを超えるスナップショットは、`Io::save_log_prefix`の呼び出し前に拒否され、
+    /// `Event::SnapshotRejectedTooLarge`が発行される.
+    /// ディスク容量が限られているノードで、保存の途中で失敗してしまうことを防ぐために使用される.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub max_snapshot_size: usize,
+
+    /// リーダ状態のノードに対して、コミット済みの地点を超えるスナップショットのインストールを許可するかどうか.
+    ///
+    /// リーダ上での`ReplicatedLog::install_snapshot`の呼び出しは、通常は自身のローカルログの圧縮のためのものであり、
+    /// コミット済みの地点までのスナップショットであれば、この値に関わらず常に許可される.
+    /// 一方で、コミット済みの地点を超えるスナップショット(i.e., 他のノードから受け取ったもの)をリーダがインストールすると、
+    /// フォロワーとの状態がずれる恐れがあるため、デフォルトでは拒否される.
+    /// 特殊な復旧作業を行う場合には、これを`true`に設定すること.
+    ///
+    /// デフォルト値は`false`.
+    pub allow_snapshot_install_on_leader: bool,
+
+    /// デバッグ用に、メモリ上に保持しておく直近のコミット済みエントリの数.
+    ///
+    /// 保持されたエントリは、スナップショットのインストールや状態機械への適用の進行に関わらず、
+    /// `ReplicatedLog::peek_entry`で参照可能となる.
+    /// 事後調査(post-mortem)のためのものであり、エントリ群の複製を保持するため、
+    /// おおよそ「エントリの平均サイズ x この値」分のメモリが追加で消費される点に注意.
+    ///
+    /// デフォルト値は`0` (i.e., 保持しない).
+    pub debug_retain_entries: usize,
+
+    /// 同じIDを持つ複数のノードが存在する疑いがあるフォロワーの応答を無視するかどうか.
+    ///
+    /// `true`の場合には、`Event::DuplicateNodeIdSuspected`の対象となったフォロワーの応答は、
+    /// 現在の選挙期間中はコミットの判定やログの同期に使用されなくなる.
+    ///
+    /// デフォルト値は`false` (i.e., イベントの発行のみを行う).
+    pub ignore_suspected_duplicate_nodes: bool,
+
+    /// 生成時に、ローカルノードが`members`に含まれていることを要求するかどうか.
+    ///
+    /// `true`の場合には、ローカルノードが含まれない`members`を指定して`ReplicatedLog::with_options`を呼び出すと、
+    /// `ErrorKind::InvalidInput`エラーが返される (ノードIDの指定ミスの早期検出用).
+    ///
+    /// 構成変更によって新たに追加されるノードは、通常、自身を含まない既存の構成を指定して生成されるため、
+    /// そのようなノードに対しては`false`を指定すること.
+    ///
+    /// デフォルト値は`false`.
+    pub require_local_membership: bool,
+
+    /// 選挙の開始前に、事前投票(pre-vote)を行うかどうか.
+    ///
+    /// `true`の場合、選挙のタイムアウトに達したノードは、まず`term`を増やさずに事前投票を依頼し、
+    /// 過半数から承認を得られた場合にのみ、`term`を増やして実際に立候補する.
+    /// 事前投票は、最後にリーダと通信してから選挙のタイムアウトに達しているノードによってのみ承認される.
+    ///
+    /// これにより、ネットワーク分断によって孤立したノードが`term`を増やし続け、
+    /// 復帰時に健全なリーダを退任させてしまうことを防げる.
+    ///
+    /// なお、クラスタ内の一部のノードのみで有効にした場合でも正しく動作するが、
+    /// 上記の効果を得るためには全てのノードで有効にする必要がある.
+    ///
+    /// デフォルト値は`false`.
+    pub pre_vote: bool,
+
+    /// リーダシップの移譲(`ReplicatedLog::transfer_leadership`)を中止するまでの、ハートビートの回数.
+    ///
+    /// この回数のハートビートを送信しても移譲が完了しない場合には、移譲は中止され、
+    /// `Event::LeadershipTransferAborted`が発行される.
+    ///
+    /// デフォルト値は`10`.
+    pub leadership_transfer_max_rounds: usize,
+
+    /// リーダが一度のポーリング(`Stream::poll`の呼び出し)で処理する`AppendEntriesReply`の最大数.
+    ///
+    /// 大規模なクラスタで大量の応答が一度に届いた場合に、一回のポーリングの処理時間が延びるのを防ぐ.
+    /// 上限に達した場合、残りのメッセージは次回のポーリングで処理される
+    /// (その際には、現在のタスクに対して再ポーリングが通知される).
+    /// 各応答の処理毎にコミット位置の更新は行われるので、上限によってコミットの進行が止まることはない.
+    ///
+    /// デフォルト値は`0` (i.e., 制限なし).
+    pub max_replies_per_run: usize,
+
+    /// リーダが休止状態に移行するまでの、アイドル状態のハートビートの回数.
+    ///
+    /// 新規の提案がなく、全てのフォロワーのログが追い付いている状態が、この回数のハートビートの間継続した場合に、
+    /// リーダは休止状態に移行し、`quiescent_heartbeat_interval`回に一度しかハートビートを送信しなくなる.
+    /// 新規の提案があった場合や、遅れているフォロワーが現れた場合には、通常の間隔に戻る.
+    ///
+    /// 休止中のハートビートの間隔に合わせて、フォロワー側も選挙のタイムアウトを延長するため、
+    /// 休止中はリーダの障害検知が遅れる点に注意.
+    ///
+    /// デフォルト値は`0` (i.e., 休止しない).
+    pub quiescence_idle_rounds: u64,
+
+    /// 休止状態のリーダが、ハートビートを送信する間隔(リーダのタイムアウトの回数).
+    ///
+    /// フォロワーは、休止中はこの回数分まで選挙のタイムアウトを延長する.
+    ///
+    /// デフォルト値は`10`.
+    pub quiescent_heartbeat_interval: u64,
+
+    /// 利用者のトランスポート層が一度に送信可能な、メッセージの最大バイト数.
+    ///
+    /// 現在は、`snapshot_chunk_size`の検証にのみ使用される.
+    ///
+    /// デフォルト値は`0` (i.e., 上限なし).
+    pub max_message_size: usize,
+
+    /// スナップショットを分割して転送する際の、一つのチャンクの最大サイズ(バイト単位).
+    ///
+    /// 値を大きくするとメッセージ数は減るが、一度に送受信・保持されるデータ量が増える.
+    /// チャンクは一つのメッセージとして送信されるため、`max_message_size`以下である必要がある.
+    ///
+    /// なお、分割されるのはメッセージのみである.
+    /// 送信側のリーダは、スナップショット全体を`Io::load_log`でメモリ上に読み込んでから分割するため、
+    /// 送信中は(一時的にはチャンク群の分も含めて)スナップショットの二倍程度のメモリを必要とする.
+    /// 受信側で`Io::supports_snapshot_chunks`が`false`の場合には、受信側でも全体がメモリ上で連結される.
+    ///
+    /// `0`や、`max_message_size`(指定されている場合)を超える値を指定した場合には、
+    /// `ReplicatedLog::with_options`呼び出し時に`ErrorKind::InvalidInput`エラーが返される.
+    ///
+    /// デフォルト値は`1048576` (i.e., 1MiB).
+    pub snapshot_chunk_size: usize,
+
+    /// リーダが保持する提案の追跡情報(`CommandTrace`)の最大数.
+    ///
+    /// 上限を超えた場合には、古い提案のものから順に破棄される.
+    ///
+    /// デフォルト値は`0` (i.e., 追跡は行われない).
+    pub command_trace_capacity: usize,
+
+    /// `true`の場合には、投票状況の保存とローカルログの追記を、単一の順序付きキューを経由して実行する.
+    ///
+    /// キューに投入された書き込みは、発行された順に一つずつ実行され、
+    /// 役割の遷移等によって完了を待たずに破棄された書き込みも、後続の書き込みに追い越されることはない.
+    /// 必須の順序関係については`Io`のドキュメントを参照のこと.
+    ///
+    /// デフォルト値は`false` (i.e., 各書き込みは`Io`に即座に発行される).
+    pub ordered_persistence: bool,
+
+    /// リーダが提案をまとめて追記する際の、バッファ内のエントリ数の上限.
+    ///
+    /// `0`より大きい値が指定された場合には、提案は即座には追記されずにバッファに積まれ、
+    /// 次に`ReplicatedLog`がポーリングされた時点、`ReplicatedLog::flush_proposals`が呼び出された時点、
+    /// ないし、バッファ内の数がこの値に達した時点で、まとめて一つの`LogSuffix`として追記およびブロードキャストされる.
+    ///
+    /// デフォルト値は`0` (i.e., 提案は即座に追記される).
+    pub proposal_batch_size: usize,
+
+    /// 選挙用の(i.e., フォロワーおよび候補者用の)タイムアウトに加える、ランダムな揺らぎの最大値.
+    ///
+    /// `0`より大きい値が指定された場合には、タイムアウトの設定の度に`0`からこの値までの揺らぎが選ばれ、
+    /// `Io::create_jittered_timeout`を用いて、役割に応じた時間にそれを加えたタイムアウトが生成される.
+    /// 複数のノードが同時にタイムアウトして、票が割れ続けることを防ぐのに有用である.
+    ///
+    /// `0`より大きい値を指定する場合には、`Io::supports_jittered_timeout`が`true`を返す`Io`実装が必要であり、
+    /// そうではない場合には`ReplicatedLog`の生成がエラーとなる.
+    ///
+    /// デフォルト値は`0` (i.e., 揺らぎは加えられず、タイムアウトの生成は`Io::create_timeout`に委ねられる).
+    pub election_timeout_jitter: Duration,
+
+    /// `election_timeout_jitter`の揺らぎの決定に使用される乱数のシード.
+    ///
+    /// シードにはローカルノードのIDが混ぜ合わされるので、全てのノードで同じ値を指定しても、
+    /// 各ノードの揺らぎは異なるものとなる.
+    /// 同じシードとノードIDの組み合わせでは、常に同じ順序で揺らぎが選ばれるので、テストの再現に使用できる.
+    ///
+    /// デフォルト値は`None` (i.e., ランダムなシードが使用される).
+    pub election_timeout_jitter_seed: Option<u64>,
+
+    /// `true`の場合には、コミットされた`LogEntry::Command`のバイト数の分布が記録される.
+    ///
+    /// 記録された分布は`ReplicatedLog::entry_size_histogram`で取得できる.
+    ///
+    /// デフォルト値は`false`.
+    pub track_entry_sizes: bool,
+
+    /// タイムアウト(`Io::Timeout`)がエラーを返した場合に、それを作り直して再試行する連続回数の上限.
+    ///
+    /// タイマーの一時的な障害によって、ノード全体が停止してしまうことを防ぐために使用される.
+    /// エラーを捕捉する度に`Event::TimerError`が発行され、連続してこの回数を超えて失敗した場合には、
+    /// そのエラーが`ReplicatedLog`の呼び出し元に返される.
+    ///
+    /// デフォルト値は`3`.
+    pub max_timer_retries: usize,
+
+    /// リーダが、投票権を有するメンバの過半数との通信を失ったと判定するまでの、ハートビートの回数.
+    ///
+    /// 直近のこの回数のハートビートに一度も応答しなかったメンバが過半数に達した場合には、
+    /// 以後の提案は(過半数からの応答が得られるまで)`ErrorKind::NoQuorum`を理由としたエラーとなる.
+    /// 選挙のタイムアウトに相当する回数を指定することが想定されている.
+    ///
+    /// デフォルト値は`0` (i.e., 判定を行わない).
+    pub quorum_loss_ticks: u64,
+
+    /// リーダが、投票権を有するメンバの過半数との通信を失ったことを理由に、フォロワーに退くまでのtick数.
+    ///
+    /// tickはリーダ用のタイムアウト(i.e., ハートビートの間隔)毎に進み、
+    /// 休止状態(`quiescence_idle_rounds`参照)でハートビートの送信を見送った場合にも数えられる.
+    /// ハートビートに応答しないままこのtick数が経過したメンバが過半数に達した場合には、
+    /// リーダはフォロワーに遷移する.
+    /// なお、休止状態のリーダは、送信したハートビートに過半数が応答しなかった時点で休止状態を抜けるので、
+    /// 検知が最大で`quiescent_heartbeat_interval`tick分遅れることがある.
+    /// ネットワーク分断によって取り残されたリーダが、リーダであり続けることを防ぐために使用される.
+    /// 選挙のタイムアウトに相当する回数を指定することが想定されている.
+    ///
+    /// デフォルト値は`0` (i.e., 通信を失ってもリーダから退かない).
+    pub quorum_step_down_ticks: u64,
+
+    /// ローカルログへの追記が完了する度に、`Event::AppendPersisted`を発行するかどうか.
+    ///
+    /// デフォルト値は`false`.
+    pub append_persisted_events: bool,
+
+    /// クラスタ構成の変更のコミット時に、`Event::ConfigChanged`に加えて、
+    /// ノード単位の詳細なイベント(`Event::NodePromoted`および`Event::NodeDemoted`)を発行するかどうか.
+    ///
+    /// デフォルト値は`false`.
+    pub config_change_detail_events: bool,
+
+    /// ローカルノードを含まないクラスタ構成のコミットを把握した後の、メッセージの扱い方.
+    ///
+    /// デフォルト値は`RemovedNodePolicy::Follow`.
+    pub removed_node_policy: RemovedNodePolicy,
+}
+impl Default for ReplicatedLogOptions {
+    fn default() -> Self {
+        ReplicatedLogOptions {
+            heartbeat_health_window: 10,
+            peer_unhealthy_threshold_per_mille: 0,
+            batch_committed_events: false,
+            skip_noop_committed_events: false,
+            role_flapping_threshold: 0,
+            role_flapping_window_ticks: 10,
+            role_flapping_backoff_ticks: 10,
+            commit_notify_stride: 0,
+            event_queue_high_water_mark: 0,
+            event_queue_overflow_policy: EventQueueOverflowPolicy::Backpressure,
+            initial_leader_hint: None,
+            status_event_interval_ticks: 0,
+            leader_lease_ticks: 0,
+            catch_up_threshold: 0,
+            max_broadcast_sends_per_run: 0,
+            max_inflight_entries: 0,
+            max_append_bytes: 0,
+            bootstrap_deadline_ticks: 0,
+            apply_backlog_threshold: 0,
+            apply_stall_threshold_ticks: 0,
+            max_snapshot_size: 0,
+            allow_snapshot_install_on_leader: false,
+            debug_retain_entries: 0,
+            ignore_suspected_duplicate_nodes: false,
+            require_local_membership: false,
+            pre_vote: false,
+            leadership_transfer_max_rounds: 10,
+            max_replies_per_run: 0,
+            quiescence_idle_rounds: 0,
+            quiescent_heartbeat_interval: 10,
+            max_message_size: 0,
+            snapshot_chunk_size: 1024 * 1024,
+            command_trace_capacity: 0,
+            ordered_persistence: false,
+            proposal_batch_size: 0,
+            election_timeout_jitter: Duration::from_secs(0),
+            election_timeout_jitter_seed: None,
+            track_entry_sizes: false,
+            max_timer_retries: 3,
+            quorum_loss_ticks: 0,
+            quorum_step_down_ticks: 0,
+            append_persisted_events: false,
+            config_change_detail_events: false,
+            removed_node_policy: RemovedNodePolicy::Follow,
+        }
+    }
+}
+
+/// ローカルノードのリーダシップの状況.
+///
+/// ローカルノードが書き込みに応じられない場合に、その理由をクライアントに説明したり、
+/// 適切なノードへの転送先を決定するために使用される.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeadershipStatus {
+    /// ローカルノードがリーダ.
+    Leader,
+
+    /// ローカルノードがフォロワー.
+    Follower {
+        /// 現在の`Term`のリーダとして確認済みのノード (不明な場合には`None`).
+        ///
+        /// 投票しただけの候補者は、そのノードからの`AppendEntriesCall`を受信するまでは含まれない.
+        leader: Option<NodeId>,
+    },
+
+    /// ローカルノードが選挙に立候補中.
+    Candidate {
+        /// 立候補中の選挙の`Term`.
+        term: Term,
+    },
+
+    /// スナップショットのインストール中.
+    InstallingSnapshot,
+
+    /// ローカルノードが、現在のクラスタ構成に含まれていない.
+    Removed,
+}
+
+/// 未消費のイベント数が上限に達した場合の挙動.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventQueueOverflowPolicy {
+    /// イベントは破棄せずに、`ReplicatedLog::is_event_queue_congested`が`true`を返すようにする.
+    ///
+    /// 利用者は、このフラグを確認して、新しい提案の発行等を控える必要がある.
+    Backpressure,
+
+    /// 連続する`Event::Committed`群を破棄して、`Event::EventsDropped`と`Event::CommitAdvanced`に置き換える.
+    ///
+    /// なお、構成変更エントリの`Event::Committed`は破棄されない.
+    Coalesce,
+}
+
+/// クラスタから取り除かれたノード(i.e., `Event::SelfRemoved`を発行済みのノード)における、メッセージの扱い方.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovedNodePolicy {
+    /// 取り除かれる前と同様に、全てのメッセージを処理する.
+    ///
+    /// ローカルノードは立候補こそしないものの、リーダからのログの追記には追従し続ける.
+    Follow,
+
+    /// 受動モードとなり、ほとんどのメッセージを処理しなくなる.
+    ///
+    /// 取り除かれたことをまだ把握していないリーダからの`AppendEntriesCall`には、
+    /// ログの追記を行わずに、`removed`フラグを立てた応答を返す.
+    /// 応答を受け取ったリーダは、以後、そのノードに対するログの同期を行わない.
+    ///
+    /// 投票の依頼を含む、それ以外のメッセージは全て破棄される.
+    ///
+    /// 受動モードのノードは、構成変更エントリを含めてログの追記を一切行わないので、
+    /// 後から同じ`NodeId`がクラスタ構成に再び追加されても、それを把握できない
+    /// (リーダ側でも、`removed`フラグ付きの応答を受け取った時点で、そのノードに対する同期は止められる).
+    /// そのため、このポリシーの下では、一度取り除かれたノードの`NodeId`は再利用できない.
+    /// 同じノードを再びクラスタに参加させる場合には、ローカルログを破棄した上で、新しい`NodeId`を割り当てること.
+    Passive,
+}
+
+/// スナップショットのインストール完了時に呼び出されるコールバック.
+///
+/// 詳細は`ReplicatedLog::install_snapshot_with_callback`を参照のこと.
+pub type SnapshotInstallCallback = Box<dyn FnOnce(Result<LogPosition>) + Send + 'static>;
+
+/// ReadIndex方式の読み込みにおける、リーダシップの確認の完了を待機するための`Future`.
+///
+/// 詳細は`ReplicatedLog::confirm_read_index`を参照のこと.
+pub struct ReadIndex(oneshot::Receiver<LogIndex>);
+impl ReadIndex {
+    pub(crate) fn new(rx: oneshot::Receiver<LogIndex>) -> Self {
+        ReadIndex(rx)
+    }
+}
+impl Future for ReadIndex {
+    type Item = LogIndex;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(a) => Ok(a),
+            Err(_) => track_panic!(
+                ErrorKind::NotLeader,
+                "Leadership was lost before the read index was confirmed"
+            ),
+        }
+    }
+}
+impl fmt::Debug for ReadIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReadIndex(_)")
+    }
+}
+
 /// `ReplicatedLog`から発生するイベント一覧.
 #[derive(Debug, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -296,6 +1843,32 @@ pub enum Event {
     /// 新しいリーダーが選出された.
     NewLeaderElected,
 
+    /// ローカルノードが認識しているリーダが`leader`に確定した.
+    ///
+    /// ローカルノードが選挙に勝利した場合、ないし、フォロワーが新しいリーダからの`AppendEntriesCall`を受け付けた場合に発行される.
+    /// `NewLeaderElected`とは異なり、リーダが実際に変わった場合にのみ発行される.
+    /// `term`が変わった場合には、このイベントは`TermChanged`の後に発行される.
+    LeaderElected { leader: NodeId, term: Term },
+
+    /// ローカルノードが認識していたリーダを見失った.
+    ///
+    /// フォロワーがリーダからの通信を受けないまま選挙のタイムアウトを迎えた場合や、
+    /// 立候補ないし他の候補者への投票を行った場合に発行される.
+    LeaderLost,
+
+    /// ローカルノードを含まないクラスタ構成がコミットされた.
+    ///
+    /// 以後、ローカルノードは(リーダであった場合には退いた上で)フォロワーとなり、
+    /// 選挙のタイムアウトを迎えても立候補しなくなる.
+    /// ノードをクラスタから取り除くために、プロセスを停止して構わない.
+    SelfRemoved,
+
+    /// ノードの停止処理(`ReplicatedLog::begin_shutdown`)が開始された.
+    ///
+    /// 以後、ローカルノードは選挙のタイムアウトを迎えても立候補しなくなる.
+    /// リーダであった場合には、リーダシップの移譲を試みた後に、フォロワーに退く.
+    ShuttingDown,
+
     /// 新しいログエントリがコミットされた.
     ///
     /// エントリの内容がコマンドの場合には、
@@ -304,7 +1877,32 @@ pub enum Event {
     ///
     /// ログエントリはインデックスの昇順でコミットされ,
     /// インデックスは常に一ずつ増加する.
-    Committed { index: LogIndex, entry: LogEntry },
+    /// (ただし`ReplicatedLogOptions::skip_noop_committed_events`が有効な場合には、`Noop`の分だけ飛ぶ)
+    ///
+    /// `append_to_commit`は、エントリがローカルログに追記されてから、コミットが通知されるまでの所要時間.
+    /// `Io::now`が時刻を返さない場合や、ローカルノードが追記を観測していない
+    /// (e.g., 再起動前に追記されていた)場合には`None`となる.
+    Committed {
+        index: LogIndex,
+        entry: LogEntry,
+        append_to_commit: Option<Duration>,
+    },
+
+    /// 連続する複数のログエントリがコミットされた.
+    ///
+    /// `ReplicatedLogOptions::batch_committed_events`が有効な場合にのみ発行される.
+    ///
+    /// `entries`はインデックスの昇順に並んでおり、バッチ内のエントリ群をどのように
+    /// (e.g., 並列に)状態機械に適用するかは利用者の裁量に任される.
+    /// なお、構成変更エントリがバッチに含まれることはない.
+    ///
+    /// `append_to_commit`は、バッチ内のエントリ群の追記からコミットの通知までの所要時間の最大値
+    /// (i.e., 最も長く待たされたエントリの所要時間).
+    /// 計時できたエントリが一つも無い場合には`None`となる (条件は`Event::Committed`と同様).
+    CommittedBatch {
+        entries: Vec<(LogIndex, LogEntry)>,
+        append_to_commit: Option<Duration>,
+    },
 
     /// スナップショットがロードされた.
     ///
@@ -320,4 +1918,950 @@ pub enum Event {
     /// もし`new_head`の位置が、最新のコミット済み地点よりも
     /// 新しい場合には、これとは別に`SnapshotLoaded`イベントが発行される.
     SnapshotInstalled { new_head: LogPosition },
+
+    /// インストール中のスナップショットが、より新しい地点のスナップショットによって置き換えられた.
+    ///
+    /// `old_head`のスナップショットの保存処理は、完了を待たずに破棄されている.
+    /// 以後は`new_head`のスナップショットのインストールが行われる.
+    SnapshotSuperseded {
+        old_head: LogPosition,
+        new_head: LogPosition,
+    },
+
+    /// ハートビートの応答率が閾値を下回ったフォロワーが検出された.
+    ///
+    /// このイベントはリーダでのみ発行され、フォロワーの状態が正常から異常に変化したタイミングで、
+    /// 一度だけ通知される.
+    ///
+    /// `ratio_per_mille`は、検出時点の応答率の千分率(`0..=1000`).
+    PeerUnhealthy { node: NodeId, ratio_per_mille: u32 },
+
+    /// 短期間に役割の変更が繰り返されていることが検知された.
+    ///
+    /// `count`は検知時点での窓内の役割変更回数.
+    ///
+    /// このイベントの発行後、ローカルノードは一定期間、新しい選挙を開始しなくなる.
+    RoleFlappingDetected { count: usize },
+
+    /// コミット済みログ領域の終端が進んだ.
+    ///
+    /// `ReplicatedLogOptions::commit_notify_stride`の倍数を跨いだ場合にのみ発行される.
+    CommitAdvanced { committed_tail: LogIndex },
+
+    /// ローカルログへの追記(`Io::save_log_suffix`)が完了した.
+    ///
+    /// `through`より前のエントリは、全てローカルノードで永続化済みとなっている.
+    /// 一つの提案が複数回の保存に分けて追記される場合もあるので、
+    /// 最後の保存の完了を把握するには、この値が対象のエントリの位置を超えたかどうかを確認すれば良い.
+    /// コミット済みかどうか(i.e., 過半数での永続化)とは無関係なローカルな水位であることに注意.
+    ///
+    /// `ReplicatedLogOptions::append_persisted_events`が有効な場合にのみ発行される.
+    AppendPersisted { through: LogIndex },
+
+    /// 新しいクラスタ構成がコミットされた.
+    ///
+    /// `from_index`は、新しい構成が有効となった位置(構成変更エントリのインデックス)で、
+    /// `diff`は、直前にコミットされていた構成からの変更内容.
+    /// 一度に複数の構成変更エントリがコミットされた場合には、最後の構成までの変更がまとめて一つのイベントとなる.
+    /// また`CatchUp`から`Joint`への遷移のように、メンバの変更を伴わない場合には`diff`は空となる.
+    ///
+    /// `ReplicatedLogOptions::config_change_detail_events`が有効な場合には、
+    /// このイベントの直後に、`diff`の内容に対応する詳細なイベントが以下の順番で発行される:
+    ///
+    /// 1. `Event::NodePromoted` (ノードIDの昇順)
+    /// 2. `Event::NodeDemoted` (ノードIDの昇順)
+    ///
+    /// なお`Event::JointConsensusEntered`および`Event::JointConsensusExited`は、
+    /// コミット時ではなく、構成変更エントリの追記時に発行される.
+    ConfigChanged {
+        from_index: LogIndex,
+        diff: ConfigDiff,
+    },
+
+    /// ラーナーの`node`が、投票権を有するメンバに昇格した構成がコミットされた.
+    ///
+    /// `ReplicatedLogOptions::config_change_detail_events`が有効な場合にのみ発行される.
+    NodePromoted { node: NodeId },
+
+    /// 投票権を有するメンバの`node`が、ラーナーに降格した構成がコミットされた.
+    ///
+    /// `ReplicatedLogOptions::config_change_detail_events`が有効な場合にのみ発行される.
+    NodeDemoted { node: NodeId },
+
+    /// 未消費のイベント数が上限に達したため、`count`個のイベントが破棄された.
+    ///
+    /// `EventQueueOverflowPolicy::Coalesce`が指定されている場合にのみ発行される.
+    EventsDropped { count: usize },
+
+    /// ローカルノードの状態の要約.
+    ///
+    /// `ReplicatedLogOptions::status_event_interval_ticks`毎に発行される.
+    Status(CommonDebugState),
+
+    /// 新旧両方の構成の合意が必要な状態(`ClusterState::Joint`)に遷移した.
+    JointConsensusEntered,
+
+    /// 新旧両方の構成の合意が必要な状態(`ClusterState::Joint`)が終了した.
+    JointConsensusExited,
+
+    /// 大幅な遅れを取り戻して、`through`までのコミット済みエントリを消費した.
+    ///
+    /// `ReplicatedLogOptions::catch_up_threshold`が指定されている場合にのみ発行される.
+    /// 遅れを取り戻すまでの間の`Event::Committed`は省略されている.
+    CaughtUp { through: LogIndex },
+
+    /// 現在よりも小さい`term`への更新が試みられたが、拒否された.
+    ///
+    /// `term`は単調増加する必要があるため、このイベントが発行された場合には、
+    /// ストレージないしピアの実装に何らかの不具合が存在する可能性が高い.
+    TermRegressionRejected { attempted: Term },
+
+    /// 起動時に復元した投票状況(`loaded`)が、ローカルログやクラスタ構成と矛盾していた.
+    ///
+    /// 以下のいずれかの場合に、投票状況が壊れていると判断される:
+    ///
+    /// - 投票先(`voted_for`)が、ローカルノード自身でも、最新のクラスタ構成に含まれるノードでもない
+    /// - `term`が、ローカルログに含まれるエントリの`Term`よりも小さい
+    ///
+    /// この場合、投票先を取り消した投票状況(`recovered`)に置き換えられ、
+    /// ノードはそれの永続化が完了するまで何もしないフォロワーとして起動する.
+    /// `term`は維持される(ローカルログの`Term`よりも小さい場合には、それに合わせて引き上げられる)ので、
+    /// 同じ`term`内で二重に投票してしまうことはない.
+    ///
+    /// 投票状況の破損は、ストレージの不具合や誤操作を示す深刻な事態なので、
+    /// このイベントを受け取った場合には、原因を調査することが望ましい.
+    BallotCorrupted { loaded: Ballot, recovered: Ballot },
+
+    /// 適用待ちのコミット済みエントリ数が`ReplicatedLogOptions::apply_backlog_threshold`を超えた.
+    ///
+    /// 状態機械への適用が、合意の進行に追い付いていないことを示している.
+    /// 一度発行された後は、適用待ちの数が閾値以下に戻るまでは、再度発行されることはない.
+    ApplyBacklogHigh { backlog: u64 },
+
+    /// 状態機械への適用が`ReplicatedLogOptions::apply_stall_threshold_ticks`以上の間、停滞している.
+    ///
+    /// `ticks`は適用が最後に進んでからの経過tick数で、`backlog`は適用待ちのコミット済みエントリ数.
+    /// 一度発行された後は、適用が進むまでは、再度発行されることはない.
+    ApplyStalled { ticks: u64, backlog: u64 },
+
+    /// コミット済みの全てのエントリが`Event::Committed`等によって通知された.
+    ///
+    /// 未処理のコミット済みエントリが存在する状態から、それらの処理が完了した時点で発行される.
+    /// 起動直後のノードが、過去のエントリの再生を終えたことを検知するのに使用できる.
+    /// 再び処理が遅れた後に追い付いた場合にも、改めて発行される.
+    FullyConsumed { at: LogIndex },
+
+    /// リーダシップの移譲が、制限回数内に完了しなかったので中止された.
+    ///
+    /// 移譲の開始後に停止されていた新規の提案の受け付けは、再開される.
+    LeadershipTransferAborted { target: NodeId },
+
+    /// スナップショットの大きさが`ReplicatedLogOptions::max_snapshot_size`を超えていたので、
+    /// インストールを拒否した.
+    SnapshotRejectedTooLarge { size: usize, max: usize },
+
+    /// ローカルログへの追記が完了する前にリーダシップを失ったため、提案群が破棄された.
+    ///
+    /// これらの提案は、どのノードにも送信されていないため、コミットされることはない.
+    /// 必要であれば、新しいリーダに対して改めて提案し直す必要がある.
+    ProposalsAbandoned { proposals: Vec<ProposalId> },
+
+    /// 同じ`NodeId`を持つ複数のノードが存在する疑いがある.
+    ///
+    /// リーダが、同じ選挙期間中にフォロワーから受け取った応答の内容に矛盾
+    /// (e.g., より新しい応答で、ローカルログの終端が後退している)を検知した場合に発行される.
+    /// 設定ミスによるものである可能性が高く、放置するとログの整合性が崩れる恐れがある.
+    DuplicateNodeIdSuspected { node: NodeId },
+
+    /// タイムアウト(`Io::Timeout`)がエラーを返したので、新しく作り直した.
+    ///
+    /// `attempt`は連続して発生したエラーの回数で、`reason`はエラーの内容.
+    /// `ReplicatedLogOptions::max_timer_retries`を超えて連続で失敗した場合には、
+    /// このイベントは発行されずに、エラーが呼び出し元に返される.
+    TimerError { attempt: usize, reason: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Async;
+    use std::collections::VecDeque;
+    use std::mem;
+    use std::sync::Mutex;
+    use trackable::result::TestResult;
+
+    use crate::log::{LogPosition, LogSuffix};
+    use crate::message::{AppendEntriesCall, Message, MessageHeader};
+    use crate::test_util::tests::{ManualClock, TestIo, TestIoBuilder};
+
+    /// "node1"をリーダとするクラスタ内のフォロワー("node2")に、ハートビートを送り続けて、発行されたイベント群を返す.
+    ///
+    /// 最初のハートビートでは`entries`が追記され、それらは即座にコミット済みとなる.
+    /// ハートビートの間隔は、フォロワーの選挙用のタイムアウトよりも十分に短い.
+    fn run_follower_with_heartbeats(
+        options: ReplicatedLogOptions,
+        entries: Vec<LogEntry>,
+        iterations: u64,
+    ) -> Result<(ReplicatedLog<TestIo>, Vec<Event>)> {
+        let members: ClusterMembers = vec!["node1".into(), "node2".into(), "node3".into()]
+            .into_iter()
+            .collect();
+        let clock = ManualClock::new();
+        let mut io = TestIoBuilder::new().finish();
+        io.follower_timeout = Duration::from_millis(100);
+        io.manual_clock = Some(clock.clone());
+        let inbound = io.inbound_messages.clone();
+        let mut rlog = track!(ReplicatedLog::with_options(
+            "node2".into(),
+            members,
+            io,
+            options,
+            &MetricBuilder::new()
+        ))?;
+
+        let committed = LogIndex::new(entries.len() as u64);
+        let mut suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        };
+        let mut events = Vec::new();
+        for i in 0..iterations {
+            let call = AppendEntriesCall {
+                header: MessageHeader {
+                    sender: "node1".into(),
+                    destination: "node2".into(),
+                    seq_no: SequenceNumber::new(i),
+                    term: Term::new(1),
+                },
+                committed_log_tail: committed,
+                suffix: suffix.clone(),
+                quiescent: false,
+            };
+            inbound.lock().expect("Never fails").push_back(call.into());
+            suffix = LogSuffix {
+                head: rlog.local_history().tail(),
+                entries: Vec::new(),
+            };
+            while let Async::Ready(Some(e)) = track!(rlog.poll())? {
+                events.push(e);
+            }
+            clock.advance(Duration::from_millis(2));
+        }
+        Ok((rlog, events))
+    }
+
+    #[test]
+    fn apply_stall_is_detected_on_follower_receiving_heartbeats() -> TestResult {
+        let options = ReplicatedLogOptions {
+            apply_stall_threshold_ticks: 3,
+            ..Default::default()
+        };
+        let entries = vec![LogEntry::Command {
+            term: Term::new(1),
+            command: b"foo".to_vec(),
+        }];
+        let (rlog, events) = track!(run_follower_with_heartbeats(options, entries, 50))?;
+        assert_eq!(rlog.local_node().role, Role::Follower);
+        assert_eq!(
+            rlog.local_history().committed_tail().index,
+            LogIndex::new(1)
+        );
+
+        // `notify_applied`が呼ばれないまま、リーダからのハートビートを受信し続けると、停滞が検知される
+        let stalls = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::ApplyStalled { ticks, backlog } => Some((*ticks, *backlog)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stalls, vec![(3, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn replace_io_returns_original_log_when_busy() -> TestResult {
+        let members: ClusterMembers = vec!["node1".into(), "node2".into(), "node3".into()]
+            .into_iter()
+            .collect();
+        let io = TestIoBuilder::new().finish();
+        let rlog = track!(ReplicatedLog::new(
+            "node1".into(),
+            members,
+            io,
+            &MetricBuilder::new()
+        ))?;
+
+        // ローカルログの読み込み中は差し替え不可だが、元のインスタンスは失われない
+        assert!(!rlog.is_io_replaceable());
+        let new_io = TestIoBuilder::new().finish();
+        let e = rlog.replace_io(new_io).err().expect("Busy");
+        assert_eq!(*e.error.kind(), ErrorKind::Busy);
+        let mut rlog = *e.log;
+        let new_io = e.new_io;
+
+        // リーダからのハートビートを受信してフォロワーとなった後は、返されたインスタンスとI/O実装で差し替えられる
+        let call = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "node2".into(),
+                destination: "node1".into(),
+                seq_no: SequenceNumber::new(0),
+                term: Term::new(10),
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix::default(),
+            quiescent: false,
+        };
+        let inbound = rlog.io().inbound_messages.clone();
+        inbound.lock().expect("Never fails").push_back(call.into());
+        while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+        assert!(rlog.is_io_replaceable());
+        let rlog = track!(rlog.replace_io(new_io).map_err(Error::from))?;
+        assert_eq!(rlog.local_node().role, Role::Follower);
+        Ok(())
+    }
+
+    #[test]
+    fn ticks_advance_on_follower_receiving_heartbeats() -> TestResult {
+        let options = ReplicatedLogOptions {
+            status_event_interval_ticks: 2,
+            ..Default::default()
+        };
+        let (rlog, events) = track!(run_follower_with_heartbeats(options, Vec::new(), 50))?;
+
+        // 選挙用のタイムアウトは発火していないが、tickは進んでいる
+        assert_eq!(rlog.local_node().role, Role::Follower);
+        assert_eq!(rlog.local_node().ballot.term, Term::new(1));
+        assert!(rlog.node.common.ticks() >= 4);
+
+        let statuses = events
+            .iter()
+            .filter(|e| matches!(e, Event::Status(_)))
+            .count();
+        assert!(statuses >= 2, "{:?}", events);
+        Ok(())
+    }
+
+    /// 二つのノードからなるクラスタで、リーダが選出されるまでに要したイテレーション数を返す.
+    ///
+    /// 上限の`max_iterations`以内に選出されなかった場合には`None`が返される.
+    fn iterations_until_leader(jitter: Duration, max_iterations: usize) -> Result<Option<usize>> {
+        let names = ["node1", "node2"];
+        let members: ClusterMembers = names.iter().map(|&n| n.into()).collect();
+        let clock = ManualClock::new();
+        let mut nodes = Vec::new();
+        for &name in &names {
+            let mut io = TestIoBuilder::new().finish();
+            io.manual_clock = Some(clock.clone());
+            let inbound = io.inbound_messages.clone();
+            let sent = io.sent_messages.clone();
+            let options = ReplicatedLogOptions {
+                election_timeout_jitter: jitter,
+                election_timeout_jitter_seed: Some(0),
+                ..Default::default()
+            };
+            let rlog = track!(ReplicatedLog::with_options(
+                name.into(),
+                members.clone(),
+                io,
+                options,
+                &MetricBuilder::new()
+            ))?;
+            nodes.push((rlog, inbound, sent));
+        }
+
+        // 両ノードのタイムアウトを、可能な限り同時に設定し直す
+        for (rlog, _, _) in &mut nodes {
+            while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+        }
+        for (rlog, _, _) in &mut nodes {
+            rlog.node.common.reset_election_timeout();
+        }
+
+        for i in 0..max_iterations {
+            for (rlog, _, _) in &mut nodes {
+                while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+                if rlog.local_node().role == Role::Leader {
+                    return Ok(Some(i));
+                }
+            }
+
+            // 送信されたメッセージを、宛先ノードに届ける
+            for i in 0..nodes.len() {
+                let sent = mem::take(&mut *nodes[i].2.lock().expect("Never fails"));
+                for m in sent {
+                    let destination = &m.header().destination;
+                    if let Some(dst) = names.iter().position(|&n| *destination == n.into()) {
+                        nodes[dst].1.lock().expect("Never fails").push_back(m);
+                    }
+                }
+            }
+            clock.advance(Duration::from_millis(2));
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn election_timeout_jitter_resolves_split_votes() -> TestResult {
+        // 揺らぎが無い場合には、両ノードが同時に立候補して票が割れ続ける
+        let fixed = track!(iterations_until_leader(Duration::from_secs(0), 100))?;
+
+        // 揺らぎがある場合には、一方が先に立候補して、速やかにリーダが決まる
+        let jittered = track!(iterations_until_leader(Duration::from_millis(30), 100))?;
+        let jittered = jittered.expect("A leader should be elected");
+        assert!(
+            jittered < fixed.unwrap_or(100),
+            "{} vs {:?}",
+            jittered,
+            fixed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn require_local_membership_works() -> TestResult {
+        let members: ClusterMembers = vec!["node1".into(), "node2".into()].into_iter().collect();
+        let options = ReplicatedLogOptions {
+            require_local_membership: true,
+            ..Default::default()
+        };
+
+        // 構成に含まれないノード
+        let io = TestIoBuilder::new().finish();
+        let result = ReplicatedLog::with_options(
+            "node3".into(),
+            members.clone(),
+            io,
+            options.clone(),
+            &MetricBuilder::new(),
+        );
+        assert_eq!(
+            result.err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        // 構成に含まれるノード
+        let io = TestIoBuilder::new().finish();
+        let result = ReplicatedLog::with_options(
+            "node1".into(),
+            members.clone(),
+            io,
+            options,
+            &MetricBuilder::new(),
+        );
+        assert!(result.is_ok());
+
+        // チェックが無効な場合には、構成に含まれないノードも生成可能
+        let io = TestIoBuilder::new().finish();
+        let result = ReplicatedLog::with_options(
+            "node3".into(),
+            members,
+            io,
+            Default::default(),
+            &MetricBuilder::new(),
+        );
+        assert!(result.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn lossy_cluster_commits_entry() -> TestResult {
+        let names = ["node1", "node2", "node3"];
+        let members: ClusterMembers = names.iter().map(|&n| n.into()).collect();
+        let clock = ManualClock::new();
+        let mut nodes = Vec::new();
+        for (i, &name) in names.iter().enumerate() {
+            // 各ノードは、受信したメッセージの30%を失う
+            let mut io = TestIoBuilder::new()
+                .drop_probability(0.3)
+                .seed(i as u64)
+                .finish();
+
+            // 選挙が分裂し続けないように、ノード毎にタイムアウトをずらす
+            let offset = Duration::from_millis(10 * i as u64);
+            io.follower_timeout += offset;
+            io.candidate_timeout += offset;
+            io.manual_clock = Some(clock.clone());
+            let inbound = io.inbound_messages.clone();
+            let sent = io.sent_messages.clone();
+            let rlog = track!(ReplicatedLog::new(
+                name.into(),
+                members.clone(),
+                io,
+                &MetricBuilder::new()
+            ))?;
+            nodes.push((rlog, inbound, sent));
+        }
+
+        let mut proposal: Option<ProposalId> = None;
+        let mut committed = false;
+        'ticks: for _ in 0..2000 {
+            for (rlog, _, _) in &mut nodes {
+                while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+            }
+
+            // 送信されたメッセージを、宛先ノードに届ける
+            for i in 0..nodes.len() {
+                let sent = mem::take(&mut *nodes[i].2.lock().expect("Never fails"));
+                for m in sent {
+                    let destination = &m.header().destination;
+                    if let Some(dst) = names.iter().position(|&n| *destination == n.into()) {
+                        nodes[dst].1.lock().expect("Never fails").push_back(m);
+                    }
+                }
+            }
+
+            // リーダが決まったら提案を行い、それがコミットされるまで待つ
+            for (rlog, _, _) in &mut nodes {
+                if rlog.local_node().role != Role::Leader {
+                    continue;
+                }
+                let term = rlog.local_node().ballot.term;
+                match proposal {
+                    Some(p) if p.term == term => {
+                        if p.index < rlog.local_history().committed_tail().index {
+                            committed = true;
+                            break 'ticks;
+                        }
+                    }
+                    _ => {
+                        proposal = Some(track!(rlog.propose_command(b"foo".to_vec()))?);
+                    }
+                }
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+        assert!(committed, "No entry was committed: proposal={:?}", proposal);
+
+        // 実際にメッセージが失われていた
+        let dropped = nodes
+            .iter()
+            .map(|(rlog, _, _)| *rlog.io().dropped_messages.lock().expect("Never fails"))
+            .sum::<usize>();
+        assert!(dropped > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn proposals_on_non_leader_are_rejected() -> TestResult {
+        let members: ClusterMembers = vec!["node1".into(), "node2".into(), "node3".into()]
+            .into_iter()
+            .collect();
+        let io = TestIoBuilder::new().finish();
+        let mut rlog = track!(ReplicatedLog::new(
+            "node1".into(),
+            members.clone(),
+            io,
+            &MetricBuilder::new()
+        ))?;
+        for _ in 0..10 {
+            if rlog.local_node().role == Role::Follower {
+                break;
+            }
+            let _ = track!(rlog.poll())?;
+        }
+        assert_eq!(rlog.local_node().role, Role::Follower);
+
+        let kind = |e: Error| *e.kind();
+        let result = rlog.propose_command(vec![1, 2, 3]).map_err(kind);
+        assert_eq!(result.err(), Some(ErrorKind::NotLeader));
+        let result = rlog.propose_batch(vec![vec![1], vec![2]]).map_err(kind);
+        assert_eq!(result.err(), Some(ErrorKind::NotLeader));
+        let result = rlog.propose_config(members).map_err(kind);
+        assert_eq!(result.err(), Some(ErrorKind::NotLeader));
+        Ok(())
+    }
+
+    #[test]
+    fn election_timeout_jitter_requires_io_support() {
+        let members: ClusterMembers = vec!["node1".into()].into_iter().collect();
+        let options = ReplicatedLogOptions {
+            election_timeout_jitter: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let new = |supported| {
+            let mut io = TestIoBuilder::new().finish();
+            io.supports_jittered_timeout = supported;
+            ReplicatedLog::with_options(
+                "node1".into(),
+                members.clone(),
+                io,
+                options.clone(),
+                &MetricBuilder::new(),
+            )
+            .map(|_| ())
+            .map_err(|e| *e.kind())
+        };
+
+        // 揺らぎに対応していない`Io`実装では、揺らぎが黙って無視されずにエラーとなる
+        assert_eq!(new(false), Err(ErrorKind::InvalidInput));
+        assert_eq!(new(true), Ok(()));
+    }
+
+    #[test]
+    fn snapshot_chunk_size_is_validated() {
+        let members: ClusterMembers = vec!["node1".into()].into_iter().collect();
+        let new = |options| {
+            let io = TestIoBuilder::new().finish();
+            ReplicatedLog::with_options(
+                "node1".into(),
+                members.clone(),
+                io,
+                options,
+                &MetricBuilder::new(),
+            )
+            .map(|_| ())
+            .map_err(|e| *e.kind())
+        };
+
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 0,
+            ..Default::default()
+        };
+        assert_eq!(new(options), Err(ErrorKind::InvalidInput));
+
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 2048,
+            max_message_size: 1024,
+            ..Default::default()
+        };
+        assert_eq!(new(options), Err(ErrorKind::InvalidInput));
+
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 1024,
+            max_message_size: 1024,
+            ..Default::default()
+        };
+        assert_eq!(new(options), Ok(()));
+
+        // スナップショットの最大サイズは、チャンクのサイズとは無関係
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: 2048,
+            max_snapshot_size: 1024,
+            ..Default::default()
+        };
+        assert_eq!(new(options), Ok(()));
+    }
+
+    /// 保存済みの`entries`をローカルログとして、`node`を再起動する.
+    fn restart(
+        node: &str,
+        members: &ClusterMembers,
+        entries: Vec<LogEntry>,
+    ) -> Result<ReplicatedLog<TestIo>> {
+        let io = TestIoBuilder::new().finish();
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        };
+        io.logs
+            .lock()
+            .expect("Never fails")
+            .insert((LogIndex::new(0), None), suffix.into());
+        let mut rlog = track!(ReplicatedLog::new(
+            node.into(),
+            members.clone(),
+            io,
+            &MetricBuilder::new()
+        ))?;
+        for _ in 0..10 {
+            if !rlog.node.is_loading() {
+                break;
+            }
+            let _ = track!(rlog.poll())?;
+        }
+        assert!(!rlog.node.is_loading());
+        Ok(rlog)
+    }
+
+    #[test]
+    fn witness_membership_survives_restart() -> TestResult {
+        let names = ["node1", "node2", "node3"];
+        let members: ClusterMembers = names.iter().map(|&n| n.into()).collect();
+        let clock = ManualClock::new();
+        let mut nodes = Vec::new();
+        for (i, &name) in names.iter().enumerate() {
+            // "node1"が最初のリーダとなるように、ノード毎にタイムアウトをずらす
+            let mut io = TestIoBuilder::new().finish();
+            let offset = Duration::from_millis(20 * i as u64);
+            io.follower_timeout += offset;
+            io.candidate_timeout += offset;
+            io.manual_clock = Some(clock.clone());
+            let inbound = io.inbound_messages.clone();
+            let sent = io.sent_messages.clone();
+            let rlog = track!(ReplicatedLog::new(
+                name.into(),
+                members.clone(),
+                io,
+                &MetricBuilder::new()
+            ))?;
+            nodes.push((rlog, inbound, sent));
+        }
+
+        // リーダが決まったら、"node3"をウィットネスにしてから、コマンドを提案する
+        let witness: NodeId = "node3".into();
+        let mut proposal: Option<ProposalId> = None;
+        let mut committed = false;
+        'ticks: for _ in 0..2000 {
+            for (rlog, _, _) in &mut nodes {
+                while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+            }
+            for i in 0..nodes.len() {
+                let sent = mem::take(&mut *nodes[i].2.lock().expect("Never fails"));
+                for m in sent {
+                    let destination = &m.header().destination;
+                    if let Some(dst) = names.iter().position(|&n| *destination == n.into()) {
+                        nodes[dst].1.lock().expect("Never fails").push_back(m);
+                    }
+                }
+            }
+            if let Some(p) = proposal {
+                if p.index < nodes[2].0.local_history().committed_tail().index {
+                    committed = true;
+                    break 'ticks;
+                }
+            } else if nodes[0].0.local_node().role == Role::Leader {
+                let leader = &mut nodes[0].0;
+                track!(leader.propose_witnesses(vec![witness.clone()].into_iter().collect()))?;
+                proposal = Some(track!(leader.propose_command(b"foo".to_vec()))?);
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+        assert!(committed, "No entry was committed: proposal={:?}", proposal);
+
+        // ウィットネスのローカルログには、ウィットネスの指定とコマンドの内容を取り除いたエントリが記録されている
+        let entries = nodes[2]
+            .0
+            .io()
+            .saved_entries
+            .lock()
+            .expect("Never fails")
+            .clone();
+        assert!(entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::Config { config, .. } if config.is_witness(&witness))));
+        assert!(entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::Stripped { .. })));
+        assert!(!entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::Command { .. })));
+
+        // 再起動後も、ウィットネスのままで、立候補は行わない
+        let mut rlog = track!(restart("node3", &members, entries.clone()))?;
+        assert!(rlog.cluster_config().is_witness(&witness));
+        assert_eq!(rlog.local_node().role, Role::Follower);
+        let result = rlog.trigger_election().map_err(|e| *e.kind());
+        assert_eq!(result, Err(ErrorKind::InvalidInput));
+
+        // 構成からウィットネスの指定が失われていても、取り除かれたエントリを保持している限りは立候補しない
+        let entries = entries
+            .into_iter()
+            .map(|e| match e {
+                LogEntry::Config { term, config } => LogEntry::Config {
+                    term,
+                    config: config.with_witnesses(ClusterMembers::new()),
+                },
+                e => e,
+            })
+            .collect();
+        let mut rlog = track!(restart("node3", &members, entries))?;
+        assert!(!rlog.cluster_config().is_witness(&witness));
+        assert_eq!(rlog.local_node().role, Role::Follower);
+        let result = rlog.trigger_election().map_err(|e| *e.kind());
+        assert_eq!(result, Err(ErrorKind::InvalidInput));
+        Ok(())
+    }
+
+    type WitnessTestNode = (
+        ReplicatedLog<TestIo>,
+        Arc<Mutex<VecDeque<Message>>>,
+        Arc<Mutex<Vec<Message>>>,
+    );
+
+    /// `until`を満たすまで(最大2000ms)、仮想時刻を進めながら、各ノードのポーリングとメッセージの配送を繰り返す.
+    ///
+    /// 停止中のノードはポーリングされず、停止中または切断中のノードとの間のメッセージは失われる.
+    fn run_witness_cluster<F>(
+        nodes: &mut [WitnessTestNode],
+        clock: &ManualClock,
+        stopped: &[bool],
+        disconnected: &[bool],
+        until: F,
+    ) -> Result<bool>
+    where
+        F: Fn(&[WitnessTestNode]) -> bool,
+    {
+        for _ in 0..2000 {
+            if until(nodes) {
+                return Ok(true);
+            }
+            for (i, (rlog, _, _)) in nodes.iter_mut().enumerate() {
+                if !stopped[i] {
+                    while let Async::Ready(Some(_)) = track!(rlog.poll())? {}
+                }
+            }
+            for i in 0..nodes.len() {
+                let sent = mem::take(&mut *nodes[i].2.lock().expect("Never fails"));
+                for m in sent {
+                    let destination = &m.header().destination;
+                    let dst = nodes
+                        .iter()
+                        .position(|n| n.0.local_node().id == *destination);
+                    if let Some(dst) = dst {
+                        if !(stopped[dst] || disconnected[i] || disconnected[dst]) {
+                            nodes[dst].1.lock().expect("Never fails").push_back(m);
+                        }
+                    }
+                }
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+        Ok(until(nodes))
+    }
+
+    #[test]
+    fn broadcast_now_sends_append_entries_immediately() -> TestResult {
+        let names = ["node1", "node2", "node3"];
+        let members: ClusterMembers = names.iter().map(|&n| n.into()).collect();
+        let clock = ManualClock::new();
+        let mut nodes = Vec::new();
+        for (i, &name) in names.iter().enumerate() {
+            // "node1"がリーダとなるように、ノード毎にタイムアウトをずらす
+            let mut io = TestIoBuilder::new().finish();
+            let offset = Duration::from_millis(20 * i as u64);
+            io.follower_timeout += offset;
+            io.candidate_timeout += offset;
+            io.manual_clock = Some(clock.clone());
+            let inbound = io.inbound_messages.clone();
+            let sent = io.sent_messages.clone();
+            let rlog = track!(ReplicatedLog::new(
+                name.into(),
+                members.clone(),
+                io,
+                &MetricBuilder::new()
+            ))?;
+            nodes.push((rlog, inbound, sent));
+        }
+        let is_leader = |nodes: &[WitnessTestNode]| nodes[0].0.local_node().role == Role::Leader;
+        assert!(track!(run_witness_cluster(
+            &mut nodes,
+            &clock,
+            &[false; 3],
+            &[false; 3],
+            is_leader
+        ))?);
+
+        // 非リーダに対しては、エラーが返される
+        let result = nodes[1].0.broadcast_now().map_err(|e| *e.kind());
+        assert_eq!(result.err(), Some(ErrorKind::NotLeader));
+
+        // 時刻を進めない(i.e., ハートビートのタイムアウト前の)間は、ポーリングしても何も送信されない
+        let (leader, _, sent) = &mut nodes[0];
+        while let Async::Ready(Some(_)) = track!(leader.poll())? {}
+        sent.lock().expect("Never fails").clear();
+        while let Async::Ready(Some(_)) = track!(leader.poll())? {}
+        assert!(sent.lock().expect("Never fails").is_empty());
+
+        // `broadcast_now`を呼び出すと、即座に全フォロワーに`AppendEntriesCall`が送信される
+        track!(leader.broadcast_now())?;
+        let destinations = sent
+            .lock()
+            .expect("Never fails")
+            .iter()
+            .filter_map(|m| match m {
+                Message::AppendEntriesCall(m) => Some(m.header.destination.clone()),
+                _ => None,
+            })
+            .collect::<ClusterMembers>();
+        let followers = vec!["node2".into(), "node3".into()].into_iter().collect();
+        assert_eq!(destinations, followers);
+        Ok(())
+    }
+
+    #[test]
+    fn witness_only_commit_blocks_election_while_leader_is_down() -> TestResult {
+        let names = ["node1", "node2", "node3"];
+        let members: ClusterMembers = names.iter().map(|&n| n.into()).collect();
+        let clock = ManualClock::new();
+        let mut nodes = Vec::new();
+        for (i, &name) in names.iter().enumerate() {
+            // "node1"が最初のリーダとなるように、ノード毎にタイムアウトをずらす
+            let mut io = TestIoBuilder::new().finish();
+            let offset = Duration::from_millis(20 * i as u64);
+            io.follower_timeout += offset;
+            io.candidate_timeout += offset;
+            io.manual_clock = Some(clock.clone());
+            let inbound = io.inbound_messages.clone();
+            let sent = io.sent_messages.clone();
+            let rlog = track!(ReplicatedLog::new(
+                name.into(),
+                members.clone(),
+                io,
+                &MetricBuilder::new()
+            ))?;
+            nodes.push((rlog, inbound, sent));
+        }
+
+        // "node3"をウィットネスとする構成が、全ノードでコミットされる
+        let mut stopped = [false; 3];
+        let mut disconnected = [false; 3];
+        let is_leader = |nodes: &[WitnessTestNode]| nodes[0].0.local_node().role == Role::Leader;
+        assert!(track!(run_witness_cluster(
+            &mut nodes,
+            &clock,
+            &stopped,
+            &disconnected,
+            is_leader
+        ))?);
+        let witnesses = vec!["node3".into()].into_iter().collect();
+        let config = track!(nodes[0].0.propose_witnesses(witnesses))?;
+        let all_committed = |nodes: &[WitnessTestNode]| {
+            nodes
+                .iter()
+                .all(|n| config.index < n.0.local_history().committed_tail().index)
+        };
+        assert!(track!(run_witness_cluster(
+            &mut nodes,
+            &clock,
+            &stopped,
+            &disconnected,
+            all_committed
+        ))?);
+
+        // "node2"が切断されている間に、リーダとウィットネスのみでコマンドがコミットされる
+        disconnected[1] = true;
+        let proposal = track!(nodes[0].0.propose_command(b"foo".to_vec()))?;
+        let leader_committed = |nodes: &[WitnessTestNode]| {
+            proposal.index < nodes[0].0.local_history().committed_tail().index
+        };
+        assert!(track!(run_witness_cluster(
+            &mut nodes,
+            &clock,
+            &stopped,
+            &disconnected,
+            leader_committed
+        ))?);
+        assert!(nodes[1].0.local_history().tail().index <= proposal.index);
+
+        // リーダが停止すると、"node2"はウィットネスより古いログしか持たないので選出されず、
+        // ウィットネスは立候補しないので、リーダ不在のままとなる
+        stopped[0] = true;
+        disconnected[1] = false;
+        let has_leader = |nodes: &[WitnessTestNode]| {
+            nodes[1..]
+                .iter()
+                .any(|n| n.0.local_node().role == Role::Leader)
+        };
+        assert!(!track!(run_witness_cluster(
+            &mut nodes,
+            &clock,
+            &stopped,
+            &disconnected,
+            has_leader
+        ))?);
+        assert_eq!(nodes[1].0.local_node().role, Role::Candidate);
+        assert!(nodes[1].0.local_history().tail().index <= proposal.index);
+        Ok(())
+    }
 }
@@ -2,33 +2,43 @@
 #[cfg(test)]
 pub mod tests {
     use fibers::time::timer;
+    use futures::executor::Notify;
     use futures::{Async, Future, Poll};
-    use std::collections::{BTreeSet, HashMap};
+    use std::collections::{BTreeSet, HashMap, VecDeque};
+    use std::fmt;
     use std::sync::{Arc, Mutex};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use trackable::error::ErrorKindExt;
 
     use crate::cluster::{ClusterConfig, ClusterMembers};
     use crate::election::{Ballot, Role};
     use crate::io::Io;
-    use crate::log::{Log, LogIndex, LogPrefix, LogSuffix};
+    use crate::log::{
+        Log, LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix, SnapshotChunk,
+        SnapshotTransferProgress,
+    };
     use crate::message::Message;
     use crate::node::NodeId;
     use crate::{Error, ErrorKind, Result};
 
     type Logs = Arc<Mutex<HashMap<(LogIndex, Option<LogIndex>), Log>>>;
+    type MessagePredicate = Arc<dyn Fn(&Message) -> bool + Send + Sync>;
 
     /// `TestIo`を生成する。主にクラスタ構成をするために存在する。
     /// `Log` や `Ballot` の設定は直接 `TestIo` に対して行えばよい。
+    ///
+    /// メッセージの欠落を再現するための設定も、ここで行う。
     #[derive(Debug)]
     pub struct TestIoBuilder {
         members: ClusterMembers,
+        message_drops: MessageDrops,
     }
 
     impl TestIoBuilder {
         pub fn new() -> Self {
             Self {
                 members: BTreeSet::new(),
+                message_drops: MessageDrops::new(0),
             }
         }
 
@@ -37,15 +47,127 @@ pub mod tests {
             self
         }
 
+        /// `predicate` が `true` を返す受信メッセージを、常に破棄するようにする。
+        ///
+        /// ネットワークの分断等を再現するために使う。
+        pub fn drop_messages_matching<F>(mut self, predicate: F) -> Self
+        where
+            F: Fn(&Message) -> bool + Send + Sync + 'static,
+        {
+            self.message_drops.predicates.push(Arc::new(predicate));
+            self
+        }
+
+        /// `from` から `to` 宛ての受信メッセージを、確率 `probability` で破棄するようにする。
+        pub fn link_drop_probability(mut self, from: NodeId, to: NodeId, probability: f64) -> Self {
+            self.message_drops
+                .link_probabilities
+                .insert((from, to), probability);
+            self
+        }
+
+        /// `link_drop_probability` で個別に指定されていない経路の受信メッセージを、確率 `probability` で破棄するようにする。
+        pub fn drop_probability(mut self, probability: f64) -> Self {
+            self.message_drops.default_probability = probability;
+            self
+        }
+
+        /// 確率的な破棄に使う乱数のシードを指定する。
+        ///
+        /// シードと受信メッセージの列が同じであれば、破棄されるメッセージも同じになる。
+        pub fn seed(mut self, seed: u64) -> Self {
+            self.message_drops.rng = XorShiftRng::new(seed);
+            self
+        }
+
         pub fn finish(&self) -> TestIo {
             TestIo {
                 leader_timeout: Duration::from_millis(5),
                 follower_timeout: Duration::from_millis(10),
                 candidate_timeout: Duration::from_millis(15),
+                current_timeout: None,
                 cluster: ClusterConfig::new(self.members.clone()),
                 ballots: Arc::new(Mutex::new(Vec::new())),
                 logs: Arc::new(Mutex::new(HashMap::new())),
+                sent_messages: Arc::new(Mutex::new(Vec::new())),
+                inbound_messages: Arc::new(Mutex::new(VecDeque::new())),
+                saved_snapshot_chunks: Arc::new(Mutex::new(Vec::new())),
+                saved_log_prefixes: Arc::new(Mutex::new(Vec::new())),
+                supports_snapshot_chunks: true,
+                supports_jittered_timeout: true,
+                snapshot_transfer: Arc::new(Mutex::new(None)),
+                clock: Arc::new(Mutex::new(None)),
+                manual_clock: None,
+                stall_ballot_saves: false,
+                fail_ballot_saves: false,
+                failing_timeouts: 0,
+                saved_log_suffixes: Arc::new(Mutex::new(Vec::new())),
+                saved_entries: Arc::new(Mutex::new(Vec::new())),
+                message_drops: self.message_drops.clone(),
+                dropped_messages: Arc::new(Mutex::new(0)),
+            }
+        }
+    }
+
+    /// 受信メッセージを破棄するための設定。
+    #[derive(Clone)]
+    struct MessageDrops {
+        predicates: Vec<MessagePredicate>,
+        link_probabilities: HashMap<(NodeId, NodeId), f64>,
+        default_probability: f64,
+        rng: XorShiftRng,
+    }
+    impl MessageDrops {
+        fn new(seed: u64) -> Self {
+            MessageDrops {
+                predicates: Vec::new(),
+                link_probabilities: HashMap::new(),
+                default_probability: 0.0,
+                rng: XorShiftRng::new(seed),
+            }
+        }
+
+        fn should_drop(&mut self, message: &Message) -> bool {
+            if self.predicates.iter().any(|p| p(message)) {
+                return true;
             }
+            let header = message.header();
+            let link = (header.sender.clone(), header.destination.clone());
+            let probability = self
+                .link_probabilities
+                .get(&link)
+                .cloned()
+                .unwrap_or(self.default_probability);
+            probability > 0.0 && self.rng.next_f64() < probability
+        }
+    }
+    impl fmt::Debug for MessageDrops {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "MessageDrops {{ predicates: {}, link_probabilities: {:?}, default_probability: {}, .. }}",
+                self.predicates.len(),
+                self.link_probabilities,
+                self.default_probability
+            )
+        }
+    }
+
+    /// シード指定可能な、テスト用の簡易な擬似乱数生成器 (xorshift64*)。
+    #[derive(Debug, Clone)]
+    struct XorShiftRng(u64);
+    impl XorShiftRng {
+        fn new(seed: u64) -> Self {
+            // 状態が0だと、常に0を返すようになってしまう
+            XorShiftRng(seed ^ 0x9E37_79B9_7F4A_7C15)
+        }
+
+        fn next_f64(&mut self) -> f64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let n = self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+            (n >> 11) as f64 / (1u64 << 53) as f64
         }
     }
 
@@ -86,12 +208,47 @@ pub mod tests {
         pub leader_timeout: Duration,
         pub follower_timeout: Duration,
         pub candidate_timeout: Duration,
+        /// 最後に `create_timeout` ないし `create_jittered_timeout` で生成されたタイムアウトの時間。`current_timeout_duration` で返される。
+        current_timeout: Option<Duration>,
         /// クラスタ構成。
         pub cluster: ClusterConfig,
-        /// `LoadBallot` でロードされる。
+        /// `LoadBallot` でロードされる。`SaveBallot` で保存されたものも末尾に追加される。
         pub ballots: Arc<Mutex<Vec<Ballot>>>,
         /// `LoadLog` でロードされる。
         pub logs: Logs,
+        /// `send_message` で送信されたメッセージが記録される。
+        pub sent_messages: Arc<Mutex<Vec<Message>>>,
+        /// `try_recv_message` で順に取り出される。
+        pub inbound_messages: Arc<Mutex<VecDeque<Message>>>,
+        /// `save_snapshot_chunk` で保存されたチャンクの`(offset, done)`が記録される。
+        pub saved_snapshot_chunks: Arc<Mutex<Vec<(u64, bool)>>>,
+        /// `save_log_prefix` で保存されたスナップショットが記録される。
+        pub saved_log_prefixes: Arc<Mutex<Vec<LogPrefix>>>,
+        /// `supports_snapshot_chunks` で返される値。
+        pub supports_snapshot_chunks: bool,
+        /// `supports_jittered_timeout` で返される値。
+        pub supports_jittered_timeout: bool,
+        /// `save_snapshot_chunk` で保存途中の分割スナップショットの進捗。`load_snapshot_transfer` で返される。
+        pub snapshot_transfer: Arc<Mutex<Option<SnapshotTransferProgress>>>,
+        /// `now` で返される時刻。
+        pub clock: Arc<Mutex<Option<Instant>>>,
+        /// 指定された場合には、`create_timeout` で生成されるタイムアウトは、実時間ではなくこの時刻に従って発火する。
+        pub manual_clock: Option<ManualClock>,
+        /// `true`の場合には、`SaveBallot`が永遠に完了しなくなる。
+        pub stall_ballot_saves: bool,
+        /// `true`の場合には、`SaveBallot`が失敗する(障害の注入用)。
+        pub fail_ballot_saves: bool,
+        /// `create_timeout` で生成されるタイムアウトの内、この数だけが、ポーリング時に失敗する(障害の注入用)。
+        pub failing_timeouts: usize,
+        /// `save_log_suffix` で書き込みが発行された `LogSuffix` の開始位置が記録される。
+        pub saved_log_suffixes: Arc<Mutex<Vec<LogIndex>>>,
+        /// `save_log_suffix` で保存されたエントリ群(インデックス`0`から)。
+        /// `logs` に該当する読み込み範囲が無い場合に、`load_log` で使われる。
+        pub saved_entries: Arc<Mutex<Vec<LogEntry>>>,
+        /// `try_recv_message` で、受信メッセージを破棄するための設定。
+        message_drops: MessageDrops,
+        /// `try_recv_message` で破棄されたメッセージの数。
+        pub dropped_messages: Arc<Mutex<usize>>,
     }
 
     impl TestIo {
@@ -104,20 +261,37 @@ pub mod tests {
     }
 
     impl Io for TestIo {
-        type SaveBallot = NoopSaveBallot;
+        type SaveBallot = SaveBallotImpl;
         type LoadBallot = LoadBallotImpl;
         type SaveLog = NoopSaveLog;
         type LoadLog = LoadLogImpl;
-        type Timeout = FibersTimeout;
+        type Timeout = TestTimeout;
 
+        /// `TestIoBuilder` で指定された条件に該当するメッセージは、黙って破棄される。
         fn try_recv_message(&mut self) -> Result<Option<Message>> {
+            let mut inbound_messages = self.inbound_messages.lock().expect("Never fails");
+            while let Some(message) = inbound_messages.pop_front() {
+                if self.message_drops.should_drop(&message) {
+                    *self.dropped_messages.lock().expect("Never fails") += 1;
+                    continue;
+                }
+                return Ok(Some(message));
+            }
             Ok(None)
         }
 
-        fn send_message(&mut self, _message: Message) {}
+        fn send_message(&mut self, message: Message) {
+            let mut sent_messages = self.sent_messages.lock().expect("Never fails");
+            sent_messages.push(message);
+        }
 
-        fn save_ballot(&mut self, _ballot: Ballot) -> Self::SaveBallot {
-            NoopSaveBallot
+        fn save_ballot(&mut self, ballot: Ballot) -> Self::SaveBallot {
+            let mut ballots = self.ballots.lock().expect("Never fails");
+            ballots.push(ballot);
+            SaveBallotImpl {
+                stalled: self.stall_ballot_saves,
+                failed: self.fail_ballot_saves,
+            }
         }
 
         fn load_ballot(&mut self) -> Self::LoadBallot {
@@ -125,14 +299,57 @@ pub mod tests {
             LoadBallotImpl(ballots.pop())
         }
 
-        fn save_log_prefix(&mut self, _prefix: LogPrefix) -> Self::SaveLog {
+        fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog {
+            let mut prefixes = self.saved_log_prefixes.lock().expect("Never fails");
+            prefixes.push(prefix);
+            NoopSaveLog
+        }
+
+        fn save_snapshot_chunk(&mut self, chunk: SnapshotChunk) -> Self::SaveLog {
+            let mut chunks = self.saved_snapshot_chunks.lock().expect("Never fails");
+            chunks.push((chunk.offset, chunk.done));
+
+            let mut transfer = self.snapshot_transfer.lock().expect("Never fails");
+            let saved_bytes = chunk.offset + chunk.data.len() as u64;
+            if chunk.done {
+                *transfer = None;
+            } else if chunk.offset == 0 {
+                *transfer = Some(SnapshotTransferProgress {
+                    tail: chunk.tail,
+                    config: chunk.config,
+                    saved_bytes,
+                });
+            } else if let Some(ref mut t) = *transfer {
+                t.saved_bytes = saved_bytes;
+            }
             NoopSaveLog
         }
 
-        fn save_log_suffix(&mut self, _suffix: &LogSuffix) -> Self::SaveLog {
+        fn supports_snapshot_chunks(&self) -> bool {
+            self.supports_snapshot_chunks
+        }
+
+        fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
+            let mut suffixes = self.saved_log_suffixes.lock().expect("Never fails");
+            suffixes.push(suffix.head.index);
+
+            let mut entries = self.saved_entries.lock().expect("Never fails");
+            let head = suffix.head.index.as_u64() as usize;
+            if head <= entries.len() {
+                entries.truncate(head);
+                entries.extend(suffix.entries.iter().cloned());
+            }
             NoopSaveLog
         }
 
+        fn load_snapshot_transfer(&mut self) -> Result<Option<SnapshotTransferProgress>> {
+            Ok(self.snapshot_transfer.lock().expect("Never fails").clone())
+        }
+
+        fn now(&self) -> Option<Instant> {
+            *self.clock.lock().expect("Never fails")
+        }
+
         fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog {
             let mut logs = self.logs.lock().expect("Never fails");
             if let Some(log) = logs.remove(&(start, end)) {
@@ -151,29 +368,105 @@ pub mod tests {
                     }
                 }
             }
+            let entries = self.saved_entries.lock().expect("Never fails");
+            let (start, end) = (start.as_u64() as usize, end.map(|i| i.as_u64() as usize));
+            if let Some(end) = end.filter(|&end| start < end && end <= entries.len()) {
+                let prev_term = entries[..start]
+                    .last()
+                    .map_or(Default::default(), |e| e.term());
+                let suffix = LogSuffix {
+                    head: LogPosition {
+                        prev_term,
+                        index: LogIndex::new(start as u64),
+                    },
+                    entries: entries[start..end].to_vec(),
+                };
+                return LoadLogImpl {
+                    prefix: None,
+                    suffix: Some(suffix),
+                };
+            }
             LoadLogImpl {
                 prefix: None,
                 suffix: Some(LogSuffix::default()),
             }
         }
 
+        fn inbound_depth(&self) -> usize {
+            self.inbound_messages.lock().expect("Never fails").len()
+        }
+
+        /// 送信されたメッセージは記録されるのみなので、全て未送信として扱う。
+        fn outbound_depth(&self, peer: &NodeId) -> usize {
+            let sent_messages = self.sent_messages.lock().expect("Never fails");
+            sent_messages
+                .iter()
+                .filter(|m| m.header().destination == *peer)
+                .count()
+        }
+
         fn create_timeout(&mut self, role: Role) -> Self::Timeout {
-            match role {
-                Role::Leader => FibersTimeout(timer::timeout(self.leader_timeout)),
-                Role::Follower => FibersTimeout(timer::timeout(self.follower_timeout)),
-                Role::Candidate => FibersTimeout(timer::timeout(self.candidate_timeout)),
+            self.create_jittered_timeout(role, Duration::from_secs(0))
+        }
+
+        fn create_jittered_timeout(&mut self, role: Role, jitter: Duration) -> Self::Timeout {
+            let duration = match role {
+                Role::Leader => self.leader_timeout,
+                Role::Follower => self.follower_timeout,
+                Role::Candidate => self.candidate_timeout,
+            };
+            let duration = duration + jitter;
+            self.current_timeout = Some(duration);
+            let timer = if let Some(ref clock) = self.manual_clock {
+                Timer::Manual {
+                    clock: clock.clone(),
+                    deadline: clock.elapsed() + duration,
+                }
+            } else {
+                Timer::Fibers(timer::timeout(duration))
+            };
+            let failed = self.failing_timeouts > 0;
+            if failed {
+                self.failing_timeouts -= 1;
             }
+            TestTimeout { timer, failed }
+        }
+
+        fn supports_jittered_timeout(&self) -> bool {
+            self.supports_jittered_timeout
+        }
+
+        fn current_timeout_duration(&self) -> Option<Duration> {
+            self.current_timeout
         }
     }
 
-    /// 現時点では必要ないので何もしない。
+    /// 何もしない `Notify` 実装。
+    /// `futures::executor::spawn` を使って、テストスレッド上で `Future` や `Stream` をポーリングするために使う。
+    pub struct NoopNotify;
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    /// 保存自体は `save_ballot` の呼び出し時に完了しているので何もしない。
+    /// `stalled` が `true` の場合には、永遠に完了しない。
+    /// `failed` が `true` の場合には、エラーとなる。
     #[derive(Debug)]
-    pub struct NoopSaveBallot;
-    impl Future for NoopSaveBallot {
+    pub struct SaveBallotImpl {
+        stalled: bool,
+        failed: bool,
+    }
+    impl Future for SaveBallotImpl {
         type Item = ();
         type Error = Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            Ok(Async::Ready(()))
+            if self.failed {
+                Err(ErrorKind::Other.cause("Injected failure").into())
+            } else if self.stalled {
+                Ok(Async::NotReady)
+            } else {
+                Ok(Async::Ready(()))
+            }
         }
     }
 
@@ -222,17 +515,67 @@ pub mod tests {
         }
     }
 
-    /// fibers を使ったタイムアウトの実装。
+    /// 手動で進める仮想的な時刻。
+    ///
+    /// `TestIo::manual_clock` に設定すると、実時間の経過を待たずに、タイムアウトを決定的に発火させられる。
+    #[derive(Debug, Clone, Default)]
+    pub struct ManualClock(Arc<Mutex<Duration>>);
+    impl ManualClock {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// 時刻を `duration` だけ進める。
+        pub fn advance(&self, duration: Duration) {
+            *self.0.lock().expect("Never fails") += duration;
+        }
+
+        /// 生成時からの経過時間を返す。
+        pub fn elapsed(&self) -> Duration {
+            *self.0.lock().expect("Never fails")
+        }
+    }
+
+    #[derive(Debug)]
+    enum Timer {
+        Fibers(timer::Timeout),
+        Manual {
+            clock: ManualClock,
+            deadline: Duration,
+        },
+    }
+
+    /// テスト用のタイムアウトの実装。
+    ///
+    /// 通常は fibers のタイマーを使い、`TestIo::manual_clock` が指定されている場合には、その時刻に従って発火する。
     #[derive(Debug)]
-    pub struct FibersTimeout(timer::Timeout);
-    impl Future for FibersTimeout {
+    pub struct TestTimeout {
+        timer: Timer,
+        failed: bool,
+    }
+    impl Future for TestTimeout {
         type Item = ();
         type Error = Error;
 
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            self.0
-                .poll()
-                .map_err(|_| ErrorKind::Other.cause("Broken timer").into())
+            if self.failed {
+                return Err(ErrorKind::Other.cause("Injected timer failure").into());
+            }
+            match self.timer {
+                Timer::Fibers(ref mut timeout) => timeout
+                    .poll()
+                    .map_err(|_| ErrorKind::Other.cause("Broken timer").into()),
+                Timer::Manual {
+                    ref clock,
+                    deadline,
+                } => {
+                    if clock.elapsed() < deadline {
+                        Ok(Async::NotReady)
+                    } else {
+                        Ok(Async::Ready(()))
+                    }
+                }
+            }
         }
     }
 }